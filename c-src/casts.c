@@ -0,0 +1,32 @@
+int putchar(int c);
+
+unsigned long fold(unsigned long h, long v) {
+    return (h ^ (unsigned long) v) * 1099511628211UL;
+}
+
+int low(long x) {
+    return (int) x;
+}
+
+int main(void) {
+    unsigned long h = 14695981039346656037UL;
+    int big = 2147483647;
+    int minus = -1;
+    long wide = -4294967291L;
+    h = fold(h, (long) big * big);
+    h = fold(h, (long) (unsigned) minus);
+    h = fold(h, (unsigned long) minus >> 1);
+    h = fold(h, (long) (unsigned long) (unsigned) minus);
+    h = fold(h, (int) wide);
+    h = fold(h, (unsigned) wide);
+    h = fold(h, low(wide * 3));
+    h = fold(h, (long) -2147483647 - 1);
+    h = fold(h, (int) 4294967296L + (signed) 7u);
+    h = fold(h, (unsigned) -5 / 2);
+    h = fold(h, (int) (unsigned) -5 / 2);
+    h = fold(h, (long) (int) 3000000000u);
+    h = fold(h, (unsigned long) (long) wide % 1000);
+    putchar(65 + (int) (h % 26));
+    putchar(10);
+    return (int) (h >> 56);
+}
@@ -0,0 +1,51 @@
+int putchar(int c);
+
+long mix(long h, long v) {
+    h = h ^ v;
+    return h * 31 + (h >> 29);
+}
+
+int main(void) {
+    long h = 7;
+    int i = -10;
+    i /= 2u;
+    h = mix(h, i);
+    i = -10;
+    i %= 3u;
+    h = mix(h, i);
+    int n = -64;
+    unsigned s = 3;
+    h = mix(h, n >> s);
+    h = mix(h, n >> 2L);
+    long l = 4294967296L;
+    i = 5;
+    i += l;
+    h = mix(h, i);
+    i = 3;
+    i *= l + 7;
+    h = mix(h, i);
+    unsigned u = 10;
+    u -= 20;
+    h = mix(h, u);
+    u = 7;
+    u <<= 2L;
+    h = mix(h, u);
+    l = -1;
+    l >>= s;
+    h = mix(h, l);
+    l = 100;
+    l /= -3;
+    h = mix(h, l);
+    unsigned long ul = 5;
+    ul -= i;
+    h = mix(h, ul % 1000);
+    int j = 2147483647;
+    j += 1u;
+    h = mix(h, j);
+    h = mix(h, -u);
+    h = mix(h, ~u);
+    h = mix(h, -u > 0);
+    putchar(65 + (h & 15));
+    putchar(10);
+    return (h ^ h >> 8 ^ h >> 16 ^ h >> 24 ^ h >> 32) & 255;
+}
@@ -0,0 +1,36 @@
+int mix(int h, int v) {
+    h = h ^ v;
+    return (h << 5) ^ (h >> 27 & 31);
+}
+
+int divide(int x) {
+    int h = 0;
+    h = mix(h, x / 2);
+    h = mix(h, x % 2);
+    h = mix(h, x / -4);
+    h = mix(h, x % -4);
+    h = mix(h, x / 3);
+    h = mix(h, x % 3);
+    h = mix(h, x / 7);
+    h = mix(h, x % -7);
+    h = mix(h, x / 10);
+    h = mix(h, x % 10);
+    h = mix(h, x / -1000);
+    h = mix(h, x % 641);
+    h = mix(h, x / 1);
+    h = mix(h, x % 1);
+    h = mix(h, x / 2147483647);
+    h = mix(h, x % (-2147483647 - 1));
+    return h;
+}
+
+int main(void) {
+    int h = divide(0);
+    h = h ^ divide(1);
+    h = h ^ divide(-1);
+    h = h ^ divide(12345);
+    h = h ^ divide(-98765);
+    h = h ^ divide(2147483647);
+    h = h ^ divide(-2147483647 - 1);
+    return (h ^ h >> 8 ^ h >> 16 ^ h >> 24) & 255;
+}
@@ -0,0 +1,44 @@
+int putchar(int c);
+
+static long big = 4294967296L;
+long counter;
+
+long sum(long a, int b, long c, int d, long e, int f, long g, int h) {
+    return a + b + c + d + e + f + g + h;
+}
+
+int narrow(long x) {
+    return x;
+}
+
+long mix(long h, long v) {
+    h = h ^ v;
+    return (h << 7) ^ (h >> 57 & 127);
+}
+
+int main(void) {
+    long h = 0;
+    long x = 2147483647;
+    x = x + 1;
+    h = mix(h, x);
+    h = mix(h, big * 3 - 1);
+    h = mix(h, big / 7 + big % 7);
+    h = mix(h, -big / 3 + -big % 3);
+    h = mix(h, narrow(big + 65));
+    h = mix(h, sum(1L, 2, big, -4, 5, 6, 7L, 8));
+    h = mix(h, x > 2147483647 ? 1 : 0);
+    h = mix(h, 9223372036854775807L / -2);
+    int i = 0;
+    for (counter = 0; counter < 10000000000L; counter += 1000000000)
+        i++;
+    h = mix(h, i);
+    h = mix(h, counter);
+    int small = -5;
+    long widened = small;
+    h = mix(h, widened * 3);
+    h += 1;
+    h *= 3;
+    putchar(narrow(65 + (h & 15)));
+    putchar(10);
+    return (h ^ h >> 8 ^ h >> 16 ^ h >> 24 ^ h >> 32 ^ h >> 40 ^ h >> 48 ^ h >> 56) & 255;
+}
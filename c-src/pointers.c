@@ -0,0 +1,63 @@
+int putchar(int c);
+
+static long total;
+int *last = 0;
+
+int set(int *p, int value) {
+    int old = *p;
+    *p = value;
+    last = p;
+    return old;
+}
+
+long *pick(long *a, long *b, int first) {
+    return first ? a : b;
+}
+
+long bump(long **pp) {
+    return **pp += 10;
+}
+
+int swap(unsigned *a, unsigned *b) {
+    unsigned tmp = *a;
+    *a = *b;
+    *b = tmp;
+    return *a < *b;
+}
+
+int main(void) {
+    int x = 1;
+    int y = 2;
+    int *p = &x;
+    int **pp = &p;
+
+    *p = 5;
+    **pp += 3;
+    (*p)++;
+    ++*p;
+    p = &y;
+    *p *= 7;
+    int old = set(&x, 40);
+
+    long a = 100;
+    long b = -100;
+    long *la = pick(&a, &b, x > y);
+    long **lpp = &la;
+    bump(lpp);
+    *pick(&a, &b, 0) -= 1;
+    total = *la + b;
+    long *pt = &total;
+    *pt = *pt / 3;
+
+    unsigned u = 4000000000u;
+    unsigned v = 7;
+    int less = swap(&u, &v);
+
+    int *null = 0;
+    int checks = (null == 0) + (p != 0) + (last == &x) + (&x != &y) + !null + (p == &y);
+
+    putchar(65 + x % 26);
+    putchar(65 + y % 26);
+    putchar(10);
+    return (old + y + checks + less + (int)total + (int)(u % 100) + (v > u)) & 255;
+}
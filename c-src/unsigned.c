@@ -0,0 +1,53 @@
+int putchar(int c);
+
+static unsigned long seed = 88172645463325252UL;
+unsigned counter;
+
+unsigned long next(void) {
+    seed = seed ^ seed << 13;
+    seed = seed ^ seed >> 7;
+    seed = seed ^ seed << 17;
+    return seed;
+}
+
+unsigned hash(unsigned h, unsigned long v) {
+    h = h ^ v;
+    h = h * 16777619u;
+    return h ^ h >> 15;
+}
+
+int below(unsigned a, unsigned b) {
+    return a < b;
+}
+
+unsigned long widen(unsigned x) {
+    return x;
+}
+
+int main(void) {
+    unsigned h = 2166136261u;
+    unsigned big = 4294967295u;
+    int minus = -1;
+    h = hash(h, big / 3 + big % 7);
+    h = hash(h, big >> 31);
+    h = hash(h, below(1u, big) + 2 * below(big, 1u));
+    h = hash(h, minus < 1u);
+    h = hash(h, minus > 1ul);
+    h = hash(h, widen(big) + 1);
+    unsigned long wide = minus;
+    h = hash(h, wide >> 60);
+    h = hash(h, wide / 10 % 1000);
+    long negative = -7;
+    h = hash(h, negative / 2 + 7u);
+    h = hash(h, 18446744073709551615UL / 3000000000u);
+    for (counter = 4294967290u; counter > 10; counter = counter + 1)
+        h = hash(h, counter);
+    h = hash(h, counter);
+    int i;
+    for (i = 0; i < 20; i++)
+        h = hash(h, next() % 1000003);
+    unsigned char_code = 65 + h % 26;
+    putchar(char_code);
+    putchar(10);
+    return h % 251;
+}
@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{codegen, tacky};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Flow<'a> {
+    Label(&'a str),
+    Jump(&'a str),
+    Branch(&'a str),
+    Return,
+    Next,
+}
+
+pub trait Instruction {
+    fn flow(&self) -> Flow<'_>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum Node {
+    Entry,
+    Block(usize),
+    Exit,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block<I> {
+    pub instructions: Vec<I>,
+    pub successors: Vec<Node>,
+    pub predecessors: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cfg<I> {
+    pub blocks: Vec<Block<I>>,
+    pub exit_predecessors: Vec<Node>,
+}
+
+impl<I: Instruction> Cfg<I> {
+    pub fn new(instructions: Vec<I>) -> Self {
+        let mut blocks: Vec<Block<I>> = vec![];
+        let mut current = vec![];
+        for instr in instructions {
+            match instr.flow() {
+                Flow::Label(_) => {
+                    if !current.is_empty() {
+                        blocks.push(Block::new(std::mem::take(&mut current)));
+                    }
+                    current.push(instr);
+                }
+                Flow::Jump(_) | Flow::Branch(_) | Flow::Return => {
+                    current.push(instr);
+                    blocks.push(Block::new(std::mem::take(&mut current)));
+                }
+                Flow::Next => current.push(instr),
+            }
+        }
+        if !current.is_empty() {
+            blocks.push(Block::new(current));
+        }
+
+        let mut cfg = Cfg {
+            blocks,
+            exit_predecessors: vec![],
+        };
+        cfg.connect();
+        cfg
+    }
+
+    fn connect(&mut self) {
+        let labels = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, block)| match block.instructions.first()?.flow() {
+                Flow::Label(label) => Some((label.to_string(), i)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let target = |label: &str| {
+            Node::Block(*labels.get(label).unwrap_or_else(|| {
+                panic!("Jump to undefined label {}", label);
+            }))
+        };
+
+        let count = self.blocks.len();
+        let fallthrough = |i: usize| {
+            if i + 1 < count {
+                Node::Block(i + 1)
+            } else {
+                Node::Exit
+            }
+        };
+        let successors = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| match block.instructions.last().map(I::flow) {
+                Some(Flow::Jump(label)) => vec![target(label)],
+                Some(Flow::Branch(label)) => {
+                    let mut successors = vec![target(label), fallthrough(i)];
+                    successors.dedup();
+                    successors
+                }
+                Some(Flow::Return) => vec![Node::Exit],
+                _ => vec![fallthrough(i)],
+            })
+            .collect::<Vec<_>>();
+
+        self.exit_predecessors.clear();
+        for block in &mut self.blocks {
+            block.predecessors.clear();
+        }
+        if let Some(first) = self.blocks.first_mut() {
+            first.predecessors.push(Node::Entry);
+        }
+        for (i, successors) in successors.into_iter().enumerate() {
+            for successor in &successors {
+                match successor {
+                    Node::Block(j) => self.blocks[*j].predecessors.push(Node::Block(i)),
+                    Node::Exit => self.exit_predecessors.push(Node::Block(i)),
+                    Node::Entry => unreachable!(),
+                }
+            }
+            self.blocks[i].successors = successors;
+        }
+    }
+
+    pub fn into_instructions(self) -> Vec<I> {
+        self.blocks
+            .into_iter()
+            .flat_map(|block| block.instructions)
+            .collect()
+    }
+
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = if self.blocks.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(i) = stack.pop() {
+            if reachable[i] {
+                continue;
+            }
+            reachable[i] = true;
+            for successor in &self.blocks[i].successors {
+                if let Node::Block(j) = successor {
+                    stack.push(*j);
+                }
+            }
+        }
+        if reachable.iter().all(|r| *r) {
+            return;
+        }
+        let mut reachable = reachable.into_iter();
+        self.blocks.retain(|_| reachable.next().unwrap_or(false));
+        self.connect();
+    }
+
+    pub fn remove_redundant_jumps(&mut self) {
+        let count = self.blocks.len();
+        for i in 0..count.saturating_sub(1) {
+            let next_label = match self.blocks[i + 1].instructions.first().map(I::flow) {
+                Some(Flow::Label(label)) => label.to_string(),
+                _ => continue,
+            };
+            let block = &mut self.blocks[i];
+            let redundant = matches!(
+                block.instructions.last().map(I::flow),
+                Some(Flow::Jump(label) | Flow::Branch(label)) if label == next_label
+            );
+            if redundant {
+                block.instructions.pop();
+            }
+        }
+        self.connect();
+    }
+
+    pub fn remove_unused_labels(&mut self) {
+        let targets = self
+            .blocks
+            .iter()
+            .filter_map(|block| match block.instructions.last()?.flow() {
+                Flow::Jump(label) | Flow::Branch(label) => Some(label.to_string()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        for block in &mut self.blocks {
+            if let Some(Flow::Label(label)) = block.instructions.first().map(I::flow)
+                && !targets.contains(label)
+            {
+                block.instructions.remove(0);
+            }
+        }
+    }
+}
+
+impl<I> Block<I> {
+    fn new(instructions: Vec<I>) -> Self {
+        Self {
+            instructions,
+            successors: vec![],
+            predecessors: vec![],
+        }
+    }
+}
+
+impl Instruction for tacky::Instr {
+    fn flow(&self) -> Flow<'_> {
+        match self {
+            tacky::Instr::Label(label) => Flow::Label(label),
+            tacky::Instr::Jump { target } => Flow::Jump(target),
+            tacky::Instr::JumpIfZero { target, .. }
+            | tacky::Instr::JumpIfNotZero { target, .. } => Flow::Branch(target),
+            tacky::Instr::Return(_) => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+}
+
+impl Instruction for codegen::Instr {
+    fn flow(&self) -> Flow<'_> {
+        match self {
+            codegen::Instr::Label(label) => Flow::Label(label),
+            codegen::Instr::Jmp(label) => Flow::Jump(label),
+            codegen::Instr::JmpCC(_, label) => Flow::Branch(label),
+            codegen::Instr::Ret => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tacky::Instr;
+    use crate::tacky::text;
+    use crate::types::Const;
+
+    fn cfg(body: &str) -> Cfg<Instr> {
+        let source = format!("global function main() {{\n{}\n}}\n", body);
+        let (_, instructions, _) = text::parse_function(&source);
+        Cfg::new(instructions)
+    }
+
+    #[test]
+    fn edges() {
+        let cfg = cfg("x = 1
+            jump_if_zero x, else
+            y = 2
+            jump end
+          else:
+            y = 3
+          end:
+            return y");
+        let successors = cfg
+            .blocks
+            .iter()
+            .map(|b| b.successors.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            successors,
+            [
+                vec![Node::Block(2), Node::Block(1)],
+                vec![Node::Block(3)],
+                vec![Node::Block(3)],
+                vec![Node::Exit],
+            ]
+        );
+        assert_eq!(cfg.blocks[0].predecessors, [Node::Entry]);
+        assert_eq!(cfg.blocks[3].predecessors, [Node::Block(1), Node::Block(2)]);
+        assert_eq!(cfg.exit_predecessors, [Node::Block(3)]);
+        assert_eq!(cfg.clone().into_instructions().len(), 8);
+    }
+
+    #[test]
+    fn cleanup() {
+        let mut cfg = cfg("jump_if_zero x, a
+          a:
+            jump b
+            y = 1
+          b:
+          c:
+            return 1
+            return 0");
+        cfg.remove_unreachable_blocks();
+        assert_eq!(cfg.blocks.len(), 4);
+        cfg.remove_redundant_jumps();
+        cfg.remove_unused_labels();
+        assert_eq!(
+            cfg.into_instructions(),
+            [Instr::Return(tacky::Val::Constant(Const::Int(1)))]
+        );
+    }
+}
@@ -1,15 +1,28 @@
 use std::collections::HashMap;
 
-use crate::semantic_analysis::{Attrs, Type};
+use crate::diagnostic::{Diagnostic, Result};
+use crate::semantic_analysis::{Attrs, SymbolTable};
 use crate::tacky::{self, Tacky, TopLevel};
+use crate::types::{Const, Type};
 
-#[derive(Debug, PartialEq, Clone)]
+pub mod division;
+pub mod peephole;
+mod regalloc;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Operand {
-    Imm(i32),
+    Imm(i64),
     Reg(Register),
     Pseudo(String),
     Stack(i16),
     Data(String),
+    Memory(Register, i32),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AssemblyType {
+    Longword,
+    Quadword,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -28,9 +41,10 @@ pub enum BinaryOp {
     BitXOr,
     ShiftLeft,
     ShiftRight,
+    LogicalShiftRight,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Register {
     AX,
     CX,
@@ -41,6 +55,11 @@ pub enum Register {
     R9,
     R10,
     R11,
+    BX,
+    R12,
+    R13,
+    R14,
+    R15,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -51,37 +70,72 @@ pub enum CondCode {
     GE,
     L,
     LE,
+    A,
+    AE,
+    B,
+    BE,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Instr {
     Ret,
     Mov {
+        ty: AssemblyType,
+        src: Operand,
+        dst: Operand,
+    },
+    Movsx {
+        src: Operand,
+        dst: Operand,
+    },
+    MovZeroExtend {
         src: Operand,
         dst: Operand,
     },
     Unary {
         unop: UnaryOp,
+        ty: AssemblyType,
         dst: Operand,
     },
     Binary {
         binop: BinaryOp,
+        ty: AssemblyType,
         src: Operand,
         dst: Operand,
     },
-    IDiv(Operand),
-    Cdq,
+    IDiv(AssemblyType, Operand),
+    Div(AssemblyType, Operand),
+    IMul(AssemblyType, Operand),
+    Cdq(AssemblyType),
     AllocateStack(u16),
     Jmp(String),
     JmpCC(CondCode, String),
     SetCC(CondCode, Operand),
     Label(String),
     Cmp {
+        ty: AssemblyType,
         lhs: Operand,
         rhs: Operand,
     },
+    Test {
+        ty: AssemblyType,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Lea {
+        ty: AssemblyType,
+        base: Register,
+        index: Option<Register>,
+        offset: i32,
+        dst: Register,
+    },
+    LoadAddress {
+        src: Operand,
+        dst: Operand,
+    },
     DeallocateStack(u16),
     Push(Operand),
+    Pop(Register),
     Call(String),
 }
 #[derive(Debug, PartialEq, Clone)]
@@ -94,31 +148,97 @@ pub enum AsmTopLevel {
     AsmStatic {
         name: String,
         global: bool,
-        init: i32,
+        init: Const,
     },
 }
 
 pub type Assembly = Vec<AsmTopLevel>;
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct AllocationStats {
+    pub function: String,
+    pub moves_removed: usize,
+}
+
 struct ReplaceState<'a> {
     offsets: HashMap<String, u16>,
     max_offset: u16,
-    symbols: &'a HashMap<String, (Type, Attrs)>,
+    symbols: &'a SymbolTable,
+}
+
+pub fn assemble(top_levels: Tacky, symbols: &SymbolTable) -> Result<Assembly> {
+    let (assembly, _) = allocate(select_instructions(top_levels, symbols)?, symbols);
+    Ok(peephole::optimize(assembly, &peephole::Rule::all()))
 }
 
-pub fn assemble(top_levels: Tacky, symbols: &HashMap<String, (Type, Attrs)>) -> Assembly {
+pub fn select_instructions(top_levels: Tacky, symbols: &SymbolTable) -> Result<Assembly> {
     let mut asm_top_levels = Vec::with_capacity(top_levels.len());
     for top_level in top_levels {
-        asm_top_levels.push(assemble_top_level(top_level, symbols));
+        asm_top_levels.push(assemble_top_level(top_level, symbols)?);
     }
-    asm_top_levels
+    Ok(asm_top_levels)
+}
+
+pub fn allocate(top_levels: Assembly, symbols: &SymbolTable) -> (Assembly, Vec<AllocationStats>) {
+    let mut stats = vec![];
+    let assembly = top_levels
+        .into_iter()
+        .map(|top_level| match top_level {
+            AsmTopLevel::AsmFunction {
+                name,
+                mut instructions,
+                global,
+            } => {
+                let moves = count_moves(&instructions);
+                instructions = regalloc::allocate_registers(instructions, symbols);
+                stats.push(AllocationStats {
+                    function: name.clone(),
+                    moves_removed: moves - count_moves(&instructions),
+                });
+                let saved = regalloc::callee_saved(&instructions);
+                let stack_size = replace_pseudo(&mut instructions, symbols);
+
+                // The saved registers are pushed below the locals, so round the
+                // whole frame rather than just the locals.
+                let saved_size = 8 * saved.len() as u16;
+                let rounded = match (stack_size + saved_size) % 16 {
+                    0 => stack_size,
+                    n => stack_size + (16 - n),
+                };
+
+                let mut prologue = vec![Instr::AllocateStack(rounded)];
+                prologue.extend(saved.iter().map(|reg| Instr::Push(Operand::Reg(*reg))));
+                let mut body = Vec::with_capacity(instructions.len() + prologue.len());
+                body.extend(prologue);
+                for instr in instructions {
+                    if instr == Instr::Ret {
+                        body.extend(saved.iter().rev().map(|reg| Instr::Pop(*reg)));
+                    }
+                    body.push(instr);
+                }
+                let instructions = body;
+
+                AsmTopLevel::AsmFunction {
+                    name,
+                    instructions: fixup_instructions(instructions),
+                    global,
+                }
+            }
+            top_level => top_level,
+        })
+        .collect();
+    (assembly, stats)
+}
+
+fn count_moves(instructions: &[Instr]) -> usize {
+    instructions
+        .iter()
+        .filter(|instr| matches!(instr, Instr::Mov { .. }))
+        .count()
 }
 
-fn assemble_top_level(
-    top_level: TopLevel,
-    symbols: &HashMap<String, (Type, Attrs)>,
-) -> AsmTopLevel {
-    match top_level {
+fn assemble_top_level(top_level: TopLevel, symbols: &SymbolTable) -> Result<AsmTopLevel> {
+    let asm_top_level = match top_level {
         TopLevel::TackyFunction {
             name,
             instructions,
@@ -129,6 +249,7 @@ fn assemble_top_level(
             let mut stack_offset = 16;
             for stack_param in params.iter().skip(6) {
                 assembly.push(Instr::Mov {
+                    ty: pseudo_type(stack_param, symbols),
                     src: Operand::Stack(stack_offset),
                     dst: Operand::Pseudo(stack_param.to_string()),
                 });
@@ -148,42 +269,39 @@ fn assemble_top_level(
             for (param, src) in params.iter().zip(reg_arg_locations) {
                 let dst = Operand::Pseudo(param.to_string());
                 assembly.push(Instr::Mov {
+                    ty: pseudo_type(param, symbols),
                     src: src.clone(),
                     dst,
                 });
             }
 
-            let body = assemble_instructions(instructions);
+            let body = assemble_instructions(instructions, symbols)?;
 
             assembly.extend(body);
 
-            let stack_size = replace_pseudo(&mut assembly, symbols);
-
-            let rounded = match stack_size % 16 {
-                0 => stack_size,
-                n => stack_size + (16 - n),
-            };
-
-            assembly.insert(0, Instr::AllocateStack(rounded));
-
-            let fixed = fixup_instructions(assembly);
-
             AsmTopLevel::AsmFunction {
                 name,
-                instructions: fixed,
+                instructions: assembly,
                 global,
             }
         }
         TopLevel::StaticVar { name, global, init } => AsmTopLevel::AsmStatic { name, global, init },
-    }
+    };
+    Ok(asm_top_level)
 }
 
-fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
+fn assemble_instructions(
+    instructions: Vec<tacky::Instr>,
+    symbols: &SymbolTable,
+) -> Result<Vec<Instr>> {
+    let ty = |val: &tacky::Val| assembly_type(&tacky::type_of(val, symbols));
+    let signed = |val: &tacky::Val| tacky::type_of(val, symbols).is_signed();
     let mut assembly = Vec::new();
     for instr in instructions {
         match instr {
             tacky::Instr::Return(val) => {
                 assembly.push(Instr::Mov {
+                    ty: ty(&val),
                     src: assemble_val(val),
                     dst: Operand::Reg(Register::AX),
                 });
@@ -191,6 +309,21 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
             }
             tacky::Instr::Jump { target } => assembly.push(Instr::Jmp(target)),
             tacky::Instr::Copy { src, dst } => assembly.push(Instr::Mov {
+                ty: ty(&dst),
+                src: assemble_val(src),
+                dst: assemble_val(dst),
+            }),
+            tacky::Instr::SignExtend { src, dst } => assembly.push(Instr::Movsx {
+                src: assemble_val(src),
+                dst: assemble_val(dst),
+            }),
+            // Moving the low half is all a truncation takes.
+            tacky::Instr::Truncate { src, dst } => assembly.push(Instr::Mov {
+                ty: AssemblyType::Longword,
+                src: assemble_val(src),
+                dst: assemble_val(dst),
+            }),
+            tacky::Instr::ZeroExtend { src, dst } => assembly.push(Instr::MovZeroExtend {
                 src: assemble_val(src),
                 dst: assemble_val(dst),
             }),
@@ -201,23 +334,28 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                 dst,
             } => assembly.extend(vec![
                 Instr::Cmp {
+                    ty: ty(&src),
                     lhs: Operand::Imm(0),
                     rhs: assemble_val(src),
                 },
                 Instr::Mov {
+                    ty: ty(&dst),
                     src: Operand::Imm(0),
                     dst: assemble_val(dst.clone()),
                 },
                 Instr::SetCC(CondCode::E, assemble_val(dst)),
             ]),
             tacky::Instr::Unary { unop, src, dst } => {
+                let ty = ty(&dst);
                 let dst = assemble_val(dst);
                 assembly.push(Instr::Mov {
+                    ty,
                     src: assemble_val(src),
                     dst: dst.clone(),
                 });
                 assembly.push(Instr::Unary {
-                    unop: assemble_unop(unop),
+                    unop: assemble_unop(unop)?,
+                    ty,
                     dst,
                 });
             }
@@ -227,26 +365,51 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                 src2,
                 dst,
             } => {
+                let (ty, signed) = (ty(&src1), signed(&src1));
                 let dst = assemble_val(dst);
                 let src1 = assemble_val(src1);
                 let src2 = assemble_val(src2);
+                let remainder = binop == tacky::BinaryOp::Remainder;
+                if ty == AssemblyType::Longword
+                    && signed
+                    && let Operand::Imm(divisor) = src2
+                    && let Some(instrs) = division::divide_by_constant(
+                        src1.clone(),
+                        divisor as i32,
+                        remainder,
+                        dst.clone(),
+                    )
+                {
+                    assembly.extend(instrs);
+                    continue;
+                }
                 let out_reg = if binop == tacky::BinaryOp::Divide {
                     Register::AX
                 } else {
                     Register::DX
                 };
-                assembly.extend(vec![
-                    Instr::Mov {
-                        src: src1,
-                        dst: Operand::Reg(Register::AX),
-                    },
-                    Instr::Cdq,
-                    Instr::IDiv(src2),
-                    Instr::Mov {
-                        src: Operand::Reg(out_reg),
-                        dst,
-                    },
-                ]);
+                assembly.push(Instr::Mov {
+                    ty,
+                    src: src1,
+                    dst: Operand::Reg(Register::AX),
+                });
+                if signed {
+                    assembly.extend(vec![Instr::Cdq(ty), Instr::IDiv(ty, src2)]);
+                } else {
+                    assembly.extend(vec![
+                        Instr::Mov {
+                            ty,
+                            src: Operand::Imm(0),
+                            dst: Operand::Reg(Register::DX),
+                        },
+                        Instr::Div(ty, src2),
+                    ]);
+                }
+                assembly.push(Instr::Mov {
+                    ty,
+                    src: Operand::Reg(out_reg),
+                    dst,
+                });
             }
             tacky::Instr::Binary {
                 binop: binop @ (tacky::BinaryOp::ShiftLeft | tacky::BinaryOp::ShiftRight),
@@ -256,21 +419,26 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
             } => {
                 let binop = match binop {
                     tacky::BinaryOp::ShiftLeft => BinaryOp::ShiftLeft,
-                    tacky::BinaryOp::ShiftRight => BinaryOp::ShiftRight,
-                    _ => panic!("unreachable"),
+                    tacky::BinaryOp::ShiftRight if signed(&src1) => BinaryOp::ShiftRight,
+                    tacky::BinaryOp::ShiftRight => BinaryOp::LogicalShiftRight,
+                    _ => unreachable!(),
                 };
+                let (count_ty, ty) = (ty(&src2), ty(&src1));
                 let dst = assemble_val(dst);
                 assembly.extend(vec![
                     Instr::Mov {
+                        ty: count_ty,
                         src: assemble_val(src2),
                         dst: Operand::Reg(Register::CX),
                     },
                     Instr::Mov {
+                        ty,
                         src: assemble_val(src1),
                         dst: dst.clone(),
                     },
                     Instr::Binary {
                         binop,
+                        ty,
                         src: Operand::Reg(Register::CX),
                         dst,
                     },
@@ -281,22 +449,28 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                 src1,
                 src2,
                 dst,
-            } if is_comparison(binop) => {
-                let code = match binop {
-                    tacky::BinaryOp::Equals => CondCode::E,
-                    tacky::BinaryOp::NotEquals => CondCode::NE,
-                    tacky::BinaryOp::GreaterThan => CondCode::G,
-                    tacky::BinaryOp::GreaterThanEquals => CondCode::GE,
-                    tacky::BinaryOp::LessThan => CondCode::L,
-                    tacky::BinaryOp::LessThanEquals => CondCode::LE,
+            } if binop.is_comparison() => {
+                let code = match (binop, signed(&src1)) {
+                    (tacky::BinaryOp::Equals, _) => CondCode::E,
+                    (tacky::BinaryOp::NotEquals, _) => CondCode::NE,
+                    (tacky::BinaryOp::GreaterThan, true) => CondCode::G,
+                    (tacky::BinaryOp::GreaterThanEquals, true) => CondCode::GE,
+                    (tacky::BinaryOp::LessThan, true) => CondCode::L,
+                    (tacky::BinaryOp::LessThanEquals, true) => CondCode::LE,
+                    (tacky::BinaryOp::GreaterThan, false) => CondCode::A,
+                    (tacky::BinaryOp::GreaterThanEquals, false) => CondCode::AE,
+                    (tacky::BinaryOp::LessThan, false) => CondCode::B,
+                    (tacky::BinaryOp::LessThanEquals, false) => CondCode::BE,
                     _ => unreachable!(),
                 };
                 assembly.extend(vec![
                     Instr::Cmp {
+                        ty: ty(&src1),
                         lhs: assemble_val(src2),
                         rhs: assemble_val(src1),
                     },
                     Instr::Mov {
+                        ty: ty(&dst),
                         src: Operand::Imm(0),
                         dst: assemble_val(dst.clone()),
                     },
@@ -316,19 +490,24 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                     tacky::BinaryOp::BitAnd => BinaryOp::BitAnd,
                     tacky::BinaryOp::BitOr => BinaryOp::BitOr,
                     tacky::BinaryOp::BitXOr => BinaryOp::BitXOr,
-                    _ => panic!(
-                        "Expected add, subtract, multiply, or bitwise op, got {:?}",
-                        binop
-                    ),
+                    _ => {
+                        return Err(Diagnostic::internal(format!(
+                            "Expected add, subtract, multiply, or bitwise op, got {:?}",
+                            binop
+                        )));
+                    }
                 };
+                let ty = ty(&src1);
                 let dst = assemble_val(dst);
                 assembly.extend(vec![
                     Instr::Mov {
+                        ty,
                         src: assemble_val(src1),
                         dst: dst.clone(),
                     },
                     Instr::Binary {
                         binop,
+                        ty,
                         src: assemble_val(src2),
                         dst,
                     },
@@ -336,6 +515,7 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
             }
             tacky::Instr::JumpIfZero { condition, target } => assembly.extend(vec![
                 Instr::Cmp {
+                    ty: ty(&condition),
                     lhs: Operand::Imm(0),
                     rhs: assemble_val(condition),
                 },
@@ -343,6 +523,7 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
             ]),
             tacky::Instr::JumpIfNotZero { condition, target } => assembly.extend(vec![
                 Instr::Cmp {
+                    ty: ty(&condition),
                     lhs: Operand::Imm(0),
                     rhs: assemble_val(condition),
                 },
@@ -374,6 +555,7 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                     let reg = arg_registers[reg_index];
                     let asm_param = assemble_val(tacky_param.clone());
                     assembly.push(Instr::Mov {
+                        ty: ty(tacky_param),
                         src: asm_param,
                         dst: Operand::Reg(reg),
                     })
@@ -386,6 +568,7 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                     } else {
                         assembly.extend(vec![
                             Instr::Mov {
+                                ty: ty(tacky_param),
                                 src: asm_param,
                                 dst: Operand::Reg(Register::AX),
                             },
@@ -401,45 +584,75 @@ fn assemble_instructions(instructions: Vec<tacky::Instr>) -> Vec<Instr> {
                     assembly.push(Instr::DeallocateStack(bytes_to_pop));
                 }
 
-                let dst = assemble_val(dst);
                 assembly.push(Instr::Mov {
+                    ty: ty(&dst),
                     src: Operand::Reg(Register::AX),
-                    dst,
+                    dst: assemble_val(dst),
                 })
             }
+            tacky::Instr::GetAddress { src, dst } => assembly.push(Instr::LoadAddress {
+                src: assemble_val(src),
+                dst: assemble_val(dst),
+            }),
+            tacky::Instr::Load { src_ptr, dst } => assembly.extend(vec![
+                Instr::Mov {
+                    ty: AssemblyType::Quadword,
+                    src: assemble_val(src_ptr),
+                    dst: Operand::Reg(Register::AX),
+                },
+                Instr::Mov {
+                    ty: ty(&dst),
+                    src: Operand::Memory(Register::AX, 0),
+                    dst: assemble_val(dst),
+                },
+            ]),
+            tacky::Instr::Store { src, dst_ptr } => assembly.extend(vec![
+                Instr::Mov {
+                    ty: AssemblyType::Quadword,
+                    src: assemble_val(dst_ptr),
+                    dst: Operand::Reg(Register::AX),
+                },
+                Instr::Mov {
+                    ty: ty(&src),
+                    src: assemble_val(src),
+                    dst: Operand::Memory(Register::AX, 0),
+                },
+            ]),
         }
     }
-    assembly
+    Ok(assembly)
 }
 
-fn is_comparison(binop: tacky::BinaryOp) -> bool {
-    matches!(
-        binop,
-        tacky::BinaryOp::Equals
-            | tacky::BinaryOp::GreaterThan
-            | tacky::BinaryOp::GreaterThanEquals
-            | tacky::BinaryOp::LessThan
-            | tacky::BinaryOp::LessThanEquals
-            | tacky::BinaryOp::NotEquals
-    )
+fn assembly_type(ty: &Type) -> AssemblyType {
+    match ty {
+        Type::Long | Type::ULong | Type::Pointer(_) => AssemblyType::Quadword,
+        _ => AssemblyType::Longword,
+    }
+}
+
+fn pseudo_type(name: &str, symbols: &SymbolTable) -> AssemblyType {
+    assembly_type(&tacky::type_of(&tacky::Val::Var(name.to_string()), symbols))
 }
 
-fn assemble_unop(unop: tacky::UnaryOp) -> UnaryOp {
+fn assemble_unop(unop: tacky::UnaryOp) -> Result<UnaryOp> {
     match unop {
-        tacky::UnaryOp::Complement => UnaryOp::Not,
-        tacky::UnaryOp::Negate => UnaryOp::Neg,
-        unop => panic!("Can't assemble {:?}", unop),
+        tacky::UnaryOp::Complement => Ok(UnaryOp::Not),
+        tacky::UnaryOp::Negate => Ok(UnaryOp::Neg),
+        unop => Err(Diagnostic::internal(format!("Can't assemble {:?}", unop))),
     }
 }
 
 fn assemble_val(val: tacky::Val) -> Operand {
     match val {
-        tacky::Val::Constant(n) => Operand::Imm(n),
+        // Only the low 32 bits of a longword immediate matter, and keeping
+        // them sign-extended lets them fit the instruction's imm32.
+        tacky::Val::Constant(Const::UInt(n)) => Operand::Imm(n as i32 as i64),
+        tacky::Val::Constant(n) => Operand::Imm(n.value()),
         tacky::Val::Var(s) => Operand::Pseudo(s),
     }
 }
 
-fn replace_pseudo(instrs: &mut [Instr], symbols: &HashMap<String, (Type, Attrs)>) -> u16 {
+fn replace_pseudo(instrs: &mut [Instr], symbols: &SymbolTable) -> u16 {
     let stack_map = HashMap::new();
     let mut replace_state = ReplaceState {
         offsets: stack_map,
@@ -447,85 +660,8 @@ fn replace_pseudo(instrs: &mut [Instr], symbols: &HashMap<String, (Type, Attrs)>
         symbols,
     };
     for instr in instrs {
-        match instr {
-            Instr::Unary { unop: _, dst: _ } => {
-                let unary = std::mem::replace(instr, Instr::Ret);
-                let Instr::Unary { unop, dst: operand } = unary else {
-                    panic!("unreachable")
-                };
-                let new_operand = replace_op(operand, &mut replace_state);
-                *instr = Instr::Unary {
-                    unop,
-                    dst: new_operand,
-                };
-            }
-            Instr::Binary {
-                binop: _,
-                src: _,
-                dst: _,
-            } => {
-                let binary = std::mem::replace(instr, Instr::Ret);
-                let Instr::Binary { binop, src, dst } = binary else {
-                    panic!("unreachable");
-                };
-                let new_src = replace_op(src, &mut replace_state);
-                let new_dst = replace_op(dst, &mut replace_state);
-                *instr = Instr::Binary {
-                    binop,
-                    src: new_src,
-                    dst: new_dst,
-                };
-            }
-            Instr::IDiv(_) => {
-                let idiv = std::mem::replace(instr, Instr::Ret);
-                let Instr::IDiv(op) = idiv else {
-                    panic!("unreachable")
-                };
-                let op = replace_op(op, &mut replace_state);
-                *instr = Instr::IDiv(op);
-            }
-            Instr::Mov { src: _, dst: _ } => {
-                let mov = std::mem::replace(instr, Instr::Ret);
-                let Instr::Mov { src, dst } = mov else {
-                    panic!("unreachable")
-                };
-                let new_src = replace_op(src, &mut replace_state);
-                let new_dst = replace_op(dst, &mut replace_state);
-                *instr = Instr::Mov {
-                    src: new_src,
-                    dst: new_dst,
-                };
-            }
-            Instr::Cmp { lhs: _, rhs: _ } => {
-                let cmp = std::mem::replace(instr, Instr::Ret);
-                let Instr::Cmp { lhs, rhs } = cmp else {
-                    unreachable!()
-                };
-                let new_lhs = replace_op(lhs, &mut replace_state);
-                let new_rhs = replace_op(rhs, &mut replace_state);
-                *instr = Instr::Cmp {
-                    lhs: new_lhs,
-                    rhs: new_rhs,
-                };
-            }
-            Instr::SetCC(_, _) => {
-                let setcc = std::mem::replace(instr, Instr::Ret);
-                let Instr::SetCC(cond_code, operand) = setcc else {
-                    unreachable!();
-                };
-                let operand = replace_op(operand, &mut replace_state);
-                *instr = Instr::SetCC(cond_code, operand);
-            }
-            Instr::Push(_) => {
-                let push = std::mem::replace(instr, Instr::Ret);
-                let Instr::Push(operand) = push else {
-                    unreachable!();
-                };
-                let operand = replace_op(operand, &mut replace_state);
-                *instr = Instr::Push(operand);
-            }
-            _ => (),
-        }
+        let replaced = std::mem::replace(instr, Instr::Ret);
+        *instr = regalloc::map_operands(replaced, |op| replace_op(op, &mut replace_state));
     }
     replace_state.max_offset
 }
@@ -537,8 +673,12 @@ fn replace_op(op: Operand, state: &mut ReplaceState) -> Operand {
             if let Some((_, Attrs::Static { .. })) = state.symbols.get(&var) {
                 Operand::Data(var)
             } else {
+                let size = match pseudo_type(&var, state.symbols) {
+                    AssemblyType::Longword => 4,
+                    AssemblyType::Quadword => 8,
+                };
                 let offset = stack_map.entry(var).or_insert_with(|| {
-                    state.max_offset += 4;
+                    state.max_offset = (state.max_offset + size).next_multiple_of(size);
                     state.max_offset
                 });
                 Operand::Stack(-(*offset as i16))
@@ -549,25 +689,113 @@ fn replace_op(op: Operand, state: &mut ReplaceState) -> Operand {
 }
 
 fn is_memory(op: &Operand) -> bool {
-    matches!(op, Operand::Data(_) | Operand::Stack(_))
+    matches!(
+        op,
+        Operand::Data(_) | Operand::Stack(_) | Operand::Memory(..)
+    )
+}
+
+fn is_large(op: &Operand) -> bool {
+    matches!(op, Operand::Imm(n) if i32::try_from(*n).is_err())
+}
+
+// Only mov can take a 64-bit immediate, and only into a register.
+fn hoist_large_immediate(instr: Instr, fixed: &mut Vec<Instr>) -> Instr {
+    let mut hoist = |op: Operand| {
+        if !is_large(&op) {
+            return op;
+        }
+        fixed.push(Instr::Mov {
+            ty: AssemblyType::Quadword,
+            src: op,
+            dst: Operand::Reg(Register::R10),
+        });
+        Operand::Reg(Register::R10)
+    };
+    match instr {
+        Instr::Mov {
+            ty: AssemblyType::Longword,
+            src: Operand::Imm(n),
+            dst,
+        } => Instr::Mov {
+            ty: AssemblyType::Longword,
+            src: Operand::Imm(n as i32 as i64),
+            dst,
+        },
+        Instr::Mov { ty, src, dst } if is_memory(&dst) => Instr::Mov {
+            ty,
+            src: hoist(src),
+            dst,
+        },
+        Instr::Binary {
+            binop,
+            ty,
+            src,
+            dst,
+        } => Instr::Binary {
+            binop,
+            ty,
+            src: hoist(src),
+            dst,
+        },
+        Instr::Cmp { ty, lhs, rhs } => Instr::Cmp {
+            ty,
+            lhs: hoist(lhs),
+            rhs,
+        },
+        Instr::Push(op) => Instr::Push(hoist(op)),
+        instr => instr,
+    }
 }
 
 fn fixup_instructions(instrs: Vec<Instr>) -> Vec<Instr> {
     let mut fixed = Vec::new();
     for instr in instrs {
+        let instr = hoist_large_immediate(instr, &mut fixed);
         match instr {
-            Instr::Mov { src: s, dst: d } if is_memory(&s) || is_memory(&d) => {
+            Instr::Mov { ty, src: s, dst: d } if is_memory(&s) && is_memory(&d) => {
                 fixed.extend(vec![
                     Instr::Mov {
+                        ty,
                         src: s,
                         dst: Operand::Reg(Register::R10),
                     },
                     Instr::Mov {
+                        ty,
                         src: Operand::Reg(Register::R10),
                         dst: d,
                     },
                 ]);
             }
+            Instr::Movsx { src, dst } if matches!(src, Operand::Imm(_)) || is_memory(&dst) => {
+                let (src, src_fix) = match src {
+                    Operand::Imm(n) => (
+                        Operand::Reg(Register::R10),
+                        Some(Instr::Mov {
+                            ty: AssemblyType::Longword,
+                            src: Operand::Imm(n),
+                            dst: Operand::Reg(Register::R10),
+                        }),
+                    ),
+                    src => (src, None),
+                };
+                fixed.extend(src_fix);
+                if is_memory(&dst) {
+                    fixed.extend(vec![
+                        Instr::Movsx {
+                            src,
+                            dst: Operand::Reg(Register::R11),
+                        },
+                        Instr::Mov {
+                            ty: AssemblyType::Quadword,
+                            src: Operand::Reg(Register::R11),
+                            dst,
+                        },
+                    ]);
+                } else {
+                    fixed.push(Instr::Movsx { src, dst });
+                }
+            }
             Instr::Binary {
                 binop:
                     binop @ (BinaryOp::Add
@@ -575,16 +803,19 @@ fn fixup_instructions(instrs: Vec<Instr>) -> Vec<Instr> {
                     | BinaryOp::BitAnd
                     | BinaryOp::BitOr
                     | BinaryOp::BitXOr),
+                ty,
                 src: s,
                 dst: d,
-            } if is_memory(&s) || is_memory(&d) => {
+            } if is_memory(&s) && is_memory(&d) => {
                 fixed.extend(vec![
                     Instr::Mov {
+                        ty,
                         src: s,
                         dst: Operand::Reg(Register::R10),
                     },
                     Instr::Binary {
                         binop,
+                        ty,
                         src: Operand::Reg(Register::R10),
                         dst: d,
                     },
@@ -592,53 +823,106 @@ fn fixup_instructions(instrs: Vec<Instr>) -> Vec<Instr> {
             }
             Instr::Binary {
                 binop: BinaryOp::Mult,
+                ty,
                 src,
                 dst: d,
             } if is_memory(&d) => fixed.extend(vec![
                 Instr::Mov {
+                    ty,
                     src: d.clone(),
                     dst: Operand::Reg(Register::R11),
                 },
                 Instr::Binary {
                     binop: BinaryOp::Mult,
+                    ty,
                     src,
                     dst: Operand::Reg(Register::R11),
                 },
                 Instr::Mov {
+                    ty,
                     src: Operand::Reg(Register::R11),
                     dst: d,
                 },
             ]),
-            Instr::IDiv(Operand::Imm(n)) => fixed.extend(vec![
+            Instr::IDiv(ty, Operand::Imm(n)) => fixed.extend(vec![
                 Instr::Mov {
+                    ty,
                     src: Operand::Imm(n),
                     dst: Operand::Reg(Register::R10),
                 },
-                Instr::IDiv(Operand::Reg(Register::R10)),
+                Instr::IDiv(ty, Operand::Reg(Register::R10)),
+            ]),
+            Instr::Div(ty, Operand::Imm(n)) => fixed.extend(vec![
+                Instr::Mov {
+                    ty,
+                    src: Operand::Imm(n),
+                    dst: Operand::Reg(Register::R10),
+                },
+                Instr::Div(ty, Operand::Reg(Register::R10)),
+            ]),
+            // A longword mov clears the upper half of its destination
+            // register, which is all a zero extension takes.
+            Instr::MovZeroExtend { src, dst } if is_memory(&dst) => fixed.extend(vec![
+                Instr::Mov {
+                    ty: AssemblyType::Longword,
+                    src,
+                    dst: Operand::Reg(Register::R11),
+                },
+                Instr::Mov {
+                    ty: AssemblyType::Quadword,
+                    src: Operand::Reg(Register::R11),
+                    dst,
+                },
+            ]),
+            Instr::LoadAddress { src, dst } if is_memory(&dst) => fixed.extend(vec![
+                Instr::LoadAddress {
+                    src,
+                    dst: Operand::Reg(Register::R11),
+                },
+                Instr::Mov {
+                    ty: AssemblyType::Quadword,
+                    src: Operand::Reg(Register::R11),
+                    dst,
+                },
+            ]),
+            Instr::IMul(ty, Operand::Imm(n)) => fixed.extend(vec![
+                Instr::Mov {
+                    ty,
+                    src: Operand::Imm(n),
+                    dst: Operand::Reg(Register::R10),
+                },
+                Instr::IMul(ty, Operand::Reg(Register::R10)),
             ]),
             Instr::Cmp {
+                ty,
                 lhs,
                 rhs: Operand::Imm(n),
             } => fixed.extend(vec![
                 Instr::Mov {
+                    ty,
                     src: Operand::Imm(n),
                     dst: Operand::Reg(Register::R11),
                 },
                 Instr::Cmp {
+                    ty,
                     lhs,
                     rhs: Operand::Reg(Register::R11),
                 },
             ]),
-            Instr::Cmp { lhs: l, rhs: r } if is_memory(&l) || is_memory(&r) => fixed.extend(vec![
-                Instr::Mov {
-                    src: l,
-                    dst: Operand::Reg(Register::R10),
-                },
-                Instr::Cmp {
-                    lhs: Operand::Reg(Register::R10),
-                    rhs: r,
-                },
-            ]),
+            Instr::Cmp { ty, lhs: l, rhs: r } if is_memory(&l) && is_memory(&r) => {
+                fixed.extend(vec![
+                    Instr::Mov {
+                        ty,
+                        src: l,
+                        dst: Operand::Reg(Register::R10),
+                    },
+                    Instr::Cmp {
+                        ty,
+                        lhs: Operand::Reg(Register::R10),
+                        rhs: r,
+                    },
+                ])
+            }
             i => fixed.push(i),
         }
     }
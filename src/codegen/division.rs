@@ -0,0 +1,274 @@
+use crate::codegen::{AssemblyType, BinaryOp, Instr, Operand, Register, UnaryOp};
+
+// Signed division by a constant, without idiv. Division by zero and by -1 are
+// left to idiv so they trap exactly like the general case.
+pub fn divide_by_constant(
+    dividend: Operand,
+    divisor: i32,
+    remainder: bool,
+    dst: Operand,
+) -> Option<Vec<Instr>> {
+    let ax = Operand::Reg(Register::AX);
+    let dx = Operand::Reg(Register::DX);
+    let binary = |binop, src: Operand, dst: &Operand| Instr::Binary {
+        binop,
+        ty: AssemblyType::Longword,
+        src,
+        dst: dst.clone(),
+    };
+    let mov = |src: Operand, dst: &Operand| Instr::Mov {
+        ty: AssemblyType::Longword,
+        src,
+        dst: dst.clone(),
+    };
+
+    let mut instrs = match divisor {
+        0 | -1 => return None,
+        1 if remainder => return Some(vec![mov(Operand::Imm(0), &dst)]),
+        1 => return Some(vec![mov(dividend, &dst)]),
+        _ if divisor.unsigned_abs().is_power_of_two() => {
+            // Bias negative dividends by |d| - 1 so the shift rounds toward zero.
+            let shift = divisor.unsigned_abs().trailing_zeros() as i64;
+            let mut instrs = vec![
+                mov(dividend.clone(), &ax),
+                mov(ax.clone(), &dx),
+                binary(BinaryOp::ShiftRight, Operand::Imm(31), &dx),
+                binary(
+                    BinaryOp::BitAnd,
+                    Operand::Imm((divisor.unsigned_abs() - 1) as i64),
+                    &dx,
+                ),
+                binary(BinaryOp::Add, ax.clone(), &dx),
+                binary(BinaryOp::ShiftRight, Operand::Imm(shift), &dx),
+            ];
+            if divisor < 0 {
+                instrs.push(Instr::Unary {
+                    unop: UnaryOp::Neg,
+                    ty: AssemblyType::Longword,
+                    dst: dx.clone(),
+                });
+            }
+            instrs
+        }
+        _ => {
+            let (multiplier, shift) = magic(divisor);
+            let mut instrs = vec![
+                mov(Operand::Imm(multiplier as i64), &ax),
+                Instr::IMul(AssemblyType::Longword, dividend.clone()),
+            ];
+            if divisor > 0 && multiplier < 0 {
+                instrs.push(binary(BinaryOp::Add, dividend.clone(), &dx));
+            } else if divisor < 0 && multiplier > 0 {
+                instrs.push(binary(BinaryOp::Sub, dividend.clone(), &dx));
+            }
+            if shift > 0 {
+                instrs.push(binary(
+                    BinaryOp::ShiftRight,
+                    Operand::Imm(shift as i64),
+                    &dx,
+                ));
+            }
+            // The shifted product rounds toward negative infinity; adding its
+            // sign bit rounds toward zero instead.
+            instrs.extend([
+                mov(dx.clone(), &ax),
+                binary(BinaryOp::ShiftRight, Operand::Imm(31), &ax),
+                binary(BinaryOp::Sub, ax.clone(), &dx),
+            ]);
+            instrs
+        }
+    };
+
+    if remainder {
+        instrs.extend([
+            binary(BinaryOp::Mult, Operand::Imm(divisor as i64), &dx),
+            mov(dividend, &ax),
+            binary(BinaryOp::Sub, dx, &ax),
+            mov(ax, &dst),
+        ]);
+    } else {
+        instrs.push(mov(dx, &dst));
+    }
+    Some(instrs)
+}
+
+// Magic multiplier and shift for signed division, from Hacker's Delight
+// (figure 10-1). Valid for 2 <= |d| < 2^31.
+pub fn magic(d: i32) -> (i32, i32) {
+    const TWO31: u32 = 0x8000_0000;
+    let ad = d.unsigned_abs();
+    let t = TWO31 + ((d as u32) >> 31);
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let mut q1 = TWO31 / anc;
+    let mut r1 = TWO31 - q1 * anc;
+    let mut q2 = TWO31 / ad;
+    let mut r2 = TWO31 - q2 * ad;
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+    let multiplier = q2.wrapping_add(1) as i32;
+    let multiplier = if d < 0 {
+        multiplier.wrapping_neg()
+    } else {
+        multiplier
+    };
+    (multiplier, p - 32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Just enough of x86 to run the sequences above.
+    fn execute(instrs: &[Instr], dividend: i32) -> i32 {
+        let mut regs = [0; 16];
+        regs[Register::DI as usize] = dividend;
+        let get = |regs: &[i32; 16], op: &Operand| match op {
+            Operand::Imm(n) => *n as i32,
+            Operand::Reg(reg) => regs[*reg as usize],
+            op => panic!("unexpected operand {:?}", op),
+        };
+        let reg = |op: &Operand| match op {
+            Operand::Reg(reg) => *reg as usize,
+            op => panic!("unexpected destination {:?}", op),
+        };
+        for instr in instrs {
+            match instr {
+                Instr::Mov { src, dst, .. } => {
+                    let value = get(&regs, src);
+                    regs[reg(dst)] = value;
+                }
+                Instr::IMul(_, op) => {
+                    let product = regs[Register::AX as usize] as i64 * get(&regs, op) as i64;
+                    regs[Register::AX as usize] = product as i32;
+                    regs[Register::DX as usize] = (product >> 32) as i32;
+                }
+                Instr::Unary {
+                    unop: UnaryOp::Neg,
+                    dst,
+                    ..
+                } => {
+                    let value = get(&regs, dst).wrapping_neg();
+                    regs[reg(dst)] = value;
+                }
+                Instr::Binary {
+                    binop, src, dst, ..
+                } => {
+                    let (src, old) = (get(&regs, src), get(&regs, dst));
+                    let value = match binop {
+                        BinaryOp::Add => old.wrapping_add(src),
+                        BinaryOp::Sub => old.wrapping_sub(src),
+                        BinaryOp::Mult => old.wrapping_mul(src),
+                        BinaryOp::BitAnd => old & src,
+                        BinaryOp::ShiftRight => old >> src,
+                        binop => panic!("unexpected operator {:?}", binop),
+                    };
+                    regs[reg(dst)] = value;
+                }
+                instr => panic!("unexpected instruction {:?}", instr),
+            }
+        }
+        regs[Register::SI as usize]
+    }
+
+    fn check(divisor: i32, dividends: &[i32]) {
+        for remainder in [false, true] {
+            let instrs = divide_by_constant(
+                Operand::Reg(Register::DI),
+                divisor,
+                remainder,
+                Operand::Reg(Register::SI),
+            )
+            .unwrap();
+            for &x in dividends {
+                let expected = if remainder {
+                    x.checked_rem(divisor)
+                } else {
+                    x.checked_div(divisor)
+                };
+                assert_eq!(
+                    Some(execute(&instrs, x)),
+                    expected,
+                    "{} {} {}",
+                    x,
+                    if remainder { '%' } else { '/' },
+                    divisor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn traps_use_idiv() {
+        for divisor in [0, -1] {
+            let instrs = divide_by_constant(Operand::Imm(1), divisor, false, Operand::Imm(0));
+            assert!(instrs.is_none());
+        }
+    }
+
+    #[test]
+    fn matches_i32() {
+        let mut divisors = (-1000..=1000)
+            .filter(|d| ![0, -1].contains(d))
+            .collect::<Vec<_>>();
+        for k in 11..31 {
+            for d in [(1 << k) - 1, 1 << k, (1 << k) + 1] {
+                divisors.extend([d, -d]);
+            }
+        }
+        divisors.extend([
+            i32::MAX,
+            i32::MIN,
+            i32::MIN + 1,
+            1_000_000_007,
+            -1_000_000_007,
+        ]);
+
+        let mut dividends = vec![
+            i32::MIN,
+            i32::MIN + 1,
+            -2,
+            -1,
+            0,
+            1,
+            2,
+            i32::MAX - 1,
+            i32::MAX,
+        ];
+        let mut state = 0x2545_f491_u32;
+        for _ in 0..64 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            dividends.push(state as i32);
+            dividends.push((state % 5000) as i32 - 2500);
+        }
+
+        for divisor in divisors {
+            let mut around = dividends.clone();
+            for q in [1, 2, 3, 1000] {
+                for n in [divisor.wrapping_mul(q), divisor.wrapping_mul(-q)] {
+                    around.extend([n.wrapping_sub(1), n, n.wrapping_add(1)]);
+                }
+            }
+            check(divisor, &around);
+        }
+    }
+}
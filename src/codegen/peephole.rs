@@ -0,0 +1,375 @@
+use crate::codegen::{AsmTopLevel, Assembly, AssemblyType, BinaryOp, Instr, Operand, UnaryOp};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Rule {
+    RedundantMove,
+    TestZero,
+    XorZero,
+    LeaAdd,
+    JumpToNext,
+    EmptyStackAdjust,
+}
+
+// A rewrite looks at the instructions starting at the current position and,
+// if it matches, returns how many of them it consumed and their replacement.
+type Rewrite = fn(&[Instr]) -> Option<(usize, Vec<Instr>)>;
+
+const RULES: [(Rule, &str, Rewrite); 6] = [
+    (Rule::RedundantMove, "redundant-move", redundant_move),
+    (Rule::TestZero, "test-zero", test_zero),
+    (Rule::XorZero, "xor-zero", xor_zero),
+    (Rule::LeaAdd, "lea-add", lea_add),
+    (Rule::JumpToNext, "jump-to-next", jump_to_next),
+    (
+        Rule::EmptyStackAdjust,
+        "empty-stack-adjust",
+        empty_stack_adjust,
+    ),
+];
+
+impl Rule {
+    pub fn all() -> Vec<Rule> {
+        RULES.iter().map(|(rule, _, _)| *rule).collect()
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        RULES
+            .iter()
+            .find(|(_, name, _)| *name == s)
+            .map(|(rule, _, _)| *rule)
+            .ok_or_else(|| {
+                let names = RULES.iter().map(|(_, name, _)| *name).collect::<Vec<_>>();
+                format!(
+                    "Unknown peephole rule {} (expected {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+
+    fn rewrite(self) -> Rewrite {
+        RULES
+            .iter()
+            .find(|(rule, _, _)| *rule == self)
+            .map(|(_, _, rewrite)| *rewrite)
+            .expect("every rule has a rewrite")
+    }
+}
+
+pub fn optimize(assembly: Assembly, rules: &[Rule]) -> Assembly {
+    assembly
+        .into_iter()
+        .map(|top_level| match top_level {
+            AsmTopLevel::AsmFunction {
+                name,
+                instructions,
+                global,
+            } => AsmTopLevel::AsmFunction {
+                name,
+                instructions: optimize_function(instructions, rules),
+                global,
+            },
+            top_level => top_level,
+        })
+        .collect()
+}
+
+fn optimize_function(mut instructions: Vec<Instr>, rules: &[Rule]) -> Vec<Instr> {
+    let rewrites = rules.iter().map(|rule| rule.rewrite()).collect::<Vec<_>>();
+    loop {
+        let mut changed = false;
+        let mut optimized = Vec::with_capacity(instructions.len());
+        let mut i = 0;
+        'window: while i < instructions.len() {
+            for rewrite in &rewrites {
+                if let Some((consumed, replacement)) = rewrite(&instructions[i..]) {
+                    optimized.extend(replacement);
+                    i += consumed;
+                    changed = true;
+                    continue 'window;
+                }
+            }
+            optimized.push(instructions[i].clone());
+            i += 1;
+        }
+        instructions = optimized;
+        if !changed {
+            return instructions;
+        }
+    }
+}
+
+// Whether the flags set before `rest` can no longer be read. Labels and jumps
+// are treated as reads since another path may depend on them.
+fn flags_dead(rest: &[Instr]) -> bool {
+    for instr in rest {
+        match instr {
+            Instr::SetCC(..) | Instr::JmpCC(..) | Instr::Jmp(_) | Instr::Label(_) => return false,
+            Instr::Cmp { .. }
+            | Instr::Test { .. }
+            | Instr::IDiv(..)
+            | Instr::Div(..)
+            | Instr::IMul(..)
+            | Instr::Call(_)
+            | Instr::Ret
+            | Instr::AllocateStack(_)
+            | Instr::DeallocateStack(_)
+            | Instr::Unary {
+                unop: UnaryOp::Neg, ..
+            } => return true,
+            // Shifts by zero leave the flags untouched.
+            Instr::Binary { binop, .. } => {
+                if !matches!(
+                    binop,
+                    BinaryOp::ShiftLeft | BinaryOp::ShiftRight | BinaryOp::LogicalShiftRight
+                ) {
+                    return true;
+                }
+            }
+            Instr::Mov { .. }
+            | Instr::Movsx { .. }
+            | Instr::MovZeroExtend { .. }
+            | Instr::Unary { .. }
+            | Instr::Cdq(_)
+            | Instr::Lea { .. }
+            | Instr::LoadAddress { .. }
+            | Instr::Push(_)
+            | Instr::Pop(_) => (),
+        }
+    }
+    true
+}
+
+fn redundant_move(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    let [
+        Instr::Mov { ty, src, dst },
+        Instr::Mov {
+            ty: ty2,
+            src: src2,
+            dst: dst2,
+        },
+        ..,
+    ] = instrs
+    else {
+        return None;
+    };
+    // Loading through a pointer into the register holding it changes what
+    // the second move refers to.
+    let overwrites_base =
+        matches!((src, dst), (Operand::Memory(base, _), Operand::Reg(reg)) if base == reg);
+    if ty != ty2 || overwrites_base {
+        return None;
+    }
+    let reload = src2 == dst && dst2 == src;
+    let repeat = src2 == src && dst2 == dst && src != dst;
+    (reload || repeat).then(|| (2, vec![instrs[0].clone()]))
+}
+
+fn test_zero(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    let [
+        Instr::Cmp {
+            ty,
+            lhs: Operand::Imm(0),
+            rhs: rhs @ Operand::Reg(_),
+        },
+        ..,
+    ] = instrs
+    else {
+        return None;
+    };
+    Some((
+        1,
+        vec![Instr::Test {
+            ty: *ty,
+            lhs: rhs.clone(),
+            rhs: rhs.clone(),
+        }],
+    ))
+}
+
+fn xor_zero(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    let [
+        Instr::Mov {
+            src: Operand::Imm(0),
+            dst: dst @ Operand::Reg(_),
+            ..
+        },
+        rest @ ..,
+    ] = instrs
+    else {
+        return None;
+    };
+    // Writing the low half of a register clears the high half too.
+    flags_dead(rest).then(|| {
+        (
+            1,
+            vec![Instr::Binary {
+                binop: BinaryOp::BitXOr,
+                ty: AssemblyType::Longword,
+                src: dst.clone(),
+                dst: dst.clone(),
+            }],
+        )
+    })
+}
+
+fn lea_add(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    let [
+        Instr::Mov {
+            ty,
+            src: Operand::Reg(base),
+            dst: Operand::Reg(dst),
+        },
+        Instr::Binary {
+            binop: BinaryOp::Add,
+            ty: ty2,
+            src,
+            dst: Operand::Reg(dst2),
+        },
+        rest @ ..,
+    ] = instrs
+    else {
+        return None;
+    };
+    // After the move, the destination holds the base.
+    let (index, offset) = match src {
+        Operand::Reg(reg) if reg == dst => (Some(*base), 0),
+        Operand::Reg(reg) => (Some(*reg), 0),
+        Operand::Imm(n) => (None, i32::try_from(*n).ok()?),
+        _ => return None,
+    };
+    if ty != ty2 || dst != dst2 || !flags_dead(rest) {
+        return None;
+    }
+    Some((
+        2,
+        vec![Instr::Lea {
+            ty: *ty,
+            base: *base,
+            index,
+            offset,
+            dst: *dst,
+        }],
+    ))
+}
+
+fn jump_to_next(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    let (Instr::Jmp(target) | Instr::JmpCC(_, target)) = instrs.first()? else {
+        return None;
+    };
+    instrs[1..]
+        .iter()
+        .map_while(|instr| match instr {
+            Instr::Label(label) => Some(label),
+            _ => None,
+        })
+        .any(|label| label == target)
+        .then(|| (1, vec![]))
+}
+
+fn empty_stack_adjust(instrs: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+    matches!(
+        instrs.first()?,
+        Instr::AllocateStack(0) | Instr::DeallocateStack(0)
+    )
+    .then(|| (1, vec![]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codegen::{CondCode, Register};
+
+    fn reg(reg: Register) -> Operand {
+        Operand::Reg(reg)
+    }
+
+    fn mov(src: Operand, dst: Operand) -> Instr {
+        Instr::Mov {
+            ty: AssemblyType::Longword,
+            src,
+            dst,
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let instructions = vec![
+            Instr::AllocateStack(0),
+            mov(reg(Register::AX), Operand::Stack(-4)),
+            mov(Operand::Stack(-4), reg(Register::AX)),
+            Instr::Cmp {
+                ty: AssemblyType::Longword,
+                lhs: Operand::Imm(0),
+                rhs: reg(Register::AX),
+            },
+            mov(Operand::Imm(0), reg(Register::CX)),
+            Instr::SetCC(CondCode::E, reg(Register::CX)),
+            Instr::JmpCC(CondCode::NE, "a".to_string()),
+            Instr::Label("b".to_string()),
+            Instr::Label("a".to_string()),
+            mov(reg(Register::DI), reg(Register::AX)),
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                ty: AssemblyType::Longword,
+                src: Operand::Imm(3),
+                dst: reg(Register::AX),
+            },
+            mov(Operand::Imm(0), reg(Register::DX)),
+            Instr::Ret,
+        ];
+        assert_eq!(
+            optimize_function(instructions.clone(), &Rule::all()),
+            [
+                mov(reg(Register::AX), Operand::Stack(-4)),
+                Instr::Test {
+                    ty: AssemblyType::Longword,
+                    lhs: reg(Register::AX),
+                    rhs: reg(Register::AX),
+                },
+                mov(Operand::Imm(0), reg(Register::CX)),
+                Instr::SetCC(CondCode::E, reg(Register::CX)),
+                Instr::Label("b".to_string()),
+                Instr::Label("a".to_string()),
+                Instr::Lea {
+                    ty: AssemblyType::Longword,
+                    base: Register::DI,
+                    index: None,
+                    offset: 3,
+                    dst: Register::AX,
+                },
+                Instr::Binary {
+                    binop: BinaryOp::BitXOr,
+                    ty: AssemblyType::Longword,
+                    src: reg(Register::DX),
+                    dst: reg(Register::DX),
+                },
+                Instr::Ret,
+            ]
+        );
+        assert_eq!(optimize_function(instructions.clone(), &[]), instructions);
+        let only_jumps = optimize_function(instructions.clone(), &[Rule::JumpToNext]);
+        assert_eq!(only_jumps.len(), instructions.len() - 1);
+    }
+
+    #[test]
+    fn loads_through_pointers() {
+        let instructions = vec![
+            mov(Operand::Memory(Register::AX, 0), reg(Register::AX)),
+            mov(Operand::Memory(Register::AX, 0), reg(Register::AX)),
+            mov(Operand::Memory(Register::CX, 4), reg(Register::DX)),
+            mov(Operand::Memory(Register::CX, 4), reg(Register::DX)),
+        ];
+        assert_eq!(
+            optimize_function(instructions.clone(), &Rule::all()),
+            instructions[..3]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Rule::parse("lea-add"), Ok(Rule::LeaAdd));
+        assert!(Rule::parse("frobnicate").is_err());
+        assert_eq!(Rule::all().len(), RULES.len());
+    }
+}
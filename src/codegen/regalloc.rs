@@ -0,0 +1,823 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::cfg::{Cfg, Node};
+use crate::codegen::{self, Instr, Operand, Register};
+use crate::semantic_analysis::{Attrs, SymbolTable};
+use crate::types::Type;
+
+// Caller-saved registers come first so that callee-saved ones, which cost a
+// push and a pop, are only picked for values that need them.
+const ALLOCATABLE: [Register; 12] = [
+    Register::AX,
+    Register::CX,
+    Register::DX,
+    Register::DI,
+    Register::SI,
+    Register::R8,
+    Register::R9,
+    Register::BX,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
+const CALLEE_SAVED: [Register; 5] = [
+    Register::BX,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
+const ARGUMENT_REGISTERS: [Register; 6] = [
+    Register::DI,
+    Register::SI,
+    Register::DX,
+    Register::CX,
+    Register::R8,
+    Register::R9,
+];
+
+const CALLER_SAVED: [Register; 9] = [
+    Register::AX,
+    Register::CX,
+    Register::DX,
+    Register::DI,
+    Register::SI,
+    Register::R8,
+    Register::R9,
+    Register::R10,
+    Register::R11,
+];
+
+type Live = HashSet<Operand>;
+
+struct Graph {
+    nodes: Vec<Operand>,
+    indices: HashMap<Operand, usize>,
+    edges: Vec<BTreeSet<usize>>,
+    costs: Vec<usize>,
+}
+
+pub fn allocate_registers(mut instructions: Vec<Instr>, symbols: &SymbolTable) -> Vec<Instr> {
+    // A variable whose address is taken has to stay in memory.
+    let aliased = instructions
+        .iter()
+        .filter_map(|instr| match instr {
+            Instr::LoadAddress {
+                src: Operand::Pseudo(name),
+                ..
+            } => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    // Coalesce until nothing more can be merged, rebuilding the graph each round
+    // since merging changes liveness.
+    loop {
+        let cfg = Cfg::new(instructions);
+        let graph = build_graph(&cfg, symbols, &aliased);
+        instructions = cfg.into_instructions();
+        let merged = coalesce(&graph, &instructions, symbols);
+        if merged.is_empty() {
+            let colors = color(&graph);
+            let assignment = graph
+                .nodes
+                .iter()
+                .zip(colors)
+                .filter_map(|(node, color)| match node {
+                    Operand::Pseudo(_) => Some((node.clone(), Operand::Reg(ALLOCATABLE[color?]))),
+                    _ => None,
+                })
+                .collect::<HashMap<_, _>>();
+            return rename(instructions, &assignment);
+        }
+        instructions = rename(instructions, &merged);
+    }
+}
+
+pub fn callee_saved(instructions: &[Instr]) -> Vec<Register> {
+    let mut used = HashSet::new();
+    for instr in instructions {
+        map_operands(instr.clone(), |op| {
+            if let Operand::Reg(reg) = op {
+                used.insert(reg);
+            }
+            op
+        });
+    }
+    CALLEE_SAVED
+        .into_iter()
+        .filter(|reg| used.contains(reg))
+        .collect()
+}
+
+fn rename(instructions: Vec<Instr>, renames: &HashMap<Operand, Operand>) -> Vec<Instr> {
+    instructions
+        .into_iter()
+        .filter_map(|instr| {
+            let instr = map_operands(instr, |op| renames.get(&op).cloned().unwrap_or(op));
+            match &instr {
+                Instr::Mov { src, dst, .. } if src == dst => None,
+                _ => Some(instr),
+            }
+        })
+        .collect()
+}
+
+fn build_graph(cfg: &Cfg<Instr>, symbols: &SymbolTable, aliased: &HashSet<String>) -> Graph {
+    let mut graph = Graph {
+        nodes: vec![],
+        indices: HashMap::new(),
+        edges: vec![],
+        costs: vec![],
+    };
+    for reg in ALLOCATABLE {
+        graph.node(Operand::Reg(reg));
+    }
+    for i in 0..ALLOCATABLE.len() {
+        for j in 0..i {
+            graph.edge(i, j);
+        }
+    }
+
+    let live_out = liveness(cfg, symbols, aliased);
+    for (block, mut live) in cfg.blocks.iter().zip(live_out) {
+        for instr in block.instructions.iter().rev() {
+            let (uses, defs) = uses_and_defs(instr, symbols, aliased);
+            for op in uses.iter().chain(&defs) {
+                let node = graph.node(op.clone());
+                graph.costs[node] += 1;
+            }
+            for def in &defs {
+                let def_node = graph.node(def.clone());
+                for other in &live {
+                    let moved = matches!(instr, Instr::Mov { src, .. } if src == other);
+                    if other != def && !moved {
+                        let other_node = graph.node(other.clone());
+                        graph.edge(def_node, other_node);
+                    }
+                }
+            }
+            for def in &defs {
+                live.remove(def);
+            }
+            live.extend(uses);
+        }
+    }
+    graph
+}
+
+fn liveness(cfg: &Cfg<Instr>, symbols: &SymbolTable, aliased: &HashSet<String>) -> Vec<Live> {
+    let count = cfg.blocks.len();
+    let mut live_in = vec![Live::new(); count];
+    let mut live_out = vec![Live::new(); count];
+    let mut worklist = (0..count).rev().collect::<VecDeque<_>>();
+    let mut queued = vec![true; count];
+
+    while let Some(i) = worklist.pop_front() {
+        queued[i] = false;
+        let block = &cfg.blocks[i];
+        let mut live = Live::new();
+        for successor in &block.successors {
+            if let Node::Block(s) = successor {
+                live.extend(live_in[*s].iter().cloned());
+            }
+        }
+        live_out[i] = live.clone();
+        for instr in block.instructions.iter().rev() {
+            let (uses, defs) = uses_and_defs(instr, symbols, aliased);
+            for def in &defs {
+                live.remove(def);
+            }
+            live.extend(uses);
+        }
+        if live != live_in[i] {
+            live_in[i] = live;
+            for predecessor in &block.predecessors {
+                if let Node::Block(p) = predecessor
+                    && !queued[*p]
+                {
+                    queued[*p] = true;
+                    worklist.push_back(*p);
+                }
+            }
+        }
+    }
+    live_out
+}
+
+fn uses_and_defs(
+    instr: &Instr,
+    symbols: &SymbolTable,
+    aliased: &HashSet<String>,
+) -> (Vec<Operand>, Vec<Operand>) {
+    let regs = |regs: &[Register]| regs.iter().map(|r| Operand::Reg(*r)).collect::<Vec<_>>();
+    let (mut uses, defs) = match instr {
+        Instr::Mov { src, dst, .. }
+        | Instr::Movsx { src, dst }
+        | Instr::MovZeroExtend { src, dst }
+        | Instr::LoadAddress { src, dst } => (vec![src.clone()], vec![dst.clone()]),
+        Instr::Unary { dst, .. } => (vec![dst.clone()], vec![dst.clone()]),
+        Instr::Binary { src, dst, .. } => (vec![src.clone(), dst.clone()], vec![dst.clone()]),
+        Instr::IDiv(_, op) | Instr::Div(_, op) => (
+            vec![
+                op.clone(),
+                Operand::Reg(Register::AX),
+                Operand::Reg(Register::DX),
+            ],
+            regs(&[Register::AX, Register::DX]),
+        ),
+        Instr::IMul(_, op) => (
+            vec![op.clone(), Operand::Reg(Register::AX)],
+            regs(&[Register::AX, Register::DX]),
+        ),
+        Instr::Cdq(_) => (regs(&[Register::AX]), regs(&[Register::DX])),
+        Instr::Cmp { lhs, rhs, .. } | Instr::Test { lhs, rhs, .. } => {
+            (vec![lhs.clone(), rhs.clone()], vec![])
+        }
+        Instr::Lea {
+            base, index, dst, ..
+        } => (
+            regs(&[*base])
+                .into_iter()
+                .chain(index.map(Operand::Reg))
+                .collect(),
+            regs(&[*dst]),
+        ),
+        Instr::SetCC(_, op) => (vec![op.clone()], vec![op.clone()]),
+        Instr::Push(op) => (vec![op.clone()], vec![]),
+        Instr::Pop(reg) => (vec![], regs(&[*reg])),
+        Instr::Call(name) => {
+            let count = match symbols.get(name) {
+                Some((Type::Fun { params, .. }, _)) => params.len(),
+                _ => ARGUMENT_REGISTERS.len(),
+            };
+            let count = count.min(ARGUMENT_REGISTERS.len());
+            (regs(&ARGUMENT_REGISTERS[..count]), regs(&CALLER_SAVED))
+        }
+        Instr::Ret => (regs(&[Register::AX]), vec![]),
+        Instr::AllocateStack(_)
+        | Instr::DeallocateStack(_)
+        | Instr::Jmp(_)
+        | Instr::JmpCC(..)
+        | Instr::Label(_) => (vec![], vec![]),
+    };
+    // Reading or writing through a pointer reads the register holding it.
+    let bases = uses
+        .iter()
+        .chain(&defs)
+        .filter_map(|op| match op {
+            Operand::Memory(base, _) => Some(Operand::Reg(*base)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    uses.extend(bases);
+    let tracked = |op: &Operand| match op {
+        Operand::Reg(reg) => ALLOCATABLE.contains(reg),
+        Operand::Pseudo(name) => {
+            !aliased.contains(name) && !matches!(symbols.get(name), Some((_, Attrs::Static { .. })))
+        }
+        _ => false,
+    };
+    (
+        uses.into_iter().filter(tracked).collect(),
+        defs.into_iter().filter(tracked).collect(),
+    )
+}
+
+impl Graph {
+    fn node(&mut self, op: Operand) -> usize {
+        if let Some(index) = self.indices.get(&op) {
+            return *index;
+        }
+        let index = self.nodes.len();
+        self.indices.insert(op.clone(), index);
+        self.nodes.push(op);
+        self.edges.push(BTreeSet::new());
+        self.costs.push(0);
+        index
+    }
+
+    fn edge(&mut self, a: usize, b: usize) {
+        self.edges[a].insert(b);
+        self.edges[b].insert(a);
+    }
+}
+
+// The interference graph as coalescing reshapes it. Merged nodes point
+// towards the node they were merged into, and each node keeps count of its
+// neighbors of significant degree (k or more) so the Briggs test only has to
+// look at the neighbors the two nodes share.
+struct Merging {
+    k: usize,
+    edges: Vec<BTreeSet<usize>>,
+    significant: Vec<usize>,
+    parents: Vec<usize>,
+}
+
+impl Merging {
+    fn new(graph: &Graph) -> Self {
+        let k = ALLOCATABLE.len();
+        let edges = graph.edges.clone();
+        let significant = edges
+            .iter()
+            .map(|neighbors| neighbors.iter().filter(|n| edges[**n].len() >= k).count())
+            .collect();
+        Self {
+            k,
+            edges,
+            significant,
+            parents: (0..graph.nodes.len()).collect(),
+        }
+    }
+
+    fn find(&mut self, node: usize) -> usize {
+        let mut root = node;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        let mut node = node;
+        while self.parents[node] != root {
+            node = std::mem::replace(&mut self.parents[node], root);
+        }
+        root
+    }
+
+    fn is_significant(&self, node: usize) -> bool {
+        self.edges[node].len() >= self.k
+    }
+
+    // When a node's degree crosses k, its neighbors' counts change with it.
+    fn update_neighbors(&mut self, node: usize, was_significant: bool) {
+        match (was_significant, self.is_significant(node)) {
+            (false, true) => self.edges[node]
+                .iter()
+                .for_each(|n| self.significant[*n] += 1),
+            (true, false) => self.edges[node]
+                .iter()
+                .for_each(|n| self.significant[*n] -= 1),
+            _ => (),
+        }
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize) {
+        if self.edges[a].contains(&b) {
+            return;
+        }
+        let (was_a, was_b) = (self.is_significant(a), self.is_significant(b));
+        if was_a {
+            self.significant[b] += 1;
+        }
+        if was_b {
+            self.significant[a] += 1;
+        }
+        self.edges[a].insert(b);
+        self.edges[b].insert(a);
+        self.update_neighbors(a, was_a);
+        self.update_neighbors(b, was_b);
+    }
+
+    fn remove_edge(&mut self, a: usize, b: usize) {
+        let (was_a, was_b) = (self.is_significant(a), self.is_significant(b));
+        if was_a {
+            self.significant[b] -= 1;
+        }
+        if was_b {
+            self.significant[a] -= 1;
+        }
+        self.edges[a].remove(&b);
+        self.edges[b].remove(&a);
+        self.update_neighbors(a, was_a);
+        self.update_neighbors(b, was_b);
+    }
+
+    // A neighbor of both loses an edge in the merge, so it may stop being
+    // significant; every other neighbor counts as it does now.
+    fn briggs(&self, keep: usize, merge: usize) -> bool {
+        let (small, large) = if self.edges[keep].len() < self.edges[merge].len() {
+            (keep, merge)
+        } else {
+            (merge, keep)
+        };
+        let mut significant = self.significant[keep] + self.significant[merge];
+        for n in self.edges[small]
+            .iter()
+            .filter(|n| self.edges[large].contains(n))
+        {
+            if self.is_significant(*n) {
+                significant -= 1;
+                if self.edges[*n].len() == self.k {
+                    significant -= 1;
+                }
+            }
+        }
+        significant < self.k
+    }
+
+    fn george(&self, keep: usize, merge: usize) -> bool {
+        keep < self.k
+            && self.edges[merge]
+                .iter()
+                .all(|n| self.edges[*n].contains(&keep) || !self.is_significant(*n))
+    }
+
+    fn merge(&mut self, keep: usize, merge: usize) {
+        let neighbors = self.edges[merge].iter().copied().collect::<Vec<_>>();
+        for neighbor in neighbors {
+            self.remove_edge(merge, neighbor);
+            self.add_edge(keep, neighbor);
+        }
+        self.parents[merge] = keep;
+    }
+}
+
+fn coalesce(
+    graph: &Graph,
+    instructions: &[Instr],
+    symbols: &SymbolTable,
+) -> HashMap<Operand, Operand> {
+    let k = ALLOCATABLE.len();
+    let mut merging = Merging::new(graph);
+    for instr in instructions {
+        let Instr::Mov { src, dst, .. } = instr else {
+            continue;
+        };
+        let (Some(&src), Some(&dst)) = (graph.indices.get(src), graph.indices.get(dst)) else {
+            continue;
+        };
+        let src = merging.find(src);
+        let dst = merging.find(dst);
+        // A spilled pseudo gets a slot sized for its own type, so an int and a
+        // long can't share one.
+        if let (Operand::Pseudo(a), Operand::Pseudo(b)) = (&graph.nodes[src], &graph.nodes[dst])
+            && codegen::pseudo_type(a, symbols) != codegen::pseudo_type(b, symbols)
+        {
+            continue;
+        }
+        if src == dst || merging.edges[src].contains(&dst) {
+            continue;
+        }
+        // Hard registers always interfere with each other, so at most one side is
+        // precolored and it is the one that survives. Otherwise the node with
+        // fewer neighbors moves, which keeps merging chains of copies cheap.
+        let keep_src = src < k || (dst >= k && merging.edges[src].len() > merging.edges[dst].len());
+        let (keep, merge) = if keep_src { (src, dst) } else { (dst, src) };
+        if merging.george(keep, merge) || merging.briggs(keep, merge) {
+            merging.merge(keep, merge);
+        }
+    }
+    (0..graph.nodes.len())
+        .filter_map(|node| {
+            let root = merging.find(node);
+            (root != node).then(|| (graph.nodes[node].clone(), graph.nodes[root].clone()))
+        })
+        .collect()
+}
+
+fn color(graph: &Graph) -> Vec<Option<usize>> {
+    let k = ALLOCATABLE.len();
+    let count = graph.nodes.len();
+    let mut removed = vec![false; count];
+    let mut degrees = graph.edges.iter().map(BTreeSet::len).collect::<Vec<_>>();
+    let mut stack = vec![];
+
+    let pseudos = k..count;
+    while stack.len() < pseudos.len() {
+        let candidates = pseudos.clone().filter(|node| !removed[*node]);
+        let next = match candidates.clone().find(|node| degrees[*node] < k) {
+            Some(node) => node,
+            None => candidates
+                .min_by(|a, b| {
+                    let cost = |n: &usize| graph.costs[*n] as f64 / degrees[*n] as f64;
+                    cost(a).total_cmp(&cost(b))
+                })
+                .expect("uncolored pseudos remain"),
+        };
+        removed[next] = true;
+        for neighbor in &graph.edges[next] {
+            degrees[*neighbor] -= 1;
+        }
+        stack.push(next);
+    }
+
+    let mut colors = (0..count)
+        .map(|node| (node < k).then_some(node))
+        .collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        let used = graph.edges[node]
+            .iter()
+            .filter_map(|neighbor| colors[*neighbor])
+            .collect::<HashSet<_>>();
+        colors[node] = (0..k).find(|color| !used.contains(color));
+    }
+    colors
+}
+
+pub fn map_operands(instr: Instr, mut f: impl FnMut(Operand) -> Operand) -> Instr {
+    match instr {
+        Instr::Mov { ty, src, dst } => Instr::Mov {
+            ty,
+            src: f(src),
+            dst: f(dst),
+        },
+        Instr::Movsx { src, dst } => Instr::Movsx {
+            src: f(src),
+            dst: f(dst),
+        },
+        Instr::MovZeroExtend { src, dst } => Instr::MovZeroExtend {
+            src: f(src),
+            dst: f(dst),
+        },
+        Instr::Unary { unop, ty, dst } => Instr::Unary {
+            unop,
+            ty,
+            dst: f(dst),
+        },
+        Instr::Binary {
+            binop,
+            ty,
+            src,
+            dst,
+        } => Instr::Binary {
+            binop,
+            ty,
+            src: f(src),
+            dst: f(dst),
+        },
+        Instr::IDiv(ty, op) => Instr::IDiv(ty, f(op)),
+        Instr::Div(ty, op) => Instr::Div(ty, f(op)),
+        Instr::IMul(ty, op) => Instr::IMul(ty, f(op)),
+        Instr::Cmp { ty, lhs, rhs } => Instr::Cmp {
+            ty,
+            lhs: f(lhs),
+            rhs: f(rhs),
+        },
+        Instr::Test { ty, lhs, rhs } => Instr::Test {
+            ty,
+            lhs: f(lhs),
+            rhs: f(rhs),
+        },
+        Instr::LoadAddress { src, dst } => Instr::LoadAddress {
+            src: f(src),
+            dst: f(dst),
+        },
+        Instr::SetCC(code, op) => Instr::SetCC(code, f(op)),
+        Instr::Push(op) => Instr::Push(f(op)),
+        instr => instr,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codegen::{Assembly, AssemblyType, BinaryOp};
+    use crate::tacky::{TopLevel, text};
+
+    // Every test function is called f.
+    fn select(source: &str) -> (Assembly, SymbolTable) {
+        let (params, instructions, symbols) = text::parse_function(source);
+        let function = TopLevel::TackyFunction {
+            name: "f".to_string(),
+            params,
+            instructions,
+            global: true,
+        };
+        let assembly = codegen::select_instructions(vec![function], &symbols).unwrap();
+        (assembly, symbols)
+    }
+
+    fn allocate(source: &str) -> Vec<Instr> {
+        let (mut assembly, symbols) = select(source);
+        let codegen::AsmTopLevel::AsmFunction { instructions, .. } = assembly.remove(0) else {
+            panic!("expected a function");
+        };
+        allocate_registers(instructions, &symbols)
+    }
+
+    fn pseudos(instructions: &[Instr]) -> usize {
+        let mut count = 0;
+        for instr in instructions {
+            map_operands(instr.clone(), |op| {
+                if matches!(op, Operand::Pseudo(_)) {
+                    count += 1;
+                }
+                op
+            });
+        }
+        count
+    }
+
+    #[test]
+    fn registers() {
+        let instructions = allocate(
+            "global function f(a, b) {
+                x = a + b
+                y = x * a
+                z = y / b
+                return z
+            }",
+        );
+        assert_eq!(pseudos(&instructions), 0);
+        let divisor = instructions.iter().find_map(|instr| match instr {
+            Instr::IDiv(_, Operand::Reg(reg)) => Some(*reg),
+            _ => None,
+        });
+        assert!(!matches!(divisor, Some(Register::AX | Register::DX) | None));
+        assert!(instructions.iter().any(|instr| matches!(
+            instr,
+            Instr::Binary {
+                binop: BinaryOp::Mult,
+                src: Operand::Reg(_),
+                dst: Operand::Reg(_),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn address_taken() {
+        let instructions = allocate(
+            "global function f(a) {
+                int * p
+                x = a + 1
+                p = get_address x
+                store 2, p
+                y = x + a
+                return y
+            }",
+        );
+        for instr in &instructions {
+            map_operands(instr.clone(), |op| {
+                assert!(!matches!(&op, Operand::Pseudo(name) if name != "x"));
+                op
+            });
+        }
+        assert!(instructions.iter().any(|instr| matches!(
+            instr,
+            Instr::LoadAddress {
+                src: Operand::Pseudo(name),
+                dst: Operand::Reg(_),
+            } if name == "x"
+        )));
+    }
+
+    #[test]
+    fn calls_clobber() {
+        let instructions = allocate(
+            "extern function g/0
+
+            global function f(a) {
+                x = call g()
+                y = x + a
+                return y
+            }",
+        );
+        let a = instructions.iter().find_map(|instr| match instr {
+            Instr::Mov {
+                src: Operand::Reg(Register::DI),
+                dst: Operand::Reg(reg),
+                ..
+            } => Some(*reg),
+            _ => None,
+        });
+        assert_eq!(a, Some(Register::BX));
+        assert_eq!(callee_saved(&instructions), [Register::BX]);
+    }
+
+    #[test]
+    fn saves_callee_saved() {
+        let source = "extern function g/0
+
+            global function f(a, b) {
+                x = call g()
+                y = x + a
+                z = y + b
+                return z
+            }";
+        let (assembly, symbols) = select(source);
+        let (mut assembly, _) = codegen::allocate(assembly, &symbols);
+        let codegen::AsmTopLevel::AsmFunction { instructions, .. } = assembly.remove(0) else {
+            panic!("expected a function");
+        };
+        let saved = [Register::BX, Register::R12];
+        let Instr::AllocateStack(size) = instructions[0] else {
+            panic!("expected a stack allocation");
+        };
+        assert_eq!((size + 8 * saved.len() as u16) % 16, 0);
+        let pushes = saved.map(|reg| Instr::Push(Operand::Reg(reg)));
+        assert_eq!(instructions[1..3], pushes);
+        let n = instructions.len();
+        let pops = [
+            Instr::Pop(Register::R12),
+            Instr::Pop(Register::BX),
+            Instr::Ret,
+        ];
+        assert_eq!(instructions[n - 3..], pops);
+    }
+
+    #[test]
+    fn coalescing() {
+        let instructions = allocate(
+            "global function f(a) {
+                b = a
+                c = b + 1
+                return c
+            }",
+        );
+        assert_eq!(
+            instructions,
+            [
+                Instr::Binary {
+                    binop: BinaryOp::Add,
+                    ty: AssemblyType::Longword,
+                    src: Operand::Imm(1),
+                    dst: Operand::Reg(Register::DI),
+                },
+                Instr::Mov {
+                    ty: AssemblyType::Longword,
+                    src: Operand::Reg(Register::DI),
+                    dst: Operand::Reg(Register::AX),
+                },
+                Instr::Ret,
+            ]
+        );
+
+        let instructions = allocate(
+            "global function f(a) {
+                b = a
+                a = a + 1
+                c = a - b
+                return c
+            }",
+        );
+        let subtract = instructions.iter().find_map(|instr| match instr {
+            Instr::Binary {
+                binop: BinaryOp::Sub,
+                src,
+                dst,
+                ..
+            } => Some((src, dst)),
+            _ => None,
+        });
+        let (src, dst) = subtract.unwrap();
+        assert_ne!(src, dst);
+    }
+
+    #[test]
+    fn merging_tracks_degrees() {
+        let mut graph = Graph {
+            nodes: vec![],
+            indices: HashMap::new(),
+            edges: vec![],
+            costs: vec![],
+        };
+        for i in 0..40 {
+            graph.node(Operand::Pseudo(format!("p{}", i)));
+        }
+        for i in 0..40 {
+            for j in 0..i {
+                if (i * 7 + j * 3) % 5 < 2 {
+                    graph.edge(i, j);
+                }
+            }
+        }
+        let mut merging = Merging::new(&graph);
+        for (keep, merge) in [(0, 1), (2, 3), (0, 2), (10, 11), (10, 12), (39, 0)] {
+            let (keep, merge) = (merging.find(keep), merging.find(merge));
+            if keep == merge || merging.edges[keep].contains(&merge) {
+                continue;
+            }
+            merging.merge(keep, merge);
+            for node in 0..40 {
+                let expected = merging.edges[node]
+                    .iter()
+                    .filter(|n| merging.is_significant(**n))
+                    .count();
+                assert_eq!(merging.significant[node], expected);
+            }
+        }
+        let root = merging.find(1);
+        assert_eq!(merging.find(0), root);
+        assert!(merging.edges[1].is_empty() && !merging.edges[root].is_empty());
+    }
+
+    #[test]
+    fn spills() {
+        let mut body = String::new();
+        for i in 0..16 {
+            body.push_str(&format!("    v{} = a + {}\n", i, i));
+        }
+        body.push_str("    s = v0 + v1\n");
+        for i in 2..16 {
+            body.push_str(&format!("    s = s + v{}\n", i));
+        }
+        let instructions = allocate(&format!(
+            "global function f(a) {{\n{}    return s\n}}\n",
+            body
+        ));
+        let spilled = pseudos(&instructions);
+        assert!(spilled > 0);
+        assert!(spilled < 32);
+    }
+}
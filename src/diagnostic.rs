@@ -0,0 +1,100 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Note,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+    InvalidDirective,
+    IncludeNotFound,
+    MacroArguments,
+    ErrorDirective,
+    InvalidToken,
+    UnexpectedToken,
+    UnexpectedEof,
+    InvalidConstant,
+    InvalidSpecifiers,
+    InvalidDeclaration,
+    UndeclaredIdentifier,
+    DuplicateDeclaration,
+    DuplicateDefinition,
+    ConflictingDeclaration,
+    ConflictingLinkage,
+    InvalidStorageClass,
+    InvalidLvalue,
+    UnknownLabel,
+    DuplicateLabel,
+    MisplacedStatement,
+    DuplicateCase,
+    NonConstantExpression,
+    InvalidInitializer,
+    TypeMismatch,
+    WrongArgumentCount,
+    Io,
+    Internal,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+pub type Result<T> = std::result::Result<T, Diagnostic>;
+
+impl Diagnostic {
+    pub fn error(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            kind,
+            message: message.into(),
+            span: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::error(ErrorKind::Internal, message).with_note("this is a compiler bug")
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+        for note in &self.notes {
+            write!(f, "\n{}: {}", Severity::Note, note)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,662 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::{env, fs, io};
+
+use crate::codegen::peephole::Rule;
+use crate::diagnostic::{Diagnostic, ErrorKind};
+use crate::optimize::Optimizations;
+use crate::pretty;
+use crate::semantic_analysis::SymbolTable;
+use crate::session::{Compiler, Session, StageResult};
+use crate::tacky::{Tacky, interp};
+use crate::target::Target;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    Assembly,
+    Object,
+    Executable,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DebugStage {
+    Lex,
+    Parse,
+    Validate,
+    Tacky,
+    Optimize,
+    Codegen,
+}
+
+impl DebugStage {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "lex" => Ok(DebugStage::Lex),
+            "parse" => Ok(DebugStage::Parse),
+            "validate" => Ok(DebugStage::Validate),
+            "tacky" => Ok(DebugStage::Tacky),
+            "optimize" => Ok(DebugStage::Optimize),
+            "codegen" => Ok(DebugStage::Codegen),
+            _ => Err(format!(
+                "Unknown dump stage {} (expected lex, parse, validate, tacky, optimize or codegen)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub inputs: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub stop: Stop,
+    pub include_paths: Vec<PathBuf>,
+    pub standard_includes: bool,
+    pub library_paths: Vec<PathBuf>,
+    pub libraries: Vec<String>,
+    pub target: Target,
+    pub run: bool,
+    pub interpret: bool,
+    pub max_call_depth: usize,
+    pub save_temps: bool,
+    pub debug: Option<DebugStage>,
+    pub dumps: Vec<DebugStage>,
+    pub optimizations: Optimizations,
+    pub regalloc_stats: bool,
+    pub peephole: Vec<Rule>,
+}
+
+enum Sink<'a> {
+    Assembly(&'a Path),
+    Interpreter(&'a mut Vec<(Tacky, SymbolTable)>),
+}
+
+struct Temps {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            inputs: vec![],
+            output: None,
+            stop: Stop::Executable,
+            include_paths: vec![],
+            standard_includes: true,
+            library_paths: vec![],
+            libraries: vec![],
+            target: Target::host(),
+            run: false,
+            interpret: false,
+            max_call_depth: interp::DEFAULT_MAX_DEPTH,
+            save_temps: false,
+            debug: None,
+            dumps: vec![],
+            optimizations: Optimizations::default(),
+            regalloc_stats: false,
+            peephole: Rule::all(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| -> Result<String, String> {
+                match &arg[flag.len()..] {
+                    "" => args
+                        .next()
+                        .cloned()
+                        .ok_or(format!("Missing argument to {}", flag)),
+                    attached => Ok(attached.strip_prefix('=').unwrap_or(attached).to_string()),
+                }
+            };
+            match arg.as_str() {
+                "-S" => options.stop = Stop::Assembly,
+                "-c" => options.stop = Stop::Object,
+                "--run" => options.run = true,
+                "--interpret" => options.interpret = true,
+                "-O0" => options.optimizations = Optimizations::default(),
+                "-O" | "-O1" => options.optimizations = Optimizations::all(),
+                "--fold-constants" => options.optimizations.fold_constants = true,
+                "--propagate-copies" => options.optimizations.propagate_copies = true,
+                "--eliminate-dead-stores" => options.optimizations.eliminate_dead_stores = true,
+                "--reduce-strength" => options.optimizations.reduce_strength = true,
+                "--eliminate-unreachable-code" => {
+                    options.optimizations.eliminate_unreachable_code = true
+                }
+                "--regalloc-stats" => options.regalloc_stats = true,
+                "--save-temps" => options.save_temps = true,
+                "-nostdinc" => options.standard_includes = false,
+                "--lex" => options.debug = Some(DebugStage::Lex),
+                "--parse" => options.debug = Some(DebugStage::Parse),
+                "--validate" => options.debug = Some(DebugStage::Validate),
+                "--tacky" => options.debug = Some(DebugStage::Tacky),
+                "--codegen" => options.debug = Some(DebugStage::Codegen),
+                "--from-tacky" => options.inputs.push(value("--from-tacky")?.into()),
+                a if a.starts_with("--dump") => {
+                    for stage in value("--dump")?.split(',') {
+                        options.dumps.push(DebugStage::parse(stage)?);
+                    }
+                }
+                a if a.starts_with("--disable-peephole") => {
+                    for name in value("--disable-peephole")?.split(',') {
+                        if name == "all" {
+                            options.peephole.clear();
+                        } else {
+                            let rule = Rule::parse(name)?;
+                            options.peephole.retain(|r| *r != rule);
+                        }
+                    }
+                }
+                a if a.starts_with("--max-call-depth") => {
+                    let depth = value("--max-call-depth")?;
+                    options.max_call_depth = depth
+                        .parse()
+                        .ok()
+                        .filter(|depth| *depth > 0)
+                        .ok_or(format!("Invalid call depth {}", depth))?;
+                }
+                a if a.starts_with("--target") => options.target = value("--target")?.parse()?,
+                a if a.starts_with("-o") => options.output = Some(value("-o")?.into()),
+                a if a.starts_with("-I") => options.include_paths.push(value("-I")?.into()),
+                a if a.starts_with("-L") => options.library_paths.push(value("-L")?.into()),
+                a if a.starts_with("-l") => options.libraries.push(value("-l")?),
+                a if a.starts_with('-') => return Err(format!("Unrecognized option {}", a)),
+                input => options.inputs.push(input.into()),
+            }
+        }
+
+        if options.inputs.is_empty() {
+            return Err("No input files".to_string());
+        }
+        for input in &options.inputs {
+            if !matches!(Self::extension(input), "c" | "tacky" | "s" | "o") {
+                return Err(format!(
+                    "{}: Unrecognized input file type (expected .c, .tacky, .s or .o)",
+                    input.display()
+                ));
+            }
+        }
+        if options.output.is_some() && options.stop != Stop::Executable {
+            let produced = options
+                .inputs
+                .iter()
+                .filter(|input| Self::extension(input) != "o")
+                .count();
+            if produced > 1 {
+                return Err("Cannot specify -o with -c or -S with multiple files".to_string());
+            }
+        }
+        if options.output.as_deref() == Some(Path::new("-")) && options.stop != Stop::Assembly {
+            return Err("-o - is only supported with -S".to_string());
+        }
+        if options.run && options.stop != Stop::Executable {
+            return Err("--run requires linking an executable".to_string());
+        }
+        if options.interpret {
+            if options.run || options.stop != Stop::Executable {
+                return Err("--interpret cannot be combined with -S, -c or --run".to_string());
+            }
+            if let Some(input) = options
+                .inputs
+                .iter()
+                .find(|input| !matches!(Self::extension(input), "c" | "tacky"))
+            {
+                return Err(format!(
+                    "{}: --interpret only accepts .c and .tacky inputs",
+                    input.display()
+                ));
+            }
+        }
+        Ok(options)
+    }
+
+    fn search_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.include_paths.clone();
+        if self.standard_includes {
+            paths.extend(self.target.system_include_paths());
+        }
+        paths
+    }
+
+    fn extension(path: &Path) -> &str {
+        path.extension().and_then(|e| e.to_str()).unwrap_or("")
+    }
+
+    fn output_for(&self, input: &Path, extension: &str) -> PathBuf {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => Self::local_name(input, extension),
+        }
+    }
+
+    fn local_name(input: &Path, extension: &str) -> PathBuf {
+        PathBuf::from(input.file_name().unwrap_or_default()).with_extension(extension)
+    }
+}
+
+impl Temps {
+    fn new() -> Self {
+        Self {
+            dir: env::temp_dir().join(format!("wacc-{}", process::id())),
+            files: vec![],
+        }
+    }
+
+    fn path(&mut self, input: &Path, extension: &str) -> Result<PathBuf, String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|err| format!("Cannot create {}: {}", self.dir.display(), err))?;
+        let name = Options::local_name(input, extension);
+        let path = self
+            .dir
+            .join(format!("{}-{}", self.files.len(), name.display()));
+        self.files.push(path.clone());
+        Ok(path)
+    }
+}
+
+impl Drop for Temps {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return 1;
+        }
+    };
+    let mut temps = Temps::new();
+    match build(&options, &mut temps) {
+        Ok(code) => code,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("error: {}", err);
+            }
+            1
+        }
+    }
+}
+
+fn build(options: &Options, temps: &mut Temps) -> Result<i32, String> {
+    if options.interpret {
+        return interpret(options);
+    }
+    let mut link_inputs = vec![];
+    let mut failed = false;
+
+    for input in &options.inputs {
+        let assembly = match Options::extension(input) {
+            "c" | "tacky" => {
+                let assembly = match options.stop {
+                    Stop::Assembly => options.output_for(input, "s"),
+                    _ if options.save_temps => Options::local_name(input, "s"),
+                    _ => temps.path(input, "s")?,
+                };
+                if !compile(options, input, Sink::Assembly(&assembly))? {
+                    failed = true;
+                    continue;
+                }
+                if options.debug.is_some() {
+                    return Ok(0);
+                }
+                assembly
+            }
+            "s" => input.clone(),
+            _ => {
+                if options.stop == Stop::Executable {
+                    link_inputs.push(input.clone());
+                } else {
+                    eprintln!(
+                        "warning: {}: linker input file unused because linking not done",
+                        input.display()
+                    );
+                }
+                continue;
+            }
+        };
+
+        // Assemble separately from linking so a failure names the right tool.
+        let object = match options.stop {
+            Stop::Assembly => continue,
+            Stop::Object => options.output_for(input, "o"),
+            Stop::Executable if options.save_temps => Options::local_name(input, "o"),
+            Stop::Executable => temps.path(input, "o")?,
+        };
+        let mut command = options.target.compiler();
+        command.arg("-c").arg(&assembly).arg("-o").arg(&object);
+        invoke(command, "assembler")?;
+        if options.stop == Stop::Executable {
+            link_inputs.push(object);
+        }
+    }
+
+    if failed {
+        return Err(String::new());
+    }
+    if options.stop != Stop::Executable {
+        return Ok(0);
+    }
+
+    let executable = options
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("a.out"));
+    let mut command = options.target.compiler();
+    command.args(&link_inputs).arg("-o").arg(&executable);
+    for dir in &options.library_paths {
+        command.arg(format!("-L{}", dir.display()));
+    }
+    for library in &options.libraries {
+        command.arg(format!("-l{}", library));
+    }
+    invoke(command, "linker")?;
+
+    if !options.run {
+        return Ok(0);
+    }
+    let program = if executable.is_absolute() {
+        executable
+    } else {
+        Path::new(".").join(executable)
+    };
+    let status = Command::new(&program)
+        .status()
+        .map_err(|err| format!("Cannot run {}: {}", program.display(), err))?;
+    Ok(exit_code(status))
+}
+
+fn interpret(options: &Options) -> Result<i32, String> {
+    let mut units = vec![];
+    let mut failed = false;
+    for input in &options.inputs {
+        if !compile(options, input, Sink::Interpreter(&mut units))? {
+            failed = true;
+        } else if options.debug.is_some() {
+            return Ok(0);
+        }
+    }
+    if failed {
+        return Err(String::new());
+    }
+    match interp::run(&units, options.max_call_depth, io::stdout().lock()) {
+        Ok(code) => Ok(code),
+        Err(trap) => {
+            eprintln!("error: {}", trap);
+            Ok(trap.exit_code())
+        }
+    }
+}
+
+fn invoke(mut command: Command, what: &str) -> Result<(), String> {
+    let status = command
+        .status()
+        .map_err(|err| format!("Cannot run {}: {}", what, err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed with {}", what, status))
+    }
+}
+
+#[cfg(unix)]
+fn exit_code(status: process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: process::ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+fn compile(options: &Options, input: &Path, sink: Sink) -> Result<bool, String> {
+    let compiler = Compiler {
+        target: options.target,
+        include_paths: options.search_paths(),
+    };
+    if Options::extension(input) == "tacky" {
+        let session = compiler.session_for_tacky(input);
+        return Ok(report(&session, compile_tacky(&session, options, sink)));
+    }
+    let session = compiler.session_for_file(input);
+    if options.save_temps {
+        let i_path = Options::local_name(input, "i");
+        fs::write(&i_path, session.source())
+            .map_err(|err| format!("Cannot write {}: {}", i_path.display(), err))?;
+    }
+
+    Ok(report(&session, compile_session(&session, options, sink)))
+}
+
+fn report(session: &Session, result: StageResult<()>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", session.render(&diagnostic));
+            }
+            false
+        }
+    }
+}
+
+fn dump(options: &Options, stage: DebugStage, text: impl FnOnce() -> String) -> bool {
+    if options.debug == Some(stage) || options.dumps.contains(&stage) {
+        print!("{}", text());
+    }
+    options.debug == Some(stage)
+}
+
+fn compile_session(session: &Session, options: &Options, sink: Sink) -> StageResult<()> {
+    if options.debug == Some(DebugStage::Lex) || options.dumps.contains(&DebugStage::Lex) {
+        let tokens = session.lex()?;
+        if dump(options, DebugStage::Lex, || {
+            pretty::tokens(session.source(), &tokens)
+        }) {
+            return Ok(());
+        }
+    }
+    let parsed = session.parse()?;
+    if dump(options, DebugStage::Parse, || pretty::ast(&parsed)) {
+        return Ok(());
+    }
+    let (analyzed, mut symbols) = session.analyze(parsed)?;
+    if dump(options, DebugStage::Validate, || pretty::ast(&analyzed)) {
+        return Ok(());
+    }
+    let tackified = session.tacky(analyzed, &mut symbols)?;
+    compile_backend(session, options, tackified, &mut symbols, sink)
+}
+
+fn compile_tacky(session: &Session, options: &Options, sink: Sink) -> StageResult<()> {
+    let (tackified, mut symbols) = session.parse_tacky()?;
+    compile_backend(session, options, tackified, &mut symbols, sink)
+}
+
+fn compile_backend(
+    session: &Session,
+    options: &Options,
+    tackified: Tacky,
+    symbols: &mut SymbolTable,
+    sink: Sink,
+) -> StageResult<()> {
+    if dump(options, DebugStage::Tacky, || {
+        pretty::tacky(&tackified, symbols)
+    }) {
+        return Ok(());
+    }
+    let tackified = session.optimize(tackified, symbols, options.optimizations);
+    if dump(options, DebugStage::Optimize, || {
+        pretty::tacky(&tackified, symbols)
+    }) {
+        return Ok(());
+    }
+    let assembly = match sink {
+        Sink::Assembly(assembly) => assembly,
+        Sink::Interpreter(units) => {
+            units.push((tackified, symbols.clone()));
+            return Ok(());
+        }
+    };
+    let selected = session.select(tackified, symbols)?;
+    if dump(options, DebugStage::Codegen, || pretty::assembly(&selected)) {
+        return Ok(());
+    }
+    let (assembled, stats) = session.allocate(selected, symbols);
+    if options.regalloc_stats {
+        for stat in stats {
+            eprintln!("{}: removed {} moves", stat.function, stat.moves_removed);
+        }
+    }
+    let assembled = session.peephole(assembled, &options.peephole);
+    if assembly == Path::new("-") {
+        let mut out = io::stdout().lock();
+        session.emit(assembled, &mut out)?;
+        return out.flush().map_err(|err| {
+            vec![Diagnostic::error(
+                ErrorKind::Io,
+                format!("Cannot write assembly: {}", err),
+            )]
+        });
+    }
+    let file = fs::File::create(assembly).map_err(|err| {
+        vec![Diagnostic::error(
+            ErrorKind::Io,
+            format!("Cannot create {}: {}", assembly.display(), err),
+        )]
+    })?;
+    session.emit(assembled, io::BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn options() {
+        let options = parse("-c -o out.o -Iinc -I other --target=x86_64-apple-darwin a.c").unwrap();
+        assert_eq!(options.stop, Stop::Object);
+        assert_eq!(options.output, Some(PathBuf::from("out.o")));
+        assert_eq!(
+            options.include_paths,
+            vec![PathBuf::from("inc"), PathBuf::from("other")]
+        );
+        assert_eq!(options.target, Target::AppleDarwin);
+        assert_eq!(
+            options.search_paths(),
+            ["inc", "other", "/usr/local/include", "/usr/include"].map(PathBuf::from)
+        );
+        let options = parse("-nostdinc -Iinc a.c").unwrap();
+        assert_eq!(options.search_paths(), vec![PathBuf::from("inc")]);
+
+        let options = parse("a.c b.s c.o -lm -L lib --run --save-temps").unwrap();
+        assert_eq!(options.inputs.len(), 3);
+        assert_eq!(options.libraries, vec!["m"]);
+        assert_eq!(options.library_paths, vec![PathBuf::from("lib")]);
+        assert!(options.run && options.save_temps);
+
+        let options = parse("--dump=tacky,codegen --dump parse a.c").unwrap();
+        assert_eq!(
+            options.dumps,
+            vec![DebugStage::Tacky, DebugStage::Codegen, DebugStage::Parse]
+        );
+        assert_eq!(options.debug, None);
+
+        let options = parse("-S -o - a.c").unwrap();
+        assert_eq!(
+            options.output_for(Path::new("a.c"), "s"),
+            PathBuf::from("-")
+        );
+
+        let options = parse("--from-tacky prog.tacky -S").unwrap();
+        assert_eq!(options.inputs, vec![PathBuf::from("prog.tacky")]);
+
+        let options = parse("--interpret a.c b.tacky").unwrap();
+        assert!(options.interpret);
+        assert_eq!(options.max_call_depth, interp::DEFAULT_MAX_DEPTH);
+        let options = parse("--interpret --max-call-depth=20 a.c").unwrap();
+        assert_eq!(options.max_call_depth, 20);
+        assert_eq!(options.optimizations, Optimizations::default());
+
+        let options = parse("-O1 a.c").unwrap();
+        assert_eq!(options.optimizations, Optimizations::all());
+        let options = parse("--fold-constants a.c").unwrap();
+        assert!(options.optimizations.fold_constants);
+        assert!(
+            parse("--reduce-strength a.c")
+                .unwrap()
+                .optimizations
+                .reduce_strength
+        );
+        assert!(!options.regalloc_stats);
+        assert!(parse("--regalloc-stats a.c").unwrap().regalloc_stats);
+        assert_eq!(parse("a.c").unwrap().peephole, Rule::all());
+        let options = parse("--disable-peephole=xor-zero,lea-add a.c").unwrap();
+        assert_eq!(options.peephole.len(), Rule::all().len() - 2);
+        assert!(!options.peephole.contains(&Rule::XorZero));
+        assert!(
+            parse("--disable-peephole all a.c")
+                .unwrap()
+                .peephole
+                .is_empty()
+        );
+        assert_eq!(
+            options.output_for(Path::new("dir/a.c"), "s"),
+            PathBuf::from("a.s")
+        );
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(parse("").is_err());
+        assert!(parse("a.c -o").is_err());
+        assert!(parse("a.txt").is_err());
+        assert!(parse("--frobnicate a.c").is_err());
+        assert!(parse("-S -o x.s a.c b.c").is_err());
+        assert!(parse("-c --run a.c").is_err());
+        assert!(parse("--target riscv a.c").is_err());
+        assert!(parse("--dump=frobnicate a.c").is_err());
+        assert!(parse("--disable-peephole=frobnicate a.c").is_err());
+        assert!(parse("--interpret -S a.c").is_err());
+        assert!(parse("--interpret a.c b.o").is_err());
+        assert!(parse("--interpret --max-call-depth=0 a.c").is_err());
+        assert!(parse("--max-call-depth=lots a.c").is_err());
+        assert!(parse("-c -o - a.c").is_err());
+        assert!(parse("-o - a.c").is_err());
+    }
+
+    #[test]
+    fn tool_failures() {
+        let dir = env::temp_dir().join(format!("driver-tools-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bad = dir.join("bad.s");
+        fs::write(&bad, "\tfrobnicate %eax\n").unwrap();
+        let good = dir.join("good.s");
+        fs::write(&good, "\t.text\n").unwrap();
+        let build = |args: String| build(&parse(&args).unwrap(), &mut Temps::new());
+
+        let out = dir.join("out");
+        for flags in ["-c", ""] {
+            let err = build(format!("{} {} -o {}", flags, bad.display(), out.display()));
+            assert!(err.unwrap_err().starts_with("assembler failed"));
+        }
+        let err = build(format!("{} -o {}", good.display(), out.display()));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(err.unwrap_err().starts_with("linker failed"));
+    }
+}
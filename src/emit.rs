@@ -1,67 +1,125 @@
+use std::collections::HashSet;
 use std::io::Result;
-use std::{fs::File, io::Write};
+use std::io::Write;
 
 use crate::codegen::{
-    AsmTopLevel, Assembly, BinaryOp, CondCode, Instr, Operand, Register, UnaryOp,
+    AsmTopLevel, Assembly, AssemblyType, BinaryOp, CondCode, Instr, Operand, Register, UnaryOp,
 };
+use crate::target::Target;
+use crate::types::Const;
 
-pub fn emit(asm: Assembly, mut file: File) -> Result<()> {
+pub fn emit(asm: Assembly, target: Target, mut file: impl Write) -> Result<()> {
+    let defined = asm
+        .iter()
+        .filter_map(|top_level| match top_level {
+            AsmTopLevel::AsmFunction { name, .. } => Some(name.clone()),
+            AsmTopLevel::AsmStatic { .. } => None,
+        })
+        .collect::<HashSet<_>>();
     for top_level in asm {
-        emit_top_level(top_level, &mut file)?
+        emit_top_level(top_level, target, &defined, &mut file)?
+    }
+    if target.is_elf() {
+        file.write_all("\t.section .note.GNU-stack,\"\",@progbits\n".as_bytes())?;
     }
     Ok(())
 }
 
-fn emit_top_level(top_level: AsmTopLevel, file: &mut File) -> Result<()> {
+fn emit_top_level(
+    top_level: AsmTopLevel,
+    target: Target,
+    defined: &HashSet<String>,
+    file: &mut impl Write,
+) -> Result<()> {
     match top_level {
         AsmTopLevel::AsmFunction {
             name,
             instructions,
             global,
         } => {
+            let symbol = target.symbol(&name);
             if global {
-                file.write_all(format!("\t.globl _{}\n", name).as_bytes())?;
+                file.write_all(format!("\t.globl {}\n", symbol).as_bytes())?;
+            }
+            file.write_all("\t.text\n".as_bytes())?;
+            if target.is_elf() {
+                file.write_all(format!("\t.type {}, @function\n", symbol).as_bytes())?;
             }
-            file.write_all(format!("_{}:\n", name).as_bytes())?;
+            file.write_all(format!("{}:\n", symbol).as_bytes())?;
             file.write_all("\tpushq\t%rbp\n".as_bytes())?;
             file.write_all("\tmovq\t%rsp, %rbp\n".as_bytes())?;
             for instr in instructions {
-                emit_instr(instr, file)?;
+                emit_instr(instr, target, defined, file)?;
+            }
+            if target.is_elf() {
+                file.write_all(format!("\t.size {}, .-{}\n", symbol, symbol).as_bytes())?;
             }
         }
         AsmTopLevel::AsmStatic { name, global, init } => {
+            let symbol = target.symbol(&name);
             if global {
-                file.write_all(format!("\t.globl _{}\n", name).as_bytes())?;
+                file.write_all(format!("\t.globl {}\n", symbol).as_bytes())?;
             }
-            if init == 0 {
+            let (size, directive) = match init {
+                Const::Int(_) | Const::UInt(_) => (4, "long"),
+                Const::Long(_) | Const::ULong(_) => (8, "quad"),
+            };
+            if init.is_zero() {
                 file.write_all("\t.bss\n".as_bytes())?;
             } else {
                 file.write_all("\t.data\n".as_bytes())?;
             }
-            file.write_all("\t.balign 4\n".as_bytes())?;
-            file.write_all(format!("_{}:\n", name).as_bytes())?;
-            if init == 0 {
-                file.write_all("\t.zero 4\n".as_bytes())?;
+            file.write_all(format!("\t.balign {}\n", size).as_bytes())?;
+            if target.is_elf() {
+                file.write_all(format!("\t.type {}, @object\n", symbol).as_bytes())?;
+                file.write_all(format!("\t.size {}, {}\n", symbol, size).as_bytes())?;
+            }
+            file.write_all(format!("{}:\n", symbol).as_bytes())?;
+            if init.is_zero() {
+                file.write_all(format!("\t.zero {}\n", size).as_bytes())?;
             } else {
-                file.write_all(format!("\t.long {}\n", init).as_bytes())?;
+                file.write_all(format!("\t.{} {}\n", directive, init.value()).as_bytes())?;
             }
         }
     }
     Ok(())
 }
 
-fn emit_instr(instr: Instr, file: &mut File) -> Result<()> {
+fn emit_instr(
+    instr: Instr,
+    target: Target,
+    defined: &HashSet<String>,
+    file: &mut impl Write,
+) -> Result<()> {
     match instr {
         Instr::Ret => {
             file.write_all("\tmovq \t%rbp, %rsp\n".as_bytes())?;
             file.write_all("\tpopq\t%rbp\n".as_bytes())?;
             file.write_all("\tret\n".as_bytes())?;
         }
-        Instr::Mov { src, dst } => file.write_all(
+        Instr::Mov { ty, src, dst } => file.write_all(
+            format!(
+                "\tmov{}\t{}, {}\n",
+                suffix(ty),
+                write_operand(target, src, size(ty)),
+                write_operand(target, dst, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::Movsx { src, dst } => file.write_all(
+            format!(
+                "\tmovslq\t{}, {}\n",
+                write_operand(target, src, 4),
+                write_operand(target, dst, 8)
+            )
+            .as_bytes(),
+        )?,
+        // By now the destination is a register, whose upper half movl clears.
+        Instr::MovZeroExtend { src, dst } => file.write_all(
             format!(
                 "\tmovl\t{}, {}\n",
-                write_operand(src, 4),
-                write_operand(dst, 4)
+                write_operand(target, src, 4),
+                write_operand(target, dst, 4)
             )
             .as_bytes(),
         )?,
@@ -69,51 +127,140 @@ fn emit_instr(instr: Instr, file: &mut File) -> Result<()> {
         Instr::DeallocateStack(n) => {
             file.write_all(format!("\taddq\t${}, %rsp\n", n).as_bytes())?
         }
-        Instr::Unary { unop, dst: operand } => file.write_all(
-            format!("\t{}\t{}\n", write_unop(unop), write_operand(operand, 4)).as_bytes(),
+        Instr::Unary {
+            unop,
+            ty,
+            dst: operand,
+        } => file.write_all(
+            format!(
+                "\t{}{}\t{}\n",
+                write_unop(unop),
+                suffix(ty),
+                write_operand(target, operand, size(ty))
+            )
+            .as_bytes(),
         )?,
-        Instr::Binary { binop, src, dst } => file.write_all(
+        Instr::Binary {
+            binop,
+            ty,
+            src,
+            dst,
+        } => file.write_all(
             format!(
-                "\t{}\t{}, {}\n",
+                "\t{}{}\t{}, {}\n",
                 write_binop(binop),
-                if matches!(binop, BinaryOp::ShiftLeft | BinaryOp::ShiftRight) {
-                    write_operand(src, 1)
+                suffix(ty),
+                if matches!(
+                    binop,
+                    BinaryOp::ShiftLeft | BinaryOp::ShiftRight | BinaryOp::LogicalShiftRight
+                ) {
+                    write_operand(target, src, 1)
                 } else {
-                    write_operand(src, 4)
+                    write_operand(target, src, size(ty))
                 },
-                write_operand(dst, 4),
+                write_operand(target, dst, size(ty)),
             )
             .as_bytes(),
         )?,
-        Instr::IDiv(operand) => {
-            file.write_all(format!("\tidivl\t{}\n", write_operand(operand, 4)).as_bytes())?
-        }
-        Instr::Cdq => file.write_all("\tcdq\n".as_bytes())?,
-        Instr::Cmp { lhs, rhs } => file.write_all(
+        Instr::IDiv(ty, operand) => file.write_all(
+            format!(
+                "\tidiv{}\t{}\n",
+                suffix(ty),
+                write_operand(target, operand, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::Div(ty, operand) => file.write_all(
             format!(
-                "\tcmpl\t{}, {}\n",
-                write_operand(lhs, 4),
-                write_operand(rhs, 4)
+                "\tdiv{}\t{}\n",
+                suffix(ty),
+                write_operand(target, operand, size(ty))
             )
             .as_bytes(),
         )?,
-        Instr::Jmp(label) => file.write_all(format!("\tjmp\t.L{}\n", label).as_bytes())?,
-        Instr::JmpCC(cond_code, label) => {
-            file.write_all(format!("\tj{}\t.L{}\n", write_cond_code(cond_code), label).as_bytes())?
+        Instr::IMul(ty, operand) => file.write_all(
+            format!(
+                "\timul{}\t{}\n",
+                suffix(ty),
+                write_operand(target, operand, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::Cdq(AssemblyType::Longword) => file.write_all("\tcdq\n".as_bytes())?,
+        Instr::Cdq(AssemblyType::Quadword) => file.write_all("\tcqo\n".as_bytes())?,
+        Instr::Cmp { ty, lhs, rhs } => file.write_all(
+            format!(
+                "\tcmp{}\t{}, {}\n",
+                suffix(ty),
+                write_operand(target, lhs, size(ty)),
+                write_operand(target, rhs, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::Test { ty, lhs, rhs } => file.write_all(
+            format!(
+                "\ttest{}\t{}, {}\n",
+                suffix(ty),
+                write_operand(target, lhs, size(ty)),
+                write_operand(target, rhs, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::Lea {
+            ty,
+            base,
+            index,
+            offset,
+            dst,
+        } => file.write_all(
+            format!(
+                "\tlea{}\t{}, {}\n",
+                suffix(ty),
+                write_address(base, index, offset),
+                write_register(dst, size(ty))
+            )
+            .as_bytes(),
+        )?,
+        Instr::LoadAddress { src, dst } => file.write_all(
+            format!(
+                "\tleaq\t{}, {}\n",
+                write_operand(target, src, 8),
+                write_operand(target, dst, 8)
+            )
+            .as_bytes(),
+        )?,
+        Instr::Jmp(label) => {
+            file.write_all(format!("\tjmp\t{}\n", target.local_label(&label)).as_bytes())?
         }
+        Instr::JmpCC(cond_code, label) => file.write_all(
+            format!(
+                "\tj{}\t{}\n",
+                write_cond_code(cond_code),
+                target.local_label(&label)
+            )
+            .as_bytes(),
+        )?,
         Instr::SetCC(cond_code, operand) => file.write_all(
             format!(
                 "\tset{}\t{}\n",
                 write_cond_code(cond_code),
-                write_operand(operand, 1)
+                write_operand(target, operand, 1)
             )
             .as_bytes(),
         )?,
-        Instr::Label(label) => file.write_all(format!(".L{}:\n", label).as_bytes())?,
-        Instr::Call(name) => file.write_all(format!("\tcall _{}\n", name).as_bytes())?,
+        Instr::Label(label) => {
+            file.write_all(format!("{}:\n", target.local_label(&label)).as_bytes())?
+        }
+        Instr::Call(name) => {
+            let callee = target.call_target(&name, !defined.contains(&name));
+            file.write_all(format!("\tcall {}\n", callee).as_bytes())?
+        }
 
         Instr::Push(operand) => {
-            file.write_all(format!("\tpushq {}\n", write_operand(operand, 8)).as_bytes())?
+            file.write_all(format!("\tpushq {}\n", write_operand(target, operand, 8)).as_bytes())?
+        }
+        Instr::Pop(reg) => {
+            file.write_all(format!("\tpopq\t{}\n", write_register(reg, 8)).as_bytes())?
         }
     }
     Ok(())
@@ -127,48 +274,90 @@ fn write_cond_code(code: CondCode) -> String {
         CondCode::GE => "ge",
         CondCode::L => "l",
         CondCode::G => "g",
+        CondCode::A => "a",
+        CondCode::AE => "ae",
+        CondCode::B => "b",
+        CondCode::BE => "be",
     }
     .to_string()
 }
 
 fn write_unop(unop: UnaryOp) -> String {
     match unop {
-        UnaryOp::Neg => "negl",
-        UnaryOp::Not => "notl",
+        UnaryOp::Neg => "neg",
+        UnaryOp::Not => "not",
     }
     .to_string()
 }
 
 fn write_binop(binop: BinaryOp) -> String {
     match binop {
-        BinaryOp::Add => "addl",
-        BinaryOp::Sub => "subl",
-        BinaryOp::Mult => "imull",
-        BinaryOp::BitAnd => "andl",
-        BinaryOp::BitOr => "orl",
-        BinaryOp::BitXOr => "xorl",
-        BinaryOp::ShiftLeft => "shll",
-        BinaryOp::ShiftRight => "sarl",
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mult => "imul",
+        BinaryOp::BitAnd => "and",
+        BinaryOp::BitOr => "or",
+        BinaryOp::BitXOr => "xor",
+        BinaryOp::ShiftLeft => "shl",
+        BinaryOp::ShiftRight => "sar",
+        BinaryOp::LogicalShiftRight => "shr",
     }
     .to_string()
 }
 
-fn write_operand(op: Operand, bytes: u8) -> String {
+fn suffix(ty: AssemblyType) -> &'static str {
+    match ty {
+        AssemblyType::Longword => "l",
+        AssemblyType::Quadword => "q",
+    }
+}
+
+fn size(ty: AssemblyType) -> u8 {
+    match ty {
+        AssemblyType::Longword => 4,
+        AssemblyType::Quadword => 8,
+    }
+}
+
+fn write_operand(target: Target, op: Operand, bytes: u8) -> String {
     match op {
         Operand::Reg(reg) => write_register(reg, bytes),
         Operand::Imm(n) => format!("${}", n),
         Operand::Stack(offset) => format!("{}(%rbp)", offset),
         Operand::Pseudo(s) => panic!("Pseudo operand {} not replaced", s),
-        Operand::Data(var) => format!("_{}(%rip)", var),
+        Operand::Data(var) => format!("{}(%rip)", target.symbol(&var)),
+        Operand::Memory(base, offset) => write_address(base, None, offset),
+    }
+}
+
+fn write_address(base: Register, index: Option<Register>, offset: i32) -> String {
+    let offset = if offset == 0 {
+        String::new()
+    } else {
+        offset.to_string()
+    };
+    match index {
+        Some(index) => format!(
+            "{}({}, {})",
+            offset,
+            write_register(base, 8),
+            write_register(index, 8)
+        ),
+        None => format!("{}({})", offset, write_register(base, 8)),
     }
 }
 
 fn write_register(reg: Register, bytes: u8) -> String {
     match reg {
-        Register::AX | Register::CX | Register::DX => write_x_register(reg, bytes),
-        Register::R8 | Register::R9 | Register::R10 | Register::R11 => {
-            write_numeric_register(reg, bytes)
-        }
+        Register::AX | Register::BX | Register::CX | Register::DX => write_x_register(reg, bytes),
+        Register::R8
+        | Register::R9
+        | Register::R10
+        | Register::R11
+        | Register::R12
+        | Register::R13
+        | Register::R14
+        | Register::R15 => write_numeric_register(reg, bytes),
         Register::DI | Register::SI => write_i_register(reg, bytes),
     }
 }
@@ -185,6 +374,10 @@ fn write_numeric_register(reg: Register, bytes: u8) -> String {
         Register::R9 => 9,
         Register::R10 => 10,
         Register::R11 => 11,
+        Register::R12 => 12,
+        Register::R13 => 13,
+        Register::R14 => 14,
+        Register::R15 => 15,
         r => panic!("Bad numeric register {:?}", r),
     };
     format!("%r{}{}", num, suffix)
@@ -200,6 +393,7 @@ fn write_x_register(reg: Register, bytes: u8) -> String {
 
     let letter = match reg {
         Register::AX => "a",
+        Register::BX => "b",
         Register::CX => "c",
         Register::DX => "d",
         r => panic!("Bad x register {:?}", r),
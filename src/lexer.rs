@@ -1,15 +1,23 @@
+use crate::diagnostic::{Diagnostic, ErrorKind, Result, Span};
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenKind<'a> {
     Eof,
     Id(&'a str),
     Void,
     Int,
+    Long,
+    Signed,
+    Unsigned,
     LParen,
     RParen,
     LBrace,
     RBrace,
     Return,
     Constant(&'a str),
+    LongConstant(&'a str),
+    UnsignedConstant(&'a str),
+    UnsignedLongConstant(&'a str),
     Semicolon,
     Tilde,
     Plus,
@@ -69,6 +77,21 @@ pub struct Token<'a> {
     pub end: usize,
 }
 
+impl Token<'_> {
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineMarker<'a> {
+    pub line: usize,
+    pub file: Option<&'a str>,
+}
+
 #[derive(Debug)]
 pub struct Lexer<'a> {
     source: &'a str,
@@ -90,8 +113,30 @@ impl<'a> Lexer<'a> {
             self.position += 1;
         }
 
+        let digits = self.source.get(start_index..self.position).unwrap();
+        let mut unsigned = false;
+        let mut long = false;
+        while let Some(suffix @ ("u" | "U" | "l" | "L")) = self.peek() {
+            let seen = if suffix.eq_ignore_ascii_case("u") {
+                &mut unsigned
+            } else {
+                &mut long
+            };
+            if *seen {
+                break;
+            }
+            *seen = true;
+            self.position += 1;
+        }
+        let kind = match (unsigned, long) {
+            (false, false) => TokenKind::Constant(digits),
+            (false, true) => TokenKind::LongConstant(digits),
+            (true, false) => TokenKind::UnsignedConstant(digits),
+            (true, true) => TokenKind::UnsignedLongConstant(digits),
+        };
+
         Token {
-            kind: TokenKind::Constant(self.source.get(start_index..self.position).unwrap()),
+            kind,
             start: start_index,
             end: self.position,
         }
@@ -111,6 +156,9 @@ impl<'a> Lexer<'a> {
         let kind = match id {
             "return" => TokenKind::Return,
             "int" => TokenKind::Int,
+            "long" => TokenKind::Long,
+            "signed" => TokenKind::Signed,
+            "unsigned" => TokenKind::Unsigned,
             "void" => TokenKind::Void,
             "if" => TokenKind::If,
             "else" => TokenKind::Else,
@@ -135,6 +183,43 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    pub fn line_marker(directive: &'a str) -> Option<LineMarker<'a>> {
+        let directive = directive.trim_start();
+        let directive = directive.strip_prefix("line").unwrap_or(directive);
+        let mut words = directive.split_whitespace();
+        let line = words.next()?.parse::<usize>().ok()?;
+        let file = match words.next() {
+            Some(quoted) => Some(quoted.strip_prefix('"')?.strip_suffix('"')?),
+            None => None,
+        };
+        if !words.all(|flag| flag.parse::<u8>().is_ok()) {
+            return None;
+        }
+        Some(LineMarker { line, file })
+    }
+
+    fn directive(&mut self, start: usize) -> Result<()> {
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let directive = &self.source[start + 1..end];
+
+        let at_line_start = self.source[line_start..start].trim().is_empty();
+        let known =
+            Self::line_marker(directive).is_some() || directive.trim_start().starts_with("pragma");
+        if !(at_line_start && known) {
+            return Err(Diagnostic::error(
+                ErrorKind::InvalidToken,
+                format!("Unexpected preprocessor directive #{}", directive.trim()),
+            )
+            .with_span(Span { start, end }));
+        }
+
+        self.position = end;
+        Ok(())
+    }
+
     fn peek(&self) -> Option<&'a str> {
         if self.position >= self.source.len() {
             None
@@ -188,245 +273,271 @@ impl<'a> Lexer<'a> {
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
+    type Item = Result<Token<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let start = self.position;
         loop {
+            let start = self.position;
             let c = self.next_char()?;
 
             match c {
                 c if Self::is_whitespace(c) => {
                     continue;
                 }
+                "#" => {
+                    if let Err(diagnostic) = self.directive(start) {
+                        return Some(Err(diagnostic));
+                    }
+                    continue;
+                }
                 c if Self::is_digit(c) => {
                     let number = self.constant();
                     if let Some(next_c) = self.peek()
                         && Self::is_alpha(next_c)
                     {
-                        panic!("Bad token");
+                        let bad = self.identifier();
+                        return Some(Err(Diagnostic::error(
+                            ErrorKind::InvalidToken,
+                            format!(
+                                "Bad token {}",
+                                self.source.get(number.start..bad.end).unwrap()
+                            ),
+                        )
+                        .with_span(Span {
+                            start: number.start,
+                            end: bad.end,
+                        })));
                     }
-                    return Some(number);
+                    return Some(Ok(number));
                 }
                 c if Self::is_alpha(c) => {
-                    return Some(self.identifier());
+                    return Some(Ok(self.identifier()));
                 }
                 "-" => {
                     if let Some("-") = self.peek() {
                         self.next_char();
-                        return Some(Token {
+                        return Some(Ok(Token {
                             kind: TokenKind::DoubleMinus,
                             start,
                             end: self.position,
-                        });
+                        }));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::MinusEquals,
                             TokenKind::Minus,
                             start,
-                        ));
+                        )));
                     }
                 }
                 "<" => {
                     if let Some("<") = self.peek() {
                         self.next_char();
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::DoubleLAngleEquals,
                             TokenKind::DoubleLAngle,
                             start,
-                        ));
+                        )));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::LAngleEquals,
                             TokenKind::LAngle,
                             start,
-                        ));
+                        )));
                     }
                 }
                 ">" => {
                     if let Some(">") = self.peek() {
                         self.next_char();
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::DoubleRAngleEquals,
                             TokenKind::DoubleRAngle,
                             start,
-                        ));
+                        )));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::RAngleEquals,
                             TokenKind::RAngle,
                             start,
-                        ));
+                        )));
                     }
                 }
                 "&" => {
                     if let Some("&") = self.peek() {
                         self.next_char();
-                        return Some(Token {
+                        return Some(Ok(Token {
                             kind: TokenKind::DoubleAmpersand,
                             start,
                             end: self.position,
-                        });
+                        }));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::AmpersandEquals,
                             TokenKind::Ampersand,
                             start,
-                        ));
+                        )));
                     }
                 }
                 "|" => {
                     if let Some("|") = self.peek() {
                         self.next_char();
-                        return Some(Token {
+                        return Some(Ok(Token {
                             kind: TokenKind::DoublePipe,
                             start,
                             end: self.position,
-                        });
+                        }));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::PipeEquals,
                             TokenKind::Pipe,
                             start,
-                        ));
+                        )));
                     }
                 }
                 "=" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::DoubleEquals,
                         TokenKind::Equals,
                         start,
-                    ));
+                    )));
                 }
                 "!" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::BangEquals,
                         TokenKind::Bang,
                         start,
-                    ));
+                    )));
                 }
                 "~" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::Tilde,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 "(" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::LParen,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 ")" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::RParen,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 "{" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::LBrace,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 "}" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::RBrace,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 ";" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::Semicolon,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 "+" => {
                     if let Some("+") = self.peek() {
                         self.next_char();
-                        return Some(Token {
+                        return Some(Ok(Token {
                             kind: TokenKind::DoublePlus,
                             start,
                             end: self.position,
-                        });
+                        }));
                     } else {
-                        return Some(self.check_next_char(
+                        return Some(Ok(self.check_next_char(
                             "=",
                             TokenKind::PlusEquals,
                             TokenKind::Plus,
                             start,
-                        ));
+                        )));
                     }
                 }
                 "/" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::SlashEquals,
                         TokenKind::Slash,
                         start,
-                    ));
+                    )));
                 }
                 "%" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::PercentEquals,
                         TokenKind::Percent,
                         start,
-                    ));
+                    )));
                 }
                 "*" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::StarEquals,
                         TokenKind::Star,
                         start,
-                    ));
+                    )));
                 }
                 "^" => {
-                    return Some(self.check_next_char(
+                    return Some(Ok(self.check_next_char(
                         "=",
                         TokenKind::CaretEquals,
                         TokenKind::Caret,
                         start,
-                    ));
+                    )));
                 }
                 "?" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::Huh,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 ":" => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::Colon,
                         start,
                         end: self.position,
-                    });
+                    }));
                 }
                 "," => {
-                    return Some(Token {
+                    return Some(Ok(Token {
                         kind: TokenKind::Comma,
                         start,
                         end: self.position,
-                    });
+                    }));
+                }
+                c => {
+                    return Some(Err(Diagnostic::error(
+                        ErrorKind::InvalidToken,
+                        format!("Bad token {}", c),
+                    )
+                    .with_span(Span {
+                        start: self.position - 1,
+                        end: self.position,
+                    })));
                 }
-                c => panic!("Bad token {}", c),
             };
         }
     }
@@ -437,27 +548,75 @@ mod test {
     use super::*;
     use TokenKind::*;
 
+    fn kinds(source: &str) -> Vec<TokenKind<'_>> {
+        Lexer::new(source).map(|t| t.unwrap().kind).collect()
+    }
+
     #[test]
     fn whitespace() {
-        let tokens = Lexer::new(" \t      \n\n  \n").collect::<Vec<_>>();
+        let tokens = kinds(" \t      \n\n  \n");
         assert_eq!(tokens, vec![]);
     }
 
     #[test]
     fn numbers() {
-        let tokens = Lexer::new("1124\n").collect::<Vec<_>>();
+        let tokens = kinds("1124\n");
         assert_eq!(tokens, vec![Constant("1124")]);
+        let tokens = kinds("7L 8l long");
+        assert_eq!(tokens, vec![LongConstant("7"), LongConstant("8"), Long]);
+        let tokens = kinds("1u 2UL 3lu 4Lu unsigned signed");
+        assert_eq!(
+            tokens,
+            vec![
+                UnsignedConstant("1"),
+                UnsignedLongConstant("2"),
+                UnsignedLongConstant("3"),
+                UnsignedLongConstant("4"),
+                Unsigned,
+                Signed
+            ]
+        );
     }
 
     #[test]
     fn punctuation() {
-        let tokens = Lexer::new("; ( ) { } \n").collect::<Vec<_>>();
+        let tokens = kinds("; ( ) { } \n");
         assert_eq!(tokens, vec![Semicolon, LParen, RParen, LBrace, RBrace]);
     }
 
     #[test]
     fn identifiers() {
-        let tokens = Lexer::new("return int void ").collect::<Vec<_>>();
+        let tokens = kinds("return int void ");
         assert_eq!(tokens, vec![Return, Int, Void]);
     }
+
+    #[test]
+    fn line_markers() {
+        let tokens = kinds("# 1 \"foo.c\"\nint\n# 7 \"foo.h\" 1 3 4\n#pragma once\nvoid");
+        assert_eq!(tokens, vec![Int, Void]);
+        assert_eq!(
+            Lexer::line_marker(" 7 \"foo.h\" 1 3 4"),
+            Some(LineMarker {
+                line: 7,
+                file: Some("foo.h")
+            })
+        );
+        assert_eq!(Lexer::line_marker("define X 1"), None);
+    }
+
+    #[test]
+    fn bad_tokens() {
+        let errors = Lexer::new("1foo @ 2Lx")
+            .filter_map(|t| t.err())
+            .map(|d| (d.kind, d.span))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (ErrorKind::InvalidToken, Some(Span { start: 0, end: 4 })),
+                (ErrorKind::InvalidToken, Some(Span { start: 5, end: 6 })),
+                (ErrorKind::InvalidToken, Some(Span { start: 7, end: 10 })),
+            ]
+        );
+    }
 }
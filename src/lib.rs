@@ -0,0 +1,18 @@
+pub mod cfg;
+pub mod codegen;
+pub mod diagnostic;
+pub mod driver;
+pub mod emit;
+pub mod lexer;
+pub mod optimize;
+pub mod parser;
+pub mod preprocessor;
+pub mod pretty;
+pub mod semantic_analysis;
+pub mod session;
+pub mod source_map;
+pub mod tacky;
+pub mod target;
+pub mod types;
+
+pub use session::{Compiler, Session, StageResult};
@@ -1,88 +1,8 @@
-use std::{env, fs, path};
+use std::{env, process};
 
-use parser::Parser;
-use semantic_analysis::analyze;
-
-mod codegen;
-mod emit;
-mod lexer;
-mod parser;
-mod semantic_analysis;
-mod tacky;
+use writing_a_compiler::driver;
 
 fn main() {
-    let mut args = env::args().collect::<Vec<String>>();
-    let library = args.contains(&"-c".to_string());
-    let c_path = &args.pop().unwrap().clone();
-    let path = path::Path::new(c_path);
-    let i_path = path.with_extension("i");
-    std::process::Command::new("gcc")
-        .args(["-E", "-P", c_path, "-o", i_path.to_str().unwrap()])
-        .output()
-        .expect("Failed to preprocess .c file");
-    let data = fs::read_to_string(i_path);
-    let s_path = path.with_extension("s");
-    match data {
-        Ok(text) => compile_file(text, &s_path, &args),
-        Err(err) => println!("Error reading source file: [{}]", err),
-    }
-    let out_path = if library {
-        path.with_extension("o")
-    } else {
-        path.with_extension("")
-    };
-    std::process::Command::new("arch")
-        .args([
-            "-x86_64",
-            "gcc",
-            if library { "-c" } else { "" },
-            s_path.to_str().unwrap(),
-            "-o",
-            out_path.to_str().unwrap(),
-        ])
-        .output()
-        .expect("Failed to assemble .s file");
-    if !library {
-        std::process::Command::new(out_path.to_str().unwrap())
-            .output()
-            .expect("Failed to run executable");
-    }
-}
-
-fn compile_file(text: String, assembly_path: &path::Path, rest_args: &[String]) {
-    let lexed = lexer::Lexer::new(&text);
-    if rest_args.iter().any(|s| s == "--lex") {
-        println!("{:?}", lexed.collect::<Vec<_>>());
-        std::process::exit(0);
-    }
-    let parsed = Parser::new(lexed).parse();
-    if rest_args.iter().any(|s| s == "--parse") {
-        println!("{:?}", parsed);
-        std::process::exit(0);
-    }
-    let (analyzed, symbols) = analyze(parsed);
-    if rest_args.iter().any(|s| s == "--validate") {
-        println!("{:?}", analyzed);
-        std::process::exit(0);
-    }
-    let tackified = tacky::emit_tacky(analyzed, &symbols);
-    if rest_args.iter().any(|s| s == "--tacky") {
-        println!("{:?}", tackified);
-        std::process::exit(0);
-    }
-    let assembled = codegen::assemble(tackified, &symbols);
-    if rest_args.iter().any(|s| s == "--codegen") {
-        println!("{:?}", assembled);
-        std::process::exit(0);
-    }
-    let result = emit::emit(
-        assembled,
-        fs::File::create(assembly_path).expect("Error opening .s file"),
-    );
-    match result {
-        Ok(_) => (),
-        Err(err) => {
-            panic!("{:?}", err);
-        }
-    }
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    process::exit(driver::run(&args));
 }
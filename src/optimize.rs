@@ -0,0 +1,81 @@
+use crate::semantic_analysis::SymbolTable;
+use crate::tacky::{Instr, Tacky, TopLevel};
+
+pub mod copy_propagation;
+pub mod dead_store;
+pub mod fold;
+pub mod strength;
+pub mod unreachable;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Optimizations {
+    pub fold_constants: bool,
+    pub eliminate_unreachable_code: bool,
+    pub propagate_copies: bool,
+    pub eliminate_dead_stores: bool,
+    pub reduce_strength: bool,
+}
+
+impl Optimizations {
+    pub fn all() -> Self {
+        Self {
+            fold_constants: true,
+            eliminate_unreachable_code: true,
+            propagate_copies: true,
+            eliminate_dead_stores: true,
+            reduce_strength: true,
+        }
+    }
+}
+
+pub fn optimize(program: Tacky, symbols: &mut SymbolTable, optimizations: Optimizations) -> Tacky {
+    program
+        .into_iter()
+        .map(|top_level| match top_level {
+            TopLevel::TackyFunction {
+                name,
+                params,
+                instructions,
+                global,
+            } => TopLevel::TackyFunction {
+                name,
+                params,
+                instructions: optimize_function(instructions, symbols, optimizations),
+                global,
+            },
+            top_level => top_level,
+        })
+        .collect()
+}
+
+fn optimize_function(
+    mut instructions: Vec<Instr>,
+    symbols: &mut SymbolTable,
+    optimizations: Optimizations,
+) -> Vec<Instr> {
+    loop {
+        let before = instructions.clone();
+        if optimizations.reduce_strength {
+            instructions = strength::reduce_strength(instructions, symbols);
+        }
+        if optimizations.fold_constants {
+            instructions = fold::fold_constants(instructions, symbols);
+        }
+        if optimizations.eliminate_unreachable_code {
+            instructions = unreachable::eliminate_unreachable_code(instructions);
+        }
+        if optimizations.propagate_copies {
+            instructions = copy_propagation::propagate_copies(
+                instructions,
+                symbols,
+                optimizations.fold_constants,
+            );
+        }
+        if optimizations.eliminate_dead_stores {
+            instructions = dead_store::eliminate_dead_stores(instructions, symbols);
+        }
+        if instructions == before {
+            return instructions;
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::cfg::{Cfg, Node};
+use crate::optimize::fold;
+use crate::semantic_analysis::{Attrs, SymbolTable};
+use crate::tacky::{Instr, Val, type_of};
+
+// The copies reaching a point, keyed by destination. Each destination has at
+// most one, since a copy kills the earlier ones into the same variable. The
+// index from sources to destinations lets a redefinition find the copies it
+// kills without scanning them all.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Copies {
+    sources: HashMap<Val, Val>,
+    destinations: HashMap<Val, HashSet<Val>>,
+}
+
+impl Copies {
+    fn get(&self, dst: &Val) -> Option<&Val> {
+        self.sources.get(dst)
+    }
+
+    fn contains(&self, src: &Val, dst: &Val) -> bool {
+        self.get(dst) == Some(src)
+    }
+
+    fn insert(&mut self, src: Val, dst: Val) {
+        self.kill(&dst);
+        self.destinations
+            .entry(src.clone())
+            .or_default()
+            .insert(dst.clone());
+        self.sources.insert(dst, src);
+    }
+
+    fn kill(&mut self, var: &Val) {
+        if let Some(src) = self.sources.remove(var)
+            && let Some(dsts) = self.destinations.get_mut(&src)
+        {
+            dsts.remove(var);
+            if dsts.is_empty() {
+                self.destinations.remove(&src);
+            }
+        }
+        for dst in self.destinations.remove(var).unwrap_or_default() {
+            self.sources.remove(&dst);
+        }
+    }
+
+    fn intersect(&mut self, other: &Copies) {
+        self.sources
+            .retain(|dst, src| other.sources.get(dst) == Some(src));
+        self.destinations.clear();
+        for (dst, src) in &self.sources {
+            self.destinations
+                .entry(src.clone())
+                .or_default()
+                .insert(dst.clone());
+        }
+    }
+}
+
+// With `fold_constants` set, instructions whose operands all become constants are
+// folded as they're rewritten, so the resulting copies reach the rest of the
+// block in the same pass instead of one link of a chain per -O iteration.
+pub fn propagate_copies(
+    instructions: Vec<Instr>,
+    symbols: &SymbolTable,
+    fold_constants: bool,
+) -> Vec<Instr> {
+    let aliased = aliased(&instructions, symbols);
+    let mut cfg = Cfg::new(instructions);
+    let reaching = reaching_copies(&cfg, symbols, &aliased);
+
+    for (block, mut copies) in cfg.blocks.iter_mut().zip(reaching) {
+        let instructions = std::mem::take(&mut block.instructions);
+        for mut instr in instructions {
+            rewrite(&mut instr, &copies);
+            if fold_constants
+                && !matches!(
+                    instr,
+                    Instr::JumpIfZero { .. } | Instr::JumpIfNotZero { .. }
+                )
+            {
+                instr = fold::fold(instr, symbols).expect("only jumps fold away");
+            }
+            if let Instr::Copy { src, dst } = &instr
+                && (src == dst || copies.contains(src, dst) || copies.contains(dst, src))
+            {
+                continue;
+            }
+            transfer(&instr, &mut copies, symbols, &aliased);
+            block.instructions.push(instr);
+        }
+    }
+    cfg.into_instructions()
+}
+
+// Statics and variables whose address is taken can change behind the
+// function's back, through a call or a store through a pointer.
+pub fn aliased(instructions: &[Instr], symbols: &SymbolTable) -> HashSet<String> {
+    let statics = symbols
+        .iter()
+        .filter(|(_, (_, attrs))| matches!(attrs, Attrs::Static { .. }))
+        .map(|(name, _)| name.clone());
+    let address_taken = instructions.iter().filter_map(|instr| match instr {
+        Instr::GetAddress {
+            src: Val::Var(name),
+            ..
+        } => Some(name.clone()),
+        _ => None,
+    });
+    statics.chain(address_taken).collect()
+}
+
+// A block that hasn't been visited yet has no outgoing copies to speak of,
+// and stands for every copy in the function: it doesn't constrain the meet.
+fn reaching_copies(
+    cfg: &Cfg<Instr>,
+    symbols: &SymbolTable,
+    aliased: &HashSet<String>,
+) -> Vec<Copies> {
+    let count = cfg.blocks.len();
+    let mut ins = vec![Copies::default(); count];
+    let mut outs: Vec<Option<Copies>> = vec![None; count];
+    let mut worklist = (0..count).collect::<VecDeque<_>>();
+    let mut queued = vec![true; count];
+
+    while let Some(i) = worklist.pop_front() {
+        queued[i] = false;
+        let block = &cfg.blocks[i];
+        let mut meet: Option<Copies> = None;
+        for predecessor in &block.predecessors {
+            let incoming = match predecessor {
+                Node::Block(p) => match &outs[*p] {
+                    Some(out) => out,
+                    None => continue,
+                },
+                _ => &Copies::default(),
+            };
+            match &mut meet {
+                Some(copies) => copies.intersect(incoming),
+                None => meet = Some(incoming.clone()),
+            }
+        }
+        let Some(mut copies) = meet else {
+            continue;
+        };
+        ins[i] = copies.clone();
+        for instr in &block.instructions {
+            transfer(instr, &mut copies, symbols, aliased);
+        }
+        if outs[i].as_ref() != Some(&copies) {
+            outs[i] = Some(copies);
+            for successor in &block.successors {
+                if let Node::Block(s) = successor
+                    && !queued[*s]
+                {
+                    queued[*s] = true;
+                    worklist.push_back(*s);
+                }
+            }
+        }
+    }
+    ins
+}
+
+fn transfer(instr: &Instr, copies: &mut Copies, symbols: &SymbolTable, aliased: &HashSet<String>) {
+    match instr {
+        Instr::Copy { src, dst } => {
+            if copies.contains(dst, src) {
+                return;
+            }
+            copies.kill(dst);
+            // A copy between int and unsigned int reinterprets the value, so
+            // uses of dst can't read src instead.
+            if type_of(src, symbols) == type_of(dst, symbols) {
+                copies.insert(src.clone(), dst.clone());
+            }
+        }
+        Instr::Unary { dst, .. }
+        | Instr::Binary { dst, .. }
+        | Instr::SignExtend { dst, .. }
+        | Instr::Truncate { dst, .. }
+        | Instr::ZeroExtend { dst, .. }
+        | Instr::GetAddress { dst, .. }
+        | Instr::Load { dst, .. } => copies.kill(dst),
+        Instr::Call { dst, .. } => {
+            kill_aliased(copies, aliased);
+            copies.kill(dst);
+        }
+        Instr::Store { .. } => kill_aliased(copies, aliased),
+        _ => (),
+    }
+}
+
+fn kill_aliased(copies: &mut Copies, aliased: &HashSet<String>) {
+    for name in aliased {
+        copies.kill(&Val::Var(name.clone()));
+    }
+}
+
+fn rewrite(instr: &mut Instr, copies: &Copies) {
+    let replace = |val: &mut Val| {
+        if let Some(src) = copies.get(val) {
+            *val = src.clone();
+        }
+    };
+    match instr {
+        Instr::Return(val)
+        | Instr::Unary { src: val, .. }
+        | Instr::Copy { src: val, .. }
+        | Instr::SignExtend { src: val, .. }
+        | Instr::Truncate { src: val, .. }
+        | Instr::ZeroExtend { src: val, .. }
+        | Instr::JumpIfZero { condition: val, .. }
+        | Instr::JumpIfNotZero { condition: val, .. }
+        | Instr::Load { src_ptr: val, .. } => replace(val),
+        Instr::Binary { src1, src2, .. }
+        | Instr::Store {
+            src: src1,
+            dst_ptr: src2,
+        } => {
+            replace(src1);
+            replace(src2);
+        }
+        Instr::Call { params, .. } => params.iter_mut().for_each(replace),
+        // The address of a variable isn't the address of its copy.
+        Instr::GetAddress { .. } | Instr::Jump { .. } | Instr::Label(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tacky::BinaryOp;
+    use crate::tacky::text;
+    use crate::types::Const;
+
+    fn propagate(source: &str) -> Vec<Instr> {
+        let (_, instructions, symbols) = text::parse_function(source);
+        propagate_copies(instructions, &symbols, false)
+    }
+
+    fn var(name: &str) -> Val {
+        Val::Var(name.to_string())
+    }
+
+    #[test]
+    fn straight_line() {
+        let instructions = propagate(
+            "function f(a) {
+                x = a
+                y = x
+                z = y + 1
+                x = 5
+                w = x * y
+                y = x
+                return w
+            }",
+        );
+        assert_eq!(
+            instructions[2],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: var("a"),
+                src2: Val::Constant(Const::Int(1)),
+                dst: var("z"),
+            }
+        );
+        assert_eq!(
+            instructions[4],
+            Instr::Binary {
+                binop: BinaryOp::Multiply,
+                src1: Val::Constant(Const::Int(5)),
+                src2: var("a"),
+                dst: var("w"),
+            }
+        );
+        assert_eq!(instructions.len(), 7);
+    }
+
+    #[test]
+    fn redundant_copies() {
+        let instructions = propagate(
+            "function f(a) {
+                x = a
+                a = x
+                x = a
+                return x
+            }",
+        );
+        assert_eq!(
+            instructions,
+            [
+                Instr::Copy {
+                    src: var("a"),
+                    dst: var("x"),
+                },
+                Instr::Return(var("a")),
+            ]
+        );
+    }
+
+    #[test]
+    fn folds_chains() {
+        let (_, instructions, symbols) = text::parse_function(
+            "function f(a) {
+                x = 2
+                y = x * 3
+                z = y + a
+                w = y - 1
+                jump_if_zero w, end
+                return z
+              end:
+                return w
+            }",
+        );
+        let instructions = propagate_copies(instructions, &symbols, true);
+        assert_eq!(
+            instructions[2],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: Val::Constant(Const::Int(6)),
+                src2: var("a"),
+                dst: var("z"),
+            }
+        );
+        assert_eq!(
+            instructions[4],
+            Instr::JumpIfZero {
+                condition: Val::Constant(Const::Int(5)),
+                target: "end".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn merges() {
+        let instructions = propagate(
+            "function f(a) {
+                x = 1
+                y = 1
+                jump_if_zero a, other
+                y = 2
+              other:
+                z = x + y
+                return z
+            }",
+        );
+        assert_eq!(
+            instructions[5],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: Val::Constant(Const::Int(1)),
+                src2: var("y"),
+                dst: var("z"),
+            }
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let instructions = propagate(
+            "function f(a) {
+                x = 0
+              top:
+                y = x
+                x = y + 1
+                jump_if_not_zero a, top
+                return y
+            }",
+        );
+        assert_eq!(
+            instructions[2],
+            Instr::Copy {
+                src: var("x"),
+                dst: var("y"),
+            }
+        );
+        assert_eq!(
+            instructions[3],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: var("x"),
+                src2: Val::Constant(Const::Int(1)),
+                dst: var("x"),
+            }
+        );
+    }
+
+    #[test]
+    fn address_taken() {
+        let instructions = propagate(
+            "function f(a) {
+                int * p
+                x = a
+                p = get_address x
+                y = x
+                store 7, p
+                z = x + y
+                return z
+            }",
+        );
+        assert_eq!(
+            instructions[1],
+            Instr::GetAddress {
+                src: var("x"),
+                dst: var("p"),
+            }
+        );
+        assert_eq!(
+            instructions[2],
+            Instr::Copy {
+                src: var("a"),
+                dst: var("y"),
+            }
+        );
+        assert_eq!(
+            instructions[4],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: var("x"),
+                src2: var("a"),
+                dst: var("z"),
+            }
+        );
+    }
+
+    #[test]
+    fn statics_and_calls() {
+        let instructions = propagate(
+            "extern static s
+            extern function g/0
+
+            function f(a) {
+                s = a
+                t = s
+                r = call g()
+                u = s + t
+                return u
+            }",
+        );
+        assert_eq!(
+            instructions[3],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: var("s"),
+                src2: var("a"),
+                dst: var("u"),
+            }
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::cfg::{Cfg, Node};
+use crate::optimize::copy_propagation::aliased;
+use crate::semantic_analysis::SymbolTable;
+use crate::tacky::{Instr, Val};
+
+type Live<'a> = HashSet<&'a str>;
+
+pub fn eliminate_dead_stores(instructions: Vec<Instr>, symbols: &SymbolTable) -> Vec<Instr> {
+    let aliased = aliased(&instructions, symbols);
+    let aliased = aliased.iter().map(String::as_str).collect::<Live>();
+    let mut cfg = Cfg::new(instructions);
+    let live_out = liveness(&cfg, &aliased);
+
+    let mut dead = vec![];
+    for (i, (block, mut live)) in cfg.blocks.iter().zip(live_out).enumerate() {
+        for (j, instr) in block.instructions.iter().enumerate().rev() {
+            if let Some(Val::Var(dst)) = destination(instr)
+                && !matches!(instr, Instr::Call { .. })
+                && !live.contains(dst.as_str())
+            {
+                dead.push((i, j));
+                continue;
+            }
+            transfer(instr, &mut live, &aliased);
+        }
+    }
+    for (i, j) in dead {
+        cfg.blocks[i].instructions.remove(j);
+    }
+    cfg.into_instructions()
+}
+
+// Aliased variables are live wherever something could read them behind the
+// function's back: across calls and loads, and on the way out.
+fn liveness<'a>(cfg: &'a Cfg<Instr>, aliased: &Live<'a>) -> Vec<Live<'a>> {
+    let count = cfg.blocks.len();
+    let mut live_in = vec![Live::new(); count];
+    let mut live_out = vec![Live::new(); count];
+    let mut worklist = (0..count).rev().collect::<VecDeque<_>>();
+    let mut queued = vec![true; count];
+
+    while let Some(i) = worklist.pop_front() {
+        queued[i] = false;
+        let block = &cfg.blocks[i];
+        let mut live = Live::new();
+        for successor in &block.successors {
+            match successor {
+                Node::Block(s) => live.extend(&live_in[*s]),
+                _ => live.extend(aliased),
+            }
+        }
+        live_out[i] = live.clone();
+        for instr in block.instructions.iter().rev() {
+            transfer(instr, &mut live, aliased);
+        }
+        if live != live_in[i] {
+            live_in[i] = live;
+            for predecessor in &block.predecessors {
+                if let Node::Block(p) = predecessor
+                    && !queued[*p]
+                {
+                    queued[*p] = true;
+                    worklist.push_back(*p);
+                }
+            }
+        }
+    }
+    live_out
+}
+
+fn destination(instr: &Instr) -> Option<&Val> {
+    match instr {
+        Instr::Unary { dst, .. }
+        | Instr::Binary { dst, .. }
+        | Instr::Copy { dst, .. }
+        | Instr::SignExtend { dst, .. }
+        | Instr::Truncate { dst, .. }
+        | Instr::ZeroExtend { dst, .. }
+        | Instr::GetAddress { dst, .. }
+        | Instr::Load { dst, .. }
+        | Instr::Call { dst, .. } => Some(dst),
+        _ => None,
+    }
+}
+
+fn transfer<'a>(instr: &'a Instr, live: &mut Live<'a>, aliased: &Live<'a>) {
+    if let Some(Val::Var(dst)) = destination(instr) {
+        live.remove(dst.as_str());
+    }
+    let mut uses = |val: &'a Val| {
+        if let Val::Var(name) = val {
+            live.insert(name.as_str());
+        }
+    };
+    match instr {
+        Instr::Return(val)
+        | Instr::Unary { src: val, .. }
+        | Instr::Copy { src: val, .. }
+        | Instr::SignExtend { src: val, .. }
+        | Instr::Truncate { src: val, .. }
+        | Instr::ZeroExtend { src: val, .. }
+        | Instr::JumpIfZero { condition: val, .. }
+        | Instr::JumpIfNotZero { condition: val, .. } => uses(val),
+        Instr::Binary { src1, src2, .. } => {
+            uses(src1);
+            uses(src2);
+        }
+        Instr::Call { params, .. } => {
+            params.iter().for_each(uses);
+            live.extend(aliased);
+        }
+        Instr::Load { src_ptr, .. } => {
+            uses(src_ptr);
+            live.extend(aliased);
+        }
+        Instr::Store { src, dst_ptr } => {
+            uses(src);
+            uses(dst_ptr);
+        }
+        Instr::GetAddress { .. } | Instr::Jump { .. } | Instr::Label(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tacky::TopLevel;
+    use crate::tacky::text;
+
+    fn eliminate(source: &str) -> String {
+        let (params, instructions, symbols) = text::parse_function(source);
+        let function = TopLevel::TackyFunction {
+            name: "f".to_string(),
+            params,
+            instructions: eliminate_dead_stores(instructions, &symbols),
+            global: false,
+        };
+        function.to_string()
+    }
+
+    #[test]
+    fn dead_stores() {
+        let result = eliminate(
+            "function f(a) {
+                x = a + 1
+                y = x * 2
+                x = 3
+                z = call f(x)
+                return a
+            }",
+        );
+        assert_eq!(
+            result,
+            "function f(a) {
+    x = 3
+    z = call f(x)
+    return a
+}
+"
+        );
+    }
+
+    #[test]
+    fn address_taken() {
+        let result = eliminate(
+            "function f(a) {
+                int * p
+                x = 1
+                p = get_address x
+                y = load p
+                x = 2
+                z = 3
+                return y
+            }",
+        );
+        assert_eq!(
+            result,
+            "function f(a) {
+    x = 1
+    p = get_address x
+    y = load p
+    x = 2
+    return y
+}
+"
+        );
+    }
+
+    #[test]
+    fn loops_and_statics() {
+        let result = eliminate(
+            "extern static s
+            extern function g/0
+
+            function f(a) {
+                i = 0
+                t = 5
+                s = 1
+                u = 2
+              top:
+                i = i + 1
+                jump_if_zero i, top
+                s = t
+                u = call g()
+                s = 4
+                return i
+            }",
+        );
+        assert_eq!(
+            result,
+            "function f(a) {
+    i = 0
+    t = 5
+  top:
+    i = i + 1
+    jump_if_zero i, top
+    s = t
+    u = call g()
+    s = 4
+    return i
+}
+"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::semantic_analysis::SymbolTable;
+use crate::tacky::{Instr, Val, type_of};
+
+pub fn fold_constants(instructions: Vec<Instr>, symbols: &SymbolTable) -> Vec<Instr> {
+    instructions
+        .into_iter()
+        .filter_map(|instr| fold(instr, symbols))
+        .collect()
+}
+
+pub fn fold(instr: Instr, symbols: &SymbolTable) -> Option<Instr> {
+    match instr {
+        Instr::Unary {
+            unop,
+            src: Val::Constant(src),
+            dst,
+        } => Some(Instr::Copy {
+            src: Val::Constant(unop.evaluate(src)),
+            dst,
+        }),
+        Instr::Binary {
+            binop,
+            src1: Val::Constant(lhs),
+            src2: Val::Constant(rhs),
+            dst,
+        } => match binop.evaluate(lhs, rhs) {
+            Some(result) => Some(Instr::Copy {
+                src: Val::Constant(result),
+                dst,
+            }),
+            None => Some(Instr::Binary {
+                binop,
+                src1: Val::Constant(lhs),
+                src2: Val::Constant(rhs),
+                dst,
+            }),
+        },
+        Instr::SignExtend {
+            src: Val::Constant(src),
+            dst,
+        }
+        | Instr::Truncate {
+            src: Val::Constant(src),
+            dst,
+        }
+        | Instr::ZeroExtend {
+            src: Val::Constant(src),
+            dst,
+        } => Some(Instr::Copy {
+            src: Val::Constant(src.convert(&type_of(&dst, symbols))),
+            dst,
+        }),
+        // Casts between int and unsigned int are copies; retyping the
+        // constant lets later passes treat it like any other of dst's type.
+        Instr::Copy {
+            src: Val::Constant(src),
+            dst,
+        } if type_of(&dst, symbols).size() == src.ty().size() => Some(Instr::Copy {
+            src: Val::Constant(src.convert(&type_of(&dst, symbols))),
+            dst,
+        }),
+        Instr::JumpIfZero {
+            condition: Val::Constant(n),
+            target,
+        } => n.is_zero().then_some(Instr::Jump { target }),
+        Instr::JumpIfNotZero {
+            condition: Val::Constant(n),
+            target,
+        } => (!n.is_zero()).then_some(Instr::Jump { target }),
+        instr => Some(instr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tacky::BinaryOp;
+    use crate::tacky::text;
+    use crate::types::Const;
+
+    fn fold_text(body: &str) -> Vec<Instr> {
+        let source = format!("global function main() {{\n{}\n}}\n", body);
+        let (_, instructions, symbols) = text::parse_function(&source);
+        fold_constants(instructions, &symbols)
+    }
+
+    fn copy(dst: &str, n: Const) -> Instr {
+        Instr::Copy {
+            src: Val::Constant(n),
+            dst: Val::Var(dst.to_string()),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let folded = fold_text(
+            "long k
+             a = 2147483647 + 1
+             b = - -2147483648
+             c = 1 << 33
+             d = -17 >> 2
+             e = -7 % 2
+             f = ! 0
+             g = 3 <= 2
+             h = 9223372036854775807L + 1L
+             i = 1L << 33
+             j = 3L > 2L
+             k = sign_extend -1
+             l = truncate 4294967298L
+             return g",
+        );
+        assert_eq!(
+            folded[..12],
+            [
+                copy("a", Const::Int(i32::MIN)),
+                copy("b", Const::Int(i32::MIN)),
+                copy("c", Const::Int(2)),
+                copy("d", Const::Int(-5)),
+                copy("e", Const::Int(-1)),
+                copy("f", Const::Int(1)),
+                copy("g", Const::Int(0)),
+                copy("h", Const::Long(i64::MIN)),
+                copy("i", Const::Long(1 << 33)),
+                copy("j", Const::Int(1)),
+                copy("k", Const::Long(-1)),
+                copy("l", Const::Int(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsigned_arithmetic() {
+        let folded = fold_text(
+            "unsigned int a, b, c, d, g
+             unsigned long e, f
+             long h
+             a = 4294967295U / 2U
+             b = 4294967295U >> 28
+             c = 0U - 1U
+             d = -1
+             e = zero_extend 4294967295U
+             f = sign_extend -1
+             g = truncate 18446744073709551615UL
+             h = 18446744073709551615UL
+             i = 4294967295U > 1U
+             j = 18446744073709551615UL % 10UL
+             return i",
+        );
+        assert_eq!(
+            folded[..10],
+            [
+                copy("a", Const::UInt(i32::MAX as u32)),
+                copy("b", Const::UInt(15)),
+                copy("c", Const::UInt(u32::MAX)),
+                copy("d", Const::UInt(u32::MAX)),
+                copy("e", Const::ULong(u32::MAX as u64)),
+                copy("f", Const::ULong(u64::MAX)),
+                copy("g", Const::UInt(u32::MAX)),
+                copy("h", Const::Long(-1)),
+                copy("i", Const::Int(1)),
+                copy("j", Const::ULong(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn traps_are_kept() {
+        let folded = fold_text(
+            "a = 1 / 0
+             b = -2147483648 / -1
+             c = a % 0
+             return c",
+        );
+        assert!(matches!(
+            folded[0],
+            Instr::Binary {
+                binop: BinaryOp::Divide,
+                ..
+            }
+        ));
+        assert!(matches!(folded[1], Instr::Binary { .. }));
+        assert!(matches!(folded[2], Instr::Binary { .. }));
+    }
+
+    #[test]
+    fn branches() {
+        let folded = fold_text(
+            "jump_if_zero 0, a
+             jump_if_zero 1, a
+             jump_if_not_zero 5, a
+             jump_if_not_zero 0, a
+             jump_if_zero x, a
+           a:
+             return 0",
+        );
+        assert_eq!(
+            folded,
+            [
+                Instr::Jump {
+                    target: "a".to_string()
+                },
+                Instr::Jump {
+                    target: "a".to_string()
+                },
+                Instr::JumpIfZero {
+                    condition: Val::Var("x".to_string()),
+                    target: "a".to_string()
+                },
+                Instr::Label("a".to_string()),
+                Instr::Return(Val::Constant(Const::Int(0))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashSet;
+
+use crate::codegen::division;
+use crate::semantic_analysis::{Attrs, SymbolTable};
+use crate::tacky::{BinaryOp, Instr, UnaryOp, Val};
+use crate::types::{Const, Type};
+
+// Division and remainder of ints by constants become the sequences codegen
+// uses for them: shifts and masks for powers of two, and a multiplication by
+// a magic number otherwise. Dividing by zero or -1 is left alone since it may
+// trap. The temporaries are added to the symbol table.
+pub fn reduce_strength(instructions: Vec<Instr>, symbols: &mut SymbolTable) -> Vec<Instr> {
+    let mut names = HashSet::new();
+    for instr in &instructions {
+        let vals = match instr {
+            Instr::Return(val)
+            | Instr::JumpIfZero { condition: val, .. }
+            | Instr::JumpIfNotZero { condition: val, .. } => vec![val],
+            Instr::Unary { src, dst, .. }
+            | Instr::Copy { src, dst }
+            | Instr::SignExtend { src, dst }
+            | Instr::Truncate { src, dst }
+            | Instr::ZeroExtend { src, dst }
+            | Instr::GetAddress { src, dst }
+            | Instr::Load { src_ptr: src, dst }
+            | Instr::Store { src, dst_ptr: dst } => vec![src, dst],
+            Instr::Binary {
+                src1, src2, dst, ..
+            } => vec![src1, src2, dst],
+            Instr::Call { params, dst, .. } => params.iter().chain([dst]).collect(),
+            Instr::Jump { .. } | Instr::Label(_) => vec![],
+        };
+        for val in vals {
+            if let Val::Var(name) = val {
+                names.insert(name.clone());
+            }
+        }
+    }
+    let mut next = 0;
+    let mut fresh = |symbols: &mut SymbolTable, ty: Type| loop {
+        let name = format!("div.{}", next);
+        next += 1;
+        if !names.contains(&name) && !symbols.contains_key(&name) {
+            symbols.insert(name.clone(), (ty, Attrs::Local));
+            return Val::Var(name);
+        }
+    };
+    let binary = |binop, src1: &Val, src2: Val, dst: &Val| Instr::Binary {
+        binop,
+        src1: src1.clone(),
+        src2,
+        dst: dst.clone(),
+    };
+
+    let mut reduced = Vec::with_capacity(instructions.len());
+    for instr in instructions {
+        let Instr::Binary {
+            binop: binop @ (BinaryOp::Divide | BinaryOp::Remainder),
+            src1,
+            src2: Val::Constant(Const::Int(divisor)),
+            dst,
+        } = instr
+        else {
+            reduced.push(instr);
+            continue;
+        };
+        let magnitude = divisor.unsigned_abs();
+        if divisor == 1 {
+            let src = match binop {
+                BinaryOp::Divide => src1,
+                _ => Val::Constant(Const::Int(0)),
+            };
+            reduced.push(Instr::Copy { src, dst });
+            continue;
+        }
+        if divisor == 0 || divisor == -1 {
+            reduced.push(Instr::Binary {
+                binop,
+                src1,
+                src2: Val::Constant(Const::Int(divisor)),
+                dst,
+            });
+            continue;
+        }
+        if !magnitude.is_power_of_two() {
+            // The high half of codegen's 32-bit multiply, with its correction
+            // for multipliers that overflow an int folded into a long one.
+            let (multiplier, shift) = division::magic(divisor);
+            let multiplier = match multiplier as i64 {
+                m if divisor > 0 && m < 0 => m + (1 << 32),
+                m if divisor < 0 && m > 0 => m - (1 << 32),
+                m => m,
+            };
+            let (wide, product, high) = (
+                fresh(symbols, Type::Long),
+                fresh(symbols, Type::Long),
+                fresh(symbols, Type::Long),
+            );
+            let (truncated, sign) = (fresh(symbols, Type::Int), fresh(symbols, Type::Int));
+            let quotient = match binop {
+                BinaryOp::Divide => dst.clone(),
+                _ => fresh(symbols, Type::Int),
+            };
+            reduced.extend([
+                Instr::SignExtend {
+                    src: src1.clone(),
+                    dst: wide.clone(),
+                },
+                binary(
+                    BinaryOp::Multiply,
+                    &wide,
+                    Val::Constant(Const::Long(multiplier)),
+                    &product,
+                ),
+                binary(
+                    BinaryOp::ShiftRight,
+                    &product,
+                    Val::Constant(Const::Int(32 + shift)),
+                    &high,
+                ),
+                Instr::Truncate {
+                    src: high,
+                    dst: truncated.clone(),
+                },
+                binary(
+                    BinaryOp::ShiftRight,
+                    &truncated,
+                    Val::Constant(Const::Int(31)),
+                    &sign,
+                ),
+                binary(BinaryOp::Subtract, &truncated, sign, &quotient),
+            ]);
+            if binop == BinaryOp::Remainder {
+                let multiple = fresh(symbols, Type::Int);
+                reduced.extend([
+                    binary(
+                        BinaryOp::Multiply,
+                        &quotient,
+                        Val::Constant(Const::Int(divisor)),
+                        &multiple,
+                    ),
+                    binary(BinaryOp::Subtract, &src1, multiple, &dst),
+                ]);
+            }
+            continue;
+        }
+
+        let (sign, bias, biased) = (
+            fresh(symbols, Type::Int),
+            fresh(symbols, Type::Int),
+            fresh(symbols, Type::Int),
+        );
+        reduced.extend([
+            binary(
+                BinaryOp::ShiftRight,
+                &src1,
+                Val::Constant(Const::Int(31)),
+                &sign,
+            ),
+            binary(
+                BinaryOp::BitAnd,
+                &sign,
+                Val::Constant(Const::Int((magnitude - 1) as i32)),
+                &bias,
+            ),
+            binary(BinaryOp::Add, &src1, bias.clone(), &biased),
+        ]);
+        let shift = Val::Constant(Const::Int(magnitude.trailing_zeros() as i32));
+        match binop {
+            BinaryOp::Divide if divisor > 0 => {
+                reduced.push(binary(BinaryOp::ShiftRight, &biased, shift, &dst));
+            }
+            BinaryOp::Divide => {
+                let quotient = fresh(symbols, Type::Int);
+                reduced.extend([
+                    binary(BinaryOp::ShiftRight, &biased, shift, &quotient),
+                    Instr::Unary {
+                        unop: UnaryOp::Negate,
+                        src: quotient,
+                        dst,
+                    },
+                ]);
+            }
+            _ => {
+                let rounded = fresh(symbols, Type::Int);
+                reduced.extend([
+                    binary(
+                        BinaryOp::BitAnd,
+                        &biased,
+                        Val::Constant(Const::Int(magnitude.wrapping_neg() as i32)),
+                        &rounded,
+                    ),
+                    binary(BinaryOp::Subtract, &src1, rounded, &dst),
+                ]);
+            }
+        }
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tacky::{TopLevel, interp};
+
+    fn var(name: &str) -> Val {
+        Val::Var(name.to_string())
+    }
+
+    // Computes `x / d` or `x % d` for each dividend in one reduced function,
+    // writing every result out byte by byte through putchar.
+    fn evaluate(binop: BinaryOp, dividends: &[i32], divisor: i32) -> Option<Vec<i32>> {
+        let mut instructions = vec![];
+        for &x in dividends {
+            instructions.extend([
+                Instr::Copy {
+                    src: Val::Constant(Const::Int(x)),
+                    dst: var("x"),
+                },
+                Instr::Binary {
+                    binop,
+                    src1: var("x"),
+                    src2: Val::Constant(Const::Int(divisor)),
+                    dst: var("y"),
+                },
+            ]);
+            for shift in [0, 8, 16, 24] {
+                instructions.extend([
+                    Instr::Binary {
+                        binop: BinaryOp::ShiftRight,
+                        src1: var("y"),
+                        src2: Val::Constant(Const::Int(shift)),
+                        dst: var("byte"),
+                    },
+                    Instr::Call {
+                        name: "putchar".to_string(),
+                        params: vec![var("byte")],
+                        dst: var("ignored"),
+                    },
+                ]);
+            }
+        }
+        instructions.push(Instr::Return(Val::Constant(Const::Int(0))));
+        let mut symbols = SymbolTable::new();
+        let main = TopLevel::TackyFunction {
+            name: "main".to_string(),
+            params: vec![],
+            instructions: reduce_strength(instructions, &mut symbols),
+            global: true,
+        };
+
+        let mut out = vec![];
+        interp::run(
+            &[(vec![main], symbols)],
+            interp::DEFAULT_MAX_DEPTH,
+            &mut out,
+        )
+        .ok()?;
+        Some(
+            out.chunks(4)
+                .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn matches_i32() {
+        let mut divisors = (-100..=100)
+            .filter(|d| ![0, -1].contains(d))
+            .collect::<Vec<_>>();
+        for k in 7..31 {
+            for d in [(1 << k) - 1, 1 << k, (1 << k) + 1] {
+                divisors.extend([d, -d]);
+            }
+        }
+        divisors.extend([i32::MAX, i32::MIN, i32::MIN + 1, 1_000_000_007]);
+
+        let mut dividends = vec![i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX];
+        dividends.extend(-3..=3);
+        let mut state = 0x2545_f491_u32;
+        for _ in 0..8 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            dividends.push(state as i32);
+        }
+
+        for divisor in divisors {
+            let mut around = dividends.clone();
+            for q in [1, 2, 1000] {
+                for n in [divisor.wrapping_mul(q), divisor.wrapping_mul(-q)] {
+                    around.extend([n.wrapping_sub(1), n, n.wrapping_add(1)]);
+                }
+            }
+            for binop in [BinaryOp::Divide, BinaryOp::Remainder] {
+                let expected = around
+                    .iter()
+                    .map(|&x| match binop {
+                        BinaryOp::Divide => x.checked_div(divisor),
+                        _ => x.checked_rem(divisor),
+                    })
+                    .collect::<Option<Vec<_>>>();
+                assert_eq!(
+                    evaluate(binop, &around, divisor),
+                    expected,
+                    "{:?} by {}",
+                    binop,
+                    divisor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn traps_are_kept() {
+        let instr = |divisor| Instr::Binary {
+            binop: BinaryOp::Divide,
+            src1: Val::Var("x".to_string()),
+            src2: Val::Constant(Const::Int(divisor)),
+            dst: Val::Var("y".to_string()),
+        };
+        for divisor in [0, -1] {
+            let kept = reduce_strength(vec![instr(divisor)], &mut SymbolTable::new());
+            assert_eq!(kept, [instr(divisor)]);
+        }
+        assert_eq!(evaluate(BinaryOp::Divide, &[i32::MIN], -1), None);
+        assert_eq!(evaluate(BinaryOp::Remainder, &[7], 0), None);
+        assert_eq!(evaluate(BinaryOp::Divide, &[-7, 7], -1), Some(vec![7, -7]));
+    }
+
+    #[test]
+    fn magic_numbers() {
+        let mut symbols = SymbolTable::new();
+        let reduced = reduce_strength(
+            vec![Instr::Binary {
+                binop: BinaryOp::Remainder,
+                src1: Val::Var("x".to_string()),
+                src2: Val::Constant(Const::Int(10)),
+                dst: Val::Var("y".to_string()),
+            }],
+            &mut symbols,
+        );
+        assert!(!reduced.iter().any(|instr| matches!(
+            instr,
+            Instr::Binary {
+                binop: BinaryOp::Divide | BinaryOp::Remainder,
+                ..
+            }
+        )));
+        assert!(matches!(reduced[0], Instr::SignExtend { .. }));
+        assert_eq!(symbols["div.0"].0, Type::Long);
+    }
+}
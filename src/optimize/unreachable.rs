@@ -0,0 +1,10 @@
+use crate::cfg::Cfg;
+use crate::tacky::Instr;
+
+pub fn eliminate_unreachable_code(instructions: Vec<Instr>) -> Vec<Instr> {
+    let mut cfg = Cfg::new(instructions);
+    cfg.remove_unreachable_blocks();
+    cfg.remove_redundant_jumps();
+    cfg.remove_unused_labels();
+    cfg.into_instructions()
+}
@@ -1,4 +1,6 @@
+use crate::diagnostic::{Diagnostic, ErrorKind, Result, Span};
 use crate::lexer::{Lexer, Token, TokenKind};
+use crate::types::{Const, Type};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum UnaryOperator {
@@ -57,8 +59,15 @@ pub enum Crement {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expression {
-    Constant(i32),
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+    pub ty: Option<Type>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExpressionKind {
+    Constant(Const),
     Unary(UnaryOperator, Box<Expression>),
     Binary(BinaryOperator, Box<Expression>, Box<Expression>),
     Compound(CompoundOperator, Box<Expression>, Box<Expression>),
@@ -67,10 +76,19 @@ pub enum Expression {
     Assign(Box<Expression>, Box<Expression>),
     Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
     Call(String, Vec<Expression>),
+    Cast(Type, Box<Expression>),
+    AddressOf(Box<Expression>),
+    Dereference(Box<Expression>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatementKind {
     Return(Expression),
     Exp(Expression),
     If(Expression, Box<Statement>, Option<Box<Statement>>),
@@ -82,7 +100,7 @@ pub enum Statement {
     While(String, Expression, Box<Statement>),
     For(
         String,
-        ForInit,
+        Box<ForInit>,
         Option<Expression>,
         Option<Expression>,
         Box<Statement>,
@@ -97,11 +115,12 @@ pub enum Statement {
     Case(String, Expression, Box<Statement>),
     Default(String, Box<Statement>),
     Null,
+    Error,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum CaseInfo {
-    Case { expr: i32, label: String },
+    Case { expr: Const, label: String },
     Default { label: String },
 }
 
@@ -118,6 +137,12 @@ pub enum Declaration {
     Func(Function),
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub declarations: Vec<Declaration>,
+    pub errors: Vec<Diagnostic>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum BlockItem {
     S(Statement),
@@ -127,16 +152,20 @@ pub enum BlockItem {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Var {
     pub name: String,
+    pub ty: Type,
     pub init: Option<Expression>,
     pub storage: Option<StorageClass>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Function {
     pub name: String,
     pub params: Vec<String>,
+    pub ty: Type,
     pub body: Option<Vec<BlockItem>>,
     pub storage: Option<StorageClass>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -145,6 +174,49 @@ pub enum StorageClass {
     Extern,
 }
 
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            ty: None,
+        }
+    }
+
+    pub fn typed(kind: ExpressionKind, span: Span, ty: Type) -> Self {
+        Self {
+            kind,
+            span,
+            ty: Some(ty),
+        }
+    }
+
+    // Only the type checker's output has types.
+    pub fn ty(&self) -> &Type {
+        self.ty.as_ref().expect("expression has been type checked")
+    }
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Program {
+    pub fn is_erroneous(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn into_result(self) -> std::result::Result<Vec<Declaration>, Vec<Diagnostic>> {
+        if self.is_erroneous() {
+            Err(self.errors)
+        } else {
+            Ok(self.declarations)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
 enum Prec {
     Bottom,
@@ -170,331 +242,584 @@ pub struct Parser<'a> {
     tokens: Lexer<'a>,
     current_token: Option<Token<'a>>,
     next_token: Option<Token<'a>>,
+    previous_end: usize,
+    errors: Vec<Diagnostic>,
 }
 
 const UNLABELLED: &str = "unlabelled";
 
 impl<'a> Parser<'a> {
-    pub fn new(mut tokens: Lexer<'a>) -> Self {
-        let current_token = tokens.next();
-        let next_token = tokens.next();
+    pub fn new(tokens: Lexer<'a>) -> Self {
         Self {
             tokens,
-            current_token,
-            next_token,
+            current_token: None,
+            next_token: None,
+            previous_end: 0,
+            errors: vec![],
         }
     }
 
-    pub fn advance(&mut self) -> Token<'a> {
+    pub fn advance(&mut self) {
+        if let Some(t) = self.current_token {
+            self.previous_end = t.end;
+        }
         self.current_token = self.next_token;
-        self.next_token = self.tokens.next();
-        self.current_token.unwrap_or(Token {
-            kind: TokenKind::Eof,
-            start: 0,
-            end: 0,
-        })
+        self.next_token = loop {
+            match self.tokens.next() {
+                Some(Err(diagnostic)) => self.errors.push(diagnostic),
+                Some(Ok(token)) => break Some(token),
+                None => break None,
+            }
+        };
     }
 
-    pub fn current(&mut self) -> Token<'a> {
+    pub fn current(&self) -> Token<'a> {
         match self.current_token {
-            None => Token {
-                kind: TokenKind::Eof,
-                start: 0,
-                end: 0,
-            },
+            None => self.eof(),
             Some(t) => t,
         }
     }
 
-    pub fn next(&mut self) -> Token<'a> {
+    pub fn next(&self) -> Token<'a> {
         match self.next_token {
-            None => Token {
-                kind: TokenKind::Eof,
-                start: 0,
-                end: 0,
-            },
+            None => self.eof(),
             Some(t) => t,
         }
     }
 
-    fn consume(&mut self, kind: TokenKind) {
+    fn eof(&self) -> Token<'a> {
+        Token {
+            kind: TokenKind::Eof,
+            start: self.previous_end,
+            end: self.previous_end,
+        }
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.previous_end,
+        }
+    }
+
+    fn unexpected(token: Token, message: String) -> Diagnostic {
+        let kind = if token.kind == TokenKind::Eof {
+            ErrorKind::UnexpectedEof
+        } else {
+            ErrorKind::UnexpectedToken
+        };
+        Diagnostic::error(kind, message).with_span(token.span())
+    }
+
+    fn consume(&mut self, kind: TokenKind) -> Result<()> {
         match self.current() {
             t if t.kind == kind => {
                 self.advance();
+                Ok(())
             }
-            t => panic!("Expected {:?}, got {:?}", kind, t),
+            t => Err(Self::unexpected(
+                t,
+                format!("Expected {:?}, got {:?}", kind, t.kind),
+            )),
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Declaration> {
-        let mut decls = vec![];
+    pub fn parse(&mut self) -> Program {
+        self.advance();
+        self.advance();
+
+        let mut declarations = vec![];
         while self.current().kind != TokenKind::Eof {
-            decls.push(self.declaration())
+            let start = self.current().start;
+            match self.declaration() {
+                Ok(declaration) => declarations.push(declaration),
+                Err(diagnostic) => self.recover(diagnostic, start, true),
+            }
         }
 
-        decls
+        Program {
+            declarations,
+            errors: std::mem::take(&mut self.errors),
+        }
+    }
+
+    fn recover(&mut self, diagnostic: Diagnostic, start: usize, top_level: bool) {
+        if self.errors.last().map(|d| d.span) != Some(diagnostic.span) {
+            self.errors.push(diagnostic);
+        }
+        self.synchronize(top_level);
+        let current = self.current();
+        if current.start == start && current.kind != TokenKind::Eof {
+            self.advance();
+        }
+    }
+
+    fn synchronize(&mut self, top_level: bool) {
+        loop {
+            match self.current() {
+                Token {
+                    kind: TokenKind::Eof,
+                    ..
+                } => return,
+                Token {
+                    kind: TokenKind::Semicolon,
+                    ..
+                } => {
+                    self.advance();
+                    return;
+                }
+                Token {
+                    kind: TokenKind::RBrace,
+                    ..
+                } => {
+                    if top_level {
+                        self.advance();
+                    }
+                    return;
+                }
+                Token {
+                    kind: TokenKind::LBrace,
+                    ..
+                } => {
+                    self.skip_braces();
+                    return;
+                }
+                t if Self::is_specifier(t) => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn skip_braces(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current().kind {
+                TokenKind::Eof => return,
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => (),
+            }
+            self.advance();
+        }
     }
 
-    fn block(&mut self) -> Vec<BlockItem> {
-        self.consume(TokenKind::LBrace);
+    fn block(&mut self) -> Result<Vec<BlockItem>> {
+        self.consume(TokenKind::LBrace)?;
 
         let mut block_items = Vec::new();
 
-        while self.current().kind != TokenKind::RBrace {
-            let item = self.block_item();
-            block_items.push(item);
+        while !matches!(self.current().kind, TokenKind::RBrace | TokenKind::Eof) {
+            let start = self.current().start;
+            match self.block_item() {
+                Ok(item) => block_items.push(item),
+                Err(diagnostic) => {
+                    self.recover(diagnostic, start, false);
+                    let error = Statement::new(StatementKind::Error, self.span_from(start));
+                    block_items.push(BlockItem::S(error));
+                }
+            }
         }
 
-        self.consume(TokenKind::RBrace);
-        block_items
+        self.consume(TokenKind::RBrace)?;
+        Ok(block_items)
     }
 
-    fn name(&mut self) -> String {
-        match self.current().kind {
-            TokenKind::Id(id) => {
+    fn name(&mut self) -> Result<String> {
+        match self.current() {
+            Token {
+                kind: TokenKind::Id(id),
+                ..
+            } => {
                 self.advance();
-                id.to_string()
+                Ok(id.to_string())
             }
-            t => panic!("Expected identifier, got {:?}", t),
+            t => Err(Self::unexpected(
+                t,
+                format!("Expected identifier, got {:?}", t.kind),
+            )),
         }
     }
 
-    fn declaration(&mut self) -> Declaration {
+    fn declaration(&mut self) -> Result<Declaration> {
         let mut storage_and_type = vec![];
         while Self::is_specifier(self.current()) {
             storage_and_type.push(self.current());
             self.advance();
         }
 
-        let storage = Self::storage_class(storage_and_type);
+        let (ty, storage) = Self::specifiers(storage_and_type, self.current())?;
+        let ty = self.pointers(ty);
         if self.next().kind == TokenKind::LParen {
-            Declaration::Func(self.func_declaration(storage))
+            Ok(Declaration::Func(self.func_declaration(ty, storage)?))
         } else {
-            Declaration::Var(self.var_declaration(storage))
+            Ok(Declaration::Var(self.var_declaration(ty, storage)?))
         }
     }
 
-    fn func_declaration(&mut self, storage: Option<StorageClass>) -> Function {
-        let name = self.name();
-        self.consume(TokenKind::LParen);
-        let params = self.param_list();
-        self.consume(TokenKind::RParen);
+    fn func_declaration(&mut self, ret: Type, storage: Option<StorageClass>) -> Result<Function> {
+        let span = self.current().span();
+        let name = self.name()?;
+        self.consume(TokenKind::LParen)?;
+        let (params, param_types) = self.param_list()?;
+        self.consume(TokenKind::RParen)?;
         let body = if self.current().kind == TokenKind::LBrace {
-            Some(self.block())
+            Some(self.block()?)
         } else {
-            self.consume(TokenKind::Semicolon);
+            self.consume(TokenKind::Semicolon)?;
             None
         };
 
-        Function {
+        Ok(Function {
             name,
             body,
             params,
+            ty: Type::Fun {
+                params: param_types,
+                ret: Box::new(ret),
+            },
             storage,
-        }
+            span,
+        })
     }
 
-    fn var_declaration(&mut self, storage: Option<StorageClass>) -> Var {
-        let name = self.name();
-        let init = match self.current().kind {
-            TokenKind::Equals => {
-                self.consume(TokenKind::Equals);
-                Some(self.expression(Prec::Bottom))
+    fn var_declaration(&mut self, ty: Type, storage: Option<StorageClass>) -> Result<Var> {
+        let span = self.current().span();
+        let name = self.name()?;
+        let init = match self.current() {
+            Token {
+                kind: TokenKind::Equals,
+                ..
+            } => {
+                self.consume(TokenKind::Equals)?;
+                Some(self.expression(Prec::Bottom)?)
+            }
+            Token {
+                kind: TokenKind::Semicolon,
+                ..
+            } => None,
+            t => {
+                return Err(Self::unexpected(
+                    t,
+                    format!("Expected assignment or ;, got {:?}", t.kind),
+                ));
             }
-            TokenKind::Semicolon => None,
-            kind => panic!("Expected assignment or ;, got {:?}", kind),
         };
 
-        self.consume(TokenKind::Semicolon);
-        Var {
+        self.consume(TokenKind::Semicolon)?;
+        Ok(Var {
             name,
+            ty,
             init,
             storage,
-        }
+            span,
+        })
     }
 
-    fn storage_class(specifiers: Vec<Token>) -> Option<StorageClass> {
+    fn specifiers(specifiers: Vec<Token>, after: Token) -> Result<(Type, Option<StorageClass>)> {
         let mut storage_classes = vec![];
-        let mut has_type = false;
-        for specifier in specifiers {
+        let mut types = vec![];
+        for specifier in specifiers.iter() {
             match specifier.kind {
-                TokenKind::Int => has_type = true,
+                TokenKind::Int | TokenKind::Long | TokenKind::Signed | TokenKind::Unsigned => {
+                    types.push(*specifier)
+                }
                 TokenKind::Static | TokenKind::Extern => storage_classes.push(specifier.kind),
-                _ => panic!("Bad declaration specifier {:?}", specifier),
+                _ => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidSpecifiers,
+                        format!(
+                            "Bad declaration specifier '{}'",
+                            Self::spell_specifiers(&[*specifier])
+                        ),
+                    )
+                    .with_span(specifier.span()));
+                }
             }
         }
 
-        if !has_type {
-            panic!("Missing type specifier");
-        }
+        let span = specifiers.first().unwrap_or(&after).span();
+        let ty = Self::type_specifiers(&types, span)?;
 
         match &storage_classes[..] {
-            [] => None,
-            [TokenKind::Extern] => Some(StorageClass::Extern),
-            [TokenKind::Static] => Some(StorageClass::Static),
-            l => panic!("Too many storage classes {:?}", l),
+            [] => Ok((ty, None)),
+            [TokenKind::Extern] => Ok((ty, Some(StorageClass::Extern))),
+            [TokenKind::Static] => Ok((ty, Some(StorageClass::Static))),
+            _ => Err(Diagnostic::error(
+                ErrorKind::InvalidSpecifiers,
+                format!(
+                    "Too many storage classes in '{}'",
+                    Self::spell_specifiers(&specifiers)
+                ),
+            )
+            .with_span(Self::specifiers_span(&specifiers))),
+        }
+    }
+
+    // `span` is where the specifiers should have been if there are none.
+    fn type_specifiers(types: &[Token], span: Span) -> Result<Type> {
+        let kinds = types.iter().map(|t| t.kind).collect::<Vec<_>>();
+        if kinds.is_empty() {
+            return Err(
+                Diagnostic::error(ErrorKind::InvalidSpecifiers, "Missing type specifier")
+                    .with_span(span),
+            );
+        }
+        let has = |kind| kinds.contains(&kind);
+        let repeated = (1..kinds.len()).any(|i| kinds[..i].contains(&kinds[i]));
+        if repeated || (has(TokenKind::Signed) && has(TokenKind::Unsigned)) {
+            return Err(Diagnostic::error(
+                ErrorKind::InvalidSpecifiers,
+                format!(
+                    "Invalid type specifier combination '{}'",
+                    Self::spell_specifiers(types)
+                ),
+            )
+            .with_span(Self::specifiers_span(types)));
+        }
+        Ok(match (has(TokenKind::Unsigned), has(TokenKind::Long)) {
+            (false, false) => Type::Int,
+            (false, true) => Type::Long,
+            (true, false) => Type::UInt,
+            (true, true) => Type::ULong,
+        })
+    }
+
+    fn spell_specifiers(specifiers: &[Token]) -> String {
+        let keywords = specifiers.iter().map(|t| match t.kind {
+            TokenKind::Int => "int",
+            TokenKind::Long => "long",
+            TokenKind::Signed => "signed",
+            TokenKind::Unsigned => "unsigned",
+            TokenKind::Static => "static",
+            TokenKind::Extern => "extern",
+            _ => "?",
+        });
+        keywords.collect::<Vec<_>>().join(" ")
+    }
+
+    fn specifiers_span(specifiers: &[Token]) -> Span {
+        Span {
+            start: specifiers[0].start,
+            end: specifiers[specifiers.len() - 1].end,
         }
     }
 
-    fn param_list(&mut self) -> Vec<String> {
+    // Each `*` before the declared name wraps the type in another pointer.
+    fn pointers(&mut self, mut ty: Type) -> Type {
+        while self.current().kind == TokenKind::Star {
+            self.advance();
+            ty = Type::Pointer(Box::new(ty));
+        }
+        ty
+    }
+
+    fn param_list(&mut self) -> Result<(Vec<String>, Vec<Type>)> {
         let mut params = vec![];
+        let mut types = vec![];
         if self.current().kind == TokenKind::Void {
-            self.consume(TokenKind::Void);
-            return params;
+            self.consume(TokenKind::Void)?;
+            return Ok((params, types));
         }
 
         while {
-            self.consume(TokenKind::Int);
-            let name = self.name();
+            let start = self.current();
+            let mut specifiers = vec![];
+            while Self::is_type_specifier(self.current()) {
+                specifiers.push(self.current());
+                self.advance();
+            }
+            let ty = Self::type_specifiers(&specifiers, start.span())?;
+            types.push(self.pointers(ty));
+            let name = self.name()?;
             params.push(name.clone());
 
             let comma = self.current().kind == TokenKind::Comma;
             if comma {
-                self.consume(TokenKind::Comma);
+                self.consume(TokenKind::Comma)?;
             }
             comma
         } {}
 
-        params
+        Ok((params, types))
     }
 
-    fn block_item(&mut self) -> BlockItem {
+    fn block_item(&mut self) -> Result<BlockItem> {
         match self.current() {
-            t if Self::is_specifier(t) => BlockItem::D(self.declaration()),
-            Token {
+            t if Self::is_specifier(t) => Ok(BlockItem::D(self.declaration()?)),
+            t @ Token {
                 kind: TokenKind::Eof,
                 ..
-            } => panic!("Unexpected end of input parsing block item"),
-            _ => BlockItem::S(self.statement()),
+            } => Err(Self::unexpected(
+                t,
+                "Unexpected end of input parsing block item".to_string(),
+            )),
+            _ => Ok(BlockItem::S(self.statement()?)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        let start = self.current().start;
+        match self.statement_kind() {
+            Ok(kind) => Ok(Statement::new(kind, self.span_from(start))),
+            Err(diagnostic) => {
+                self.recover(diagnostic, start, false);
+                Ok(Statement::new(StatementKind::Error, self.span_from(start)))
+            }
         }
     }
 
-    fn statement(&mut self) -> Statement {
-        match self.current().kind {
+    fn statement_kind(&mut self) -> Result<StatementKind> {
+        let kind = match self.current().kind {
             TokenKind::If => {
-                self.consume(TokenKind::If);
-                self.consume(TokenKind::LParen);
-                let condition = self.expression(Prec::Bottom);
-                self.consume(TokenKind::RParen);
-                let if_stmt = self.statement();
+                self.consume(TokenKind::If)?;
+                self.consume(TokenKind::LParen)?;
+                let condition = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::RParen)?;
+                let if_stmt = self.statement()?;
                 let else_stmt = match self.current().kind {
                     TokenKind::Else => {
-                        self.consume(TokenKind::Else);
-                        let else_stmt = self.statement();
+                        self.consume(TokenKind::Else)?;
+                        let else_stmt = self.statement()?;
                         Some(Box::new(else_stmt))
                     }
                     _ => None,
                 };
-                Statement::If(condition, Box::new(if_stmt), else_stmt)
+                StatementKind::If(condition, Box::new(if_stmt), else_stmt)
             }
             TokenKind::Return => {
-                self.consume(TokenKind::Return);
-                let expr = self.expression(Prec::Bottom);
+                self.consume(TokenKind::Return)?;
+                let expr = self.expression(Prec::Bottom)?;
 
-                self.consume(TokenKind::Semicolon);
+                self.consume(TokenKind::Semicolon)?;
 
-                Statement::Return(expr)
+                StatementKind::Return(expr)
             }
             TokenKind::Semicolon => {
-                self.consume(TokenKind::Semicolon);
-                Statement::Null
+                self.consume(TokenKind::Semicolon)?;
+                StatementKind::Null
             }
             TokenKind::Id(id) if self.next().kind == TokenKind::Colon => {
                 self.advance();
-                self.consume(TokenKind::Colon);
-                let stmt = self.statement();
-                Statement::Label(id.to_string(), Box::new(stmt))
+                self.consume(TokenKind::Colon)?;
+                let stmt = self.statement()?;
+                StatementKind::Label(id.to_string(), Box::new(stmt))
             }
             TokenKind::Goto => {
-                self.consume(TokenKind::Goto);
-                match self.current().kind {
-                    TokenKind::Id(id) => {
+                self.consume(TokenKind::Goto)?;
+                match self.current() {
+                    Token {
+                        kind: TokenKind::Id(id),
+                        ..
+                    } => {
                         self.advance();
-                        self.consume(TokenKind::Semicolon);
-                        Statement::Goto(id.to_string())
+                        self.consume(TokenKind::Semicolon)?;
+                        StatementKind::Goto(id.to_string())
+                    }
+                    t => {
+                        return Err(Self::unexpected(
+                            t,
+                            format!("Expected identifier after goto, got {:?}", t.kind),
+                        ));
                     }
-                    kind => panic!("Expected identifier after goto, got {:?}", kind),
                 }
             }
-            TokenKind::LBrace => Statement::Compound(self.block()),
+            TokenKind::LBrace => StatementKind::Compound(self.block()?),
             TokenKind::Break => {
                 self.advance();
-                let stmt = Statement::Break(UNLABELLED.to_string());
-                self.consume(TokenKind::Semicolon);
+                let stmt = StatementKind::Break(UNLABELLED.to_string());
+                self.consume(TokenKind::Semicolon)?;
                 stmt
             }
             TokenKind::Continue => {
                 self.advance();
-                let stmt = Statement::Continue(UNLABELLED.to_string());
-                self.consume(TokenKind::Semicolon);
+                let stmt = StatementKind::Continue(UNLABELLED.to_string());
+                self.consume(TokenKind::Semicolon)?;
                 stmt
             }
             TokenKind::While => {
-                self.consume(TokenKind::While);
-                self.consume(TokenKind::LParen);
-                let cond = self.expression(Prec::Bottom);
-                self.consume(TokenKind::RParen);
-                let body = self.statement();
-                Statement::While(UNLABELLED.to_string(), cond, Box::new(body))
+                self.consume(TokenKind::While)?;
+                self.consume(TokenKind::LParen)?;
+                let cond = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::RParen)?;
+                let body = self.statement()?;
+                StatementKind::While(UNLABELLED.to_string(), cond, Box::new(body))
             }
             TokenKind::Do => {
-                self.consume(TokenKind::Do);
-                let body = self.statement();
-                self.consume(TokenKind::While);
-                self.consume(TokenKind::LParen);
-                let cond = self.expression(Prec::Bottom);
-                self.consume(TokenKind::RParen);
-                self.consume(TokenKind::Semicolon);
-                Statement::DoWhile(UNLABELLED.to_string(), Box::new(body), cond)
+                self.consume(TokenKind::Do)?;
+                let body = self.statement()?;
+                self.consume(TokenKind::While)?;
+                self.consume(TokenKind::LParen)?;
+                let cond = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::RParen)?;
+                self.consume(TokenKind::Semicolon)?;
+                StatementKind::DoWhile(UNLABELLED.to_string(), Box::new(body), cond)
             }
             TokenKind::For => {
-                self.consume(TokenKind::For);
-                self.consume(TokenKind::LParen);
+                self.consume(TokenKind::For)?;
+                self.consume(TokenKind::LParen)?;
                 let init = match self.current() {
-                    t if Self::is_specifier(t) => match self.declaration() {
-                        Declaration::Func(_) => panic!("Function declaration in for loop init"),
+                    t if Self::is_specifier(t) => match self.declaration()? {
+                        Declaration::Func(_) => {
+                            return Err(Diagnostic::error(
+                                ErrorKind::InvalidDeclaration,
+                                "Function declaration in for loop init",
+                            )
+                            .with_span(t.span()));
+                        }
                         Declaration::Var(var) => ForInit::Decl(var),
                     },
                     Token {
                         kind: TokenKind::Semicolon,
                         ..
                     } => {
-                        self.consume(TokenKind::Semicolon);
+                        self.consume(TokenKind::Semicolon)?;
                         ForInit::Null
                     }
                     _ => {
-                        let expr = ForInit::Exp(self.expression(Prec::Bottom));
-                        self.consume(TokenKind::Semicolon);
+                        let expr = ForInit::Exp(self.expression(Prec::Bottom)?);
+                        self.consume(TokenKind::Semicolon)?;
                         expr
                     }
                 };
                 let cond = if self.current().kind != TokenKind::Semicolon {
-                    let expr = Some(self.expression(Prec::Bottom));
-                    self.consume(TokenKind::Semicolon);
+                    let expr = Some(self.expression(Prec::Bottom)?);
+                    self.consume(TokenKind::Semicolon)?;
                     expr
                 } else {
-                    self.consume(TokenKind::Semicolon);
+                    self.consume(TokenKind::Semicolon)?;
                     None
                 };
                 let post = if self.current().kind != TokenKind::RParen {
-                    let expr = Some(self.expression(Prec::Bottom));
-                    self.consume(TokenKind::RParen);
+                    let expr = Some(self.expression(Prec::Bottom)?);
+                    self.consume(TokenKind::RParen)?;
                     expr
                 } else {
-                    self.consume(TokenKind::RParen);
+                    self.consume(TokenKind::RParen)?;
                     None
                 };
-                let body = self.statement();
+                let body = self.statement()?;
 
-                Statement::For(UNLABELLED.to_string(), init, cond, post, Box::new(body))
+                StatementKind::For(
+                    UNLABELLED.to_string(),
+                    Box::new(init),
+                    cond,
+                    post,
+                    Box::new(body),
+                )
             }
             TokenKind::Switch => {
-                self.consume(TokenKind::Switch);
-                self.consume(TokenKind::LParen);
-                let expr = self.expression(Prec::Bottom);
-                self.consume(TokenKind::RParen);
-                let body = Box::new(self.statement());
-                Statement::Switch {
+                self.consume(TokenKind::Switch)?;
+                self.consume(TokenKind::LParen)?;
+                let expr = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::RParen)?;
+                let body = Box::new(self.statement()?);
+                StatementKind::Switch {
                     label: UNLABELLED.to_string(),
                     expr,
                     body,
@@ -502,39 +827,73 @@ impl<'a> Parser<'a> {
                 }
             }
             TokenKind::Case => {
-                self.consume(TokenKind::Case);
-                let expr = self.expression(Prec::Bottom);
-                self.consume(TokenKind::Colon);
-                let stmt = self.statement();
-                Statement::Case(UNLABELLED.to_string(), expr, Box::new(stmt))
+                self.consume(TokenKind::Case)?;
+                let expr = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::Colon)?;
+                let stmt = self.statement()?;
+                StatementKind::Case(UNLABELLED.to_string(), expr, Box::new(stmt))
             }
             TokenKind::Default => {
-                self.consume(TokenKind::Default);
-                self.consume(TokenKind::Colon);
-                let stmt = self.statement();
-                Statement::Default(UNLABELLED.to_string(), Box::new(stmt))
+                self.consume(TokenKind::Default)?;
+                self.consume(TokenKind::Colon)?;
+                let stmt = self.statement()?;
+                StatementKind::Default(UNLABELLED.to_string(), Box::new(stmt))
+            }
+            TokenKind::Eof => {
+                return Err(Self::unexpected(
+                    self.current(),
+                    "Unexpected end of input parsing statement".to_string(),
+                ));
             }
-            TokenKind::Eof => panic!("Unexpected end of input parsing statement"),
             _ => {
-                let expr = Statement::Exp(self.expression(Prec::Bottom));
-                self.consume(TokenKind::Semicolon);
+                let expr = StatementKind::Exp(self.expression(Prec::Bottom)?);
+                self.consume(TokenKind::Semicolon)?;
                 expr
             }
-        }
+        };
+        Ok(kind)
     }
 
-    fn constant(&mut self) -> Expression {
-        let n_str = match self.current().kind {
-            TokenKind::Constant(n_str) => n_str,
-            err => panic!("bad numeric parse: {:?}", err),
+    fn constant(&mut self) -> Result<Expression> {
+        let token = self.current();
+        let (n_str, long, unsigned) = match token.kind {
+            TokenKind::Constant(n_str) => (n_str, false, false),
+            TokenKind::LongConstant(n_str) => (n_str, true, false),
+            TokenKind::UnsignedConstant(n_str) => (n_str, false, true),
+            TokenKind::UnsignedLongConstant(n_str) => (n_str, true, true),
+            kind => {
+                return Err(Self::unexpected(
+                    token,
+                    format!("Expected constant, got {:?}", kind),
+                ));
+            }
         };
 
-        let n = match n_str.parse::<i32>() {
-            Ok(n) => n,
-            err => panic!("bad numeric parse: {:?}", err),
+        // Constants too large for their suffix's type are long, or unsigned
+        // long when that is what the suffix asked for, as in C.
+        let constant = match n_str.parse::<u64>() {
+            Ok(n) if !unsigned && n > i64::MAX as u64 => Err(format!("{} does not fit in long", n)),
+            Ok(n) if !unsigned && !long && n <= i32::MAX as u64 => Ok(Const::Int(n as i32)),
+            Ok(n) if !unsigned => Ok(Const::Long(n as i64)),
+            Ok(n) if !long && n <= u32::MAX as u64 => Ok(Const::UInt(n as u32)),
+            Ok(n) => Ok(Const::ULong(n)),
+            Err(err) => Err(err.to_string()),
+        };
+        let constant = match constant {
+            Ok(constant) => constant,
+            Err(err) => {
+                return Err(Diagnostic::error(
+                    ErrorKind::InvalidConstant,
+                    format!("Bad numeric constant {}: {}", n_str, err),
+                )
+                .with_span(token.span()));
+            }
         };
         self.advance();
-        Expression::Constant(n)
+        Ok(Expression::new(
+            ExpressionKind::Constant(constant),
+            token.span(),
+        ))
     }
 
     fn get_prec(t: Token) -> Prec {
@@ -570,8 +929,8 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expression(&mut self, prec: Prec) -> Expression {
-        let mut lhs = self.factor();
+    fn expression(&mut self, prec: Prec) -> Result<Expression> {
+        let mut lhs = self.factor()?;
         let mut next = self.current();
 
         while (Self::is_binary_op(&next)
@@ -579,42 +938,62 @@ impl<'a> Parser<'a> {
             || Self::is_postfix_op(&next))
             && Self::get_prec(next) >= prec
         {
+            let start = lhs.span.start;
             let next_prec = Self::get_prec(next);
             if next.kind == TokenKind::Equals {
-                self.consume(TokenKind::Equals);
-                let rhs = self.expression(next_prec);
-                lhs = Expression::Assign(Box::new(lhs), Box::new(rhs));
+                self.consume(TokenKind::Equals)?;
+                let rhs = self.expression(next_prec)?;
+                lhs = self
+                    .expression_from(start, ExpressionKind::Assign(Box::new(lhs), Box::new(rhs)));
             } else if next.kind == TokenKind::Huh {
-                self.consume(TokenKind::Huh);
-                let if_expr = self.expression(Prec::Bottom);
-                self.consume(TokenKind::Colon);
-                let else_expr = self.expression(next_prec);
-                lhs =
-                    Expression::Conditional(Box::new(lhs), Box::new(if_expr), Box::new(else_expr));
+                self.consume(TokenKind::Huh)?;
+                let if_expr = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::Colon)?;
+                let else_expr = self.expression(next_prec)?;
+                lhs = self.expression_from(
+                    start,
+                    ExpressionKind::Conditional(
+                        Box::new(lhs),
+                        Box::new(if_expr),
+                        Box::new(else_expr),
+                    ),
+                );
             } else if Self::is_compound_op(&next) {
-                let compound_op = self.compound_op();
-                let rhs = self.expression(next_prec);
-                lhs = Expression::Compound(compound_op, Box::new(lhs), Box::new(rhs));
+                let compound_op = self.compound_op()?;
+                let rhs = self.expression(next_prec)?;
+                lhs = self.expression_from(
+                    start,
+                    ExpressionKind::Compound(compound_op, Box::new(lhs), Box::new(rhs)),
+                );
             } else if Self::is_postfix_op(&next) {
                 match next.kind {
                     TokenKind::DoublePlus => {
-                        self.consume(TokenKind::DoublePlus);
-                        lhs = Expression::Crement(Fixity::Post, Crement::Inc, Box::new(lhs));
+                        self.consume(TokenKind::DoublePlus)?;
+                        lhs = self.expression_from(
+                            start,
+                            ExpressionKind::Crement(Fixity::Post, Crement::Inc, Box::new(lhs)),
+                        );
                     }
                     TokenKind::DoubleMinus => {
-                        self.consume(TokenKind::DoubleMinus);
-                        lhs = Expression::Crement(Fixity::Post, Crement::Dec, Box::new(lhs));
+                        self.consume(TokenKind::DoubleMinus)?;
+                        lhs = self.expression_from(
+                            start,
+                            ExpressionKind::Crement(Fixity::Post, Crement::Dec, Box::new(lhs)),
+                        );
                     }
                     _ => (),
                 }
             } else {
-                let binop = self.binary_op();
-                let rhs = self.expression(Self::increment_prec(&next_prec));
-                lhs = Expression::Binary(binop, Box::new(lhs), Box::new(rhs));
+                let binop = self.binary_op()?;
+                let rhs = self.expression(Self::increment_prec(&next_prec))?;
+                lhs = self.expression_from(
+                    start,
+                    ExpressionKind::Binary(binop, Box::new(lhs), Box::new(rhs)),
+                );
             }
             next = self.current();
         }
-        lhs
+        Ok(lhs)
     }
 
     fn increment_prec(prec: &Prec) -> Prec {
@@ -684,62 +1063,125 @@ impl<'a> Parser<'a> {
         .contains(&token.kind)
     }
 
-    fn factor(&mut self) -> Expression {
-        match self.current().kind {
-            TokenKind::Constant(_) => self.constant(),
-            TokenKind::LParen => {
-                self.consume(TokenKind::LParen);
-                let sub_expr = self.expression(Prec::Bottom);
-                self.consume(TokenKind::RParen);
-                sub_expr
+    fn factor(&mut self) -> Result<Expression> {
+        let start = self.current().start;
+        let kind = match self.current() {
+            Token {
+                kind:
+                    TokenKind::Constant(_)
+                    | TokenKind::LongConstant(_)
+                    | TokenKind::UnsignedConstant(_)
+                    | TokenKind::UnsignedLongConstant(_),
+                ..
+            } => return self.constant(),
+            // A type after the parenthesis makes it a cast rather than a
+            // parenthesized expression.
+            Token {
+                kind: TokenKind::LParen,
+                ..
+            } if Self::is_type_specifier(self.next()) => {
+                self.consume(TokenKind::LParen)?;
+                let mut specifiers = vec![];
+                while Self::is_type_specifier(self.current()) {
+                    specifiers.push(self.current());
+                    self.advance();
+                }
+                let ty = Self::type_specifiers(&specifiers, specifiers[0].span())?;
+                let ty = self.pointers(ty);
+                self.consume(TokenKind::RParen)?;
+                let inner_expr = self.expression(Prec::Unary)?;
+                ExpressionKind::Cast(ty, Box::new(inner_expr))
             }
-            TokenKind::Tilde | TokenKind::Minus | TokenKind::Bang => {
-                let un_op = self.unary_op();
-                let inner_expr = self.expression(Prec::Unary);
-                Expression::Unary(un_op, Box::new(inner_expr))
+            Token {
+                kind: TokenKind::LParen,
+                ..
+            } => {
+                self.consume(TokenKind::LParen)?;
+                let sub_expr = self.expression(Prec::Bottom)?;
+                self.consume(TokenKind::RParen)?;
+                return Ok(sub_expr);
             }
-            TokenKind::Id(id) => {
+            Token {
+                kind: TokenKind::Tilde | TokenKind::Minus | TokenKind::Bang,
+                ..
+            } => {
+                let un_op = self.unary_op()?;
+                let inner_expr = self.expression(Prec::Unary)?;
+                ExpressionKind::Unary(un_op, Box::new(inner_expr))
+            }
+            Token {
+                kind: TokenKind::Ampersand,
+                ..
+            } => {
+                self.advance();
+                let inner_expr = self.expression(Prec::Unary)?;
+                ExpressionKind::AddressOf(Box::new(inner_expr))
+            }
+            Token {
+                kind: TokenKind::Star,
+                ..
+            } => {
+                self.advance();
+                let inner_expr = self.expression(Prec::Unary)?;
+                ExpressionKind::Dereference(Box::new(inner_expr))
+            }
+            Token {
+                kind: TokenKind::Id(id),
+                ..
+            } => {
                 self.advance();
                 let id = id.to_string();
                 if self.current().kind == TokenKind::LParen {
-                    self.consume(TokenKind::LParen);
+                    self.consume(TokenKind::LParen)?;
                     let mut params = vec![];
                     if self.current().kind == TokenKind::RParen {
-                        self.consume(TokenKind::RParen);
+                        self.consume(TokenKind::RParen)?;
                     } else {
                         while {
-                            let expr = self.expression(Prec::Bottom);
+                            let expr = self.expression(Prec::Bottom)?;
                             params.push(expr);
                             let comma = self.current().kind == TokenKind::Comma;
                             if comma {
-                                self.consume(TokenKind::Comma);
+                                self.consume(TokenKind::Comma)?;
                             }
                             comma
                         } {}
-                        self.consume(TokenKind::RParen);
+                        self.consume(TokenKind::RParen)?;
                     }
-                    Expression::Call(id, params)
+                    ExpressionKind::Call(id, params)
                 } else {
-                    Expression::Var(id)
+                    ExpressionKind::Var(id)
                 }
             }
-            TokenKind::DoublePlus | TokenKind::DoubleMinus => {
-                let crement = match self.current().kind {
+            Token {
+                kind: kind @ (TokenKind::DoublePlus | TokenKind::DoubleMinus),
+                ..
+            } => {
+                let crement = match kind {
                     TokenKind::DoublePlus => Crement::Inc,
                     TokenKind::DoubleMinus => Crement::Dec,
                     _ => unreachable!(),
                 };
                 self.advance();
-                let inner_expr = self.factor();
-                Expression::Crement(Fixity::Pre, crement, Box::new(inner_expr))
+                let inner_expr = self.factor()?;
+                ExpressionKind::Crement(Fixity::Pre, crement, Box::new(inner_expr))
             }
-            t => panic!("Unexpected token {:?}", t),
-        }
+            t => {
+                return Err(Self::unexpected(
+                    t,
+                    format!("Unexpected token {:?}", t.kind),
+                ));
+            }
+        };
+        Ok(self.expression_from(start, kind))
     }
 
-    fn compound_op(&mut self) -> CompoundOperator {
+    fn expression_from(&self, start: usize, kind: ExpressionKind) -> Expression {
+        Expression::new(kind, self.span_from(start))
+    }
+
+    fn compound_op(&mut self) -> Result<CompoundOperator> {
         let compound = match self.current().kind {
-            TokenKind::Eof => panic!("Ran out of tokens while parsing expression"),
             TokenKind::PlusEquals => CompoundOperator::Add,
             TokenKind::MinusEquals => CompoundOperator::Subtract,
             TokenKind::StarEquals => CompoundOperator::Multiply,
@@ -750,15 +1192,19 @@ impl<'a> Parser<'a> {
             TokenKind::CaretEquals => CompoundOperator::BitXOr,
             TokenKind::DoubleLAngleEquals => CompoundOperator::ShiftLeft,
             TokenKind::DoubleRAngleEquals => CompoundOperator::ShiftRight,
-            kind => panic!("Expected compound operator, got {:?}", kind),
+            kind => {
+                return Err(Self::unexpected(
+                    self.current(),
+                    format!("Expected compound operator, got {:?}", kind),
+                ));
+            }
         };
         self.advance();
-        compound
+        Ok(compound)
     }
 
-    fn binary_op(&mut self) -> BinaryOperator {
+    fn binary_op(&mut self) -> Result<BinaryOperator> {
         let binop = match self.current().kind {
-            TokenKind::Eof => panic!("Ran out of tokens while parsing expression"),
             TokenKind::Plus => BinaryOperator::Add,
             TokenKind::Minus => BinaryOperator::Subtract,
             TokenKind::Star => BinaryOperator::Multiply,
@@ -778,27 +1224,298 @@ impl<'a> Parser<'a> {
             TokenKind::LAngle => BinaryOperator::Less,
             TokenKind::LAngleEquals => BinaryOperator::LessOrEqual,
             TokenKind::Huh => BinaryOperator::Conditional,
-            kind => panic!("Expected binary operator, got {:?}", kind),
+            kind => {
+                return Err(Self::unexpected(
+                    self.current(),
+                    format!("Expected binary operator, got {:?}", kind),
+                ));
+            }
         };
         self.advance();
-        binop
+        Ok(binop)
     }
 
-    fn unary_op(&mut self) -> UnaryOperator {
+    fn unary_op(&mut self) -> Result<UnaryOperator> {
         let unop = match self.current().kind {
             TokenKind::Tilde => UnaryOperator::Complement,
             TokenKind::Minus => UnaryOperator::Negate,
             TokenKind::Bang => UnaryOperator::Not,
-            _ => panic!("unreachable"),
+            kind => {
+                return Err(Self::unexpected(
+                    self.current(),
+                    format!("Expected unary operator, got {:?}", kind),
+                ));
+            }
         };
         self.advance();
-        unop
+        Ok(unop)
     }
 
-    fn is_specifier(t: Token<'_>) -> bool {
+    fn is_type_specifier(t: Token<'_>) -> bool {
         matches!(
             t.kind,
-            TokenKind::Int | TokenKind::Extern | TokenKind::Static
+            TokenKind::Int | TokenKind::Long | TokenKind::Signed | TokenKind::Unsigned
         )
     }
+
+    fn is_specifier(t: Token<'_>) -> bool {
+        Self::is_type_specifier(t) || matches!(t.kind, TokenKind::Extern | TokenKind::Static)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse()
+    }
+
+    fn error_lines(source: &str) -> Vec<usize> {
+        parse(source)
+            .errors
+            .iter()
+            .map(|d| source[..d.span.unwrap().start].matches('\n').count() + 1)
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        let program = parse("int x = 1;\nint main(void) { return x; }");
+        assert!(!program.is_erroneous());
+        assert_eq!(program.declarations.len(), 2);
+    }
+
+    #[test]
+    fn type_specifiers() {
+        let program = parse(
+            "long a; long int b; int long c = 5L;\n\
+             long f(int x, long int y);",
+        );
+        assert!(!program.is_erroneous());
+        let types: Vec<_> = program
+            .declarations
+            .iter()
+            .map(|d| match d {
+                Declaration::Func(f) => f.ty.clone(),
+                Declaration::Var(v) => v.ty.clone(),
+            })
+            .collect();
+        assert_eq!(types[..3], [Type::Long, Type::Long, Type::Long]);
+        assert_eq!(
+            types[3],
+            Type::Fun {
+                params: vec![Type::Int, Type::Long],
+                ret: Box::new(Type::Long),
+            }
+        );
+
+        let program = parse(
+            "unsigned a; unsigned long int b; long unsigned c; signed long d;\n\
+             signed e; int f(unsigned int x);",
+        );
+        assert!(!program.is_erroneous());
+        let types: Vec<_> = program
+            .declarations
+            .iter()
+            .map(|d| match d {
+                Declaration::Func(f) => f.ty.clone(),
+                Declaration::Var(v) => v.ty.clone(),
+            })
+            .collect();
+        assert_eq!(
+            types[..5],
+            [Type::UInt, Type::ULong, Type::ULong, Type::Long, Type::Int]
+        );
+        assert_eq!(
+            types[5],
+            Type::Fun {
+                params: vec![Type::UInt],
+                ret: Box::new(Type::Int),
+            }
+        );
+
+        let source = "long long a;\nint long int b;\nstatic c;\nlong d;\n\
+                      signed unsigned e;\nunsigned unsigned f;";
+        assert_eq!(error_lines(source), vec![1, 2, 3, 5, 6]);
+        let kinds: Vec<_> = parse(source).errors.iter().map(|d| d.kind).collect();
+        assert!(kinds.iter().all(|k| *k == ErrorKind::InvalidSpecifiers));
+
+        let errors = parse(source).errors;
+        assert_eq!(
+            errors[1].message,
+            "Invalid type specifier combination 'int long int'"
+        );
+        let span = errors[1].span.unwrap();
+        assert_eq!(&source[span.start..span.end], "int long int");
+        let errors = parse("static extern int g;").errors;
+        assert_eq!(
+            errors[0].message,
+            "Too many storage classes in 'static extern int'"
+        );
+        assert_eq!(errors[0].span, Some(Span { start: 0, end: 17 }));
+    }
+
+    #[test]
+    fn constants() {
+        let constant = |source: &str| {
+            let mut parser = Parser::new(Lexer::new(source));
+            parser.advance();
+            parser.advance();
+            parser.constant().map(|e| e.kind)
+        };
+        let cases = [
+            ("2147483647", Const::Int(i32::MAX)),
+            ("2147483648", Const::Long(1 << 31)),
+            ("5l", Const::Long(5)),
+            ("4294967295u", Const::UInt(u32::MAX)),
+            ("4294967296U", Const::ULong(1 << 32)),
+            ("5ul", Const::ULong(5)),
+            ("18446744073709551615u", Const::ULong(u64::MAX)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(constant(source), Ok(ExpressionKind::Constant(expected)));
+        }
+        assert!(constant("9223372036854775808").is_err());
+        assert!(constant("18446744073709551616u").is_err());
+    }
+
+    #[test]
+    fn casts() {
+        let program = parse("int main(void) { return (long) x + (unsigned long) -(y); }");
+        assert!(!program.is_erroneous());
+        let Declaration::Func(Function {
+            body: Some(body), ..
+        }) = &program.declarations[0]
+        else {
+            panic!("expected a function definition");
+        };
+        let BlockItem::S(Statement {
+            kind: StatementKind::Return(expr),
+            ..
+        }) = &body[0]
+        else {
+            panic!("expected a return statement");
+        };
+        let ExpressionKind::Binary(BinaryOperator::Add, lhs, rhs) = &expr.kind else {
+            panic!("expected an addition, got {:?}", expr.kind);
+        };
+        assert!(matches!(&lhs.kind, ExpressionKind::Cast(Type::Long, inner)
+            if inner.kind == ExpressionKind::Var("x".to_string())));
+        assert!(matches!(&rhs.kind, ExpressionKind::Cast(Type::ULong, inner)
+            if matches!(inner.kind, ExpressionKind::Unary(UnaryOperator::Negate, _))));
+
+        let source = "int main(void) {\n\
+                      return (signed unsigned) x;\n\
+                      return (long x;\n\
+                      }";
+        assert_eq!(error_lines(source), vec![2, 3]);
+    }
+
+    #[test]
+    fn pointers() {
+        let program = parse("long **f(int *a) { return *&a ? (long **) 0 : 0; }");
+        assert!(!program.is_erroneous());
+        let Declaration::Func(Function {
+            ty: Type::Fun { params, ret },
+            body: Some(body),
+            ..
+        }) = &program.declarations[0]
+        else {
+            panic!("expected a function definition");
+        };
+        let pointer = |ty| Type::Pointer(Box::new(ty));
+        assert_eq!(params, &[pointer(Type::Int)]);
+        assert_eq!(**ret, pointer(pointer(Type::Long)));
+        let BlockItem::S(Statement {
+            kind: StatementKind::Return(expr),
+            ..
+        }) = &body[0]
+        else {
+            panic!("expected a return statement");
+        };
+        let ExpressionKind::Conditional(condition, cast, _) = &expr.kind else {
+            panic!("expected a conditional, got {:?}", expr.kind);
+        };
+        assert!(matches!(&condition.kind, ExpressionKind::Dereference(inner)
+            if matches!(inner.kind, ExpressionKind::AddressOf(_))));
+        assert!(matches!(&cast.kind, ExpressionKind::Cast(ty, _)
+            if *ty == pointer(pointer(Type::Long))));
+
+        let program = parse("int main(void) { return a * *b & &c; }");
+        assert!(!program.is_erroneous());
+        assert_eq!(error_lines("int main(void) {\n int * = 1;\n}"), vec![2]);
+    }
+
+    #[test]
+    fn statement_recovery() {
+        let source = "int main(void) {\n\
+                      int a = 1 +;\n\
+                      a = (2;\n\
+                      if (a) return ; else a = 3;\n\
+                      return a;\n\
+                      }";
+        assert_eq!(error_lines(source), vec![2, 3, 4]);
+
+        let program = parse(source);
+        let Declaration::Func(Function {
+            body: Some(body), ..
+        }) = &program.declarations[0]
+        else {
+            panic!("expected function definition");
+        };
+        assert_eq!(body.len(), 4);
+        assert!(matches!(
+            &body[0],
+            BlockItem::S(Statement {
+                kind: StatementKind::Error,
+                ..
+            })
+        ));
+        assert!(matches!(
+            &body[2],
+            BlockItem::S(Statement {
+                kind: StatementKind::If(_, _, Some(_)),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn declaration_recovery() {
+        let source = "int f(int x,) { return x; }\n\
+                      int g(void) { return 1; }\n\
+                      static extern int y;\n\
+                      int z;\n\
+                      }\n\
+                      int main(void) { return 2; }";
+        assert_eq!(error_lines(source), vec![1, 3, 5]);
+
+        let names: Vec<_> = parse(source)
+            .declarations
+            .iter()
+            .map(|d| match d {
+                Declaration::Func(f) => f.name.clone(),
+                Declaration::Var(v) => v.name.clone(),
+            })
+            .collect();
+        assert_eq!(names, vec!["g", "z", "main"]);
+    }
+
+    #[test]
+    fn lexer_and_eof_errors() {
+        let source = "int main(void) {\n\
+                      int a = 1 @ 2;\n\
+                      return a;\n";
+        let program = parse(source);
+        let kinds: Vec<_> = program.errors.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::InvalidToken,
+                ErrorKind::UnexpectedToken,
+                ErrorKind::UnexpectedEof
+            ]
+        );
+    }
 }
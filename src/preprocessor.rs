@@ -0,0 +1,1408 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::{Diagnostic, ErrorKind, Result, Span};
+
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+const PUNCTUATORS: [&str; 24] = [
+    "<<=", ">>=", "...", "##", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "#",
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum PpKind {
+    Ident,
+    Number,
+    Literal,
+    Punct,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct PpToken {
+    kind: PpKind,
+    text: String,
+    space: bool,
+    column: Option<usize>,
+    hide: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Macro {
+    Object(Vec<PpToken>),
+    Function(Vec<String>, Vec<PpToken>),
+}
+
+// A logical line can span several physical ones, through splices and
+// block comments. `breaks` holds the offsets in `text` where each following
+// physical line starts, so tokens can be put back on the line they came from.
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    text: String,
+    breaks: Vec<usize>,
+}
+
+impl Line {
+    fn physical(&self, column: usize) -> usize {
+        self.number + self.breaks.iter().filter(|&&b| b <= column).count()
+    }
+}
+
+#[derive(Debug)]
+struct File {
+    name: String,
+    path: PathBuf,
+    expected: usize,
+}
+
+#[derive(Debug)]
+struct Conditional {
+    parent_active: bool,
+    active: bool,
+    taken: bool,
+    else_seen: bool,
+    line: Line,
+}
+
+#[derive(Debug)]
+pub struct Preprocessed {
+    pub text: String,
+    pub errors: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+pub struct Preprocessor {
+    include_paths: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    once: HashSet<PathBuf>,
+    depth: usize,
+    output: String,
+    errors: Vec<Diagnostic>,
+}
+
+impl PpToken {
+    fn is(&self, kind: PpKind, text: &str) -> bool {
+        self.kind == kind && self.text == text
+    }
+
+    fn span(&self) -> Span {
+        let start = self.column.unwrap_or(0);
+        Span {
+            start,
+            end: start + self.text.len(),
+        }
+    }
+}
+
+impl Preprocessor {
+    pub fn new(include_paths: Vec<PathBuf>) -> Self {
+        Self {
+            include_paths,
+            macros: HashMap::new(),
+            once: HashSet::new(),
+            depth: 0,
+            output: String::new(),
+            errors: vec![],
+        }
+    }
+
+    pub fn preprocess_file(self, path: &Path) -> Preprocessed {
+        match fs::read_to_string(path) {
+            Ok(source) => self.preprocess(path, &source),
+            Err(err) => Preprocessed {
+                text: String::new(),
+                errors: vec![Diagnostic::error(
+                    ErrorKind::IncludeNotFound,
+                    format!("Cannot read {}: {}", path.display(), err),
+                )],
+            },
+        }
+    }
+
+    pub fn preprocess(mut self, path: &Path, source: &str) -> Preprocessed {
+        self.file(path, source, None);
+        Preprocessed {
+            text: self.output,
+            errors: self.errors,
+        }
+    }
+
+    fn file(&mut self, path: &Path, source: &str, flag: Option<u8>) {
+        let mut file = File {
+            name: path.display().to_string(),
+            path: path.to_path_buf(),
+            expected: 1,
+        };
+        self.marker(1, &file.name, flag);
+
+        let lines = Self::logical_lines(source);
+        let mut conditionals: Vec<Conditional> = vec![];
+        let mut index = 0;
+        while index < lines.len() {
+            let line = &lines[index];
+            index += 1;
+            while file.expected < line.number {
+                self.output.push('\n');
+                file.expected += 1;
+            }
+
+            let tokens = Self::tokenize(&line.text);
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let result = match tokens.first() {
+                Some(t) if t.is(PpKind::Punct, "#") => {
+                    self.directive(&mut file, line, &tokens[1..], &mut conditionals)
+                }
+                _ if active => {
+                    let mut tokens = tokens;
+                    loop {
+                        match self.expand(&file, line, tokens.clone()) {
+                            Ok(Some(expanded)) => {
+                                let text = Self::spell(&expanded, &line.breaks);
+                                file.expected += text.matches('\n').count();
+                                self.output.push_str(&text);
+                                break Ok(false);
+                            }
+                            Ok(None) if index < lines.len() => {
+                                let mut more = Self::tokenize(&lines[index].text);
+                                index += 1;
+                                for t in more.iter_mut() {
+                                    t.column = None;
+                                }
+                                if let Some(t) = more.first_mut() {
+                                    t.space = true;
+                                }
+                                tokens.extend(more);
+                            }
+                            Ok(None) => {
+                                break Err(Diagnostic::error(
+                                    ErrorKind::MacroArguments,
+                                    "Unterminated argument list invoking macro",
+                                )
+                                .with_span(Span {
+                                    start: 0,
+                                    end: line.text.len(),
+                                }));
+                            }
+                            Err(diagnostic) => break Err(diagnostic),
+                        }
+                    }
+                }
+                _ => Ok(false),
+            };
+
+            match result {
+                Ok(true) => {
+                    file.expected = line.number + 1;
+                    self.marker(file.expected, &file.name, Some(2));
+                }
+                Ok(false) => {
+                    self.output.push('\n');
+                    file.expected += 1;
+                }
+                Err(diagnostic) => self.report(&mut file, line, diagnostic),
+            }
+        }
+
+        for conditional in conditionals {
+            let diagnostic = Diagnostic::error(ErrorKind::InvalidDirective, "Unterminated #if")
+                .with_span(Span {
+                    start: 0,
+                    end: conditional.line.text.len(),
+                });
+            self.report(&mut file, &conditional.line, diagnostic);
+        }
+    }
+
+    fn marker(&mut self, line: usize, name: &str, flag: Option<u8>) {
+        self.output.push_str(&format!("# {} \"{}\"", line, name));
+        if let Some(flag) = flag {
+            self.output.push_str(&format!(" {}", flag));
+        }
+        self.output.push('\n');
+    }
+
+    fn report(&mut self, file: &mut File, line: &Line, diagnostic: Diagnostic) {
+        self.marker(line.number, &file.name, None);
+        let start = self.output.len();
+        let mut previous = 0;
+        for &offset in &line.breaks {
+            self.output.push_str(&line.text[previous..offset]);
+            self.output.push('\n');
+            previous = offset;
+        }
+        self.output.push_str(&line.text[previous..]);
+        self.output.push('\n');
+        file.expected = line.number + line.breaks.len() + 1;
+
+        // Every break before an offset moves it past one more newline.
+        let position = |offset: usize, inclusive: bool| {
+            let offset = offset.min(line.text.len());
+            let newlines = line
+                .breaks
+                .iter()
+                .filter(|&&b| b < offset || (inclusive && b == offset))
+                .count();
+            start + offset + newlines
+        };
+        let span = diagnostic.span.map(|span| Span {
+            start: position(span.start, true),
+            end: position(span.end, false),
+        });
+        self.errors.push(Diagnostic { span, ..diagnostic });
+    }
+
+    fn directive(
+        &mut self,
+        file: &mut File,
+        line: &Line,
+        tokens: &[PpToken],
+        conditionals: &mut Vec<Conditional>,
+    ) -> Result<bool> {
+        let Some(name) = tokens.first() else {
+            return Ok(false);
+        };
+        let rest = &tokens[1..];
+        let active = conditionals.last().is_none_or(|c| c.active);
+
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = if !active {
+                    Ok(false)
+                } else if name.text == "if" {
+                    self.condition(file, line, name, rest)
+                } else {
+                    Self::macro_name(name, rest).map(|macro_name| {
+                        self.macros.contains_key(&macro_name) == (name.text == "ifdef")
+                    })
+                };
+                let active_branch = *condition.as_ref().unwrap_or(&false);
+                conditionals.push(Conditional {
+                    parent_active: active,
+                    active: active_branch,
+                    taken: active_branch || !active,
+                    else_seen: false,
+                    line: line.clone(),
+                });
+                condition?;
+            }
+            "elif" => {
+                let conditional = Self::open_conditional(conditionals, name)?;
+                if conditional.else_seen {
+                    return Err(Self::invalid(name, "#elif after #else"));
+                }
+                let evaluate = conditional.parent_active && !conditional.taken;
+                let condition = evaluate && self.condition(file, line, name, rest)?;
+                let conditional = Self::open_conditional(conditionals, name)?;
+                conditional.active = condition;
+                conditional.taken |= condition;
+            }
+            "else" => {
+                let conditional = Self::open_conditional(conditionals, name)?;
+                if conditional.else_seen {
+                    return Err(Self::invalid(name, "#else after #else"));
+                }
+                conditional.else_seen = true;
+                conditional.active = conditional.parent_active && !conditional.taken;
+                conditional.taken = true;
+            }
+            "endif" => {
+                Self::open_conditional(conditionals, name)?;
+                conditionals.pop();
+            }
+            _ if !active => (),
+            "define" => self.define(name, rest)?,
+            "undef" => {
+                let macro_name = Self::macro_name(name, rest)?;
+                self.macros.remove(&macro_name);
+            }
+            "include" => return self.include(file, line, name, rest),
+            "error" => {
+                let message = match rest.first().and_then(|t| t.column) {
+                    Some(column) => line.text[column..].trim(),
+                    None => "",
+                };
+                return Err(Diagnostic::error(
+                    ErrorKind::ErrorDirective,
+                    format!("#error {}", message),
+                )
+                .with_span(Span {
+                    start: 0,
+                    end: line.text.len(),
+                }));
+            }
+            "pragma" => {
+                if rest.first().is_some_and(|t| t.is(PpKind::Ident, "once")) {
+                    self.once.insert(Self::canonical(&file.path));
+                } else {
+                    self.output.push_str(line.text.trim_start());
+                }
+            }
+            _ => {
+                return Err(Self::invalid(
+                    name,
+                    &format!("Invalid preprocessing directive #{}", name.text),
+                ));
+            }
+        }
+        Ok(false)
+    }
+
+    fn invalid(token: &PpToken, message: &str) -> Diagnostic {
+        Diagnostic::error(ErrorKind::InvalidDirective, message).with_span(token.span())
+    }
+
+    fn open_conditional<'c>(
+        conditionals: &'c mut [Conditional],
+        name: &PpToken,
+    ) -> Result<&'c mut Conditional> {
+        match conditionals.last_mut() {
+            Some(conditional) => Ok(conditional),
+            None => Err(Self::invalid(name, &format!("#{} without #if", name.text))),
+        }
+    }
+
+    fn macro_name(directive: &PpToken, rest: &[PpToken]) -> Result<String> {
+        match rest.first() {
+            Some(t) if t.kind == PpKind::Ident => Ok(t.text.clone()),
+            Some(t) => Err(Self::invalid(t, "Macro names must be identifiers")),
+            None => Err(Self::invalid(
+                directive,
+                &format!("No macro name given in #{} directive", directive.text),
+            )),
+        }
+    }
+
+    fn define(&mut self, directive: &PpToken, rest: &[PpToken]) -> Result<()> {
+        let name = Self::macro_name(directive, rest)?;
+        if name == "defined" {
+            return Err(Self::invalid(
+                &rest[0],
+                "\"defined\" cannot be used as a macro name",
+            ));
+        }
+
+        let mut body = &rest[1..];
+        let params = match body.first() {
+            Some(t) if t.is(PpKind::Punct, "(") && !t.space => {
+                let mut params: Vec<String> = vec![];
+                let mut index = 1;
+                loop {
+                    match body.get(index) {
+                        Some(t) if t.is(PpKind::Punct, ")") && params.is_empty() => break,
+                        Some(t) if t.kind == PpKind::Ident => {
+                            if params.contains(&t.text) {
+                                return Err(Self::invalid(
+                                    t,
+                                    &format!("Duplicate macro parameter {}", t.text),
+                                ));
+                            }
+                            params.push(t.text.clone());
+                        }
+                        Some(t) => {
+                            return Err(Self::invalid(
+                                t,
+                                &format!("Expected parameter name, got {}", t.text),
+                            ));
+                        }
+                        None => {
+                            return Err(Self::invalid(directive, "Missing ) in macro parameters"));
+                        }
+                    }
+                    match body.get(index + 1) {
+                        Some(t) if t.is(PpKind::Punct, ",") => index += 2,
+                        Some(t) if t.is(PpKind::Punct, ")") => {
+                            index += 1;
+                            break;
+                        }
+                        Some(t) => {
+                            return Err(Self::invalid(
+                                t,
+                                &format!("Expected , or ) in macro parameters, got {}", t.text),
+                            ));
+                        }
+                        None => {
+                            return Err(Self::invalid(directive, "Missing ) in macro parameters"));
+                        }
+                    }
+                }
+                body = &body[index + 1..];
+                Some(params)
+            }
+            _ => None,
+        };
+
+        if let Some(t) = body.first().filter(|t| t.is(PpKind::Punct, "##")) {
+            return Err(Self::invalid(
+                t,
+                "'##' cannot appear at either end of a macro expansion",
+            ));
+        }
+        if let Some(t) = body.last().filter(|t| t.is(PpKind::Punct, "##")) {
+            return Err(Self::invalid(
+                t,
+                "'##' cannot appear at either end of a macro expansion",
+            ));
+        }
+        if let Some(params) = &params {
+            for (i, t) in body.iter().enumerate() {
+                let param = body.get(i + 1).filter(|p| params.contains(&p.text));
+                if t.is(PpKind::Punct, "#") && param.is_none() {
+                    return Err(Self::invalid(t, "'#' is not followed by a macro parameter"));
+                }
+            }
+        }
+
+        let mut body = body.to_vec();
+        for t in body.iter_mut() {
+            t.column = None;
+        }
+        if let Some(t) = body.first_mut() {
+            t.space = false;
+        }
+        let definition = match params {
+            Some(params) => Macro::Function(params, body),
+            None => Macro::Object(body),
+        };
+        self.macros.insert(name, definition);
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        file: &File,
+        line: &Line,
+        directive: &PpToken,
+        rest: &[PpToken],
+    ) -> Result<bool> {
+        let (header, quoted) = match Self::header_name(rest) {
+            Some(header) => header,
+            None => {
+                let expanded = self.expand(file, line, rest.to_vec())?.unwrap_or_default();
+                Self::header_name(&expanded).ok_or_else(|| {
+                    Self::invalid(directive, "#include expects \"FILENAME\" or <FILENAME>")
+                })?
+            }
+        };
+
+        let local = file.path.parent().map(Path::to_path_buf);
+        let search = quoted
+            .then_some(local)
+            .flatten()
+            .into_iter()
+            .chain(self.include_paths.iter().cloned());
+        let Some(path) = search.map(|dir| dir.join(&header)).find(|p| p.is_file()) else {
+            return Err(Diagnostic::error(
+                ErrorKind::IncludeNotFound,
+                format!("{}: No such file or directory", header),
+            )
+            .with_span(Span {
+                start: directive.span().start,
+                end: line.text.len(),
+            }));
+        };
+
+        if self.once.contains(&Self::canonical(&path)) {
+            return Ok(false);
+        }
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(Self::invalid(directive, "#include nested too deeply"));
+        }
+        let source = fs::read_to_string(&path).map_err(|err| {
+            Self::invalid(
+                directive,
+                &format!("Cannot read {}: {}", path.display(), err),
+            )
+        })?;
+
+        self.depth += 1;
+        self.file(&path, &source, Some(1));
+        self.depth -= 1;
+        Ok(true)
+    }
+
+    fn header_name(tokens: &[PpToken]) -> Option<(String, bool)> {
+        match tokens {
+            [t] if t.kind == PpKind::Literal && t.text.starts_with('"') => {
+                Some((t.text[1..t.text.len() - 1].to_string(), true))
+            }
+            [open, inner @ .., close]
+                if open.is(PpKind::Punct, "<") && close.is(PpKind::Punct, ">") =>
+            {
+                let mut header = String::new();
+                for t in inner {
+                    if t.space && !header.is_empty() {
+                        header.push(' ');
+                    }
+                    header.push_str(&t.text);
+                }
+                Some((header, false))
+            }
+            _ => None,
+        }
+    }
+
+    fn canonical(path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    fn condition(
+        &self,
+        file: &File,
+        line: &Line,
+        directive: &PpToken,
+        rest: &[PpToken],
+    ) -> Result<bool> {
+        let mut replaced = vec![];
+        let mut tokens = rest.iter();
+        while let Some(t) = tokens.next() {
+            if !t.is(PpKind::Ident, "defined") {
+                replaced.push(t.clone());
+                continue;
+            }
+            let name = match tokens.next() {
+                Some(open) if open.is(PpKind::Punct, "(") => {
+                    let name = tokens.next().filter(|n| n.kind == PpKind::Ident);
+                    match (name, tokens.next()) {
+                        (Some(name), Some(close)) if close.is(PpKind::Punct, ")") => name,
+                        _ => return Err(Self::invalid(t, "Missing ) after \"defined\"")),
+                    }
+                }
+                Some(name) if name.kind == PpKind::Ident => name,
+                _ => {
+                    return Err(Self::invalid(
+                        t,
+                        "Operator \"defined\" requires an identifier",
+                    ));
+                }
+            };
+            let value = if self.macros.contains_key(&name.text) {
+                "1"
+            } else {
+                "0"
+            };
+            replaced.push(PpToken {
+                kind: PpKind::Number,
+                text: value.to_string(),
+                ..t.clone()
+            });
+        }
+
+        let expanded = self.expand(file, line, replaced)?.ok_or_else(|| {
+            Diagnostic::error(
+                ErrorKind::MacroArguments,
+                "Unterminated argument list invoking macro",
+            )
+            .with_span(directive.span())
+        })?;
+        if expanded.is_empty() {
+            return Err(Self::invalid(directive, "#if with no expression"));
+        }
+
+        let mut evaluator = Evaluator {
+            tokens: &expanded,
+            position: 0,
+            directive,
+        };
+        let value = evaluator.conditional(true)?;
+        match evaluator.tokens.get(evaluator.position) {
+            Some(t) => Err(evaluator.error(
+                Some(t),
+                &format!("Missing binary operator before token {}", t.text),
+            )),
+            None => Ok(value != 0),
+        }
+    }
+
+    fn expand(
+        &self,
+        file: &File,
+        line: &Line,
+        tokens: Vec<PpToken>,
+    ) -> Result<Option<Vec<PpToken>>> {
+        let mut input = VecDeque::from(tokens);
+        let mut output = vec![];
+        // Expanded tokens have no column, so they are placed at the last
+        // source token, which is the macro name that produced them.
+        let mut source = Span { start: 0, end: 0 };
+
+        while let Some(token) = input.pop_front() {
+            if token.column.is_some() {
+                source = token.span();
+            }
+            if token.kind != PpKind::Ident || token.hide.contains(&token.text) {
+                output.push(token);
+                continue;
+            }
+
+            let builtin = match token.text.as_str() {
+                "__FILE__" => Some((PpKind::Literal, Self::quote(&file.name))),
+                "__LINE__" => Some((PpKind::Number, line.physical(source.start).to_string())),
+                _ => None,
+            };
+            if let Some((kind, text)) = builtin {
+                output.push(PpToken {
+                    kind,
+                    text,
+                    column: None,
+                    ..token
+                });
+                continue;
+            }
+
+            let mut hide = token.hide.clone();
+            hide.push(token.text.clone());
+            let expansion = match self.macros.get(&token.text) {
+                None => {
+                    output.push(token);
+                    continue;
+                }
+                Some(Macro::Object(body)) => self.substitute(file, line, source, &[], body, &[])?,
+                Some(Macro::Function(params, body)) => {
+                    if !input.front().is_some_and(|t| t.is(PpKind::Punct, "(")) {
+                        output.push(token);
+                        continue;
+                    }
+                    let Some(args) = Self::arguments(&mut input) else {
+                        return Ok(None);
+                    };
+                    let arity_ok = args.len() == params.len()
+                        || (params.is_empty() && args.len() == 1 && args[0].is_empty());
+                    if !arity_ok {
+                        return Err(Diagnostic::error(
+                            ErrorKind::MacroArguments,
+                            format!(
+                                "Macro {} expects {} arguments, but {} given",
+                                token.text,
+                                params.len(),
+                                args.len()
+                            ),
+                        )
+                        .with_span(token.span()));
+                    }
+                    self.substitute(file, line, source, params, body, &args)?
+                }
+            };
+
+            for (i, mut t) in expansion.into_iter().enumerate().rev() {
+                t.hide.extend(hide.iter().cloned());
+                t.column = None;
+                if i == 0 {
+                    t.space = token.space;
+                }
+                input.push_front(t);
+            }
+        }
+
+        Ok(Some(output))
+    }
+
+    fn arguments(input: &mut VecDeque<PpToken>) -> Option<Vec<Vec<PpToken>>> {
+        input.pop_front();
+        let mut args = vec![vec![]];
+        let mut depth = 0;
+        loop {
+            let t = input.pop_front()?;
+            match t.text.as_str() {
+                ")" if depth == 0 => return Some(args),
+                "," if depth == 0 => args.push(vec![]),
+                _ => {
+                    if t.is(PpKind::Punct, "(") {
+                        depth += 1;
+                    } else if t.is(PpKind::Punct, ")") {
+                        depth -= 1;
+                    }
+                    args.last_mut()?.push(t);
+                }
+            }
+        }
+    }
+
+    fn substitute(
+        &self,
+        file: &File,
+        line: &Line,
+        site: Span,
+        params: &[String],
+        body: &[PpToken],
+        args: &[Vec<PpToken>],
+    ) -> Result<Vec<PpToken>> {
+        let param = |t: &PpToken| {
+            (t.kind == PpKind::Ident)
+                .then(|| params.iter().position(|p| *p == t.text))
+                .flatten()
+        };
+
+        let mut output: Vec<PpToken> = vec![];
+        let mut placemarker = false;
+        let mut index = 0;
+        while index < body.len() {
+            let t = &body[index];
+            let next = body.get(index + 1);
+
+            if t.is(PpKind::Punct, "#")
+                && let Some(p) = next.and_then(param)
+            {
+                output.push(PpToken {
+                    kind: PpKind::Literal,
+                    text: Self::stringify(&args[p]),
+                    space: t.space,
+                    column: None,
+                    hide: vec![],
+                });
+                placemarker = false;
+                index += 2;
+                continue;
+            }
+
+            if t.is(PpKind::Punct, "##") {
+                let rhs_token = &body[index + 1];
+                let rhs = match param(rhs_token) {
+                    Some(p) => args[p].clone(),
+                    None => vec![rhs_token.clone()],
+                };
+                index += 2;
+                if placemarker {
+                    placemarker = rhs.is_empty();
+                    output.extend(rhs);
+                } else if let Some((first, rest)) = rhs.split_first() {
+                    let lhs = output.pop().expect("## has a left operand");
+                    output.push(Self::paste(&lhs, first, site)?);
+                    output.extend(rest.iter().cloned());
+                }
+                continue;
+            }
+
+            match param(t) {
+                Some(p) => {
+                    let mut arg = if next.is_some_and(|n| n.is(PpKind::Punct, "##")) {
+                        args[p].clone()
+                    } else {
+                        self.expand(file, line, args[p].clone())?
+                            .unwrap_or_else(|| args[p].clone())
+                    };
+                    placemarker = arg.is_empty();
+                    if let Some(first) = arg.first_mut() {
+                        first.space = t.space;
+                    }
+                    output.extend(arg);
+                }
+                None => {
+                    placemarker = false;
+                    output.push(t.clone());
+                }
+            }
+            index += 1;
+        }
+        Ok(output)
+    }
+
+    fn paste(lhs: &PpToken, rhs: &PpToken, site: Span) -> Result<PpToken> {
+        let text = format!("{}{}", lhs.text, rhs.text);
+        match &Self::tokenize(&text)[..] {
+            [token] => Ok(PpToken {
+                space: lhs.space,
+                column: None,
+                hide: vec![],
+                ..token.clone()
+            }),
+            _ => Err(Diagnostic::error(
+                ErrorKind::InvalidDirective,
+                format!(
+                    "Pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+                    lhs.text, rhs.text
+                ),
+            )
+            .with_span(
+                [lhs, rhs]
+                    .into_iter()
+                    .find(|t| t.column.is_some())
+                    .map_or(site, PpToken::span),
+            )),
+        }
+    }
+
+    fn stringify(tokens: &[PpToken]) -> String {
+        let mut text = String::new();
+        for t in tokens {
+            if t.space && !text.is_empty() {
+                text.push(' ');
+            }
+            if t.kind == PpKind::Literal {
+                text.push_str(&t.text.replace('\\', "\\\\").replace('"', "\\\""));
+            } else {
+                text.push_str(&t.text);
+            }
+        }
+        format!("\"{}\"", text)
+    }
+
+    fn quote(text: &str) -> String {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    fn spell(tokens: &[PpToken], breaks: &[usize]) -> String {
+        let mut text = String::new();
+        let mut previous: Option<&PpToken> = None;
+        let mut aligned = true;
+        let mut breaks = breaks.iter().peekable();
+        // Where the current physical line starts, in the source and the output.
+        let mut base = 0;
+        let mut start = 0;
+        for t in tokens {
+            while let Some(column) = t.column
+                && let Some(&&offset) = breaks.peek()
+                && offset <= column
+            {
+                breaks.next();
+                text.push('\n');
+                base = offset;
+                start = text.len();
+                previous = None;
+                aligned = true;
+            }
+            let width = text.len() - start;
+            aligned &= t.column.is_some_and(|column| column - base >= width);
+            match (t.column, previous) {
+                (Some(column), _) if aligned => text.push_str(&" ".repeat(column - base - width)),
+                (_, Some(p)) if t.space || Self::would_merge(p, t) => text.push(' '),
+                _ => (),
+            }
+            previous = Some(t);
+            text.push_str(&t.text);
+        }
+        text
+    }
+
+    fn would_merge(lhs: &PpToken, rhs: &PpToken) -> bool {
+        let joined = format!("{}{}", lhs.text, rhs.text);
+        Self::tokenize(&joined)
+            .first()
+            .is_none_or(|t| t.text != lhs.text)
+    }
+
+    fn logical_lines(source: &str) -> Vec<Line> {
+        #[derive(PartialEq)]
+        enum State {
+            Code,
+            BlockComment,
+            LineComment,
+            Literal(char),
+        }
+
+        let mut lines = vec![];
+        let mut text = String::new();
+        let mut breaks = vec![];
+        let mut number = 1;
+        let mut physical = 1;
+        let mut state = State::Code;
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                continue;
+            }
+            if c == '\\' && matches!(chars.peek(), Some('\n') | Some('\r')) {
+                if chars.next() == Some('\r') {
+                    chars.next();
+                }
+                physical += 1;
+                breaks.push(text.len());
+                continue;
+            }
+            if c == '\n' {
+                physical += 1;
+                if state == State::BlockComment {
+                    breaks.push(text.len());
+                    continue;
+                }
+                state = State::Code;
+                lines.push(Line {
+                    number,
+                    text: std::mem::take(&mut text),
+                    breaks: std::mem::take(&mut breaks),
+                });
+                number = physical;
+                continue;
+            }
+
+            match state {
+                State::Code => match (c, chars.peek()) {
+                    ('/', Some('*')) => {
+                        chars.next();
+                        text.push_str("  ");
+                        state = State::BlockComment;
+                    }
+                    ('/', Some('/')) => state = State::LineComment,
+                    ('"' | '\'', _) => {
+                        text.push(c);
+                        state = State::Literal(c);
+                    }
+                    _ => text.push(c),
+                },
+                State::BlockComment => {
+                    text.push(' ');
+                    if c == '*' && chars.peek() == Some(&'/') {
+                        chars.next();
+                        text.push(' ');
+                        state = State::Code;
+                    }
+                }
+                State::LineComment => (),
+                State::Literal(quote) => {
+                    text.push(c);
+                    if c == '\\' {
+                        if let Some(&escaped) = chars.peek().filter(|e| **e != '\n') {
+                            chars.next();
+                            text.push(escaped);
+                        }
+                    } else if c == quote {
+                        state = State::Code;
+                    }
+                }
+            }
+        }
+
+        if !text.is_empty() {
+            lines.push(Line {
+                number,
+                text,
+                breaks,
+            });
+        }
+        lines
+    }
+
+    fn tokenize(text: &str) -> Vec<PpToken> {
+        let mut tokens = vec![];
+        let mut space = false;
+        let mut position = 0;
+
+        while let Some(c) = text[position..].chars().next() {
+            let start = position;
+            let rest = &text[position..];
+            let kind = if c.is_whitespace() {
+                position += c.len_utf8();
+                space = true;
+                continue;
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                position += rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                PpKind::Ident
+            } else if c.is_ascii_digit()
+                || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+            {
+                let bytes = rest.as_bytes();
+                let mut end = 1;
+                while end < bytes.len() {
+                    let b = bytes[end];
+                    let exponent_sign = matches!(b, b'+' | b'-')
+                        && matches!(bytes[end - 1], b'e' | b'E' | b'p' | b'P');
+                    if !(exponent_sign || b.is_ascii_alphanumeric() || b == b'_' || b == b'.') {
+                        break;
+                    }
+                    end += 1;
+                }
+                position += end;
+                PpKind::Number
+            } else if c == '"' || c == '\'' {
+                let mut escaped = false;
+                let mut end = rest.len();
+                for (i, d) in rest.char_indices().skip(1) {
+                    if escaped {
+                        escaped = false;
+                    } else if d == '\\' {
+                        escaped = true;
+                    } else if d == c {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                position += end;
+                PpKind::Literal
+            } else if let Some(punct) = PUNCTUATORS.iter().find(|p| rest.starts_with(**p)) {
+                position += punct.len();
+                PpKind::Punct
+            } else if "[](){}.&*+-~!/%<>^|?:;=,".contains(c) {
+                position += 1;
+                PpKind::Punct
+            } else {
+                position += c.len_utf8();
+                PpKind::Other
+            };
+
+            tokens.push(PpToken {
+                kind,
+                text: text[start..position].to_string(),
+                space,
+                column: Some(start),
+                hide: vec![],
+            });
+            space = false;
+        }
+        tokens
+    }
+}
+
+struct Evaluator<'t> {
+    tokens: &'t [PpToken],
+    position: usize,
+    directive: &'t PpToken,
+}
+
+impl Evaluator<'_> {
+    fn error(&self, token: Option<&PpToken>, message: &str) -> Diagnostic {
+        let span = token
+            .filter(|t| t.column.is_some())
+            .unwrap_or(self.directive)
+            .span();
+        Diagnostic::error(ErrorKind::InvalidDirective, message).with_span(span)
+    }
+
+    fn peek(&self) -> Option<&PpToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        match self.peek() {
+            Some(t) if t.is(PpKind::Punct, text) => {
+                self.position += 1;
+                Ok(())
+            }
+            t => Err(self.error(t, &format!("Expected {} in #if expression", text))),
+        }
+    }
+
+    fn conditional(&mut self, evaluate: bool) -> Result<i64> {
+        let condition = self.binary(1, evaluate)?;
+        if !self.peek().is_some_and(|t| t.is(PpKind::Punct, "?")) {
+            return Ok(condition);
+        }
+        self.position += 1;
+        let then = self.conditional(evaluate && condition != 0)?;
+        self.expect(":")?;
+        let otherwise = self.conditional(evaluate && condition == 0)?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn precedence(token: &PpToken) -> Option<u8> {
+        if token.kind != PpKind::Punct {
+            return None;
+        }
+        match token.text.as_str() {
+            "||" => Some(1),
+            "&&" => Some(2),
+            "|" => Some(3),
+            "^" => Some(4),
+            "&" => Some(5),
+            "==" | "!=" => Some(6),
+            "<" | ">" | "<=" | ">=" => Some(7),
+            "<<" | ">>" => Some(8),
+            "+" | "-" => Some(9),
+            "*" | "/" | "%" => Some(10),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, min: u8, evaluate: bool) -> Result<i64> {
+        let mut lhs = self.unary(evaluate)?;
+        while let Some(op) = self.peek().cloned()
+            && let Some(prec) = Self::precedence(&op).filter(|p| *p >= min)
+        {
+            self.position += 1;
+            let rhs_evaluate = match op.text.as_str() {
+                "&&" => evaluate && lhs != 0,
+                "||" => evaluate && lhs == 0,
+                _ => evaluate,
+            };
+            let rhs = self.binary(prec + 1, rhs_evaluate)?;
+            lhs = match op.text.as_str() {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => {
+                    if evaluate {
+                        return Err(self.error(Some(&op), "Division by zero in #if"));
+                    }
+                    0
+                }
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, evaluate: bool) -> Result<i64> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error(None, "#if expression ends unexpectedly"));
+        };
+        self.position += 1;
+        match (token.kind, token.text.as_str()) {
+            (PpKind::Punct, "+") => self.unary(evaluate),
+            (PpKind::Punct, "-") => Ok(self.unary(evaluate)?.wrapping_neg()),
+            (PpKind::Punct, "~") => Ok(!self.unary(evaluate)?),
+            (PpKind::Punct, "!") => Ok((self.unary(evaluate)? == 0) as i64),
+            (PpKind::Punct, "(") => {
+                let value = self.conditional(evaluate)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            (PpKind::Ident, _) => Ok(0),
+            (PpKind::Number, text) => Self::number(text)
+                .ok_or_else(|| self.error(Some(&token), &format!("Invalid integer {}", text))),
+            (PpKind::Literal, text) if text.starts_with('\'') => Self::character(text)
+                .ok_or_else(|| self.error(Some(&token), &format!("Invalid character {}", text))),
+            _ => Err(self.error(
+                Some(&token),
+                &format!("Token {} is not valid in #if expression", token.text),
+            )),
+        }
+    }
+
+    fn number(text: &str) -> Option<i64> {
+        let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+        let value = if let Some(hex) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            u64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            u64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse::<u64>()
+        };
+        value.ok().map(|v| v as i64)
+    }
+
+    fn character(text: &str) -> Option<i64> {
+        let inner = text.strip_prefix('\'')?.strip_suffix('\'')?;
+        let value = match inner.as_bytes() {
+            [c] => *c,
+            [b'\\', b'n'] => b'\n',
+            [b'\\', b't'] => b'\t',
+            [b'\\', b'r'] => b'\r',
+            [b'\\', b'0'] => 0,
+            [b'\\', c @ (b'\\' | b'\'' | b'"' | b'?')] => *c,
+            _ => return None,
+        };
+        Some(value as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source_map::SourceMap;
+
+    fn preprocess(source: &str) -> Preprocessed {
+        Preprocessor::new(vec![]).preprocess(Path::new("test.c"), source)
+    }
+
+    fn code(source: &str) -> Vec<String> {
+        let preprocessed = preprocess(source);
+        assert_eq!(preprocessed.errors, vec![]);
+        preprocessed
+            .text
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.trim().is_empty())
+            .map(|l| l.trim().to_string())
+            .collect()
+    }
+
+    fn error_kinds(source: &str) -> Vec<ErrorKind> {
+        preprocess(source).errors.iter().map(|d| d.kind).collect()
+    }
+
+    #[test]
+    fn comments_and_splices() {
+        let source = "int /* a */ x; // b\nint\\\n y = \"//\";\n/* multi\nline */ int z;";
+        assert_eq!(
+            code(source),
+            vec!["int         x;", "int", "y = \"//\";", "int z;"]
+        );
+        let text = preprocess(source).text;
+        let map = SourceMap::new("test.c", &text);
+        let line = |needle: &str| map.location(text.find(needle).unwrap());
+        assert_eq!(line("x;").line, 1);
+        assert_eq!(line("y =").line, 3);
+        assert_eq!(line("int z;").line, 5);
+        assert_eq!(line("int z;").column, 9);
+    }
+
+    #[test]
+    fn diagnostics_after_comments() {
+        let preprocessed = preprocess("/* a\n */\n#error a /* b\n c */ d\nint y;\n");
+        let span = preprocessed.errors[0].span.unwrap();
+        let text = &preprocessed.text;
+        assert_eq!(&text[span.start..span.end], "#error a     \n      d");
+        let map = SourceMap::new("test.c", text);
+        assert_eq!(map.location(text.find("int y;").unwrap()).line, 5);
+    }
+
+    #[test]
+    fn object_macros() {
+        assert_eq!(
+            code("#define N 10\n#define M N + N\nint x = M;\n#undef N\nint y = N;"),
+            vec!["int x = 10 + 10;", "int y = N;"]
+        );
+        assert_eq!(code("#define A -1\nint x = -A;"), vec!["int x = - -1;"]);
+        assert_eq!(code("#define X X + 1\nint x = X;"), vec!["int x = X + 1;"]);
+    }
+
+    #[test]
+    fn function_macros() {
+        assert_eq!(
+            code("#define MAX(a, b) ((a) > (b) ? (a) : (b))\nint x = MAX(1, f(2, 3));"),
+            vec!["int x = ((1) > (f(2, 3)) ? (1) : (f(2, 3)));"]
+        );
+        assert_eq!(
+            code("#define F(x) x\nint F = F(F)(1);"),
+            vec!["int F = F(1);"]
+        );
+        assert_eq!(
+            code("#define ADD(a,\\\n b) a + b\nint x = ADD(1,\n 2);\nint y;"),
+            vec!["int x = 1 + 2;", "int y;"]
+        );
+        assert_eq!(code("#define F() 1\nint x = F();"), vec!["int x = 1;"]);
+    }
+
+    #[test]
+    fn stringify_and_paste() {
+        assert_eq!(
+            code("#define S(x) #x\n#define CAT(a, b) a ## b\nS( a  \"b\" ) CAT(foo, 12) CAT(, x)"),
+            vec!["\"a \\\"b\\\"\" foo12 x"]
+        );
+        assert_eq!(
+            code("#define CAT(a, b) a ## b\n#define AB done\nCAT(A, B)"),
+            vec!["done"]
+        );
+    }
+
+    #[test]
+    fn builtins() {
+        assert_eq!(code("\n\nint x = __LINE__;"), vec!["int x = 3;"]);
+        assert_eq!(code("char *f = __FILE__;"), vec!["char *f = \"test.c\";"]);
+        assert_eq!(
+            code(
+                "/* a\n b */ int a = __LINE__;\nint b = \\\n __LINE__;\n#define L __LINE__\n/*\n*/ int c = L;"
+            ),
+            vec!["int a = 2;", "int b = 4", ";", "int c = 7;"]
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "#define A 2\n\
+                      #if A * 2 == 4 && !defined(B)\nyes1\n#else\nno1\n#endif\n\
+                      #ifdef B\nno2\n#elif defined A\nyes2\n#elif 1 / 0\nno3\n#endif\n\
+                      #ifndef A\n#if 1 / 0\n#endif\n#else\nyes3\n#endif\n\
+                      #if 0 ? 1 / 0 : (3 > 2 ? 0x10 : 0) == 16\nyes4\n#endif\n\
+                      #if 0 && 1 / 0\nno4\n#endif";
+        assert_eq!(code(source), vec!["yes1", "yes2", "yes3", "yes4"]);
+    }
+
+    #[test]
+    fn line_structure() {
+        let preprocessed = preprocess("#define X 1\n\nint x = X;\n#if 0\nno\n#endif\nint y;\n");
+        assert_eq!(
+            preprocessed.text,
+            "# 1 \"test.c\"\n\n\nint x = 1;\n\n\n\nint y;\n"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error_kinds("#error stop here\n#foo\n#endif\n#if 1 +\n#endif\n#if 1\n"),
+            vec![
+                ErrorKind::ErrorDirective,
+                ErrorKind::InvalidDirective,
+                ErrorKind::InvalidDirective,
+                ErrorKind::InvalidDirective,
+                ErrorKind::InvalidDirective,
+            ]
+        );
+        assert_eq!(
+            error_kinds("#define F(a, b) a\nF(1)\nF(1,\n"),
+            vec![ErrorKind::MacroArguments, ErrorKind::MacroArguments]
+        );
+        assert_eq!(
+            error_kinds("#include \"missing.h\"\n#include <missing.h>"),
+            vec![ErrorKind::IncludeNotFound, ErrorKind::IncludeNotFound]
+        );
+    }
+
+    #[test]
+    fn error_spans() {
+        let preprocessed = preprocess("int x;\n#error stop\nint y;");
+        let span = preprocessed.errors[0].span.unwrap();
+        assert_eq!(&preprocessed.text[span.start..span.end], "#error stop");
+
+        let preprocessed = preprocess(
+            "#define CAT(a, b) a ## b\n#define P x ## +\nint x = CAT(1, +);\nint y = P;\n",
+        );
+        let spans = preprocessed
+            .errors
+            .iter()
+            .map(|d| d.span.unwrap())
+            .map(|span| &preprocessed.text[span.start..span.end])
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["1", "P"]);
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("pp-includes-{}", std::process::id()));
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(dir.join("once.h"), "#pragma once\nint once;\n").unwrap();
+        fs::write(
+            sub.join("angle.h"),
+            "#include \"once.h\"\nint angle = VALUE;\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sub").join("once.h"), "int nested;\n").unwrap();
+        let main = dir.join("main.c");
+        fs::write(
+            &main,
+            "#define VALUE 7\n#include \"once.h\"\n#include \"once.h\"\n#include <angle.h>\nint main;\n",
+        )
+        .unwrap();
+
+        let preprocessed = Preprocessor::new(vec![sub.clone()]).preprocess_file(&main);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(preprocessed.errors, vec![]);
+        let code: Vec<_> = preprocessed
+            .text
+            .lines()
+            .filter(|l| !l.starts_with('#') && !l.is_empty())
+            .collect();
+        assert_eq!(
+            code,
+            vec!["int once;", "int nested;", "int angle = 7;", "int main;"]
+        );
+        assert!(
+            preprocessed
+                .text
+                .contains(&format!("# 5 \"{}\" 2", main.display()))
+        );
+    }
+}
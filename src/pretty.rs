@@ -0,0 +1,1040 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::codegen::{self, AsmTopLevel, AssemblyType, CondCode, Register};
+use crate::lexer::Token;
+use crate::parser::{
+    BinaryOperator, BlockItem, CompoundOperator, Crement, Declaration, Expression, ExpressionKind,
+    Fixity, ForInit, Function, Statement, StatementKind, StorageClass, UnaryOperator, Var,
+};
+use crate::semantic_analysis::{Attrs, SymbolTable};
+use crate::tacky::{self, TopLevel, Val};
+use crate::types::{Const, Type};
+
+const INDENT: &str = "    ";
+
+const ASSIGN: u8 = 1;
+const CONDITIONAL: u8 = 2;
+const UNARY: u8 = 13;
+const POSTFIX: u8 = 14;
+const PRIMARY: u8 = 15;
+
+pub fn tokens(source: &str, tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let _ = writeln!(
+            out,
+            "{}..{}\t{:?}\t{}",
+            token.start,
+            token.end,
+            token.kind,
+            &source[token.start..token.end]
+        );
+    }
+    out
+}
+
+pub fn ast(declarations: &[Declaration]) -> String {
+    declarations
+        .iter()
+        .map(|declaration| declaration.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn tacky(top_levels: &[TopLevel], symbols: &SymbolTable) -> String {
+    let mut sections = vec![];
+    let externs = tacky::text::externs(top_levels, symbols);
+    if !externs.is_empty() {
+        sections.push(externs.join("\n") + "\n");
+    }
+    sections.extend(
+        top_levels
+            .iter()
+            .map(|top_level| Typed(top_level, symbols).to_string()),
+    );
+    sections.join("\n")
+}
+
+pub fn assembly(top_levels: &[AsmTopLevel]) -> String {
+    top_levels
+        .iter()
+        .map(|top_level| top_level.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Printer<'f, 'a> {
+    f: &'f mut Formatter<'a>,
+    depth: usize,
+}
+
+impl Printer<'_, '_> {
+    fn line(&mut self, text: impl Display) -> fmt::Result {
+        writeln!(self.f, "{}{}", INDENT.repeat(self.depth), text)
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) -> fmt::Result {
+        match declaration {
+            Declaration::Var(var) => self.line(VarDecl(var)),
+            Declaration::Func(function) => self.function(function),
+        }
+    }
+
+    fn function(&mut self, function: &Function) -> fmt::Result {
+        let Type::Fun {
+            params: param_types,
+            ret,
+        } = &function.ty
+        else {
+            panic!("function {} without a function type", function.name);
+        };
+        let params = if function.params.is_empty() {
+            "void".to_string()
+        } else {
+            function
+                .params
+                .iter()
+                .zip(param_types)
+                .map(|(p, ty)| Declarator(ty, p).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let signature = format!(
+            "{}{}({})",
+            Storage(function.storage),
+            Declarator(ret, &function.name),
+            params
+        );
+        match &function.body {
+            None => self.line(format_args!("{};", signature)),
+            Some(body) => {
+                self.line(format_args!("{} {{", signature))?;
+                self.block_items(body)?;
+                self.line("}")
+            }
+        }
+    }
+
+    fn block_items(&mut self, items: &[BlockItem]) -> fmt::Result {
+        self.depth += 1;
+        for item in items {
+            match item {
+                BlockItem::D(declaration) => self.declaration(declaration)?,
+                BlockItem::S(statement) => self.statement(statement)?,
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn body(&mut self, header: impl Display, body: &Statement, dangling_else: bool) -> fmt::Result {
+        match &body.kind {
+            StatementKind::Compound(items) => {
+                self.line(format_args!("{} {{", header))?;
+                self.block_items(items)?;
+                self.line("}")
+            }
+            _ if dangling_else && Self::dangles(body) => {
+                self.line(format_args!("{} {{", header))?;
+                self.depth += 1;
+                self.statement(body)?;
+                self.depth -= 1;
+                self.line("}")
+            }
+            _ => {
+                self.line(header)?;
+                self.depth += 1;
+                self.statement(body)?;
+                self.depth -= 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn dangles(statement: &Statement) -> bool {
+        match &statement.kind {
+            StatementKind::If(_, _, None) => true,
+            StatementKind::If(_, _, Some(body))
+            | StatementKind::Label(_, body)
+            | StatementKind::While(_, _, body)
+            | StatementKind::For(_, _, _, _, body)
+            | StatementKind::Switch { body, .. }
+            | StatementKind::Case(_, _, body)
+            | StatementKind::Default(_, body) => Self::dangles(body),
+            _ => false,
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> fmt::Result {
+        match &statement.kind {
+            StatementKind::Return(expr) => self.line(format_args!("return {};", expr)),
+            StatementKind::Exp(expr) => self.line(format_args!("{};", expr)),
+            StatementKind::If(condition, then, otherwise) => {
+                self.body(
+                    format_args!("if ({})", condition),
+                    then,
+                    otherwise.is_some(),
+                )?;
+                if let Some(otherwise) = otherwise {
+                    self.body("else", otherwise, false)?;
+                }
+                Ok(())
+            }
+            StatementKind::Goto(label) => self.line(format_args!("goto {};", label)),
+            StatementKind::Label(label, body) => {
+                self.line(format_args!("{}:", label))?;
+                self.statement(body)
+            }
+            StatementKind::Compound(items) => {
+                self.line("{")?;
+                self.block_items(items)?;
+                self.line("}")
+            }
+            StatementKind::Break(_) => self.line("break;"),
+            StatementKind::Continue(_) => self.line("continue;"),
+            StatementKind::While(_, condition, body) => {
+                self.body(format_args!("while ({})", condition), body, false)
+            }
+            StatementKind::DoWhile(_, body, condition) => {
+                self.body("do", body, false)?;
+                self.line(format_args!("while ({});", condition))
+            }
+            StatementKind::For(_, init, condition, post, body) => {
+                let init = match &**init {
+                    ForInit::Decl(var) => VarDecl(var).to_string(),
+                    ForInit::Exp(expr) => format!("{};", expr),
+                    ForInit::Null => ";".to_string(),
+                };
+                let condition = condition.as_ref().map(|c| format!(" {}", c));
+                let post = post.as_ref().map(|p| format!(" {}", p));
+                let header = format!(
+                    "for ({}{};{})",
+                    init,
+                    condition.unwrap_or_default(),
+                    post.unwrap_or_default()
+                );
+                self.body(header, body, false)
+            }
+            StatementKind::Switch { expr, body, .. } => {
+                self.body(format_args!("switch ({})", expr), body, false)
+            }
+            StatementKind::Case(_, expr, body) => {
+                self.line(format_args!("case {}:", expr))?;
+                self.statement(body)
+            }
+            StatementKind::Default(_, body) => {
+                self.line("default:")?;
+                self.statement(body)
+            }
+            StatementKind::Null => self.line(";"),
+            StatementKind::Error => self.line("/* error */;"),
+        }
+    }
+}
+
+struct Storage(Option<StorageClass>);
+
+impl Display for Storage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(StorageClass::Static) => write!(f, "static "),
+            Some(StorageClass::Extern) => write!(f, "extern "),
+            None => Ok(()),
+        }
+    }
+}
+
+// A type followed by a name, with the name attached to a pointer's `*`.
+struct Declarator<'a>(&'a Type, &'a str);
+
+impl Display for Declarator<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let ty = self.0.to_string();
+        if ty.ends_with('*') {
+            write!(f, "{}{}", ty, self.1)
+        } else {
+            write!(f, "{} {}", ty, self.1)
+        }
+    }
+}
+
+struct VarDecl<'a>(&'a Var);
+
+impl Display for VarDecl<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            Storage(self.0.storage),
+            Declarator(&self.0.ty, &self.0.name)
+        )?;
+        if let Some(init) = &self.0.init {
+            write!(f, " = {}", init)?;
+        }
+        write!(f, ";")
+    }
+}
+
+impl Display for Declaration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer { f, depth: 0 }.declaration(self)
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer { f, depth: 0 }.statement(self)
+    }
+}
+
+impl Expression {
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExpressionKind::Constant(n) if n.value() < 0 => UNARY,
+            ExpressionKind::Constant(_) | ExpressionKind::Var(_) | ExpressionKind::Call(..) => {
+                PRIMARY
+            }
+            ExpressionKind::Unary(..)
+            | ExpressionKind::AddressOf(_)
+            | ExpressionKind::Dereference(_)
+            | ExpressionKind::Cast(..)
+            | ExpressionKind::Crement(Fixity::Pre, ..) => UNARY,
+            ExpressionKind::Crement(Fixity::Post, ..) => POSTFIX,
+            ExpressionKind::Binary(op, ..) => binary_precedence(*op),
+            ExpressionKind::Assign(..) | ExpressionKind::Compound(..) => ASSIGN,
+            ExpressionKind::Conditional(..) => CONDITIONAL,
+        }
+    }
+}
+
+fn binary_precedence(op: BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::Conditional => CONDITIONAL,
+        BinaryOperator::Or => 3,
+        BinaryOperator::And => 4,
+        BinaryOperator::BitOr => 5,
+        BinaryOperator::BitXOr => 6,
+        BinaryOperator::BitAnd => 7,
+        BinaryOperator::Equal | BinaryOperator::NotEqual => 8,
+        BinaryOperator::Less
+        | BinaryOperator::LessOrEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterOrEqual => 9,
+        BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 10,
+        BinaryOperator::Add | BinaryOperator::Subtract => 11,
+        BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 12,
+    }
+}
+
+struct Nested<'a>(&'a Expression, u8);
+
+impl Display for Nested<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Nested(expr, min) = *self;
+        if expr.precedence() < min {
+            write!(f, "({})", expr)
+        } else {
+            write!(f, "{}", expr)
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExpressionKind::Constant(n) => write!(f, "{}", n),
+            ExpressionKind::Var(name) => write!(f, "{}", name),
+            ExpressionKind::Unary(op, inner) => {
+                let op = match op {
+                    UnaryOperator::Complement => "~",
+                    UnaryOperator::Negate => "-",
+                    UnaryOperator::Not => "!",
+                };
+                let inner = Nested(inner, UNARY).to_string();
+                let space = if op == "-" && inner.starts_with('-') {
+                    " "
+                } else {
+                    ""
+                };
+                write!(f, "{}{}{}", op, space, inner)
+            }
+            ExpressionKind::Binary(op, lhs, rhs) => {
+                let precedence = binary_precedence(*op);
+                write!(
+                    f,
+                    "{} {} {}",
+                    Nested(lhs, precedence),
+                    binary_operator(*op),
+                    Nested(rhs, precedence + 1)
+                )
+            }
+            ExpressionKind::Compound(op, lhs, rhs) => write!(
+                f,
+                "{} {} {}",
+                Nested(lhs, UNARY),
+                compound_operator(*op),
+                Nested(rhs, ASSIGN)
+            ),
+            ExpressionKind::Assign(lhs, rhs) => {
+                write!(f, "{} = {}", Nested(lhs, UNARY), Nested(rhs, ASSIGN))
+            }
+            ExpressionKind::Conditional(condition, then, otherwise) => write!(
+                f,
+                "{} ? {} : {}",
+                Nested(condition, CONDITIONAL + 1),
+                then,
+                Nested(otherwise, CONDITIONAL)
+            ),
+            ExpressionKind::Crement(fixity, crement, inner) => {
+                let op = match crement {
+                    Crement::Inc => "++",
+                    Crement::Dec => "--",
+                };
+                match fixity {
+                    Fixity::Pre if inner.precedence() == POSTFIX => write!(f, "{}({})", op, inner),
+                    Fixity::Pre => write!(f, "{}{}", op, Nested(inner, UNARY)),
+                    Fixity::Post => write!(f, "{}{}", Nested(inner, POSTFIX), op),
+                }
+            }
+            ExpressionKind::Call(name, args) => {
+                let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            ExpressionKind::Cast(ty, inner) => write!(f, "({}) {}", ty, Nested(inner, UNARY)),
+            ExpressionKind::AddressOf(inner) => write!(f, "&{}", Nested(inner, UNARY)),
+            ExpressionKind::Dereference(inner) => write!(f, "*{}", Nested(inner, UNARY)),
+        }
+    }
+}
+
+fn binary_operator(op: BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Remainder => "%",
+        BinaryOperator::BitAnd => "&",
+        BinaryOperator::BitOr => "|",
+        BinaryOperator::BitXOr => "^",
+        BinaryOperator::ShiftLeft => "<<",
+        BinaryOperator::ShiftRight => ">>",
+        BinaryOperator::And => "&&",
+        BinaryOperator::Or => "||",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessOrEqual => "<=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterOrEqual => ">=",
+        BinaryOperator::Conditional => "?",
+    }
+}
+
+fn compound_operator(op: CompoundOperator) -> &'static str {
+    match op {
+        CompoundOperator::Add => "+=",
+        CompoundOperator::Subtract => "-=",
+        CompoundOperator::Multiply => "*=",
+        CompoundOperator::Divide => "/=",
+        CompoundOperator::Remainder => "%=",
+        CompoundOperator::BitAnd => "&=",
+        CompoundOperator::BitOr => "|=",
+        CompoundOperator::BitXOr => "^=",
+        CompoundOperator::ShiftLeft => "<<=",
+        CompoundOperator::ShiftRight => ">>=",
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Long => write!(f, "long"),
+            Type::UInt => write!(f, "unsigned int"),
+            Type::ULong => write!(f, "unsigned long"),
+            Type::Pointer(inner) if inner.is_pointer() => write!(f, "{}*", inner),
+            Type::Pointer(inner) => write!(f, "{} *", inner),
+            Type::Fun { params, ret } => {
+                let params = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "{}({})", ret, params.join(", "))
+            }
+        }
+    }
+}
+
+impl Display for Const {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Const::Int(n) => write!(f, "{}", n),
+            Const::Long(n) => write!(f, "{}L", n),
+            Const::UInt(n) => write!(f, "{}U", n),
+            Const::ULong(n) => write!(f, "{}UL", n),
+        }
+    }
+}
+
+impl Display for Val {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Val::Constant(n) => write!(f, "{}", n),
+            Val::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Display for tacky::UnaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            tacky::UnaryOp::Complement => "~",
+            tacky::UnaryOp::Negate => "-",
+            tacky::UnaryOp::Not => "!",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Display for tacky::BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            tacky::BinaryOp::Add => "+",
+            tacky::BinaryOp::Subtract => "-",
+            tacky::BinaryOp::Multiply => "*",
+            tacky::BinaryOp::Divide => "/",
+            tacky::BinaryOp::Remainder => "%",
+            tacky::BinaryOp::BitAnd => "&",
+            tacky::BinaryOp::BitOr => "|",
+            tacky::BinaryOp::BitXOr => "^",
+            tacky::BinaryOp::ShiftLeft => "<<",
+            tacky::BinaryOp::ShiftRight => ">>",
+            tacky::BinaryOp::LessThan => "<",
+            tacky::BinaryOp::LessThanEquals => "<=",
+            tacky::BinaryOp::GreaterThan => ">",
+            tacky::BinaryOp::GreaterThanEquals => ">=",
+            tacky::BinaryOp::Equals => "==",
+            tacky::BinaryOp::NotEquals => "!=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Display for tacky::Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            tacky::Instr::Return(val) => write!(f, "{}return {}", INDENT, val),
+            tacky::Instr::Unary { unop, src, dst } => {
+                write!(f, "{}{} = {} {}", INDENT, dst, unop, src)
+            }
+            tacky::Instr::Binary {
+                binop,
+                src1,
+                src2,
+                dst,
+            } => write!(f, "{}{} = {} {} {}", INDENT, dst, src1, binop, src2),
+            tacky::Instr::Copy { src, dst } => write!(f, "{}{} = {}", INDENT, dst, src),
+            tacky::Instr::SignExtend { src, dst } => {
+                write!(f, "{}{} = sign_extend {}", INDENT, dst, src)
+            }
+            tacky::Instr::Truncate { src, dst } => {
+                write!(f, "{}{} = truncate {}", INDENT, dst, src)
+            }
+            tacky::Instr::ZeroExtend { src, dst } => {
+                write!(f, "{}{} = zero_extend {}", INDENT, dst, src)
+            }
+            tacky::Instr::Jump { target } => write!(f, "{}jump {}", INDENT, target),
+            tacky::Instr::JumpIfZero { condition, target } => {
+                write!(f, "{}jump_if_zero {}, {}", INDENT, condition, target)
+            }
+            tacky::Instr::JumpIfNotZero { condition, target } => {
+                write!(f, "{}jump_if_not_zero {}, {}", INDENT, condition, target)
+            }
+            tacky::Instr::Label(label) => write!(f, "  {}:", label),
+            tacky::Instr::Call { name, params, dst } => {
+                let args = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "{}{} = call {}({})", INDENT, dst, name, args.join(", "))
+            }
+            tacky::Instr::GetAddress { src, dst } => {
+                write!(f, "{}{} = get_address {}", INDENT, dst, src)
+            }
+            tacky::Instr::Load { src_ptr, dst } => {
+                write!(f, "{}{} = load {}", INDENT, dst, src_ptr)
+            }
+            tacky::Instr::Store { src, dst_ptr } => {
+                write!(f, "{}store {}, {}", INDENT, src, dst_ptr)
+            }
+        }
+    }
+}
+
+impl Display for TopLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_top_level(f, self, &[])
+    }
+}
+
+// A top level along with the `long` declaration line its function needs to
+// be read back with the same types.
+struct Typed<'a>(&'a TopLevel, &'a SymbolTable);
+
+impl Display for Typed<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Typed(top_level, symbols) = *self;
+        let mut declared: Vec<(&Type, Vec<&str>)> = vec![];
+        if let TopLevel::TackyFunction {
+            params,
+            instructions,
+            ..
+        } = top_level
+        {
+            let vars = instructions
+                .iter()
+                .flat_map(instr_vals)
+                .filter_map(|val| match val {
+                    Val::Var(name) => Some(name),
+                    Val::Constant(_) => None,
+                });
+            for name in params.iter().chain(vars) {
+                let Some((ty, Attrs::Local)) = symbols.get(name) else {
+                    continue;
+                };
+                if *ty == Type::Int || declared.iter().any(|(_, names)| names.contains(&&**name)) {
+                    continue;
+                }
+                match declared
+                    .iter_mut()
+                    .find(|(declared_ty, _)| *declared_ty == ty)
+                {
+                    Some((_, names)) => names.push(name),
+                    None => declared.push((ty, vec![name])),
+                }
+            }
+        }
+        write_top_level(f, top_level, &declared)
+    }
+}
+
+fn instr_vals(instr: &tacky::Instr) -> Vec<&Val> {
+    match instr {
+        tacky::Instr::Return(val)
+        | tacky::Instr::JumpIfZero { condition: val, .. }
+        | tacky::Instr::JumpIfNotZero { condition: val, .. } => vec![val],
+        tacky::Instr::Unary { src, dst, .. }
+        | tacky::Instr::Copy { src, dst }
+        | tacky::Instr::SignExtend { src, dst }
+        | tacky::Instr::Truncate { src, dst }
+        | tacky::Instr::ZeroExtend { src, dst }
+        | tacky::Instr::GetAddress { src, dst }
+        | tacky::Instr::Load { src_ptr: src, dst }
+        | tacky::Instr::Store { src, dst_ptr: dst } => vec![src, dst],
+        tacky::Instr::Binary {
+            src1, src2, dst, ..
+        } => vec![src1, src2, dst],
+        tacky::Instr::Call { params, dst, .. } => params.iter().chain([dst]).collect(),
+        tacky::Instr::Jump { .. } | tacky::Instr::Label(_) => vec![],
+    }
+}
+
+fn write_top_level(
+    f: &mut Formatter<'_>,
+    top_level: &TopLevel,
+    declared: &[(&Type, Vec<&str>)],
+) -> fmt::Result {
+    match top_level {
+        TopLevel::TackyFunction {
+            name,
+            params,
+            instructions,
+            global,
+        } => {
+            let linkage = if *global { "global " } else { "" };
+            writeln!(f, "{}function {}({}) {{", linkage, name, params.join(", "))?;
+            for (ty, names) in declared {
+                writeln!(f, "{}{} {}", INDENT, ty, names.join(", "))?;
+            }
+            for instr in instructions {
+                writeln!(f, "{}", instr)?;
+            }
+            writeln!(f, "}}")
+        }
+        TopLevel::StaticVar { name, global, init } => {
+            let linkage = if *global { "global " } else { "" };
+            writeln!(f, "{}static {} = {}", linkage, name, init)
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Register::AX => "%eax",
+            Register::CX => "%ecx",
+            Register::DX => "%edx",
+            Register::DI => "%edi",
+            Register::SI => "%esi",
+            Register::R8 => "%r8d",
+            Register::R9 => "%r9d",
+            Register::R10 => "%r10d",
+            Register::R11 => "%r11d",
+            Register::BX => "%ebx",
+            Register::R12 => "%r12d",
+            Register::R13 => "%r13d",
+            Register::R14 => "%r14d",
+            Register::R15 => "%r15d",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for codegen::Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            codegen::Operand::Imm(n) => write!(f, "${}", n),
+            codegen::Operand::Reg(reg) => write!(f, "{}", reg),
+            codegen::Operand::Pseudo(name) => write!(f, "{}", name),
+            codegen::Operand::Stack(offset) => write!(f, "{}(%rbp)", offset),
+            codegen::Operand::Data(name) => write!(f, "{}(%rip)", name),
+            codegen::Operand::Memory(base, offset) => write!(
+                f,
+                "{}({})",
+                offset,
+                Sized(&codegen::Operand::Reg(*base), AssemblyType::Quadword)
+            ),
+        }
+    }
+}
+
+// An operand as an instruction of the given size refers to it.
+struct Sized<'a>(&'a codegen::Operand, AssemblyType);
+
+impl Display for Sized<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Sized(op, ty) = *self;
+        let codegen::Operand::Reg(reg) = op else {
+            return write!(f, "{}", op);
+        };
+        if ty == AssemblyType::Longword {
+            return write!(f, "{}", reg);
+        }
+        let name = match reg {
+            Register::AX => "%rax",
+            Register::CX => "%rcx",
+            Register::DX => "%rdx",
+            Register::DI => "%rdi",
+            Register::SI => "%rsi",
+            Register::R8 => "%r8",
+            Register::R9 => "%r9",
+            Register::R10 => "%r10",
+            Register::R11 => "%r11",
+            Register::BX => "%rbx",
+            Register::R12 => "%r12",
+            Register::R13 => "%r13",
+            Register::R14 => "%r14",
+            Register::R15 => "%r15",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn suffix(ty: AssemblyType) -> &'static str {
+    match ty {
+        AssemblyType::Longword => "l",
+        AssemblyType::Quadword => "q",
+    }
+}
+
+impl Display for CondCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            CondCode::E => "e",
+            CondCode::NE => "ne",
+            CondCode::G => "g",
+            CondCode::GE => "ge",
+            CondCode::L => "l",
+            CondCode::LE => "le",
+            CondCode::A => "a",
+            CondCode::AE => "ae",
+            CondCode::B => "b",
+            CondCode::BE => "be",
+        };
+        write!(f, "{}", code)
+    }
+}
+
+impl Display for codegen::Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use codegen::Instr;
+        match self {
+            Instr::Ret => write!(f, "{}ret", INDENT),
+            Instr::Mov { ty, src, dst } => write!(
+                f,
+                "{}mov{}\t{}, {}",
+                INDENT,
+                suffix(*ty),
+                Sized(src, *ty),
+                Sized(dst, *ty)
+            ),
+            Instr::Movsx { src, dst } => write!(
+                f,
+                "{}movslq\t{}, {}",
+                INDENT,
+                src,
+                Sized(dst, AssemblyType::Quadword)
+            ),
+            Instr::MovZeroExtend { src, dst } => write!(
+                f,
+                "{}movl\t{}, {}",
+                INDENT,
+                Sized(src, AssemblyType::Longword),
+                Sized(dst, AssemblyType::Longword)
+            ),
+            Instr::Unary { unop, ty, dst } => {
+                let op = match unop {
+                    codegen::UnaryOp::Neg => "neg",
+                    codegen::UnaryOp::Not => "not",
+                };
+                write!(f, "{}{}{}\t{}", INDENT, op, suffix(*ty), Sized(dst, *ty))
+            }
+            Instr::Binary {
+                binop,
+                ty,
+                src,
+                dst,
+            } => {
+                let op = match binop {
+                    codegen::BinaryOp::Add => "add",
+                    codegen::BinaryOp::Sub => "sub",
+                    codegen::BinaryOp::Mult => "imul",
+                    codegen::BinaryOp::BitAnd => "and",
+                    codegen::BinaryOp::BitOr => "or",
+                    codegen::BinaryOp::BitXOr => "xor",
+                    codegen::BinaryOp::ShiftLeft => "shl",
+                    codegen::BinaryOp::ShiftRight => "sar",
+                    codegen::BinaryOp::LogicalShiftRight => "shr",
+                };
+                write!(
+                    f,
+                    "{}{}{}\t{}, {}",
+                    INDENT,
+                    op,
+                    suffix(*ty),
+                    Sized(src, *ty),
+                    Sized(dst, *ty)
+                )
+            }
+            Instr::IDiv(ty, operand) => {
+                write!(f, "{}idiv{}\t{}", INDENT, suffix(*ty), Sized(operand, *ty))
+            }
+            Instr::Div(ty, operand) => {
+                write!(f, "{}div{}\t{}", INDENT, suffix(*ty), Sized(operand, *ty))
+            }
+            Instr::IMul(ty, operand) => {
+                write!(f, "{}imul{}\t{}", INDENT, suffix(*ty), Sized(operand, *ty))
+            }
+            Instr::Cdq(AssemblyType::Longword) => write!(f, "{}cdq", INDENT),
+            Instr::Cdq(AssemblyType::Quadword) => write!(f, "{}cqo", INDENT),
+            Instr::AllocateStack(n) => write!(f, "{}subq\t${}, %rsp", INDENT, n),
+            Instr::DeallocateStack(n) => write!(f, "{}addq\t${}, %rsp", INDENT, n),
+            Instr::Jmp(label) => write!(f, "{}jmp\t.L{}", INDENT, label),
+            Instr::JmpCC(code, label) => write!(f, "{}j{}\t.L{}", INDENT, code, label),
+            Instr::SetCC(code, operand) => write!(f, "{}set{}\t{}", INDENT, code, operand),
+            Instr::Label(label) => write!(f, ".L{}:", label),
+            Instr::Cmp { ty, lhs, rhs } => write!(
+                f,
+                "{}cmp{}\t{}, {}",
+                INDENT,
+                suffix(*ty),
+                Sized(lhs, *ty),
+                Sized(rhs, *ty)
+            ),
+            Instr::Test { ty, lhs, rhs } => write!(
+                f,
+                "{}test{}\t{}, {}",
+                INDENT,
+                suffix(*ty),
+                Sized(lhs, *ty),
+                Sized(rhs, *ty)
+            ),
+            Instr::Lea {
+                ty,
+                base,
+                index: Some(index),
+                offset,
+                dst,
+            } => write!(
+                f,
+                "{}lea{}\t{}({}, {}), {}",
+                INDENT,
+                suffix(*ty),
+                offset,
+                base,
+                index,
+                Sized(&codegen::Operand::Reg(*dst), *ty)
+            ),
+            Instr::Lea {
+                ty,
+                base,
+                index: None,
+                offset,
+                dst,
+            } => write!(
+                f,
+                "{}lea{}\t{}({}), {}",
+                INDENT,
+                suffix(*ty),
+                offset,
+                base,
+                Sized(&codegen::Operand::Reg(*dst), *ty)
+            ),
+            Instr::LoadAddress { src, dst } => write!(
+                f,
+                "{}leaq\t{}, {}",
+                INDENT,
+                src,
+                Sized(dst, AssemblyType::Quadword)
+            ),
+            Instr::Push(operand) => write!(f, "{}pushq\t{}", INDENT, operand),
+            Instr::Pop(reg) => write!(f, "{}popq\t{}", INDENT, reg),
+            Instr::Call(name) => write!(f, "{}call\t{}", INDENT, name),
+        }
+    }
+}
+
+impl Display for AsmTopLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmTopLevel::AsmFunction {
+                name,
+                instructions,
+                global,
+            } => {
+                if *global {
+                    writeln!(f, "{}.globl {}", INDENT, name)?;
+                }
+                writeln!(f, "{}:", name)?;
+                for instr in instructions {
+                    writeln!(f, "{}", instr)?;
+                }
+                Ok(())
+            }
+            AsmTopLevel::AsmStatic { name, global, init } => {
+                if *global {
+                    writeln!(f, "{}.globl {}", INDENT, name)?;
+                }
+                writeln!(f, "{}:", name)?;
+                let directive = match init {
+                    Const::Int(_) | Const::UInt(_) => "long",
+                    Const::Long(_) | Const::ULong(_) => "quad",
+                };
+                writeln!(f, "{}.{} {}", INDENT, directive, init.value())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::semantic_analysis;
+
+    fn parse(source: &str) -> Vec<Declaration> {
+        Parser::new(Lexer::new(source))
+            .parse()
+            .into_result()
+            .unwrap()
+    }
+
+    #[test]
+    fn ast_round_trip() {
+        let source = "static int n = 3;
+extern int f(int a, int b);
+long g(long a, int b);
+unsigned long h(unsigned int a);
+int **p(int *a, long **b);
+int main(void) {
+    long y = -5L;
+    unsigned long z = 5UL + 4294967295U;
+    z = (unsigned long) (int) -y * (long) z;
+    int x = -(-n) - -1;
+    int *q = (int *) &x;
+    *q = -*q * **&q;
+    x = (x = 2) * (1 + 2) - (3 - 4) - 5;
+    x += x ? 1 : x ? 2 : 3;
+    x = (x ? 1 : 2) ? x || 0 : 5;
+    x = ~!x++ << ++x >> 2 & 3 ^ 4 | 5 && 6;
+    for (;;)
+        if (x)
+            if (x > 1)
+                break;
+            else
+                continue;
+    if (x) {
+        if (x)
+            x--;
+    } else
+        switch (x) {
+            case 1:
+                return f(x, x = 1);
+            default:
+                goto end;
+        }
+    do
+        --x;
+    while (x >= 0);
+  end:
+    return n;
+}
+";
+        let printed = ast(&parse(source));
+        assert_eq!(ast(&parse(&printed)), printed);
+        assert!(printed.contains("int x = - -n - -1;"));
+        assert!(printed.contains("x = (x = 2) * (1 + 2) - (3 - 4) - 5;"));
+        assert!(printed.contains("x += x ? 1 : x ? 2 : 3;"));
+        assert!(printed.contains("long g(long a, int b);"));
+        assert!(printed.contains("long y = -5L;"));
+        assert!(printed.contains("int **p(int *a, long **b);"));
+        assert!(printed.contains("int *q = (int *) &x;"));
+        assert!(printed.contains("*q = -*q * **&q;"));
+
+        let dangling = "int main(void) { if (1) { if (2) return 1; } else return 2; }";
+        let printed = ast(&parse(dangling));
+        assert_eq!(ast(&parse(&printed)), printed);
+        assert!(printed.contains("    if (1) {\n        if (2)\n"));
+    }
+
+    fn lower(source: &str) -> (Vec<TopLevel>, SymbolTable) {
+        let (ast, mut symbols) = semantic_analysis::analyze(parse(source)).unwrap();
+        (tacky::emit_tacky(ast, &mut symbols).unwrap(), symbols)
+    }
+
+    #[test]
+    fn tacky_text() {
+        let (program, symbols) = lower(
+            "static int s = 4; extern int e; int f(int a, int b);
+            int main(void) { return s && -f(e, 2); }",
+        );
+        let text = tacky(&program, &symbols);
+        assert!(
+            text.starts_with("extern function f/2\nextern static e\n\nglobal function main() {\n")
+        );
+        assert!(text.contains("    jump_if_zero s, "));
+        assert!(text.contains(" = call f(e, 2)\n"));
+        assert!(text.contains(" = - call."));
+        assert!(text.ends_with("static s = 4\n"));
+    }
+
+    #[test]
+    fn assembly_text() {
+        let (program, symbols) = lower("int f(int a, int b) { return a / b; }");
+        let text = assembly(&codegen::select_instructions(program, &symbols).unwrap());
+        assert!(text.starts_with("    .globl f\nf:\n    movl\t%edi, a."));
+        assert!(text.contains("    cdq\n    idivl\t"));
+        assert!(text.contains("    ret\n"));
+
+        let (program, symbols) = lower("int f(int a) { return a / 3; }");
+        let text = assembly(&codegen::select_instructions(program, &symbols).unwrap());
+        assert!(text.contains("    movl\t$1431655766, %eax\n    imull\ta."));
+        assert!(!text.contains("idivl"));
+    }
+}
@@ -1,8 +1,11 @@
 use std::collections::{HashMap, HashSet};
 
+use crate::diagnostic::{Diagnostic, ErrorKind, Result, Span};
 use crate::parser::{
-    BlockItem, CaseInfo, Declaration, Expression, ForInit, Function, Statement, StorageClass, Var,
+    BinaryOperator, BlockItem, CaseInfo, CompoundOperator, Declaration, Expression, ExpressionKind,
+    ForInit, Function, Statement, StatementKind, StorageClass, UnaryOperator, Var,
 };
+use crate::types::{Const, Type};
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 enum Linkage {
@@ -28,23 +31,25 @@ struct ResolveState {
 }
 
 impl ResolveState {
-    pub fn block(&mut self, block_items: Vec<BlockItem>) -> Vec<BlockItem> {
+    pub fn block(&mut self, block_items: Vec<BlockItem>) -> Result<Vec<BlockItem>> {
         let mut resolved_items = Vec::new();
         for block_item in block_items {
             match block_item {
-                BlockItem::S(stmt) => resolved_items.push(BlockItem::S(self.statement(stmt))),
-                BlockItem::D(decl) => resolved_items.push(BlockItem::D(self.declaration(decl))),
+                BlockItem::S(stmt) => resolved_items.push(BlockItem::S(self.statement(stmt)?)),
+                BlockItem::D(decl) => resolved_items.push(BlockItem::D(self.declaration(decl)?)),
             }
         }
-        resolved_items
+        Ok(resolved_items)
     }
 
     fn block_var_declaration(
         &mut self,
         Var {
             name,
+            ty,
             init,
             storage,
+            span,
         }: Var,
     ) -> Var {
         self.put_env(
@@ -56,8 +61,10 @@ impl ResolveState {
         );
         Var {
             name,
+            ty,
             init,
             storage,
+            span,
         }
     }
 
@@ -65,15 +72,21 @@ impl ResolveState {
         &mut self,
         Var {
             name,
+            ty,
             init,
             storage,
+            span,
         }: Var,
-    ) -> Var {
+    ) -> Result<Var> {
         if self.current_scope_has(&name)
-            && let Some(ResolutionInfo { name, linkage }) = self.get_env(&name)
+            && let Some(ResolutionInfo { linkage, .. }) = self.get_env(&name)
             && !(*linkage != Linkage::None && storage == Some(StorageClass::Extern))
         {
-            panic!("Duplicate variable name {}", name);
+            return Err(Diagnostic::error(
+                ErrorKind::DuplicateDeclaration,
+                format!("Duplicate variable name {}", name),
+            )
+            .with_span(span));
         }
         if storage == Some(StorageClass::Extern) {
             let res_info = ResolutionInfo {
@@ -81,11 +94,13 @@ impl ResolveState {
                 linkage: Linkage::External,
             };
             self.put_env(name.clone(), res_info);
-            Var {
+            Ok(Var {
                 name,
+                ty,
                 storage,
                 init,
-            }
+                span,
+            })
         } else {
             let new_name = self.new_temp(name.clone());
             let res_info = ResolutionInfo {
@@ -94,18 +109,24 @@ impl ResolveState {
             };
             self.put_env(name, res_info);
 
-            let init = init.map(|exp| self.expression(exp));
-            Var {
+            let init = init.map(|exp| self.expression(exp)).transpose()?;
+            Ok(Var {
                 name: new_name,
+                ty,
                 init,
                 storage,
-            }
+                span,
+            })
         }
     }
 
-    fn param(&mut self, name: String) -> String {
+    fn param(&mut self, name: String, span: Span) -> Result<String> {
         if self.current_scope_has(&name) {
-            panic!("Duplicate variable name {}", name);
+            return Err(Diagnostic::error(
+                ErrorKind::DuplicateDeclaration,
+                format!("Duplicate parameter name {}", name),
+            )
+            .with_span(span));
         }
         let new_name = self.new_temp(name.clone());
         let res_info = ResolutionInfo {
@@ -113,7 +134,7 @@ impl ResolveState {
             linkage: Linkage::None,
         };
         self.put_env(name, res_info);
-        new_name
+        Ok(new_name)
     }
 
     fn func_declaration(
@@ -121,22 +142,32 @@ impl ResolveState {
         Function {
             name,
             params,
+            ty,
             body,
             storage,
+            span,
         }: Function,
         scope: DeclScope,
-    ) -> Function {
+    ) -> Result<Function> {
         if scope == DeclScope::Block && storage == Some(StorageClass::Static) {
-            panic!(
-                "Illegal static function declaration {} at block scope",
-                name
+            return Err(Diagnostic::error(
+                ErrorKind::InvalidStorageClass,
+                format!(
+                    "Illegal static function declaration {} at block scope",
+                    name
+                ),
             )
+            .with_span(span));
         }
         if self.current_scope_has(&name)
-            && let Some(ResolutionInfo { name, linkage }) = self.get_env(&name)
+            && let Some(ResolutionInfo { linkage, .. }) = self.get_env(&name)
             && *linkage == Linkage::None
         {
-            panic!("Duplicate function declaration {}", name);
+            return Err(Diagnostic::error(
+                ErrorKind::DuplicateDeclaration,
+                format!("Duplicate function declaration {}", name),
+            )
+            .with_span(span));
         }
 
         self.put_env(
@@ -151,169 +182,226 @@ impl ResolveState {
 
         let mut new_params = Vec::with_capacity(params.len());
         for param in params {
-            new_params.push(self.param(param));
+            new_params.push(self.param(param, span)?);
         }
 
         if body.is_some() && self.env.len() > 2 {
-            panic!("Nested function declaration {}", name)
+            return Err(Diagnostic::error(
+                ErrorKind::InvalidDeclaration,
+                format!("Nested function definition {}", name),
+            )
+            .with_span(span));
         }
 
-        let body = body.map(|body| self.block(body));
+        let body = body.map(|body| self.block(body)).transpose()?;
 
         self.env.pop();
 
-        Function {
+        Ok(Function {
             name,
             params: new_params,
+            ty,
             body,
             storage,
-        }
+            span,
+        })
     }
 
-    pub fn declaration(&mut self, decl: Declaration) -> Declaration {
+    pub fn declaration(&mut self, decl: Declaration) -> Result<Declaration> {
         match decl {
-            Declaration::Var(var) => Declaration::Var(self.local_var_declaration(var)),
-            Declaration::Func(func) => {
-                Declaration::Func(self.func_declaration(func, DeclScope::Block))
-            }
+            Declaration::Var(var) => Ok(Declaration::Var(self.local_var_declaration(var)?)),
+            Declaration::Func(func) => Ok(Declaration::Func(
+                self.func_declaration(func, DeclScope::Block)?,
+            )),
         }
     }
 
-    pub fn statement(&mut self, stmt: Statement) -> Statement {
-        match stmt {
-            Statement::Null => Statement::Null,
-            Statement::Return(expr) => Statement::Return(self.expression(expr)),
-            Statement::Exp(expr) => Statement::Exp(self.expression(expr)),
-            Statement::If(cond, if_stmt, else_stmt) => {
-                let cond = self.expression(cond);
-                let if_stmt = self.statement(*if_stmt);
-                let else_stmt = else_stmt.map(|else_stmt| Box::new(self.statement(*else_stmt)));
-                Statement::If(cond, Box::new(if_stmt), else_stmt)
+    pub fn statement(&mut self, Statement { kind, span }: Statement) -> Result<Statement> {
+        let kind = match kind {
+            StatementKind::Null => StatementKind::Null,
+            StatementKind::Error => {
+                return Err(
+                    Diagnostic::internal("Erroneous statement reached semantic analysis")
+                        .with_span(span),
+                );
+            }
+            StatementKind::Return(expr) => StatementKind::Return(self.expression(expr)?),
+            StatementKind::Exp(expr) => StatementKind::Exp(self.expression(expr)?),
+            StatementKind::If(cond, if_stmt, else_stmt) => {
+                let cond = self.expression(cond)?;
+                let if_stmt = self.statement(*if_stmt)?;
+                let else_stmt = else_stmt
+                    .map(|else_stmt| self.statement(*else_stmt).map(Box::new))
+                    .transpose()?;
+                StatementKind::If(cond, Box::new(if_stmt), else_stmt)
             }
-            Statement::Label(id, stmt) => {
-                let stmt = self.statement(*stmt);
-                Statement::Label(id, Box::new(stmt))
+            StatementKind::Label(id, stmt) => {
+                let stmt = self.statement(*stmt)?;
+                StatementKind::Label(id, Box::new(stmt))
             }
-            Statement::Goto(id) => Statement::Goto(id),
-            Statement::Compound(block_items) => {
+            StatementKind::Goto(id) => StatementKind::Goto(id),
+            StatementKind::Compound(block_items) => {
                 self.env.push(HashMap::new());
-                let block_items = self.block(block_items);
+                let block_items = self.block(block_items)?;
                 self.env.pop();
-                Statement::Compound(block_items)
+                StatementKind::Compound(block_items)
             }
-            Statement::Break(id) => Statement::Break(id),
-            Statement::Continue(id) => Statement::Continue(id),
-            Statement::DoWhile(label, body, cond) => Statement::DoWhile(
+            StatementKind::Break(id) => StatementKind::Break(id),
+            StatementKind::Continue(id) => StatementKind::Continue(id),
+            StatementKind::DoWhile(label, body, cond) => StatementKind::DoWhile(
                 label,
-                Box::new(self.statement(*body)),
-                self.expression(cond),
+                Box::new(self.statement(*body)?),
+                self.expression(cond)?,
             ),
-            Statement::While(label, cond, body) => Statement::While(
+            StatementKind::While(label, cond, body) => StatementKind::While(
                 label,
-                self.expression(cond),
-                Box::new(self.statement(*body)),
+                self.expression(cond)?,
+                Box::new(self.statement(*body)?),
             ),
-            Statement::For(label, init, cond, post, body) => {
+            StatementKind::For(label, init, cond, post, body) => {
                 self.env.push(HashMap::new());
-                let init = match init {
-                    ForInit::Decl(decl) => ForInit::Decl(self.local_var_declaration(decl)),
-                    ForInit::Exp(expr) => ForInit::Exp(self.expression(expr)),
+                let init = match *init {
+                    ForInit::Decl(decl) => ForInit::Decl(self.local_var_declaration(decl)?),
+                    ForInit::Exp(expr) => ForInit::Exp(self.expression(expr)?),
                     ForInit::Null => ForInit::Null,
                 };
-                let cond = cond.map(|cond| self.expression(cond));
-                let post = post.map(|post| self.expression(post));
-                let body = self.statement(*body);
+                let cond = cond.map(|cond| self.expression(cond)).transpose()?;
+                let post = post.map(|post| self.expression(post)).transpose()?;
+                let body = self.statement(*body)?;
                 self.env.pop();
-                Statement::For(label, init, cond, post, Box::new(body))
+                StatementKind::For(label, Box::new(init), cond, post, Box::new(body))
             }
-            Statement::Case(label, expr, body) => Statement::Case(
+            StatementKind::Case(label, expr, body) => StatementKind::Case(
                 label,
-                self.expression(expr),
-                Box::new(self.statement(*body)),
+                self.expression(expr)?,
+                Box::new(self.statement(*body)?),
             ),
-            Statement::Default(label, body) => {
-                Statement::Default(label, Box::new(self.statement(*body)))
+            StatementKind::Default(label, body) => {
+                StatementKind::Default(label, Box::new(self.statement(*body)?))
             }
-            Statement::Switch {
+            StatementKind::Switch {
                 label,
                 expr,
                 body,
                 cases,
-            } => Statement::Switch {
+            } => StatementKind::Switch {
                 label,
-                expr: self.expression(expr),
-                body: Box::new(self.statement(*body)),
+                expr: self.expression(expr)?,
+                body: Box::new(self.statement(*body)?),
                 cases,
             },
-        }
+        };
+        Ok(Statement::new(kind, span))
     }
 
-    pub fn expression(&mut self, expr: Expression) -> Expression {
-        match expr {
-            Expression::Assign(lhs, rhs) => {
-                if let Expression::Var(_) = *lhs {
-                    Expression::Assign(
-                        Box::new(self.expression(*lhs)),
-                        Box::new(self.expression(*rhs)),
+    pub fn expression(&mut self, Expression { kind, span, .. }: Expression) -> Result<Expression> {
+        let kind = match kind {
+            ExpressionKind::Assign(lhs, rhs) => {
+                if is_lvalue(&lhs) {
+                    ExpressionKind::Assign(
+                        Box::new(self.expression(*lhs)?),
+                        Box::new(self.expression(*rhs)?),
                     )
                 } else {
-                    panic!("Assignment to non-lvalue {:?}", lhs);
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidLvalue,
+                        "Assignment to non-lvalue",
+                    )
+                    .with_span(span));
                 }
             }
-            Expression::Var(id) => {
+            ExpressionKind::Var(id) => {
                 if let Some(ResolutionInfo { name, .. }) = self.get_env(&id) {
-                    Expression::Var(name.to_string())
+                    ExpressionKind::Var(name.to_string())
                 } else {
-                    panic!("Undeclared variable {:?}", id);
+                    return Err(Diagnostic::error(
+                        ErrorKind::UndeclaredIdentifier,
+                        format!("Undeclared variable {}", id),
+                    )
+                    .with_span(span));
                 }
             }
-            Expression::Unary(unop, expr) => {
-                Expression::Unary(unop, Box::new(self.expression(*expr)))
+            ExpressionKind::Unary(unop, expr) => {
+                ExpressionKind::Unary(unop, Box::new(self.expression(*expr)?))
             }
-            Expression::Binary(binop, lhs, rhs) => Expression::Binary(
+            ExpressionKind::Binary(binop, lhs, rhs) => ExpressionKind::Binary(
                 binop,
-                Box::new(self.expression(*lhs)),
-                Box::new(self.expression(*rhs)),
+                Box::new(self.expression(*lhs)?),
+                Box::new(self.expression(*rhs)?),
             ),
-            Expression::Compound(compound_op, lhs, rhs) => {
-                if let Expression::Var(_) = *lhs {
-                    Expression::Compound(
+            ExpressionKind::Compound(compound_op, lhs, rhs) => {
+                if is_lvalue(&lhs) {
+                    ExpressionKind::Compound(
                         compound_op,
-                        Box::new(self.expression(*lhs)),
-                        Box::new(self.expression(*rhs)),
+                        Box::new(self.expression(*lhs)?),
+                        Box::new(self.expression(*rhs)?),
                     )
                 } else {
-                    panic!("Compound operation on non-value {:?}", lhs)
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidLvalue,
+                        "Compound assignment to non-lvalue",
+                    )
+                    .with_span(span));
                 }
             }
-            Expression::Constant(n) => Expression::Constant(n),
-            Expression::Crement(fixity, crement, expr) => {
-                if let Expression::Var(_) = *expr {
-                    Expression::Crement(fixity, crement, Box::new(self.expression(*expr)))
+            ExpressionKind::Constant(n) => ExpressionKind::Constant(n),
+            ExpressionKind::Crement(fixity, crement, expr) => {
+                if is_lvalue(&expr) {
+                    ExpressionKind::Crement(fixity, crement, Box::new(self.expression(*expr)?))
                 } else {
-                    panic!("Increment/decrement operation on non-lvalue {:?}", expr);
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidLvalue,
+                        "Increment/decrement operation on non-lvalue",
+                    )
+                    .with_span(span));
                 }
             }
-            Expression::Conditional(cond_expr, if_expr, else_expr) => {
-                let cond_expr = self.expression(*cond_expr);
-                let if_expr = self.expression(*if_expr);
-                let else_expr = self.expression(*else_expr);
-                Expression::Conditional(Box::new(cond_expr), Box::new(if_expr), Box::new(else_expr))
+            ExpressionKind::Conditional(cond_expr, if_expr, else_expr) => {
+                let cond_expr = self.expression(*cond_expr)?;
+                let if_expr = self.expression(*if_expr)?;
+                let else_expr = self.expression(*else_expr)?;
+                ExpressionKind::Conditional(
+                    Box::new(cond_expr),
+                    Box::new(if_expr),
+                    Box::new(else_expr),
+                )
             }
-            Expression::Call(name, args) => {
+            ExpressionKind::Call(name, args) => {
                 if let Some(ResolutionInfo { name, .. }) = self.get_env(&name) {
                     let name = name.to_string();
                     let mut new_args = Vec::with_capacity(args.len());
                     for arg in args {
-                        new_args.push(self.expression(arg));
+                        new_args.push(self.expression(arg)?);
                     }
 
-                    Expression::Call(name, new_args)
+                    ExpressionKind::Call(name, new_args)
                 } else {
-                    panic!("Undeclared function {}", name);
+                    return Err(Diagnostic::error(
+                        ErrorKind::UndeclaredIdentifier,
+                        format!("Undeclared function {}", name),
+                    )
+                    .with_span(span));
                 }
             }
-        }
+            ExpressionKind::Cast(ty, expr) => {
+                ExpressionKind::Cast(ty, Box::new(self.expression(*expr)?))
+            }
+            ExpressionKind::AddressOf(expr) => {
+                if is_lvalue(&expr) {
+                    ExpressionKind::AddressOf(Box::new(self.expression(*expr)?))
+                } else {
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidLvalue,
+                        "Address of non-lvalue",
+                    )
+                    .with_span(span));
+                }
+            }
+            ExpressionKind::Dereference(expr) => {
+                ExpressionKind::Dereference(Box::new(self.expression(*expr)?))
+            }
+        };
+        Ok(Expression::new(kind, span))
     }
 
     fn new_temp(&mut self, var_name: String) -> String {
@@ -340,9 +428,14 @@ impl ResolveState {
     }
 }
 
-pub fn analyze(
-    declarations: Vec<Declaration>,
-) -> (Vec<Declaration>, HashMap<String, (Type, Attrs)>) {
+fn is_lvalue(expr: &Expression) -> bool {
+    matches!(
+        expr.kind,
+        ExpressionKind::Var(_) | ExpressionKind::Dereference(_)
+    )
+}
+
+pub fn analyze(declarations: Vec<Declaration>) -> Result<(Vec<Declaration>, SymbolTable)> {
     let mut analyzed = Vec::with_capacity(declarations.len());
     let mut resolve_state = ResolveState {
         env: vec![HashMap::new()],
@@ -352,10 +445,10 @@ pub fn analyze(
     for declaration in declarations {
         match declaration {
             Declaration::Func(function) => {
-                let function = resolve_state.func_declaration(function, DeclScope::File);
+                let function = resolve_state.func_declaration(function, DeclScope::File)?;
 
-                check_labels(&function);
-                let mut function = label_loops(function);
+                check_labels(&function)?;
+                let mut function = label_loops(function)?;
                 let Function {
                     name: _,
                     body: ref mut block_items,
@@ -363,7 +456,7 @@ pub fn analyze(
                 } = function;
 
                 if let Some(b) = block_items {
-                    gather_block(b, None)
+                    gather_block(b, None)?
                 }
 
                 analyzed.push(Declaration::Func(function));
@@ -375,72 +468,82 @@ pub fn analyze(
         }
     }
 
-    let symbols = TypeChecker::check_program(&analyzed);
-
-    (analyzed, symbols)
+    TypeChecker::check_program(analyzed)
 }
 
-fn check_labels(Function { body, .. }: &Function) {
+fn check_labels(Function { body, .. }: &Function) -> Result<()> {
     let mut label_ids = HashSet::new();
-    let mut gotos = HashSet::new();
+    let mut gotos = HashMap::new();
     if let Some(b) = body {
-        check_block_label(b, &mut label_ids, &mut gotos);
+        check_block_label(b, &mut label_ids, &mut gotos)?;
     }
 
-    for goto in gotos {
+    for (goto, span) in gotos {
         if !label_ids.contains(&goto) {
-            panic!("Goto to unknown label {}", goto)
+            return Err(Diagnostic::error(
+                ErrorKind::UnknownLabel,
+                format!("Goto to unknown label {}", goto),
+            )
+            .with_span(span));
         }
     }
+    Ok(())
 }
 
 fn check_block_label(
     block_items: &Vec<BlockItem>,
     label_ids: &mut HashSet<String>,
-    gotos: &mut HashSet<String>,
-) {
+    gotos: &mut HashMap<String, Span>,
+) -> Result<()> {
     for block_item in block_items {
         if let BlockItem::S(stmt) = block_item {
-            check_statement_label(stmt, label_ids, gotos);
+            check_statement_label(stmt, label_ids, gotos)?;
         }
     }
+    Ok(())
 }
 
 fn check_statement_label(
     label: &Statement,
     label_ids: &mut HashSet<String>,
-    gotos: &mut HashSet<String>,
-) {
-    match label {
-        Statement::Label(id, stmt) => {
+    gotos: &mut HashMap<String, Span>,
+) -> Result<()> {
+    match &label.kind {
+        StatementKind::Label(id, stmt) => {
             if label_ids.contains(id) {
-                panic!("Duplicate label {:?}", id)
+                return Err(Diagnostic::error(
+                    ErrorKind::DuplicateLabel,
+                    format!("Duplicate label {}", id),
+                )
+                .with_span(label.span));
             }
             label_ids.insert(id.to_string());
-            check_statement_label(stmt, label_ids, gotos);
+            check_statement_label(stmt, label_ids, gotos)?;
         }
-        Statement::If(_cond, if_stmt, else_stmt) => {
-            check_statement_label(if_stmt, label_ids, gotos);
+        StatementKind::If(_cond, if_stmt, else_stmt) => {
+            check_statement_label(if_stmt, label_ids, gotos)?;
             if let Some(stmt) = else_stmt {
-                check_statement_label(stmt, label_ids, gotos)
+                check_statement_label(stmt, label_ids, gotos)?
             }
         }
-        Statement::Compound(block_items) => check_block_label(block_items, label_ids, gotos),
-        Statement::While(_, _, body) => check_statement_label(body, label_ids, gotos),
-        Statement::For(_, _, _, _, body) => check_statement_label(body, label_ids, gotos),
-        Statement::DoWhile(_, body, _) => check_statement_label(body, label_ids, gotos),
-        Statement::Switch { body, .. } => check_statement_label(body, label_ids, gotos),
-        Statement::Case(_, _, stmt) => check_statement_label(stmt, label_ids, gotos),
-        Statement::Default(_, stmt) => check_statement_label(stmt, label_ids, gotos),
-        Statement::Goto(label) => {
-            gotos.insert(label.to_string());
+        StatementKind::Compound(block_items) => check_block_label(block_items, label_ids, gotos)?,
+        StatementKind::While(_, _, body) => check_statement_label(body, label_ids, gotos)?,
+        StatementKind::For(_, _, _, _, body) => check_statement_label(body, label_ids, gotos)?,
+        StatementKind::DoWhile(_, body, _) => check_statement_label(body, label_ids, gotos)?,
+        StatementKind::Switch { body, .. } => check_statement_label(body, label_ids, gotos)?,
+        StatementKind::Case(_, _, stmt) => check_statement_label(stmt, label_ids, gotos)?,
+        StatementKind::Default(_, stmt) => check_statement_label(stmt, label_ids, gotos)?,
+        StatementKind::Goto(target) => {
+            gotos.entry(target.to_string()).or_insert(label.span);
         }
-        Statement::Break(_)
-        | Statement::Continue(_)
-        | Statement::Exp(_)
-        | Statement::Null
-        | Statement::Return(_) => (),
+        StatementKind::Break(_)
+        | StatementKind::Continue(_)
+        | StatementKind::Exp(_)
+        | StatementKind::Null
+        | StatementKind::Error
+        | StatementKind::Return(_) => (),
     }
+    Ok(())
 }
 
 struct Labeller {
@@ -462,15 +565,21 @@ fn label_loops(
         name,
         body,
         params,
+        ty,
         storage,
+        span,
     }: Function,
-) -> Function {
-    Function {
+) -> Result<Function> {
+    Ok(Function {
         name,
-        body: body.map(|body| Labeller::new().label_block(body, None, None)),
+        body: body
+            .map(|body| Labeller::new().label_block(body, None, None))
+            .transpose()?,
         params,
+        ty,
         storage,
-    }
+        span,
+    })
 }
 
 impl Labeller {
@@ -483,7 +592,7 @@ impl Labeller {
         block_items: Vec<BlockItem>,
         break_label: Option<String>,
         continue_label: Option<String>,
-    ) -> Vec<BlockItem> {
+    ) -> Result<Vec<BlockItem>> {
         let mut labeled = Vec::with_capacity(block_items.len());
         for block_item in block_items {
             match block_item {
@@ -491,94 +600,117 @@ impl Labeller {
                     stmt,
                     break_label.clone(),
                     continue_label.clone(),
-                ))),
+                )?)),
                 decl => labeled.push(decl),
             }
         }
-        labeled
+        Ok(labeled)
     }
 
     fn label_statement(
         &mut self,
-        stmt: Statement,
+        Statement { kind, span }: Statement,
         break_label: Option<String>,
         continue_label: Option<String>,
-    ) -> Statement {
-        match stmt {
-            stmt @ (Statement::Return(_)
-            | Statement::Exp(_)
-            | Statement::Goto(_)
-            | Statement::Null) => stmt,
-            Statement::If(cond, if_stmt, else_stmt) => Statement::If(
+    ) -> Result<Statement> {
+        let kind = match kind {
+            kind @ (StatementKind::Return(_)
+            | StatementKind::Exp(_)
+            | StatementKind::Goto(_)
+            | StatementKind::Null
+            | StatementKind::Error) => kind,
+            StatementKind::If(cond, if_stmt, else_stmt) => StatementKind::If(
                 cond,
                 Box::new(self.label_statement(
                     *if_stmt,
                     break_label.clone(),
                     continue_label.clone(),
-                )),
+                )?),
                 else_stmt
-                    .map(|stmt| Box::new(self.label_statement(*stmt, break_label, continue_label))),
+                    .map(|stmt| {
+                        self.label_statement(*stmt, break_label, continue_label)
+                            .map(Box::new)
+                    })
+                    .transpose()?,
             ),
-            Statement::Label(id, stmt) => Statement::Label(
+            StatementKind::Label(id, stmt) => StatementKind::Label(
                 id,
-                Box::new(self.label_statement(*stmt, break_label, continue_label)),
+                Box::new(self.label_statement(*stmt, break_label, continue_label)?),
             ),
-            Statement::Break(_) if break_label.is_none() => {
-                panic!("Break statement outside of loop or switch")
-            }
-            Statement::Break(_) => Statement::Break(break_label.unwrap().to_string()),
-            Statement::Continue(_) if continue_label.is_none() => {
-                panic!("Continue statement outside of loop")
-            }
-            Statement::Continue(_) => Statement::Continue(continue_label.unwrap().to_string()),
-            Statement::Compound(block_items) => {
-                Statement::Compound(self.label_block(block_items, break_label, continue_label))
-            }
-            Statement::While(_, cond, body) => {
+            StatementKind::Break(_) => match break_label {
+                Some(label) => StatementKind::Break(label),
+                None => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::MisplacedStatement,
+                        "Break statement outside of loop or switch",
+                    )
+                    .with_span(span));
+                }
+            },
+            StatementKind::Continue(_) => match continue_label {
+                Some(label) => StatementKind::Continue(label),
+                None => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::MisplacedStatement,
+                        "Continue statement outside of loop",
+                    )
+                    .with_span(span));
+                }
+            },
+            StatementKind::Compound(block_items) => StatementKind::Compound(self.label_block(
+                block_items,
+                break_label,
+                continue_label,
+            )?),
+            StatementKind::While(_, cond, body) => {
                 let new_label = self.new_label(LabelType::While);
                 let body =
-                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()));
-                Statement::While(new_label.to_string(), cond, Box::new(body))
+                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()))?;
+                StatementKind::While(new_label.to_string(), cond, Box::new(body))
             }
-            Statement::DoWhile(_, body, cond) => {
+            StatementKind::DoWhile(_, body, cond) => {
                 let new_label = self.new_label(LabelType::DoWhile);
                 let body =
-                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()));
-                Statement::DoWhile(new_label.to_string(), Box::new(body), cond)
+                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()))?;
+                StatementKind::DoWhile(new_label.to_string(), Box::new(body), cond)
             }
-            Statement::For(_, init_decl, cond, post, body) => {
+            StatementKind::For(_, init_decl, cond, post, body) => {
                 let new_label = self.new_label(LabelType::For);
                 let body =
-                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()));
-                Statement::For(new_label.to_string(), init_decl, cond, post, Box::new(body))
+                    self.label_statement(*body, Some(new_label.clone()), Some(new_label.clone()))?;
+                StatementKind::For(new_label.to_string(), init_decl, cond, post, Box::new(body))
             }
-            Statement::Case(_, expr, stmt) => {
-                let stmt = self.label_statement(*stmt, break_label.clone(), continue_label);
+            StatementKind::Case(_, expr, stmt) => {
+                let stmt = self.label_statement(*stmt, break_label.clone(), continue_label)?;
                 let l = self.new_label(LabelType::Case);
-                Statement::Case(l.to_string(), expr, Box::new(stmt))
+                StatementKind::Case(l.to_string(), expr, Box::new(stmt))
             }
-            Statement::Default(_, stmt) => {
-                let stmt = self.label_statement(*stmt, break_label, continue_label);
+            StatementKind::Default(_, stmt) => {
+                let stmt = self.label_statement(*stmt, break_label, continue_label)?;
                 let l = self.new_label(LabelType::Default);
-                Statement::Default(l.to_string(), Box::new(stmt))
+                StatementKind::Default(l.to_string(), Box::new(stmt))
             }
-            Statement::Switch {
+            StatementKind::Switch {
                 label: _,
                 expr,
                 body,
                 cases,
             } => {
                 let new_label = self.new_label(LabelType::Switch);
-                let body =
-                    Box::new(self.label_statement(*body, Some(new_label.clone()), continue_label));
-                Statement::Switch {
+                let body = Box::new(self.label_statement(
+                    *body,
+                    Some(new_label.clone()),
+                    continue_label,
+                )?);
+                StatementKind::Switch {
                     label: new_label,
                     expr,
                     body,
                     cases,
                 }
             }
-        }
+        };
+        Ok(Statement::new(kind, span))
     }
 
     fn new_label(&mut self, label_type: LabelType) -> String {
@@ -596,61 +728,91 @@ impl Labeller {
     }
 }
 
-fn gather_block(block_items: &mut Vec<BlockItem>, mut cases: Option<&mut Vec<CaseInfo>>) {
+fn gather_block(
+    block_items: &mut Vec<BlockItem>,
+    mut cases: Option<&mut Vec<CaseInfo>>,
+) -> Result<()> {
     for block_item in block_items {
         if let BlockItem::S(stmt) = block_item {
-            gather_statement(stmt, cases.as_deref_mut()); // TODO bad
+            gather_statement(stmt, cases.as_deref_mut())?; // TODO bad
         }
     }
+    Ok(())
 }
 
-fn gather_statement(stmt: &mut Statement, mut cases: Option<&mut Vec<CaseInfo>>) {
-    match stmt {
-        Statement::If(_, if_stmt, else_stmt) => {
-            gather_statement(if_stmt, cases.as_deref_mut());
+fn gather_statement(stmt: &mut Statement, mut cases: Option<&mut Vec<CaseInfo>>) -> Result<()> {
+    match &mut stmt.kind {
+        StatementKind::If(_, if_stmt, else_stmt) => {
+            gather_statement(if_stmt, cases.as_deref_mut())?;
             if let Some(stmt) = else_stmt {
-                gather_statement(stmt, cases);
+                gather_statement(stmt, cases)?;
             }
         }
-        Statement::Label(_, stmt) => gather_statement(stmt, cases),
-        Statement::Compound(block_items) => gather_block(block_items, cases),
-        Statement::While(_, _, stmt) => gather_statement(stmt, cases),
-        Statement::For(_, _, _, _, body) => gather_statement(body, cases),
-        Statement::DoWhile(_, body, _) => gather_statement(body, cases),
-        Statement::Switch {
+        StatementKind::Label(_, stmt) => gather_statement(stmt, cases)?,
+        StatementKind::Compound(block_items) => gather_block(block_items, cases)?,
+        StatementKind::While(_, _, stmt) => gather_statement(stmt, cases)?,
+        StatementKind::For(_, _, _, _, body) => gather_statement(body, cases)?,
+        StatementKind::DoWhile(_, body, _) => gather_statement(body, cases)?,
+        StatementKind::Switch {
             label: _,
             expr: _,
             body,
             cases,
-        } => gather_statement(body, Some(cases)),
-        Statement::Case(label, expr, stmt) => {
-            gather_statement(stmt, cases.as_deref_mut());
-            match expr {
-                Expression::Constant(n) if cases.is_some() => {
+        } => gather_statement(body, Some(cases))?,
+        StatementKind::Case(label, expr, stmt) => {
+            gather_statement(stmt, cases.as_deref_mut())?;
+            match &expr.kind {
+                ExpressionKind::Constant(n) if cases.is_some() => {
                     let c = cases.unwrap();
                     if c.iter()
                         .any(|ci| matches!(ci, CaseInfo::Case { expr: m, label: _ } if n == m))
                     {
-                        panic!("Duplicate case in switch statement");
+                        return Err(Diagnostic::error(
+                            ErrorKind::DuplicateCase,
+                            format!("Duplicate case {} in switch statement", n),
+                        )
+                        .with_span(stmt.span));
                     }
                     c.push(CaseInfo::Case {
                         expr: *n,
                         label: label.to_string(),
                     });
                 }
-                _ if cases.is_some() => panic!("Non-integral expression in case"),
-                _ => panic!("Case outside of switch statement"),
+                _ if cases.is_some() => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::NonConstantExpression,
+                        "Non-integral expression in case",
+                    )
+                    .with_span(stmt.span));
+                }
+                _ => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::MisplacedStatement,
+                        "Case outside of switch statement",
+                    )
+                    .with_span(stmt.span));
+                }
             }
         }
-        Statement::Default(label, stmt) => {
-            gather_statement(stmt, cases.as_deref_mut());
+        StatementKind::Default(label, stmt) => {
+            gather_statement(stmt, cases.as_deref_mut())?;
             match cases {
-                None => panic!("Default outside of switch statement"),
+                None => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::MisplacedStatement,
+                        "Default outside of switch statement",
+                    )
+                    .with_span(stmt.span));
+                }
                 Some(c) => {
                     if c.iter()
                         .any(|ci| matches!(ci, CaseInfo::Default { label: _ }))
                     {
-                        panic!("Duplicate default inside of switch")
+                        return Err(Diagnostic::error(
+                            ErrorKind::DuplicateCase,
+                            "Duplicate default inside of switch",
+                        )
+                        .with_span(stmt.span));
                     }
                     c.push(CaseInfo::Default {
                         label: label.to_string(),
@@ -660,13 +822,11 @@ fn gather_statement(stmt: &mut Statement, mut cases: Option<&mut Vec<CaseInfo>>)
         }
         _ => (),
     }
+    Ok(())
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
-pub enum Type {
-    Int,
-    Fun { param_count: u8 },
-}
+pub type SymbolTable = HashMap<String, (Type, Attrs)>;
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum Attrs {
     Fun { defined: bool, global: bool },
@@ -677,27 +837,32 @@ pub enum Attrs {
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum InitValue {
     Tentative,
-    Initial(i32),
+    Initial(Const),
     NoInit,
 }
 
 struct TypeChecker {
-    symbols: HashMap<String, (Type, Attrs)>,
+    symbols: SymbolTable,
+    return_type: Type,
 }
 
 impl TypeChecker {
-    fn check_program(program: &Vec<Declaration>) -> HashMap<String, (Type, Attrs)> {
+    fn check_program(program: Vec<Declaration>) -> Result<(Vec<Declaration>, SymbolTable)> {
         let mut type_checker = TypeChecker {
             symbols: HashMap::new(),
+            return_type: Type::Int,
         };
 
+        let mut checked = Vec::with_capacity(program.len());
         for declaration in program {
-            match declaration {
-                Declaration::Func(function) => type_checker.check_function_decl(function),
-                Declaration::Var(var) => type_checker.check_file_var_decl(var),
-            }
+            checked.push(match declaration {
+                Declaration::Func(function) => {
+                    Declaration::Func(type_checker.check_function_decl(function)?)
+                }
+                Declaration::Var(var) => Declaration::Var(type_checker.check_file_var_decl(var)?),
+            });
         }
-        type_checker.symbols
+        Ok((checked, type_checker.symbols))
     }
 
     fn check_function_decl(
@@ -705,146 +870,257 @@ impl TypeChecker {
         Function {
             name,
             params,
+            ty,
             body,
             storage,
-        }: &Function,
-    ) {
+            span,
+        }: Function,
+    ) -> Result<Function> {
         let mut already_defined = false;
-        let mut global = *storage != Some(StorageClass::Static);
-        if let Some(ty) = self.symbols.get(name) {
+        let mut global = storage != Some(StorageClass::Static);
+        if let Some(old) = self.symbols.get(&name) {
             if let (
-                Type::Fun { param_count },
+                old_ty @ Type::Fun { .. },
                 Attrs::Fun {
                     defined,
                     global: old_global,
                 },
-            ) = ty
+            ) = old
             {
-                if *param_count != params.len() as u8 {
-                    panic!(
-                        "Incompatible declaration of function {} with first declaration having {} params, second having {}",
-                        name,
-                        param_count,
-                        params.len()
-                    );
+                if *old_ty != ty {
+                    return Err(Diagnostic::error(
+                        ErrorKind::ConflictingDeclaration,
+                        format!(
+                            "Incompatible declaration of function {} with first declaration having type {}, second having {}",
+                            name, old_ty, ty
+                        ),
+                    )
+                    .with_span(span));
                 }
                 if *defined && body.is_some() {
-                    panic!("Duplicate definition of function {}", name);
+                    return Err(Diagnostic::error(
+                        ErrorKind::DuplicateDefinition,
+                        format!("Duplicate definition of function {}", name),
+                    )
+                    .with_span(span));
                 }
                 already_defined = *defined;
-                if *old_global && *storage == Some(StorageClass::Static) {
-                    panic!("Static function declaration {} follows non-static", name);
+                if *old_global && storage == Some(StorageClass::Static) {
+                    return Err(Diagnostic::error(
+                        ErrorKind::ConflictingLinkage,
+                        format!("Static function declaration {} follows non-static", name),
+                    )
+                    .with_span(span));
                 }
                 global = *old_global;
             } else {
-                panic!("Function {} already defined as variable", name);
+                return Err(Diagnostic::error(
+                    ErrorKind::ConflictingDeclaration,
+                    format!("Function {} already defined as variable", name),
+                )
+                .with_span(span));
             }
         }
-        let fun_type = Type::Fun {
-            param_count: params.len() as u8,
-        };
         let attrs = Attrs::Fun {
             defined: body.is_some() || already_defined,
             global,
         };
 
-        self.symbols.insert(name.to_string(), (fun_type, attrs));
+        self.symbols.insert(name.to_string(), (ty.clone(), attrs));
 
-        if let Some(block_items) = body {
-            for param in params {
-                self.symbols
-                    .insert(param.to_string(), (Type::Int, Attrs::Local));
+        let body = match (body, &ty) {
+            (Some(block_items), Type::Fun { params: types, ret }) => {
+                for (param, param_ty) in params.iter().zip(types) {
+                    self.symbols
+                        .insert(param.to_string(), (param_ty.clone(), Attrs::Local));
+                }
+                self.return_type = *ret.clone();
+                Some(self.check_block(block_items)?)
             }
-            self.check_block(block_items);
-        }
+            (body, _) => body,
+        };
+        Ok(Function {
+            name,
+            params,
+            ty,
+            body,
+            storage,
+            span,
+        })
     }
 
-    fn check_block(&mut self, block_items: &Vec<BlockItem>) {
+    fn check_block(&mut self, block_items: Vec<BlockItem>) -> Result<Vec<BlockItem>> {
+        let mut checked = Vec::with_capacity(block_items.len());
         for block_item in block_items {
-            match block_item {
-                BlockItem::D(decl) => match decl {
-                    Declaration::Var(var) => self.check_block_var_decl(var),
-                    Declaration::Func(func) => self.check_function_decl(func),
-                },
-                BlockItem::S(stmt) => self.check_statement(stmt),
-            }
+            checked.push(match block_item {
+                BlockItem::D(Declaration::Var(var)) => {
+                    BlockItem::D(Declaration::Var(self.check_block_var_decl(var)?))
+                }
+                BlockItem::D(Declaration::Func(func)) => {
+                    BlockItem::D(Declaration::Func(self.check_function_decl(func)?))
+                }
+                BlockItem::S(stmt) => BlockItem::S(self.check_statement(stmt)?),
+            });
         }
+        Ok(checked)
     }
 
-    fn check_statement(&mut self, stmt: &Statement) {
-        match stmt {
-            Statement::Return(expr) => self.check_expr(expr),
-            Statement::Exp(expr) => self.check_expr(expr),
-            Statement::If(cond, if_stmt, else_stmt) => {
-                self.check_expr(cond);
-                self.check_statement(if_stmt);
-                if let Some(else_stmt) = else_stmt.as_ref() {
-                    self.check_statement(else_stmt)
-                }
+    fn check_statement(&mut self, Statement { kind, span }: Statement) -> Result<Statement> {
+        let kind = match kind {
+            StatementKind::Return(expr) => {
+                let return_type = self.return_type.clone();
+                StatementKind::Return(self.check_and_convert(expr, &return_type)?)
+            }
+            StatementKind::Exp(expr) => StatementKind::Exp(self.check_expr(expr)?),
+            StatementKind::If(cond, if_stmt, else_stmt) => StatementKind::If(
+                self.check_expr(cond)?,
+                Box::new(self.check_statement(*if_stmt)?),
+                else_stmt
+                    .map(|else_stmt| self.check_statement(*else_stmt).map(Box::new))
+                    .transpose()?,
+            ),
+            StatementKind::Label(label, stmt) => {
+                StatementKind::Label(label, Box::new(self.check_statement(*stmt)?))
             }
-            Statement::Goto(_) => (),
-            Statement::Label(_, stmt) => self.check_statement(stmt),
-            Statement::Compound(block_items) => self.check_block(block_items),
-            Statement::Break(_) => (),
-            Statement::Continue(_) => (),
-            Statement::While(_, cond, body) => {
-                self.check_expr(cond);
-                self.check_statement(body);
+            StatementKind::Compound(block_items) => {
+                StatementKind::Compound(self.check_block(block_items)?)
             }
-            Statement::For(_, for_init, cond, post, body) => {
-                self.check_for_init(for_init);
-                if let Some(cond) = cond.as_ref() {
-                    self.check_expr(cond)
+            StatementKind::While(label, cond, body) => StatementKind::While(
+                label,
+                self.check_expr(cond)?,
+                Box::new(self.check_statement(*body)?),
+            ),
+            StatementKind::For(label, for_init, cond, post, body) => StatementKind::For(
+                label,
+                Box::new(self.check_for_init(*for_init)?),
+                cond.map(|cond| self.check_expr(cond)).transpose()?,
+                post.map(|post| self.check_expr(post)).transpose()?,
+                Box::new(self.check_statement(*body)?),
+            ),
+            StatementKind::DoWhile(label, body, cond) => StatementKind::DoWhile(
+                label,
+                Box::new(self.check_statement(*body)?),
+                self.check_expr(cond)?,
+            ),
+            StatementKind::Switch {
+                label,
+                expr,
+                body,
+                cases,
+            } => {
+                let expr = self.check_expr(expr)?;
+                if expr.ty().is_pointer() {
+                    return Err(Diagnostic::error(
+                        ErrorKind::TypeMismatch,
+                        format!("Switch on pointer type {}", expr.ty()),
+                    )
+                    .with_span(expr.span));
                 }
-                if let Some(post) = post.as_ref() {
-                    self.check_expr(post)
+                let expr = promote(expr);
+                let ty = expr.ty().clone();
+                // Case values compare against the controlling expression, so
+                // they take its type, and only then can collide.
+                let mut converted: Vec<CaseInfo> = Vec::with_capacity(cases.len());
+                for case in cases {
+                    let case = match case {
+                        CaseInfo::Case { expr, label } => CaseInfo::Case {
+                            expr: expr.convert(&ty),
+                            label,
+                        },
+                        default => default,
+                    };
+                    if let CaseInfo::Case { expr, .. } = &case
+                        && converted
+                            .iter()
+                            .any(|ci| matches!(ci, CaseInfo::Case { expr: m, .. } if m == expr))
+                    {
+                        return Err(Diagnostic::error(
+                            ErrorKind::DuplicateCase,
+                            format!("Duplicate case {} in switch statement", expr),
+                        )
+                        .with_span(span));
+                    }
+                    converted.push(case);
+                }
+                StatementKind::Switch {
+                    label,
+                    expr,
+                    body: Box::new(self.check_statement(*body)?),
+                    cases: converted,
                 }
-                self.check_statement(body);
-            }
-            Statement::DoWhile(_, body, cond) => {
-                self.check_statement(body);
-                self.check_expr(cond);
-            }
-            Statement::Switch { expr, body, .. } => {
-                self.check_expr(expr);
-                self.check_statement(body);
             }
-            Statement::Case(_, expr, stmt) => {
-                self.check_expr(expr);
-                self.check_statement(stmt);
+            StatementKind::Case(label, expr, stmt) => StatementKind::Case(
+                label,
+                self.check_expr(expr)?,
+                Box::new(self.check_statement(*stmt)?),
+            ),
+            StatementKind::Default(label, stmt) => {
+                StatementKind::Default(label, Box::new(self.check_statement(*stmt)?))
             }
-            Statement::Default(_, stmt) => self.check_statement(stmt),
-            Statement::Null => (),
+            kind @ (StatementKind::Goto(_)
+            | StatementKind::Break(_)
+            | StatementKind::Continue(_)
+            | StatementKind::Null
+            | StatementKind::Error) => kind,
+        };
+        Ok(Statement::new(kind, span))
+    }
+
+    fn static_init(var: &Var) -> Result<Option<Const>> {
+        match &var.init {
+            Some(Expression {
+                kind: ExpressionKind::Constant(c),
+                ..
+            }) if !var.ty.is_pointer() || c.is_zero() => Ok(Some(c.convert(&var.ty))),
+            Some(Expression {
+                kind: ExpressionKind::Constant(_),
+                ..
+            }) => Err(Diagnostic::error(
+                ErrorKind::InvalidInitializer,
+                format!("Pointer {} initialized with a non-null constant", var.name),
+            )
+            .with_span(var.span)),
+            None => Ok(None),
+            _ => Err(Diagnostic::error(
+                ErrorKind::NonConstantExpression,
+                format!("Non-constant initialization of variable {}", var.name),
+            )
+            .with_span(var.span)),
         }
     }
 
-    fn check_file_var_decl(
-        &mut self,
-        Var {
+    fn check_file_var_decl(&mut self, var: Var) -> Result<Var> {
+        let Var {
             name,
-            init,
+            ty,
             storage,
-        }: &Var,
-    ) {
-        let mut init = match init {
-            Some(Expression::Constant(n)) => InitValue::Initial(*n),
-            None => {
-                if *storage == Some(StorageClass::Extern) {
-                    InitValue::NoInit
-                } else {
-                    InitValue::Tentative
-                }
-            }
-            _ => panic!("Non-constant initialization of variable {}", name),
+            span,
+            ..
+        } = &var;
+        let mut init = match Self::static_init(&var)? {
+            Some(c) => InitValue::Initial(c),
+            None if *storage == Some(StorageClass::Extern) => InitValue::NoInit,
+            None => InitValue::Tentative,
         };
 
         let mut global = *storage != Some(StorageClass::Static);
         match self.symbols.get(name) {
             Some((Type::Fun { .. }, _)) => {
-                panic!("Function {} redeclared as variable", name)
+                return Err(Diagnostic::error(
+                    ErrorKind::ConflictingDeclaration,
+                    format!("Function {} redeclared as variable", name),
+                )
+                .with_span(*span));
+            }
+            Some((old_ty, _)) if old_ty != ty => {
+                return Err(Diagnostic::error(
+                    ErrorKind::ConflictingDeclaration,
+                    format!("Conflicting types for variable {}", name),
+                )
+                .with_span(*span));
             }
             Some((
-                Type::Int,
+                _,
                 Attrs::Static {
                     init: old_init,
                     global: old_global,
@@ -853,11 +1129,19 @@ impl TypeChecker {
                 if *storage == Some(StorageClass::Extern) {
                     global = *old_global;
                 } else if *old_global != global {
-                    panic!("Conflicting linkage of variable {}", name);
+                    return Err(Diagnostic::error(
+                        ErrorKind::ConflictingLinkage,
+                        format!("Conflicting linkage of variable {}", name),
+                    )
+                    .with_span(*span));
                 }
                 if let InitValue::Initial(_) = old_init {
                     if let InitValue::Initial(_) = init {
-                        panic!("Conflicting file scope definitions of variable {}", name);
+                        return Err(Diagnostic::error(
+                            ErrorKind::DuplicateDefinition,
+                            format!("Conflicting file scope definitions of variable {}", name),
+                        )
+                        .with_span(*span));
                     }
                     init = *old_init;
                 } else if *old_init == InitValue::Tentative
@@ -870,126 +1154,589 @@ impl TypeChecker {
         }
         self.symbols.insert(
             name.to_string(),
-            (Type::Int, Attrs::Static { init, global }),
+            (ty.clone(), Attrs::Static { init, global }),
         );
+        Ok(var)
     }
 
-    fn check_block_var_decl(
-        &mut self,
-        Var {
-            name,
-            init,
-            storage,
-        }: &Var,
-    ) {
-        match storage {
+    fn check_block_var_decl(&mut self, var: Var) -> Result<Var> {
+        match var.storage {
             Some(StorageClass::Extern) => {
-                if init.is_some() {
-                    panic!("Initializer on local extern declaration {}", name);
+                if var.init.is_some() {
+                    return Err(Diagnostic::error(
+                        ErrorKind::InvalidInitializer,
+                        format!("Initializer on local extern declaration {}", var.name),
+                    )
+                    .with_span(var.span));
                 }
-                if self.symbols.contains_key(name) {
-                    if let Some((Type::Fun { .. }, _)) = self.symbols.get(name) {
-                        panic!("Function {} redeclared as variable", name);
+                match self.symbols.get(&var.name) {
+                    Some((Type::Fun { .. }, _)) => {
+                        return Err(Diagnostic::error(
+                            ErrorKind::ConflictingDeclaration,
+                            format!("Function {} redeclared as variable", var.name),
+                        )
+                        .with_span(var.span));
+                    }
+                    Some((old_ty, _)) if *old_ty != var.ty => {
+                        return Err(Diagnostic::error(
+                            ErrorKind::ConflictingDeclaration,
+                            format!("Conflicting types for variable {}", var.name),
+                        )
+                        .with_span(var.span));
+                    }
+                    Some(_) => (),
+                    None => {
+                        self.symbols.insert(
+                            var.name.to_string(),
+                            (
+                                var.ty.clone(),
+                                Attrs::Static {
+                                    init: InitValue::NoInit,
+                                    global: true,
+                                },
+                            ),
+                        );
                     }
-                } else {
-                    self.symbols.insert(
-                        name.to_string(),
-                        (
-                            Type::Int,
-                            Attrs::Static {
-                                init: InitValue::NoInit,
-                                global: true,
-                            },
-                        ),
-                    );
                 }
+                Ok(var)
             }
             Some(StorageClass::Static) => {
-                let init = match init {
-                    Some(Expression::Constant(n)) => InitValue::Initial(*n),
-                    None => InitValue::Initial(0),
-                    _ => panic!("Non-constant initialization of variable {}", name),
-                };
+                let init = Self::static_init(&var)?.unwrap_or(Const::zero(&var.ty));
                 self.symbols.insert(
-                    name.to_string(),
+                    var.name.to_string(),
                     (
-                        Type::Int,
+                        var.ty.clone(),
                         Attrs::Static {
-                            init,
+                            init: InitValue::Initial(init),
                             global: false,
                         },
                     ),
                 );
+                Ok(var)
             }
             None => {
                 self.symbols
-                    .insert(name.to_string(), (Type::Int, Attrs::Local));
-                if let Some(expr) = init {
-                    self.check_expr(expr)
-                };
+                    .insert(var.name.to_string(), (var.ty.clone(), Attrs::Local));
+                let init = var
+                    .init
+                    .map(|expr| self.check_and_convert(expr, &var.ty))
+                    .transpose()?;
+                Ok(Var { init, ..var })
             }
         }
     }
 
-    fn check_for_init(&mut self, for_init: &ForInit) {
+    fn check_for_init(&mut self, for_init: ForInit) -> Result<ForInit> {
         match for_init {
             ForInit::Decl(Var {
                 storage: Some(StorageClass::Static),
                 name,
+                span,
                 ..
-            }) => panic!("Static initializer {} in for loop", name),
-            ForInit::Decl(var) => self.check_block_var_decl(var),
-            ForInit::Exp(expr) => self.check_expr(expr),
-            ForInit::Null => (),
+            }) => Err(Diagnostic::error(
+                ErrorKind::InvalidStorageClass,
+                format!("Static initializer {} in for loop", name),
+            )
+            .with_span(span)),
+            ForInit::Decl(var) => Ok(ForInit::Decl(self.check_block_var_decl(var)?)),
+            ForInit::Exp(expr) => Ok(ForInit::Exp(self.check_expr(expr)?)),
+            ForInit::Null => Ok(ForInit::Null),
         }
     }
 
-    fn check_expr(&mut self, expr: &Expression) {
-        match expr {
-            Expression::Constant(_) => (),
-            Expression::Unary(_, expr) => self.check_expr(expr),
-            Expression::Binary(_, lhs, rhs) => {
-                self.check_expr(lhs);
-                self.check_expr(rhs);
-            }
-            Expression::Compound(_, lhs, rhs) => {
-                self.check_expr(lhs);
-                self.check_expr(rhs);
-            }
-            Expression::Crement(_, _, expr) => self.check_expr(expr),
-            Expression::Var(id) => {
-                if let Some((Type::Fun { .. }, _)) = self.symbols.get(id) {
-                    panic!("Function {} used as variable", id)
-                }
-            }
-            Expression::Assign(lhs, rhs) => {
-                self.check_expr(lhs);
-                self.check_expr(rhs);
-            }
-            Expression::Conditional(cond, if_expr, else_expr) => {
-                self.check_expr(cond);
-                self.check_expr(if_expr);
-                self.check_expr(else_expr);
-            }
-            Expression::Call(name, params) => match self.symbols.get(name) {
-                Some((Type::Int, _)) => panic!("Variable {} used as function", name),
-                Some((Type::Fun { param_count, .. }, _)) => {
-                    if *param_count != params.len() as u8 {
-                        panic!(
-                            "Mismatched parameter count: declared as {}, called with {}",
-                            param_count,
-                            params.len()
+    fn check_and_convert(&mut self, expr: Expression, ty: &Type) -> Result<Expression> {
+        let expr = self.check_expr(expr)?;
+        convert_by_assignment(expr, ty)
+    }
+
+    // Every checked expression carries its type, with the conversions C
+    // applies implicitly spelled out as casts.
+    fn check_expr(&mut self, Expression { kind, span, .. }: Expression) -> Result<Expression> {
+        let (kind, ty) = match kind {
+            ExpressionKind::Constant(c) => (ExpressionKind::Constant(c), c.ty()),
+            ExpressionKind::Unary(UnaryOperator::Not, inner) => {
+                let inner = self.check_expr(*inner)?;
+                (
+                    ExpressionKind::Unary(UnaryOperator::Not, Box::new(inner)),
+                    Type::Int,
+                )
+            }
+            ExpressionKind::Unary(unop, inner) => {
+                let inner = promote(arithmetic(self.check_expr(*inner)?)?);
+                let ty = inner.ty().clone();
+                (ExpressionKind::Unary(unop, Box::new(inner)), ty)
+            }
+            ExpressionKind::Binary(
+                binop @ (BinaryOperator::And | BinaryOperator::Or),
+                lhs,
+                rhs,
+            ) => {
+                let lhs = self.check_expr(*lhs)?;
+                let rhs = self.check_expr(*rhs)?;
+                (
+                    ExpressionKind::Binary(binop, Box::new(lhs), Box::new(rhs)),
+                    Type::Int,
+                )
+            }
+            // The count of a shift doesn't take part in the usual arithmetic
+            // conversions; the result has the type of the left operand.
+            ExpressionKind::Binary(
+                binop @ (BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight),
+                lhs,
+                rhs,
+            ) => {
+                let lhs = promote(arithmetic(self.check_expr(*lhs)?)?);
+                let rhs = promote(arithmetic(self.check_expr(*rhs)?)?);
+                let ty = lhs.ty().clone();
+                (
+                    ExpressionKind::Binary(binop, Box::new(lhs), Box::new(rhs)),
+                    ty,
+                )
+            }
+            // Pointers can be compared with each other, and for equality
+            // with a null pointer constant, but take part in no arithmetic.
+            ExpressionKind::Binary(binop, lhs, rhs) => {
+                let lhs = self.check_expr(*lhs)?;
+                let rhs = self.check_expr(*rhs)?;
+                let common = if !lhs.ty().is_pointer() && !rhs.ty().is_pointer() {
+                    common_type(lhs.ty(), rhs.ty())
+                } else if matches!(binop, BinaryOperator::Equal | BinaryOperator::NotEqual) {
+                    common_pointer_type(&lhs, &rhs, span)?
+                } else if is_comparison(binop) && lhs.ty() == rhs.ty() {
+                    lhs.ty().clone()
+                } else {
+                    return Err(Diagnostic::error(
+                        ErrorKind::TypeMismatch,
+                        format!(
+                            "Invalid operands of types {} and {} to {:?}",
+                            lhs.ty(),
+                            rhs.ty(),
+                            binop
+                        ),
+                    )
+                    .with_span(span));
+                };
+                let lhs = convert_to(lhs, &common);
+                let rhs = convert_to(rhs, &common);
+                let ty = if is_comparison(binop) {
+                    Type::Int
+                } else {
+                    common
+                };
+                (
+                    ExpressionKind::Binary(binop, Box::new(lhs), Box::new(rhs)),
+                    ty,
+                )
+            }
+            // `a op= b` computes `a op b` in the common type, or for shifts in
+            // a's promoted type, and converts the result back to a's type.
+            // The right operand already has the type of the operation.
+            ExpressionKind::Compound(compound_op, lhs, rhs) => {
+                let lhs = arithmetic(self.check_expr(*lhs)?)?;
+                let rhs = arithmetic(self.check_expr(*rhs)?)?;
+                let ty = lhs.ty().clone();
+                let rhs = if matches!(
+                    compound_op,
+                    CompoundOperator::ShiftLeft | CompoundOperator::ShiftRight
+                ) {
+                    promote(rhs)
+                } else {
+                    let common = common_type(&ty.promoted(), rhs.ty());
+                    convert_to(rhs, &common)
+                };
+                (
+                    ExpressionKind::Compound(compound_op, Box::new(lhs), Box::new(rhs)),
+                    ty,
+                )
+            }
+            ExpressionKind::Crement(fixity, crement, inner) => {
+                let inner = arithmetic(self.check_expr(*inner)?)?;
+                let ty = inner.ty().clone();
+                (
+                    ExpressionKind::Crement(fixity, crement, Box::new(inner)),
+                    ty,
+                )
+            }
+            ExpressionKind::Var(id) => match self.symbols.get(&id) {
+                Some((Type::Fun { .. }, _)) => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::TypeMismatch,
+                        format!("Function {} used as variable", id),
+                    )
+                    .with_span(span));
+                }
+                Some((ty, _)) => {
+                    let ty = ty.clone();
+                    (ExpressionKind::Var(id), ty)
+                }
+                None => {
+                    return Err(Diagnostic::internal(format!(
+                        "Unreachable: should have resolved variable {} already",
+                        id
+                    ))
+                    .with_span(span));
+                }
+            },
+            ExpressionKind::Assign(lhs, rhs) => {
+                let lhs = self.check_expr(*lhs)?;
+                let ty = lhs.ty().clone();
+                let rhs = self.check_and_convert(*rhs, &ty)?;
+                (ExpressionKind::Assign(Box::new(lhs), Box::new(rhs)), ty)
+            }
+            ExpressionKind::Conditional(cond, if_expr, else_expr) => {
+                let cond = self.check_expr(*cond)?;
+                let if_expr = self.check_expr(*if_expr)?;
+                let else_expr = self.check_expr(*else_expr)?;
+                let common = if if_expr.ty().is_pointer() || else_expr.ty().is_pointer() {
+                    common_pointer_type(&if_expr, &else_expr, span)?
+                } else {
+                    common_type(if_expr.ty(), else_expr.ty())
+                };
+                (
+                    ExpressionKind::Conditional(
+                        Box::new(cond),
+                        Box::new(convert_to(if_expr, &common)),
+                        Box::new(convert_to(else_expr, &common)),
+                    ),
+                    common,
+                )
+            }
+            ExpressionKind::Call(name, args) => match self.symbols.get(&name) {
+                Some((Type::Fun { params, ret }, _)) => {
+                    if params.len() != args.len() {
+                        return Err(Diagnostic::error(
+                            ErrorKind::WrongArgumentCount,
+                            format!(
+                                "Mismatched parameter count: declared as {}, called with {}",
+                                params.len(),
+                                args.len()
+                            ),
                         )
+                        .with_span(span));
                     }
-                    for param in params {
-                        self.check_expr(param);
+                    let (params, ret) = (params.clone(), *ret.clone());
+                    let mut converted = Vec::with_capacity(args.len());
+                    for (arg, param_ty) in args.into_iter().zip(&params) {
+                        converted.push(self.check_and_convert(arg, param_ty)?);
                     }
+                    (ExpressionKind::Call(name, converted), ret)
+                }
+                Some(_) => {
+                    return Err(Diagnostic::error(
+                        ErrorKind::TypeMismatch,
+                        format!("Variable {} used as function", name),
+                    )
+                    .with_span(span));
+                }
+                None => {
+                    return Err(Diagnostic::internal(format!(
+                        "Unreachable: should have resolved function {} already",
+                        name
+                    ))
+                    .with_span(span));
                 }
-                _ => panic!(
-                    "Unreachable: should have resolved function {} already",
-                    name
-                ),
             },
+            ExpressionKind::Cast(ty, inner) => {
+                let inner = self.check_expr(*inner)?;
+                (ExpressionKind::Cast(ty.clone(), Box::new(inner)), ty)
+            }
+            ExpressionKind::AddressOf(inner) => {
+                let inner = self.check_expr(*inner)?;
+                let ty = Type::Pointer(Box::new(inner.ty().clone()));
+                (ExpressionKind::AddressOf(Box::new(inner)), ty)
+            }
+            ExpressionKind::Dereference(inner) => {
+                let inner = self.check_expr(*inner)?;
+                let Type::Pointer(referenced) = inner.ty() else {
+                    return Err(Diagnostic::error(
+                        ErrorKind::TypeMismatch,
+                        format!("Dereference of non-pointer type {}", inner.ty()),
+                    )
+                    .with_span(span));
+                };
+                let ty = *referenced.clone();
+                (ExpressionKind::Dereference(Box::new(inner)), ty)
+            }
+        };
+        Ok(Expression::typed(kind, span, ty))
+    }
+}
+
+// The usual arithmetic conversions: the wider type wins, and between types
+// of the same size the unsigned one does.
+fn common_type(lhs: &Type, rhs: &Type) -> Type {
+    if lhs == rhs {
+        lhs.clone()
+    } else if lhs.size() == rhs.size() {
+        if lhs.is_signed() {
+            rhs.clone()
+        } else {
+            lhs.clone()
+        }
+    } else if lhs.size() > rhs.size() {
+        lhs.clone()
+    } else {
+        rhs.clone()
+    }
+}
+
+fn is_null_pointer_constant(expr: &Expression) -> bool {
+    matches!(expr.kind, ExpressionKind::Constant(c) if c.is_zero())
+}
+
+fn common_pointer_type(lhs: &Expression, rhs: &Expression, span: Span) -> Result<Type> {
+    if lhs.ty() == rhs.ty() || is_null_pointer_constant(rhs) {
+        Ok(lhs.ty().clone())
+    } else if is_null_pointer_constant(lhs) {
+        Ok(rhs.ty().clone())
+    } else {
+        Err(Diagnostic::error(
+            ErrorKind::TypeMismatch,
+            format!("Incompatible types {} and {}", lhs.ty(), rhs.ty()),
+        )
+        .with_span(span))
+    }
+}
+
+// Arithmetic operands can't be pointers.
+fn arithmetic(expr: Expression) -> Result<Expression> {
+    if expr.ty().is_pointer() {
+        return Err(Diagnostic::error(
+            ErrorKind::TypeMismatch,
+            format!("Arithmetic on pointer type {}", expr.ty()),
+        )
+        .with_span(expr.span));
+    }
+    Ok(expr)
+}
+
+// Implicit conversions happen between arithmetic types, and from a null
+// pointer constant to any pointer; pointers of different types need a cast.
+fn convert_by_assignment(expr: Expression, to: &Type) -> Result<Expression> {
+    if expr.ty() == to
+        || (!expr.ty().is_pointer() && !to.is_pointer())
+        || (to.is_pointer() && is_null_pointer_constant(&expr))
+    {
+        Ok(convert_to(expr, to))
+    } else {
+        Err(Diagnostic::error(
+            ErrorKind::TypeMismatch,
+            format!("Cannot convert {} to {}", expr.ty(), to),
+        )
+        .with_span(expr.span))
+    }
+}
+
+fn convert_to(expr: Expression, to: &Type) -> Expression {
+    if expr.ty() == to {
+        return expr;
+    }
+    let span = expr.span;
+    Expression::typed(
+        ExpressionKind::Cast(to.clone(), Box::new(expr)),
+        span,
+        to.clone(),
+    )
+}
+
+fn promote(expr: Expression) -> Expression {
+    let ty = expr.ty().promoted();
+    convert_to(expr, &ty)
+}
+
+fn is_comparison(binop: BinaryOperator) -> bool {
+    matches!(
+        binop,
+        BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::Less
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterOrEqual
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn error_kind(source: &str) -> ErrorKind {
+        let parsed = Parser::new(Lexer::new(source))
+            .parse()
+            .into_result()
+            .unwrap();
+        analyze(parsed).unwrap_err().kind
+    }
+
+    #[test]
+    fn undeclared() {
+        assert_eq!(
+            error_kind("int main(void) { return x; }"),
+            ErrorKind::UndeclaredIdentifier
+        );
+    }
+
+    #[test]
+    fn lvalues() {
+        assert_eq!(
+            error_kind("int main(void) { 2 = 3; }"),
+            ErrorKind::InvalidLvalue
+        );
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            error_kind("int main(void) { goto l; }"),
+            ErrorKind::UnknownLabel
+        );
+        assert_eq!(
+            error_kind("int main(void) { l: l: return 0; }"),
+            ErrorKind::DuplicateLabel
+        );
+    }
+
+    fn check(source: &str) -> (Vec<Declaration>, SymbolTable) {
+        let parsed = Parser::new(Lexer::new(source))
+            .parse()
+            .into_result()
+            .unwrap();
+        analyze(parsed).unwrap()
+    }
+
+    #[test]
+    fn conversions() {
+        let (program, symbols) = check(
+            "static int s = 4294967297L;
+            long f(long a, int b) {
+                int c = a;
+                b += a;
+                return b;
+            }",
+        );
+        assert!(matches!(
+            symbols["s"],
+            (
+                Type::Int,
+                Attrs::Static {
+                    init: InitValue::Initial(Const::Int(1)),
+                    ..
+                }
+            )
+        ));
+        let body = program[1].to_string();
+        assert!(body.contains("int c.resolved.2 = (int) a.resolved.0;"));
+        assert!(body.contains("return (long) b.resolved.1;"));
+    }
+
+    #[test]
+    fn unsigned_conversions() {
+        let (program, _) = check(
+            "int f(unsigned a, int b, long c, unsigned long d) {
+                return a < b && c > a && d == c && b + 1u;
+            }",
+        );
+        let body = program[0].to_string();
+        assert!(body.contains("a.resolved.0 < (unsigned int) b.resolved.1"));
+        assert!(body.contains("c.resolved.2 > (long) a.resolved.0"));
+        assert!(body.contains("d.resolved.3 == (unsigned long) c.resolved.2"));
+        assert!(body.contains("(unsigned int) b.resolved.1 + 1U"));
+    }
+
+    #[test]
+    fn shifts_and_compound_assignment() {
+        let (program, _) = check(
+            "long f(int i, long l, unsigned u) {
+                i += l;
+                l <<= i;
+                u -= i;
+                return i << l;
+            }",
+        );
+        let body = program[0].to_string();
+        assert!(body.contains("i.resolved.0 += l.resolved.1;"));
+        assert!(body.contains("l.resolved.1 <<= i.resolved.0;"));
+        assert!(body.contains("u.resolved.2 -= (unsigned int) i.resolved.0;"));
+        assert!(body.contains("return (long) (i.resolved.0 << l.resolved.1);"));
+
+        let Declaration::Func(Function {
+            body: Some(items), ..
+        }) = &program[0]
+        else {
+            panic!("expected a function definition");
+        };
+        let BlockItem::S(Statement {
+            kind: StatementKind::Return(ret),
+            ..
+        }) = &items[3]
+        else {
+            panic!("expected a return statement");
+        };
+        assert_eq!(ret.ty(), &Type::Long);
+        let ExpressionKind::Cast(_, shift) = &ret.kind else {
+            panic!("expected a cast");
+        };
+        assert_eq!(shift.ty(), &Type::Int);
+    }
+
+    #[test]
+    fn pointers() {
+        let (program, _) = check(
+            "int f(int *a, int **b) {
+                int x = 1;
+                int *p = &x;
+                int *q = 0;
+                *p = 2;
+                **b = *a;
+                return p == q || *&x;
+            }",
+        );
+        let body = program[0].to_string();
+        assert!(body.contains("int *q.resolved.4 = (int *) 0;"));
+        assert!(body.contains("*p.resolved.3 = 2;"));
+        assert!(body.contains("**b.resolved.1 = *a.resolved.0;"));
+        assert!(body.contains("p.resolved.3 == q.resolved.4"));
+
+        assert_eq!(
+            error_kind("int main(void) { return *&3; }"),
+            ErrorKind::InvalidLvalue
+        );
+        for source in [
+            "int main(void) { int x; long *p = &x; }",
+            "int main(void) { int x; return *x; }",
+            "int main(void) { int *p; return p + 1; }",
+            "int main(void) { int *p; long *q; return p == q; }",
+            "int main(void) { int *p; return p < 0; }",
+            "int main(void) { int *p; p++; }",
+        ] {
+            assert_eq!(error_kind(source), ErrorKind::TypeMismatch, "{}", source);
         }
+        assert_eq!(error_kind("int *p = 1;"), ErrorKind::InvalidInitializer);
+    }
+
+    #[test]
+    fn conflicting_types() {
+        assert_eq!(
+            error_kind("int x; long x;"),
+            ErrorKind::ConflictingDeclaration
+        );
+        assert_eq!(
+            error_kind("int f(int a); int f(long a);"),
+            ErrorKind::ConflictingDeclaration
+        );
+        assert_eq!(
+            error_kind("int x; unsigned x;"),
+            ErrorKind::ConflictingDeclaration
+        );
+    }
+
+    #[test]
+    fn misplaced_break() {
+        assert_eq!(
+            error_kind("int main(void) { break; }"),
+            ErrorKind::MisplacedStatement
+        );
     }
 }
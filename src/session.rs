@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::codegen::peephole::{self, Rule};
+use crate::codegen::{self, AllocationStats, Assembly};
+use crate::diagnostic::{Diagnostic, ErrorKind};
+use crate::emit;
+use crate::lexer::{Lexer, Token};
+use crate::optimize::{self, Optimizations};
+use crate::parser::{Declaration, Parser};
+use crate::preprocessor::{Preprocessed, Preprocessor};
+use crate::semantic_analysis::{self, SymbolTable};
+use crate::source_map::SourceMap;
+use crate::tacky::{self, Tacky};
+use crate::target::Target;
+
+pub type StageResult<T> = std::result::Result<T, Vec<Diagnostic>>;
+
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    pub target: Target,
+    pub include_paths: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    name: String,
+    target: Target,
+    source: String,
+    errors: Vec<Diagnostic>,
+}
+
+impl Compiler {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            include_paths: vec![],
+        }
+    }
+
+    pub fn session_for_file(&self, path: &Path) -> Session {
+        let preprocessed = Preprocessor::new(self.include_paths.clone()).preprocess_file(path);
+        self.session_from(path, preprocessed)
+    }
+
+    pub fn session(&self, name: &str, source: &str) -> Session {
+        let path = Path::new(name);
+        let preprocessed = Preprocessor::new(self.include_paths.clone()).preprocess(path, source);
+        self.session_from(path, preprocessed)
+    }
+
+    pub fn session_for_tacky(&self, path: &Path) -> Session {
+        let contents = match fs::read_to_string(path) {
+            Ok(text) => Preprocessed {
+                text,
+                errors: vec![],
+            },
+            Err(err) => Preprocessed {
+                text: String::new(),
+                errors: vec![Diagnostic::error(
+                    ErrorKind::Io,
+                    format!("Cannot read {}: {}", path.display(), err),
+                )],
+            },
+        };
+        self.session_from(path, contents)
+    }
+
+    fn session_from(&self, path: &Path, preprocessed: Preprocessed) -> Session {
+        Session {
+            name: path.display().to_string(),
+            target: self.target,
+            source: preprocessed.text,
+            errors: preprocessed.errors,
+        }
+    }
+}
+
+impl Session {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn preprocessed(&self) -> StageResult<&str> {
+        if self.errors.is_empty() {
+            Ok(&self.source)
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    pub fn lex(&self) -> StageResult<Vec<Token<'_>>> {
+        Lexer::new(self.preprocessed()?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|d| vec![d])
+    }
+
+    pub fn parse(&self) -> StageResult<Vec<Declaration>> {
+        Parser::new(Lexer::new(self.preprocessed()?))
+            .parse()
+            .into_result()
+    }
+
+    pub fn analyze(&self, ast: Vec<Declaration>) -> StageResult<(Vec<Declaration>, SymbolTable)> {
+        semantic_analysis::analyze(ast).map_err(|d| vec![d])
+    }
+
+    pub fn parse_tacky(&self) -> StageResult<(Tacky, SymbolTable)> {
+        tacky::text::parse(self.preprocessed()?).map_err(|d| vec![d])
+    }
+
+    pub fn tacky(&self, ast: Vec<Declaration>, symbols: &mut SymbolTable) -> StageResult<Tacky> {
+        tacky::emit_tacky(ast, symbols).map_err(|d| vec![d])
+    }
+
+    pub fn assemble(&self, tacky: Tacky, symbols: &SymbolTable) -> StageResult<Assembly> {
+        codegen::assemble(tacky, symbols).map_err(|d| vec![d])
+    }
+
+    pub fn optimize(
+        &self,
+        tacky: Tacky,
+        symbols: &mut SymbolTable,
+        optimizations: Optimizations,
+    ) -> Tacky {
+        optimize::optimize(tacky, symbols, optimizations)
+    }
+
+    pub fn select(&self, tacky: Tacky, symbols: &SymbolTable) -> StageResult<Assembly> {
+        codegen::select_instructions(tacky, symbols).map_err(|d| vec![d])
+    }
+
+    pub fn allocate(
+        &self,
+        assembly: Assembly,
+        symbols: &SymbolTable,
+    ) -> (Assembly, Vec<AllocationStats>) {
+        codegen::allocate(assembly, symbols)
+    }
+
+    pub fn peephole(&self, assembly: Assembly, rules: &[Rule]) -> Assembly {
+        peephole::optimize(assembly, rules)
+    }
+
+    pub fn emit(&self, assembly: Assembly, out: impl Write) -> StageResult<()> {
+        emit::emit(assembly, self.target, out).map_err(|err| {
+            vec![Diagnostic::error(
+                ErrorKind::Io,
+                format!("Cannot write assembly: {}", err),
+            )]
+        })
+    }
+
+    pub fn compile(&self, out: impl Write) -> StageResult<()> {
+        let (ast, mut symbols) = self.analyze(self.parse()?)?;
+        let tacky = self.tacky(ast, &mut symbols)?;
+        let assembly = self.assemble(tacky, &symbols)?;
+        self.emit(assembly, out)
+    }
+
+    pub fn compile_tacky(&self, out: impl Write) -> StageResult<()> {
+        let (tacky, symbols) = self.parse_tacky()?;
+        let assembly = self.assemble(tacky, &symbols)?;
+        self.emit(assembly, out)
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        SourceMap::new(&self.name, &self.source).render(diagnostic)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stages() {
+        let compiler = Compiler::new(Target::LinuxGnu);
+        let session = compiler.session(
+            "test.c",
+            "#define N 2\nint twice(int x) { return x * N; }\n",
+        );
+        assert_eq!(session.lex().unwrap().len(), 13);
+
+        let (ast, mut symbols) = session.analyze(session.parse().unwrap()).unwrap();
+        assert!(symbols.contains_key("twice"));
+        let tacky = session.tacky(ast, &mut symbols).unwrap();
+        assert_eq!(tacky.len(), 1);
+
+        let mut out = vec![];
+        let assembly = session.assemble(tacky, &symbols).unwrap();
+        session.emit(assembly, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("twice:"));
+        assert!(text.contains(".note.GNU-stack"));
+    }
+
+    #[test]
+    fn errors() {
+        let compiler = Compiler::new(Target::LinuxGnu);
+        let session = compiler.session("test.c", "int main(void) { return x; }");
+        let errors = session.compile(vec![]).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::UndeclaredIdentifier);
+        assert!(session.render(&errors[0]).starts_with("test.c:1:25: error"));
+
+        let session = compiler.session("test.c", "#error nope\n");
+        assert_eq!(
+            session.parse().unwrap_err()[0].kind,
+            ErrorKind::ErrorDirective
+        );
+
+        // Lowering an AST that skipped the type checker is an error, not a panic.
+        let session = compiler.session("test.c", "int main(void) { return 1 + 2; }");
+        let ast = session.parse().unwrap();
+        let errors = session.tacky(ast, &mut SymbolTable::new()).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::Internal);
+    }
+}
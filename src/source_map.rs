@@ -0,0 +1,177 @@
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::lexer::Lexer;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug)]
+struct Marker<'a> {
+    index: usize,
+    line: usize,
+    file: &'a str,
+}
+
+#[derive(Debug)]
+pub struct SourceMap<'a> {
+    name: &'a str,
+    source: &'a str,
+    line_starts: Vec<usize>,
+    markers: Vec<Marker<'a>>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(name: &'a str, source: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+
+        let mut markers = vec![];
+        let mut file = name;
+        for (index, text) in source.lines().enumerate() {
+            if let Some(directive) = text.trim_start().strip_prefix('#')
+                && let Some(marker) = Lexer::line_marker(directive)
+            {
+                file = marker.file.unwrap_or(file);
+                markers.push(Marker {
+                    index,
+                    line: marker.line,
+                    file,
+                });
+            }
+        }
+
+        Self {
+            name,
+            source,
+            line_starts,
+            markers,
+        }
+    }
+
+    pub fn location(&self, offset: usize) -> Location {
+        let index = self.line_index(offset);
+        let column = offset - self.line_starts[index] + 1;
+        let (file, line) = match self.markers.partition_point(|m| m.index < index) {
+            0 => (self.name, index + 1),
+            n => {
+                let marker = &self.markers[n - 1];
+                (marker.file, marker.line + index - marker.index - 1)
+            }
+        };
+        Location {
+            file: file.to_string(),
+            line,
+            column,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let Some(span) = diagnostic.span else {
+            return diagnostic.to_string();
+        };
+
+        let Location { file, line, column } = self.location(span.start);
+        let mut rendered = format!(
+            "{}:{}:{}: {}: {}\n",
+            file, line, column, diagnostic.severity, diagnostic.message
+        );
+
+        let text = self.line_text(self.line_index(span.start));
+        let gutter = " ".repeat(line.to_string().len());
+        rendered.push_str(&format!(" {} | {}\n", line, text));
+        let prefix = text.get(..column - 1).unwrap_or(text);
+        rendered.push_str(&format!(
+            " {} | {}{}",
+            gutter,
+            Self::indent(prefix),
+            Self::underline(span, text.len() - prefix.len()),
+        ));
+
+        for note in &diagnostic.notes {
+            rendered.push_str(&format!("\n{}: {}", Severity::Note, note));
+        }
+        rendered
+    }
+
+    fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        }
+    }
+
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    fn indent(prefix: &str) -> String {
+        prefix
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect()
+    }
+
+    fn underline(span: Span, rest_of_line: usize) -> String {
+        let width = (span.end - span.start).clamp(1, rest_of_line.max(1));
+        format!("^{}", "~".repeat(width - 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostic::ErrorKind;
+
+    #[test]
+    fn locations() {
+        let map = SourceMap::new("test.c", "int\nmain(void)\n\n{ }\n");
+        assert_eq!(map.location(0).line, 1);
+        assert_eq!(map.location(4).line, 2);
+        assert_eq!(map.location(9).column, 6);
+        assert_eq!(map.location(16).line, 4);
+    }
+
+    #[test]
+    fn line_markers() {
+        let source = "# 1 \"main.c\"\n# 1 \"defs.h\" 1\nint f(void);\n# 3 \"main.c\" 2\n\nint x;\n";
+        let map = SourceMap::new("main.i", source);
+        let f = source.find("f(").unwrap();
+        let x = source.find("x;").unwrap();
+        assert_eq!(
+            map.location(f),
+            Location {
+                file: "defs.h".to_string(),
+                line: 1,
+                column: 5
+            }
+        );
+        assert_eq!(
+            map.location(x),
+            Location {
+                file: "main.c".to_string(),
+                line: 4,
+                column: 5
+            }
+        );
+    }
+
+    #[test]
+    fn caret() {
+        let source = "int main(void) {\n\treturn x + 1;\n}\n";
+        let map = SourceMap::new("test.c", source);
+        let diagnostic = Diagnostic::error(ErrorKind::UndeclaredIdentifier, "Undeclared x")
+            .with_span(Span { start: 25, end: 30 });
+        assert_eq!(
+            map.render(&diagnostic),
+            "test.c:2:9: error: Undeclared x\n 2 | \treturn x + 1;\n   | \t       ^~~~~"
+        );
+    }
+}
@@ -1,10 +1,13 @@
-use std::collections::HashMap;
-
+use crate::diagnostic::{Diagnostic, Result};
 use crate::parser::{
     BinaryOperator, BlockItem, CaseInfo, CompoundOperator, Crement, Declaration, Expression,
-    Fixity, ForInit, Function, Statement, UnaryOperator, Var,
+    ExpressionKind, Fixity, ForInit, Function, Statement, StatementKind, UnaryOperator, Var,
 };
-use crate::semantic_analysis::{Attrs, InitValue, Type};
+use crate::semantic_analysis::{Attrs, InitValue, SymbolTable};
+use crate::types::{Const, Type};
+
+pub mod interp;
+pub mod text;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum UnaryOp {
@@ -33,9 +36,9 @@ pub enum BinaryOp {
     NotEquals,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Val {
-    Constant(i32),
+    Constant(Const),
     Var(String),
 }
 
@@ -57,6 +60,18 @@ pub enum Instr {
         src: Val,
         dst: Val,
     },
+    SignExtend {
+        src: Val,
+        dst: Val,
+    },
+    Truncate {
+        src: Val,
+        dst: Val,
+    },
+    ZeroExtend {
+        src: Val,
+        dst: Val,
+    },
     Jump {
         target: String,
     },
@@ -74,6 +89,18 @@ pub enum Instr {
         params: Vec<Val>,
         dst: Val,
     },
+    GetAddress {
+        src: Val,
+        dst: Val,
+    },
+    Load {
+        src_ptr: Val,
+        dst: Val,
+    },
+    Store {
+        src: Val,
+        dst_ptr: Val,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -87,45 +114,235 @@ pub enum TopLevel {
     StaticVar {
         name: String,
         global: bool,
-        init: i32,
+        init: Const,
     },
 }
 use TopLevel::*;
 
 pub type Tacky = Vec<TopLevel>;
 
+// An lvalue is either a variable or the object a pointer points to, which
+// has to be read and written through the pointer.
+#[derive(Clone)]
+enum ExpResult {
+    Plain(Val),
+    Dereferenced(Val),
+}
+
 struct TackifyState<'a> {
-    count: u8,
-    symbols: &'a HashMap<String, (Type, Attrs)>,
+    count: usize,
+    symbols: &'a mut SymbolTable,
+}
+
+// Temporaries without a symbol table entry, like those in hand-written TACKY,
+// are ints.
+pub fn type_of(val: &Val, symbols: &SymbolTable) -> Type {
+    match val {
+        Val::Constant(c) => c.ty(),
+        Val::Var(name) => match symbols.get(name) {
+            Some((ty, _)) => ty.clone(),
+            None => Type::Int,
+        },
+    }
+}
+
+// Lowering is public, so it can be handed an AST that never went through
+// the type checker.
+fn expression_type(expr: &Expression) -> Result<Type> {
+    match &expr.ty {
+        Some(ty) => Ok(ty.clone()),
+        None => Err(
+            Diagnostic::internal("Untyped expression reached TACKY generation")
+                .with_span(expr.span),
+        ),
+    }
+}
+
+impl UnaryOp {
+    pub fn evaluate(self, src: Const) -> Const {
+        match src {
+            Const::Int(n) => Const::Int(self.evaluate_i32(n)),
+            Const::Long(n) => match self {
+                UnaryOp::Not => Const::Int((n == 0) as i32),
+                _ => Const::Long(self.evaluate_i64(n)),
+            },
+            Const::UInt(n) => match self {
+                UnaryOp::Not => Const::Int((n == 0) as i32),
+                _ => Const::UInt(self.evaluate_i32(n as i32) as u32),
+            },
+            Const::ULong(n) => match self {
+                UnaryOp::Not => Const::Int((n == 0) as i32),
+                _ => Const::ULong(self.evaluate_i64(n as i64) as u64),
+            },
+        }
+    }
+
+    fn evaluate_i32(self, src: i32) -> i32 {
+        match self {
+            UnaryOp::Complement => !src,
+            UnaryOp::Negate => src.wrapping_neg(),
+            UnaryOp::Not => (src == 0) as i32,
+        }
+    }
+
+    fn evaluate_i64(self, src: i64) -> i64 {
+        match self {
+            UnaryOp::Complement => !src,
+            UnaryOp::Negate => src.wrapping_neg(),
+            UnaryOp::Not => (src == 0) as i64,
+        }
+    }
+}
+
+impl BinaryOp {
+    // Same results as the generated code: arithmetic wraps, shift counts are
+    // masked to the operand width, and None wherever idiv or div would trap.
+    // The operation happens at the type of the left operand, whose signedness
+    // picks the division, right shift and comparison; comparisons are int.
+    pub fn evaluate(self, lhs: Const, rhs: Const) -> Option<Const> {
+        let result = match lhs {
+            Const::Int(lhs) => Const::Int(self.evaluate_i32(lhs, rhs.value() as i32)?),
+            Const::Long(lhs) => Const::Long(self.evaluate_i64(lhs, rhs.value())?),
+            Const::UInt(lhs) => Const::UInt(self.evaluate_u32(lhs, rhs.value() as u32)?),
+            Const::ULong(lhs) => Const::ULong(self.evaluate_u64(lhs, rhs.value() as u64)?),
+        };
+        if self.is_comparison() {
+            Some(result.convert(&Type::Int))
+        } else {
+            Some(result)
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Equals
+                | BinaryOp::NotEquals
+                | BinaryOp::LessThan
+                | BinaryOp::LessThanEquals
+                | BinaryOp::GreaterThan
+                | BinaryOp::GreaterThanEquals
+        )
+    }
+
+    fn evaluate_i32(self, lhs: i32, rhs: i32) -> Option<i32> {
+        let result = match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+            BinaryOp::Divide => lhs.checked_div(rhs)?,
+            BinaryOp::Remainder => lhs.checked_rem(rhs)?,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXOr => lhs ^ rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::LessThan => (lhs < rhs) as i32,
+            BinaryOp::LessThanEquals => (lhs <= rhs) as i32,
+            BinaryOp::GreaterThan => (lhs > rhs) as i32,
+            BinaryOp::GreaterThanEquals => (lhs >= rhs) as i32,
+            BinaryOp::Equals => (lhs == rhs) as i32,
+            BinaryOp::NotEquals => (lhs != rhs) as i32,
+        };
+        Some(result)
+    }
+
+    fn evaluate_i64(self, lhs: i64, rhs: i64) -> Option<i64> {
+        let result = match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+            BinaryOp::Divide => lhs.checked_div(rhs)?,
+            BinaryOp::Remainder => lhs.checked_rem(rhs)?,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXOr => lhs ^ rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::LessThan => (lhs < rhs) as i64,
+            BinaryOp::LessThanEquals => (lhs <= rhs) as i64,
+            BinaryOp::GreaterThan => (lhs > rhs) as i64,
+            BinaryOp::GreaterThanEquals => (lhs >= rhs) as i64,
+            BinaryOp::Equals => (lhs == rhs) as i64,
+            BinaryOp::NotEquals => (lhs != rhs) as i64,
+        };
+        Some(result)
+    }
+
+    fn evaluate_u32(self, lhs: u32, rhs: u32) -> Option<u32> {
+        let result = match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+            BinaryOp::Divide => lhs.checked_div(rhs)?,
+            BinaryOp::Remainder => lhs.checked_rem(rhs)?,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXOr => lhs ^ rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs),
+            BinaryOp::LessThan => (lhs < rhs) as u32,
+            BinaryOp::LessThanEquals => (lhs <= rhs) as u32,
+            BinaryOp::GreaterThan => (lhs > rhs) as u32,
+            BinaryOp::GreaterThanEquals => (lhs >= rhs) as u32,
+            BinaryOp::Equals => (lhs == rhs) as u32,
+            BinaryOp::NotEquals => (lhs != rhs) as u32,
+        };
+        Some(result)
+    }
+
+    fn evaluate_u64(self, lhs: u64, rhs: u64) -> Option<u64> {
+        let result = match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+            BinaryOp::Divide => lhs.checked_div(rhs)?,
+            BinaryOp::Remainder => lhs.checked_rem(rhs)?,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXOr => lhs ^ rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::LessThan => (lhs < rhs) as u64,
+            BinaryOp::LessThanEquals => (lhs <= rhs) as u64,
+            BinaryOp::GreaterThan => (lhs > rhs) as u64,
+            BinaryOp::GreaterThanEquals => (lhs >= rhs) as u64,
+            BinaryOp::Equals => (lhs == rhs) as u64,
+            BinaryOp::NotEquals => (lhs != rhs) as u64,
+        };
+        Some(result)
+    }
 }
 
-pub fn emit_tacky(
-    declarations: Vec<Declaration>,
-    symbols: &HashMap<String, (Type, Attrs)>,
-) -> Tacky {
+// Temporaries are added to the symbol table so later stages know their types.
+pub fn emit_tacky(declarations: Vec<Declaration>, symbols: &mut SymbolTable) -> Result<Tacky> {
     let mut program = Vec::new();
 
     let mut tackify_state = TackifyState::new(symbols);
 
     for declaration in declarations {
         match declaration {
-            Declaration::Func(function) => tackify_state.tackify_function(function, &mut program),
+            Declaration::Func(function) => {
+                tackify_state.tackify_function(function, &mut program)?
+            }
             Declaration::Var(_) => (),
         }
     }
 
     tackify_state.tackify_symbols(&mut program);
 
-    program
+    Ok(program)
 }
 
 impl<'a> TackifyState<'a> {
-    pub fn new(symbols: &'a HashMap<String, (Type, Attrs)>) -> Self {
+    pub fn new(symbols: &'a mut SymbolTable) -> Self {
         Self { count: 0, symbols }
     }
 
     fn tackify_symbols(&mut self, program: &mut Tacky) {
-        for (name, (_, attrs)) in self.symbols.iter() {
+        let mut symbols = self.symbols.iter().collect::<Vec<_>>();
+        symbols.sort_by_key(|(name, _)| name.as_str());
+        for (name, (ty, attrs)) in symbols {
             if let Attrs::Static { init, global } = attrs {
                 match init {
                     InitValue::Initial(n) => program.push(StaticVar {
@@ -136,7 +353,7 @@ impl<'a> TackifyState<'a> {
                     InitValue::Tentative => program.push(StaticVar {
                         name: name.to_string(),
                         global: *global,
-                        init: 0,
+                        init: Const::zero(ty),
                     }),
                     InitValue::NoInit => (),
                 }
@@ -147,15 +364,19 @@ impl<'a> TackifyState<'a> {
     fn tackify_function(
         &mut self,
         Function {
-            name, params, body, ..
+            name,
+            params,
+            ty,
+            body,
+            ..
         }: Function,
         program: &mut Tacky,
-    ) {
-        if let Some(body) = body {
+    ) -> Result<()> {
+        if let (Some(body), Type::Fun { ret, .. }) = (body, ty) {
             let mut instructions = Vec::new();
             let name = name.clone();
-            self.tackify_block(body, &mut instructions);
-            instructions.push(Instr::Return(Val::Constant(0)));
+            self.tackify_block(body, &mut instructions)?;
+            instructions.push(Instr::Return(Val::Constant(Const::zero(&ret))));
             let global = match self.symbols.get(&name) {
                 Some((_, Attrs::Fun { global, .. })) => *global,
                 _ => false,
@@ -167,26 +388,33 @@ impl<'a> TackifyState<'a> {
                 global,
             });
         }
+        Ok(())
     }
 
-    fn tackify_block(&mut self, block_items: Vec<BlockItem>, instrs: &mut Vec<Instr>) {
+    fn tackify_block(
+        &mut self,
+        block_items: Vec<BlockItem>,
+        instrs: &mut Vec<Instr>,
+    ) -> Result<()> {
         for block_item in block_items {
             match block_item {
-                BlockItem::D(decl) => self.tackify_declaration(decl, instrs),
-                BlockItem::S(stmt) => self.tackify_statement(stmt, instrs),
+                BlockItem::D(decl) => self.tackify_declaration(decl, instrs)?,
+                BlockItem::S(stmt) => self.tackify_statement(stmt, instrs)?,
             }
         }
+        Ok(())
     }
 
-    fn tackify_declaration(&mut self, decl: Declaration, instrs: &mut Vec<Instr>) {
+    fn tackify_declaration(&mut self, decl: Declaration, instrs: &mut Vec<Instr>) -> Result<()> {
         match decl {
             Declaration::Var(Var {
                 name,
                 init,
                 storage: None,
+                ..
             }) => {
                 if let Some(expr) = init {
-                    let expr = self.tackify_expr(expr, instrs);
+                    let expr = self.tackify_expr(expr, instrs)?;
                     instrs.push(Instr::Copy {
                         src: expr,
                         dst: Val::Var(name),
@@ -196,104 +424,111 @@ impl<'a> TackifyState<'a> {
             Declaration::Var(_) => (),
             Declaration::Func(_) => (),
         }
+        Ok(())
     }
 
-    fn tackify_statement(&mut self, stmt: Statement, instrs: &mut Vec<Instr>) {
-        match stmt {
-            Statement::Null => (),
-            Statement::Return(expr) => {
-                let result = Instr::Return(self.tackify_expr(expr, instrs));
+    fn tackify_statement(&mut self, stmt: Statement, instrs: &mut Vec<Instr>) -> Result<()> {
+        match stmt.kind {
+            StatementKind::Null => (),
+            StatementKind::Error => {
+                return Err(
+                    Diagnostic::internal("Erroneous statement reached TACKY generation")
+                        .with_span(stmt.span),
+                );
+            }
+            StatementKind::Return(expr) => {
+                let result = Instr::Return(self.tackify_expr(expr, instrs)?);
                 instrs.push(result);
             }
-            Statement::Exp(expr) => {
-                self.tackify_expr(expr, instrs);
+            StatementKind::Exp(expr) => {
+                self.tackify_expr(expr, instrs)?;
             }
-            Statement::If(cond, if_stmt, Some(else_stmt)) => {
-                let cond = self.tackify_expr(cond, instrs);
+            StatementKind::If(cond, if_stmt, Some(else_stmt)) => {
+                let cond = self.tackify_expr(cond, instrs)?;
                 let else_label = self.new_temp("if_else");
                 let end_label = self.new_temp("if_end");
                 instrs.push(Instr::JumpIfZero {
                     condition: cond,
                     target: else_label.clone(),
                 });
-                self.tackify_statement(*if_stmt, instrs);
+                self.tackify_statement(*if_stmt, instrs)?;
                 instrs.push(Instr::Jump {
                     target: end_label.clone(),
                 });
                 instrs.push(Instr::Label(else_label));
-                self.tackify_statement(*else_stmt, instrs);
+                self.tackify_statement(*else_stmt, instrs)?;
                 instrs.push(Instr::Label(end_label));
             }
-            Statement::If(cond, if_stmt, None) => {
-                let cond = self.tackify_expr(cond, instrs);
+            StatementKind::If(cond, if_stmt, None) => {
+                let cond = self.tackify_expr(cond, instrs)?;
                 let end_label = self.new_temp("if_end");
                 instrs.push(Instr::JumpIfZero {
                     condition: cond,
                     target: end_label.clone(),
                 });
-                self.tackify_statement(*if_stmt, instrs);
+                self.tackify_statement(*if_stmt, instrs)?;
                 instrs.push(Instr::Label(end_label));
             }
-            Statement::Label(id, stmt) => {
+            StatementKind::Label(id, stmt) => {
                 instrs.push(Instr::Label(id));
-                self.tackify_statement(*stmt, instrs);
+                self.tackify_statement(*stmt, instrs)?;
             }
-            Statement::Goto(id) => {
+            StatementKind::Goto(id) => {
                 instrs.push(Instr::Jump { target: id });
             }
-            Statement::Compound(block_items) => self.tackify_block(block_items, instrs),
-            Statement::Break(label) => instrs.push(Instr::Jump {
+            StatementKind::Compound(block_items) => self.tackify_block(block_items, instrs)?,
+            StatementKind::Break(label) => instrs.push(Instr::Jump {
                 target: "break".to_owned() + &label,
             }),
-            Statement::Continue(label) => instrs.push(Instr::Jump {
+            StatementKind::Continue(label) => instrs.push(Instr::Jump {
                 target: "continue".to_owned() + &label,
             }),
-            Statement::DoWhile(label, body, cond) => {
+            StatementKind::DoWhile(label, body, cond) => {
                 instrs.push(Instr::Label(label.clone()));
-                self.tackify_statement(*body, instrs);
+                self.tackify_statement(*body, instrs)?;
                 instrs.push(Instr::Label("continue".to_owned() + &label));
-                let cond = self.tackify_expr(cond, instrs);
+                let cond = self.tackify_expr(cond, instrs)?;
                 instrs.push(Instr::JumpIfNotZero {
                     condition: cond,
                     target: label.clone(),
                 });
                 instrs.push(Instr::Label("break".to_owned() + &label));
             }
-            Statement::While(label, cond, body) => {
+            StatementKind::While(label, cond, body) => {
                 instrs.push(Instr::Label("continue".to_owned() + &label));
-                let cond = self.tackify_expr(cond, instrs);
+                let cond = self.tackify_expr(cond, instrs)?;
                 instrs.push(Instr::JumpIfZero {
                     condition: cond,
                     target: "break".to_owned() + &label,
                 });
-                self.tackify_statement(*body, instrs);
+                self.tackify_statement(*body, instrs)?;
                 instrs.push(Instr::Jump {
                     target: "continue".to_owned() + &label,
                 });
                 instrs.push(Instr::Label("break".to_owned() + &label));
             }
-            Statement::For(label, init, cond, post, body) => {
-                match init {
+            StatementKind::For(label, init, cond, post, body) => {
+                match *init {
                     ForInit::Decl(decl) => {
-                        self.tackify_declaration(Declaration::Var(decl), instrs);
+                        self.tackify_declaration(Declaration::Var(decl), instrs)?;
                     }
                     ForInit::Exp(expr) => {
-                        self.tackify_expr(expr, instrs);
+                        self.tackify_expr(expr, instrs)?;
                     }
                     ForInit::Null => (),
                 }
                 instrs.push(Instr::Label(label.clone()));
                 if let Some(expr) = cond {
-                    let result = self.tackify_expr(expr, instrs);
+                    let result = self.tackify_expr(expr, instrs)?;
                     instrs.push(Instr::JumpIfZero {
                         condition: result,
                         target: "break".to_owned() + &label,
                     });
                 }
-                self.tackify_statement(*body, instrs);
+                self.tackify_statement(*body, instrs)?;
                 instrs.push(Instr::Label("continue".to_owned() + &label));
                 if let Some(expr) = post {
-                    self.tackify_expr(expr, instrs);
+                    self.tackify_expr(expr, instrs)?;
                 }
                 instrs.extend(vec![
                     Instr::Jump {
@@ -302,21 +537,21 @@ impl<'a> TackifyState<'a> {
                     Instr::Label("break".to_owned() + &label),
                 ])
             }
-            Statement::Case(label, _expr, stmt) => {
+            StatementKind::Case(label, _expr, stmt) => {
                 instrs.push(Instr::Label(label));
-                self.tackify_statement(*stmt, instrs);
+                self.tackify_statement(*stmt, instrs)?;
             }
-            Statement::Default(label, stmt) => {
+            StatementKind::Default(label, stmt) => {
                 instrs.push(Instr::Label(label));
-                self.tackify_statement(*stmt, instrs);
+                self.tackify_statement(*stmt, instrs)?;
             }
-            Statement::Switch {
+            StatementKind::Switch {
                 label,
                 expr,
                 body,
                 cases,
             } => {
-                let result = self.tackify_expr(expr, instrs);
+                let result = self.tackify_expr(expr, instrs)?;
                 let (cases, default): (Vec<_>, Vec<_>) = cases
                     .iter()
                     .partition(|ci| matches!(ci, CaseInfo::Case { expr: _, label: _ }));
@@ -351,20 +586,60 @@ impl<'a> TackifyState<'a> {
                 instrs.push(Instr::Jump {
                     target: "break".to_owned() + &label,
                 });
-                self.tackify_statement(*body, instrs);
+                self.tackify_statement(*body, instrs)?;
                 instrs.push(Instr::Label("break".to_owned() + &label));
             }
         }
+        Ok(())
+    }
+
+    fn tackify_expr(&mut self, expr: Expression, instrs: &mut Vec<Instr>) -> Result<Val> {
+        let ty = expression_type(&expr)?;
+        let result = self.tackify_result(expr, instrs)?;
+        Ok(self.read(result, ty, instrs))
+    }
+
+    fn read(&mut self, result: ExpResult, ty: Type, instrs: &mut Vec<Instr>) -> Val {
+        match result {
+            ExpResult::Plain(val) => val,
+            ExpResult::Dereferenced(ptr) => {
+                let dst = self.new_var("load", ty);
+                instrs.push(Instr::Load {
+                    src_ptr: ptr,
+                    dst: dst.clone(),
+                });
+                dst
+            }
+        }
+    }
+
+    fn write(&mut self, lvalue: ExpResult, src: Val, instrs: &mut Vec<Instr>) -> ExpResult {
+        match lvalue {
+            ExpResult::Plain(dst) => {
+                instrs.push(Instr::Copy {
+                    src,
+                    dst: dst.clone(),
+                });
+                ExpResult::Plain(dst)
+            }
+            ExpResult::Dereferenced(ptr) => {
+                instrs.push(Instr::Store {
+                    src: src.clone(),
+                    dst_ptr: ptr,
+                });
+                ExpResult::Plain(src)
+            }
+        }
     }
 
-    fn tackify_expr(&mut self, expr: Expression, instrs: &mut Vec<Instr>) -> Val {
-        match expr {
-            Expression::Constant(n) => Val::Constant(n),
-            Expression::Unary(un_op, inner) => {
-                let src = self.tackify_expr(*inner, instrs);
-                let dst_name = self.new_temp("tmp");
-                let dst = Val::Var(dst_name);
+    fn tackify_result(&mut self, expr: Expression, instrs: &mut Vec<Instr>) -> Result<ExpResult> {
+        let ty = expression_type(&expr)?;
+        let val = match expr.kind {
+            ExpressionKind::Constant(n) => Val::Constant(n),
+            ExpressionKind::Unary(un_op, inner) => {
+                let src = self.tackify_expr(*inner, instrs)?;
                 let op = Self::convert_unop(un_op);
+                let dst = self.new_var("tmp", ty);
                 let new_unop = Instr::Unary {
                     unop: op,
                     src,
@@ -373,25 +648,25 @@ impl<'a> TackifyState<'a> {
                 instrs.push(new_unop);
                 dst
             }
-            Expression::Binary(BinaryOperator::And, lhs, rhs) => {
+            ExpressionKind::Binary(BinaryOperator::And, lhs, rhs) => {
                 let end_label = self.new_temp("and_end");
                 let false_label = self.new_temp("and_false");
-                let ret_val = Val::Var(self.new_temp("and_result"));
+                let ret_val = self.new_var("and_result", Type::Int);
 
-                let lhs = self.tackify_expr(*lhs, instrs);
+                let lhs = self.tackify_expr(*lhs, instrs)?;
 
                 instrs.push(Instr::JumpIfZero {
                     condition: lhs,
                     target: false_label.clone(),
                 });
-                let rhs = self.tackify_expr(*rhs, instrs);
+                let rhs = self.tackify_expr(*rhs, instrs)?;
                 instrs.extend(vec![
                     Instr::JumpIfZero {
                         condition: rhs,
                         target: false_label.clone(),
                     },
                     Instr::Copy {
-                        src: Val::Constant(1),
+                        src: Val::Constant(Const::Int(1)),
                         dst: ret_val.clone(),
                     },
                     Instr::Jump {
@@ -399,7 +674,7 @@ impl<'a> TackifyState<'a> {
                     },
                     Instr::Label(false_label),
                     Instr::Copy {
-                        src: Val::Constant(0),
+                        src: Val::Constant(Const::Int(0)),
                         dst: ret_val.clone(),
                     },
                     Instr::Label(end_label),
@@ -407,24 +682,24 @@ impl<'a> TackifyState<'a> {
 
                 ret_val
             }
-            Expression::Binary(BinaryOperator::Or, lhs, rhs) => {
+            ExpressionKind::Binary(BinaryOperator::Or, lhs, rhs) => {
                 let end_label = self.new_temp("or_end");
                 let true_label = self.new_temp("or_true");
-                let ret_val = Val::Var(self.new_temp("or_result"));
+                let ret_val = self.new_var("or_result", Type::Int);
 
-                let lhs = self.tackify_expr(*lhs, instrs);
+                let lhs = self.tackify_expr(*lhs, instrs)?;
                 instrs.push(Instr::JumpIfNotZero {
                     condition: lhs,
                     target: true_label.clone(),
                 });
-                let rhs = self.tackify_expr(*rhs, instrs);
+                let rhs = self.tackify_expr(*rhs, instrs)?;
                 instrs.extend(vec![
                     Instr::JumpIfNotZero {
                         condition: rhs,
                         target: true_label.clone(),
                     },
                     Instr::Copy {
-                        src: Val::Constant(0),
+                        src: Val::Constant(Const::Int(0)),
                         dst: ret_val.clone(),
                     },
                     Instr::Jump {
@@ -432,7 +707,7 @@ impl<'a> TackifyState<'a> {
                     },
                     Instr::Label(true_label),
                     Instr::Copy {
-                        src: Val::Constant(1),
+                        src: Val::Constant(Const::Int(1)),
                         dst: ret_val.clone(),
                     },
                     Instr::Label(end_label),
@@ -440,12 +715,11 @@ impl<'a> TackifyState<'a> {
 
                 ret_val
             }
-            Expression::Binary(binop, lhs, rhs) => {
-                let src1 = self.tackify_expr(*lhs, instrs);
-                let src2 = self.tackify_expr(*rhs, instrs);
-                let dst = Val::Var(self.new_temp("tmp"));
-
-                let op = Self::convert_binop(binop);
+            ExpressionKind::Binary(binop, lhs, rhs) => {
+                let src1 = self.tackify_expr(*lhs, instrs)?;
+                let src2 = self.tackify_expr(*rhs, instrs)?;
+                let op = Self::convert_binop(binop)?;
+                let dst = self.new_var("tmp", ty);
 
                 let new_binop = Instr::Binary {
                     binop: op,
@@ -458,19 +732,21 @@ impl<'a> TackifyState<'a> {
 
                 dst
             }
-            Expression::Compound(compound_op, lhs, rhs) => {
+            ExpressionKind::Compound(compound_op, lhs, rhs) => {
                 let op = Self::convert_compound_op(compound_op);
 
-                let Expression::Var(id) = *lhs.clone() else {
-                    panic!(
-                        "Bad assignment made it through semantic analysis: {:?}",
-                        *lhs
-                    );
+                // The checker gave the right operand the operation's type,
+                // except for shift counts, which keep their own.
+                let op_ty = if matches!(op, BinaryOp::ShiftLeft | BinaryOp::ShiftRight) {
+                    ty.promoted()
+                } else {
+                    expression_type(&rhs)?
                 };
-
-                let src1 = self.tackify_expr(*lhs, instrs);
-                let src2 = self.tackify_expr(*rhs, instrs);
-                let tmp_dst = Val::Var(self.new_temp("c_tmp"));
+                let lvalue = self.tackify_result(*lhs, instrs)?;
+                let src1 = self.read(lvalue.clone(), ty.clone(), instrs);
+                let src1 = self.convert(src1, &ty, &op_ty, instrs);
+                let src2 = self.tackify_expr(*rhs, instrs)?;
+                let tmp_dst = self.new_var("c_tmp", op_ty.clone());
 
                 instrs.push(Instr::Binary {
                     binop: op,
@@ -479,31 +755,16 @@ impl<'a> TackifyState<'a> {
                     dst: tmp_dst.clone(),
                 });
 
-                instrs.push(Instr::Copy {
-                    src: tmp_dst,
-                    dst: Val::Var(id.clone()),
-                });
-
-                Val::Var(id)
+                let result = self.convert(tmp_dst, &op_ty, &ty, instrs);
+                return Ok(self.write(lvalue, result, instrs));
             }
-            Expression::Var(id) => Val::Var(id),
-            Expression::Assign(lhs, expr) => {
-                let result = self.tackify_expr(*expr, instrs);
-
-                let Expression::Var(id) = *lhs else {
-                    panic!(
-                        "Bad assignment made it through semantic analysis: {:?}",
-                        *lhs
-                    )
-                };
-
-                instrs.push(Instr::Copy {
-                    src: result,
-                    dst: Val::Var(id.clone()),
-                });
-                Val::Var(id)
+            ExpressionKind::Var(id) => Val::Var(id),
+            ExpressionKind::Assign(lhs, expr) => {
+                let lvalue = self.tackify_result(*lhs, instrs)?;
+                let result = self.tackify_expr(*expr, instrs)?;
+                return Ok(self.write(lvalue, result, instrs));
             }
-            Expression::Crement(fixity, crement, expr) => {
+            ExpressionKind::Crement(fixity, crement, expr) => {
                 let op = Self::convert_crement(crement);
 
                 let name = if crement == Crement::Inc {
@@ -511,9 +772,35 @@ impl<'a> TackifyState<'a> {
                 } else {
                     "dec"
                 };
-                let tmp_dst = Val::Var(self.new_temp(name));
-
-                let src = self.tackify_expr(*expr, instrs);
+                let one = Const::Int(1).convert(&ty);
+                let src = match self.tackify_result(*expr, instrs)? {
+                    ExpResult::Plain(src) => src,
+                    // Through a pointer, the old and new values both live in
+                    // temporaries.
+                    ExpResult::Dereferenced(ptr) => {
+                        let old = self.new_var(name, ty.clone());
+                        let new = self.new_var(name, ty);
+                        instrs.extend(vec![
+                            Instr::Load {
+                                src_ptr: ptr.clone(),
+                                dst: old.clone(),
+                            },
+                            Instr::Binary {
+                                binop: op,
+                                src1: old.clone(),
+                                src2: Val::Constant(one),
+                                dst: new.clone(),
+                            },
+                            Instr::Store {
+                                src: new.clone(),
+                                dst_ptr: ptr,
+                            },
+                        ]);
+                        let val = if fixity == Fixity::Pre { new } else { old };
+                        return Ok(ExpResult::Plain(val));
+                    }
+                };
+                let tmp_dst = self.new_var(name, ty);
 
                 instrs.extend(vec![
                     Instr::Copy {
@@ -523,23 +810,23 @@ impl<'a> TackifyState<'a> {
                     Instr::Binary {
                         binop: op,
                         src1: tmp_dst.clone(),
-                        src2: Val::Constant(1),
+                        src2: Val::Constant(one),
                         dst: src.clone(),
                     },
                 ]);
 
                 if fixity == Fixity::Pre { src } else { tmp_dst }
             }
-            Expression::Conditional(cond_expr, if_expr, else_expr) => {
-                let cond_expr = self.tackify_expr(*cond_expr, instrs);
+            ExpressionKind::Conditional(cond_expr, if_expr, else_expr) => {
+                let cond_expr = self.tackify_expr(*cond_expr, instrs)?;
                 let end_label = self.new_temp("cond_end");
                 let else_label = self.new_temp("cond_else");
-                let cond_dst = Val::Var(self.new_temp("cond_result"));
                 instrs.push(Instr::JumpIfZero {
                     condition: cond_expr,
                     target: else_label.clone(),
                 });
-                let if_expr = self.tackify_expr(*if_expr, instrs);
+                let if_expr = self.tackify_expr(*if_expr, instrs)?;
+                let cond_dst = self.new_var("cond_result", ty);
                 instrs.extend(vec![
                     Instr::Copy {
                         src: if_expr,
@@ -550,7 +837,7 @@ impl<'a> TackifyState<'a> {
                     },
                     Instr::Label(else_label),
                 ]);
-                let else_expr = self.tackify_expr(*else_expr, instrs);
+                let else_expr = self.tackify_expr(*else_expr, instrs)?;
                 instrs.extend(vec![
                     Instr::Copy {
                         src: else_expr,
@@ -560,12 +847,12 @@ impl<'a> TackifyState<'a> {
                 ]);
                 cond_dst
             }
-            Expression::Call(name, param_exprs) => {
+            ExpressionKind::Call(name, param_exprs) => {
                 let mut params = Vec::with_capacity(param_exprs.len());
                 for param in param_exprs {
-                    params.push(self.tackify_expr(param, instrs));
+                    params.push(self.tackify_expr(param, instrs)?);
                 }
-                let dst = Val::Var(self.new_temp("call"));
+                let dst = self.new_var("call", ty);
                 instrs.push(Instr::Call {
                     name,
                     params,
@@ -574,7 +861,46 @@ impl<'a> TackifyState<'a> {
 
                 dst
             }
+            ExpressionKind::Cast(ty, inner) => {
+                let src_ty = expression_type(&inner)?;
+                let src = self.tackify_expr(*inner, instrs)?;
+                self.convert(src, &src_ty, &ty, instrs)
+            }
+            ExpressionKind::AddressOf(inner) => match self.tackify_result(*inner, instrs)? {
+                ExpResult::Plain(src) => {
+                    let dst = self.new_var("addr", ty);
+                    instrs.push(Instr::GetAddress {
+                        src,
+                        dst: dst.clone(),
+                    });
+                    dst
+                }
+                ExpResult::Dereferenced(ptr) => ptr,
+            },
+            ExpressionKind::Dereference(inner) => {
+                let ptr = self.tackify_expr(*inner, instrs)?;
+                return Ok(ExpResult::Dereferenced(ptr));
+            }
+        };
+        Ok(ExpResult::Plain(val))
+    }
+
+    fn convert(&mut self, src: Val, from: &Type, to: &Type, instrs: &mut Vec<Instr>) -> Val {
+        if from == to {
+            return src;
         }
+        let dst = self.new_var("cast", to.clone());
+        let dst_copy = dst.clone();
+        instrs.push(if to.size() == from.size() {
+            Instr::Copy { src, dst: dst_copy }
+        } else if to.size() < from.size() {
+            Instr::Truncate { src, dst: dst_copy }
+        } else if from.is_signed() {
+            Instr::SignExtend { src, dst: dst_copy }
+        } else {
+            Instr::ZeroExtend { src, dst: dst_copy }
+        });
+        dst
     }
 
     fn new_temp(&mut self, var_name: &'static str) -> String {
@@ -583,6 +909,12 @@ impl<'a> TackifyState<'a> {
         format!("{}.{}", var_name, count)
     }
 
+    fn new_var(&mut self, var_name: &'static str, ty: Type) -> Val {
+        let name = self.new_temp(var_name);
+        self.symbols.insert(name.clone(), (ty, Attrs::Local));
+        Val::Var(name)
+    }
+
     fn convert_crement(crement: Crement) -> BinaryOp {
         match crement {
             Crement::Dec => BinaryOp::Subtract,
@@ -598,8 +930,8 @@ impl<'a> TackifyState<'a> {
         }
     }
 
-    fn convert_binop(binop: BinaryOperator) -> BinaryOp {
-        match binop {
+    fn convert_binop(binop: BinaryOperator) -> Result<BinaryOp> {
+        let op = match binop {
             BinaryOperator::Add => BinaryOp::Add,
             BinaryOperator::Subtract => BinaryOp::Subtract,
             BinaryOperator::Multiply => BinaryOp::Multiply,
@@ -616,8 +948,14 @@ impl<'a> TackifyState<'a> {
             BinaryOperator::Less => BinaryOp::LessThan,
             BinaryOperator::GreaterOrEqual => BinaryOp::GreaterThanEquals,
             BinaryOperator::LessOrEqual => BinaryOp::LessThanEquals,
-            binop => panic!("Unexpected binary operator {:?}", binop),
-        }
+            binop => {
+                return Err(Diagnostic::internal(format!(
+                    "Unexpected binary operator {:?}",
+                    binop
+                )));
+            }
+        };
+        Ok(op)
     }
 
     fn convert_compound_op(compound_op: CompoundOperator) -> BinaryOp {
@@ -0,0 +1,660 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+
+use crate::semantic_analysis::SymbolTable;
+use crate::tacky::{Instr, Tacky, TopLevel, Val, type_of};
+use crate::types::Const;
+
+// Frames live on the heap, so the default only has to stop runaway recursion
+// before it exhausts memory, and is deep enough for any program the native
+// stack could run. The driver's --max-call-depth overrides it.
+pub const DEFAULT_MAX_DEPTH: usize = 1_000_000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TrapKind {
+    DivisionByZero,
+    DivisionOverflow,
+    UndefinedFunction(String),
+    WrongArgumentCount(String),
+    Uninitialized(String),
+    UnknownLabel(String),
+    MissingReturn,
+    StackOverflow,
+    InvalidPointer(u64),
+    AddressOfConstant(String),
+    DuplicateDefinition(String),
+    Io(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub function: String,
+}
+
+// Functions and statics with internal linkage are private to the unit that
+// defines them, so two units can each have their own `static int s`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+struct Symbol<'a> {
+    unit: Option<usize>,
+    name: &'a str,
+}
+
+struct Function<'a> {
+    unit: usize,
+    params: &'a [String],
+    instructions: &'a [Instr],
+    labels: HashMap<&'a str, usize>,
+    symbols: &'a SymbolTable,
+}
+
+struct Frame<'a> {
+    id: usize,
+    name: &'a str,
+    function: &'a Function<'a>,
+    pc: usize,
+    locals: HashMap<&'a str, Const>,
+    dst: Option<&'a Val>,
+}
+
+// Pointers are handed out as indexes into the locations whose address has
+// been taken, so that null is never a valid address. A local lives as long
+// as the call that created it.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum Location<'a> {
+    Static(Symbol<'a>),
+    Local(usize, &'a str),
+}
+
+// Where a pointer leads once its address has been checked.
+enum Slot<'s, 'a> {
+    Static(&'s mut Const),
+    Local(&'s mut HashMap<&'a str, Const>, &'a str),
+}
+
+struct Machine<'a, W> {
+    max_depth: usize,
+    internal: HashSet<(usize, &'a str)>,
+    statics: HashMap<Symbol<'a>, Const>,
+    locations: Vec<Location<'a>>,
+    addresses: HashMap<Location<'a>, u64>,
+    frames: usize,
+    out: W,
+}
+
+// Each translation unit comes with its own symbol table, which gives the
+// types that conversions produce.
+pub fn run(units: &[(Tacky, SymbolTable)], max_depth: usize, out: impl Write) -> Result<i32, Trap> {
+    let top_levels = || {
+        units
+            .iter()
+            .enumerate()
+            .flat_map(|(unit, (program, symbols))| program.iter().map(move |t| (unit, t, symbols)))
+    };
+    let mut functions = HashMap::new();
+    let mut machine = Machine {
+        max_depth,
+        internal: HashSet::new(),
+        statics: HashMap::new(),
+        locations: vec![],
+        addresses: HashMap::new(),
+        frames: 0,
+        out,
+    };
+    for (unit, top_level, _) in top_levels() {
+        if let TopLevel::TackyFunction {
+            name,
+            global: false,
+            ..
+        }
+        | TopLevel::StaticVar {
+            name,
+            global: false,
+            ..
+        } = top_level
+        {
+            machine.internal.insert((unit, name.as_str()));
+        }
+    }
+    for (unit, top_level, symbols) in top_levels() {
+        let (TopLevel::TackyFunction { name, .. } | TopLevel::StaticVar { name, .. }) = top_level;
+        let symbol = machine.symbol(unit, name);
+        if functions.contains_key(&symbol) || machine.statics.contains_key(&symbol) {
+            return Err(Trap {
+                kind: TrapKind::DuplicateDefinition(name.clone()),
+                function: "<start>".to_string(),
+            });
+        }
+        match top_level {
+            TopLevel::TackyFunction {
+                params,
+                instructions,
+                ..
+            } => {
+                let labels = instructions
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, instr)| match instr {
+                        Instr::Label(label) => Some((label.as_str(), i)),
+                        _ => None,
+                    })
+                    .collect();
+                functions.insert(
+                    symbol,
+                    Function {
+                        unit,
+                        params,
+                        instructions,
+                        labels,
+                        symbols,
+                    },
+                );
+            }
+            TopLevel::StaticVar { init, .. } => {
+                machine.statics.insert(symbol, *init);
+            }
+        }
+    }
+    let result = machine.execute(&functions);
+    let flushed = machine.out.flush();
+    let code = result?;
+    flushed.map_err(|err| Trap {
+        kind: TrapKind::Io(err.to_string()),
+        function: "main".to_string(),
+    })?;
+    Ok(code)
+}
+
+fn enter<'a>(
+    functions: &'a HashMap<Symbol<'a>, Function<'a>>,
+    id: usize,
+    symbol: Symbol<'a>,
+    args: Vec<Const>,
+    dst: Option<&'a Val>,
+) -> Result<Frame<'a>, TrapKind> {
+    let function = functions
+        .get(&symbol)
+        .ok_or_else(|| TrapKind::UndefinedFunction(symbol.name.to_string()))?;
+    if function.params.len() != args.len() {
+        return Err(TrapKind::WrongArgumentCount(symbol.name.to_string()));
+    }
+    Ok(Frame {
+        id,
+        name: symbol.name,
+        function,
+        pc: 0,
+        locals: function
+            .params
+            .iter()
+            .map(String::as_str)
+            .zip(args)
+            .collect(),
+        dst,
+    })
+}
+
+impl<'a, W: Write> Machine<'a, W> {
+    fn execute(&mut self, functions: &'a HashMap<Symbol<'a>, Function<'a>>) -> Result<i32, Trap> {
+        let main = Symbol {
+            unit: None,
+            name: "main",
+        };
+        let entry = enter(functions, 0, main, vec![], None).map_err(|kind| Trap {
+            kind,
+            function: "<start>".to_string(),
+        })?;
+        let mut stack = vec![entry];
+        loop {
+            let (frame, callers) = stack.split_last_mut().expect("call stack is never empty");
+            let trap = |kind| Trap {
+                kind,
+                function: frame.name.to_string(),
+            };
+            let Some(instr) = frame.function.instructions.get(frame.pc) else {
+                return Err(trap(TrapKind::MissingReturn));
+            };
+            frame.pc += 1;
+            match instr {
+                Instr::Return(val) => {
+                    let value = self.get(frame, val).map_err(trap)?;
+                    let dst = frame.dst;
+                    stack.pop();
+                    match stack.last_mut() {
+                        Some(caller) => self.set(caller, dst, value),
+                        None => return Ok(value.value() as i32),
+                    }
+                }
+                Instr::Unary { unop, src, dst } => {
+                    let src = self.get(frame, src).map_err(trap)?;
+                    self.set(frame, Some(dst), unop.evaluate(src));
+                }
+                Instr::Binary {
+                    binop,
+                    src1,
+                    src2,
+                    dst,
+                } => {
+                    let lhs = self.get(frame, src1).map_err(trap)?;
+                    let rhs = self.get(frame, src2).map_err(trap)?;
+                    let result = binop.evaluate(lhs, rhs).ok_or_else(|| {
+                        trap(if rhs.is_zero() {
+                            TrapKind::DivisionByZero
+                        } else {
+                            TrapKind::DivisionOverflow
+                        })
+                    })?;
+                    self.set(frame, Some(dst), result);
+                }
+                Instr::Copy { src, dst }
+                | Instr::SignExtend { src, dst }
+                | Instr::Truncate { src, dst }
+                | Instr::ZeroExtend { src, dst } => {
+                    let src = self.get(frame, src).map_err(trap)?;
+                    let ty = type_of(dst, frame.function.symbols);
+                    self.set(frame, Some(dst), src.convert(&ty));
+                }
+                Instr::Jump { target } => frame.pc = jump(frame, target).map_err(trap)?,
+                Instr::JumpIfZero { condition, target } => {
+                    if self.get(frame, condition).map_err(trap)?.is_zero() {
+                        frame.pc = jump(frame, target).map_err(trap)?;
+                    }
+                }
+                Instr::JumpIfNotZero { condition, target } => {
+                    if !self.get(frame, condition).map_err(trap)?.is_zero() {
+                        frame.pc = jump(frame, target).map_err(trap)?;
+                    }
+                }
+                Instr::Label(_) => (),
+                Instr::Call { name, params, dst } => {
+                    let args = params
+                        .iter()
+                        .map(|param| self.get(frame, param))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(trap)?;
+                    let callee = self.symbol(frame.function.unit, name);
+                    if !functions.contains_key(&callee) {
+                        let result = self.builtin(name, &args).map_err(trap)?;
+                        self.set(frame, Some(dst), result);
+                        continue;
+                    }
+                    if stack.len() == self.max_depth {
+                        return Err(trap(TrapKind::StackOverflow));
+                    }
+                    self.frames += 1;
+                    let callee =
+                        enter(functions, self.frames, callee, args, Some(dst)).map_err(trap)?;
+                    stack.push(callee);
+                }
+                Instr::GetAddress { src, dst } => {
+                    let Val::Var(var) = src else {
+                        return Err(trap(TrapKind::AddressOfConstant(src.to_string())));
+                    };
+                    let symbol = self.symbol(frame.function.unit, var);
+                    let location = if self.statics.contains_key(&symbol) {
+                        Location::Static(symbol)
+                    } else {
+                        Location::Local(frame.id, var)
+                    };
+                    let address = *self.addresses.entry(location).or_insert_with(|| {
+                        self.locations.push(location);
+                        self.locations.len() as u64
+                    });
+                    self.set(frame, Some(dst), Const::ULong(address));
+                }
+                Instr::Load { src_ptr, dst } => {
+                    let ptr = self.get(frame, src_ptr).map_err(trap)?;
+                    let value = match self.locate(frame, callers, ptr).map_err(trap)? {
+                        Slot::Static(value) => *value,
+                        Slot::Local(locals, var) => locals
+                            .get(var)
+                            .copied()
+                            .ok_or_else(|| trap(TrapKind::Uninitialized(var.to_string())))?,
+                    };
+                    let ty = type_of(dst, frame.function.symbols);
+                    self.set(frame, Some(dst), value.convert(&ty));
+                }
+                Instr::Store { src, dst_ptr } => {
+                    let value = self.get(frame, src).map_err(trap)?;
+                    let ptr = self.get(frame, dst_ptr).map_err(trap)?;
+                    match self.locate(frame, callers, ptr).map_err(trap)? {
+                        Slot::Static(slot) => *slot = value,
+                        Slot::Local(locals, var) => {
+                            locals.insert(var, value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn builtin(&mut self, name: &str, args: &[Const]) -> Result<Const, TrapKind> {
+        match (name, args) {
+            ("putchar", [c]) => self
+                .out
+                .write_all(&[c.value() as u8])
+                .map(|()| Const::Int(c.value() as u8 as i32))
+                .map_err(|err| TrapKind::Io(err.to_string())),
+            _ => Err(TrapKind::UndefinedFunction(name.to_string())),
+        }
+    }
+
+    fn locate<'s>(
+        &'s mut self,
+        frame: &'s mut Frame<'a>,
+        callers: &'s mut [Frame<'a>],
+        ptr: Const,
+    ) -> Result<Slot<'s, 'a>, TrapKind> {
+        let address = ptr.value() as u64;
+        let invalid = TrapKind::InvalidPointer(address);
+        let location = address
+            .checked_sub(1)
+            .and_then(|index| self.locations.get(index as usize))
+            .ok_or(invalid.clone())?;
+        match *location {
+            Location::Static(symbol) => self
+                .statics
+                .get_mut(&symbol)
+                .map(Slot::Static)
+                .ok_or(invalid),
+            Location::Local(id, var) if id == frame.id => Ok(Slot::Local(&mut frame.locals, var)),
+            Location::Local(id, var) => callers
+                .iter_mut()
+                .find(|caller| caller.id == id)
+                .map(|caller| Slot::Local(&mut caller.locals, var))
+                .ok_or(invalid),
+        }
+    }
+
+    fn symbol<'n>(&self, unit: usize, name: &'n str) -> Symbol<'n> {
+        let unit = self.internal.contains(&(unit, name)).then_some(unit);
+        Symbol { unit, name }
+    }
+
+    fn get(&self, frame: &Frame<'a>, val: &Val) -> Result<Const, TrapKind> {
+        match val {
+            Val::Constant(n) => Ok(*n),
+            Val::Var(var) => frame
+                .locals
+                .get(var.as_str())
+                .or_else(|| self.statics.get(&self.symbol(frame.function.unit, var)))
+                .copied()
+                .ok_or_else(|| TrapKind::Uninitialized(var.clone())),
+        }
+    }
+
+    fn set(&mut self, frame: &mut Frame<'a>, dst: Option<&'a Val>, value: Const) {
+        let Some(Val::Var(var)) = dst else {
+            return;
+        };
+        if let Some(slot) = self.statics.get_mut(&self.symbol(frame.function.unit, var)) {
+            *slot = value;
+        } else {
+            frame.locals.insert(var, value);
+        }
+    }
+}
+
+fn jump(frame: &Frame, target: &str) -> Result<usize, TrapKind> {
+    frame
+        .function
+        .labels
+        .get(target)
+        .copied()
+        .ok_or_else(|| TrapKind::UnknownLabel(target.to_string()))
+}
+
+impl Trap {
+    pub fn exit_code(&self) -> i32 {
+        match self.kind {
+            TrapKind::DivisionByZero | TrapKind::DivisionOverflow => 128 + 8,
+            TrapKind::StackOverflow | TrapKind::InvalidPointer(_) => 128 + 11,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapKind::DivisionByZero => write!(f, "division by zero"),
+            TrapKind::DivisionOverflow => write!(f, "overflow in division"),
+            TrapKind::UndefinedFunction(name) => write!(f, "call to undefined function {}", name),
+            TrapKind::WrongArgumentCount(name) => {
+                write!(f, "wrong number of arguments to {}", name)
+            }
+            TrapKind::Uninitialized(name) => write!(f, "read of uninitialized variable {}", name),
+            TrapKind::UnknownLabel(label) => write!(f, "jump to unknown label {}", label),
+            TrapKind::MissingReturn => write!(f, "fell off the end of the function"),
+            TrapKind::StackOverflow => write!(f, "stack overflow"),
+            TrapKind::InvalidPointer(address) => {
+                write!(f, "dereference of invalid pointer {:#x}", address)
+            }
+            TrapKind::AddressOfConstant(val) => write!(f, "address of constant {}", val),
+            TrapKind::DuplicateDefinition(name) => write!(f, "multiple definitions of {}", name),
+            TrapKind::Io(err) => write!(f, "cannot write output: {}", err),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trap in {}: {}", self.function, self.kind)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::optimize::Optimizations;
+    use crate::session::Compiler;
+    use crate::tacky::text;
+    use crate::target::Target;
+    use std::path::Path;
+    use std::process::{self, Command};
+    use std::{env, fs};
+
+    fn interpret(source: &str) -> Result<i32, Trap> {
+        run(&[text::parse(source).unwrap()], DEFAULT_MAX_DEPTH, vec![])
+    }
+
+    #[test]
+    fn semantics() {
+        let source = "global function main() {
+    a = 2147483647 + 1
+    b = a / -2
+    c = -7 % 3
+    d = 1 << 33
+    e = -16 >> 2
+    f = b + c
+    g = f + d
+    h = g + e
+    return h
+}
+";
+        assert_eq!(interpret(source), Ok(1073741824 - 1 + 2 - 4));
+
+        let trap = interpret("global function main() {\n  x = 1 / 0\n  return x\n}\n");
+        assert_eq!(trap.unwrap_err().kind, TrapKind::DivisionByZero);
+        let trap = interpret("global function main() {\n  x = -2147483648 % -1\n  return x\n}\n");
+        assert_eq!(trap.unwrap_err().exit_code(), 136);
+        let trap = interpret("global function main() {\n  return x\n}\n");
+        assert_eq!(
+            trap.unwrap_err().kind,
+            TrapKind::Uninitialized("x".to_string())
+        );
+        let runaway = text::parse("global function main() {\n  x = call main()\n  return x\n}\n");
+        let trap = run(&[runaway.unwrap()], 100, vec![]);
+        assert_eq!(trap.unwrap_err().kind, TrapKind::StackOverflow);
+
+        let deep = "function rec(n) {
+    jump_if_zero n, done
+    m = n - 1
+    r = call rec(m)
+    r = r + 1
+    return r
+  done:
+    return 0
+}
+
+global function main() {
+    x = call rec(50000)
+    y = x % 256
+    return y
+}
+";
+        assert_eq!(interpret(deep), Ok(50000 % 256));
+    }
+
+    #[test]
+    fn pointers() {
+        let source = "function set(ptr) {
+    int * ptr
+    store 5, ptr
+    return 0
+}
+
+global function main() {
+    int * p
+    x = 3
+    p = get_address x
+    y = call set(p)
+    z = load p
+    return z
+}
+";
+        assert_eq!(interpret(source), Ok(5));
+
+        let trap = interpret("global function main() {\n  x = load 0UL\n  return x\n}\n");
+        assert_eq!(trap.unwrap_err().kind, TrapKind::InvalidPointer(0));
+        let trap = interpret("global function main() {\n  store 1, 99UL\n  return 0\n}\n");
+        assert_eq!(trap.unwrap_err().exit_code(), 139);
+
+        let (mut program, symbols) = text::parse(source).unwrap();
+        let TopLevel::TackyFunction { instructions, .. } = &mut program[1] else {
+            panic!("expected a function");
+        };
+        instructions[1] = Instr::GetAddress {
+            src: Val::Constant(Const::Int(5)),
+            dst: Val::Var("p".to_string()),
+        };
+        let trap = run(&[(program, symbols)], DEFAULT_MAX_DEPTH, vec![]);
+        assert_eq!(
+            trap.unwrap_err().kind,
+            TrapKind::AddressOfConstant("5".to_string())
+        );
+    }
+
+    #[test]
+    fn calls_and_statics() {
+        let source = "extern function putchar/1
+
+function bump(n) {
+    count = count + n
+    return count
+}
+
+global function main() {
+    x = call bump(3)
+    y = call bump(4)
+    z = call putchar(y)
+    return z
+}
+
+static count = 60
+";
+        let unit = text::parse(source).unwrap();
+        let mut out = vec![];
+        assert_eq!(run(&[unit], DEFAULT_MAX_DEPTH, &mut out), Ok(67));
+        assert_eq!(out, b"C");
+    }
+
+    #[test]
+    fn linkage() {
+        let unit = |value: i32, main: &str| {
+            let source = format!(
+                "function helper() {{
+    return s
+}}
+
+{}
+
+static s = {}
+",
+                main, value
+            );
+            text::parse(&source).unwrap()
+        };
+        let a = unit(
+            20,
+            "extern function other/0
+
+global function main() {
+    x = call helper()
+    y = call other()
+    z = x + y
+    return z
+}",
+        );
+        let b = unit(
+            6,
+            "global function other() {
+    r = call helper()
+    return r
+}",
+        );
+        assert_eq!(run(&[a.clone(), b], DEFAULT_MAX_DEPTH, vec![]), Ok(26));
+
+        let c = text::parse("global function main() {\n  return 0\n}\n").unwrap();
+        let trap = run(&[a, c], DEFAULT_MAX_DEPTH, vec![]).unwrap_err();
+        assert_eq!(trap.kind, TrapKind::DuplicateDefinition("main".to_string()));
+    }
+
+    #[test]
+    fn matches_native() {
+        let target = Target::host();
+        let compiler = Compiler::new(target);
+        let dir = env::temp_dir().join(format!("wacc-interp-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let sources = Path::new(env!("CARGO_MANIFEST_DIR")).join("c-src");
+        for entry in fs::read_dir(sources).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "c") {
+                continue;
+            }
+            let session = compiler.session_for_file(&path);
+            let (ast, mut symbols) = session.analyze(session.parse().unwrap()).unwrap();
+            let program = session.tacky(ast, &mut symbols).unwrap();
+            let mut expected = None;
+            for optimizations in [Optimizations::default(), Optimizations::all()] {
+                let name = format!("{} ({:?})", path.display(), optimizations);
+                let program = session.optimize(program.clone(), &mut symbols, optimizations);
+                let mut interpreted = vec![];
+                let units = [(program.clone(), symbols.clone())];
+                let code = run(&units, DEFAULT_MAX_DEPTH, &mut interpreted).unwrap();
+
+                let assembly = dir.join("test.s");
+                let executable = dir.join("test");
+                let mut out = vec![];
+                let assembled = session.assemble(program, &symbols).unwrap();
+                session.emit(assembled, &mut out).unwrap();
+                fs::write(&assembly, out).unwrap();
+                let status = target
+                    .compiler()
+                    .arg(&assembly)
+                    .arg("-o")
+                    .arg(&executable)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+                let native = Command::new(&executable).output().unwrap();
+
+                assert_eq!(native.status.code(), Some(code & 0xff), "{}", name);
+                assert_eq!(native.stdout, interpreted, "{}", name);
+                let result = (code, interpreted);
+                assert_eq!(expected.get_or_insert(result.clone()), &result, "{}", name);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,799 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::{Diagnostic, ErrorKind, Result, Span};
+use crate::semantic_analysis::{Attrs, InitValue, SymbolTable};
+use crate::tacky::{BinaryOp, Instr, Tacky, TopLevel, UnaryOp, Val};
+use crate::types::{Const, Type};
+
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    span: Span,
+}
+
+struct Line<'a> {
+    words: Vec<Word<'a>>,
+    position: usize,
+    end: Span,
+}
+
+pub fn parse(text: &str) -> Result<(Tacky, SymbolTable)> {
+    let mut lines = split_lines(text).into_iter();
+    let mut program = vec![];
+    let mut symbols = SymbolTable::new();
+    let mut calls = vec![];
+
+    while let Some(mut line) = lines.next() {
+        let first = line.next()?;
+        match first.text {
+            "extern" => {
+                let kind = line.next()?;
+                let ty = match line.peek() {
+                    Some(
+                        word @ Word {
+                            text: "int" | "long" | "unsigned",
+                            ..
+                        },
+                    ) if kind.text == "static" => {
+                        line.next()?;
+                        declared_type(word, &mut line)?
+                    }
+                    _ => Type::Int,
+                };
+                let name = line.name()?;
+                let entry = match kind.text {
+                    "static" => (
+                        ty,
+                        Attrs::Static {
+                            init: InitValue::NoInit,
+                            global: true,
+                        },
+                    ),
+                    "function" => {
+                        line.expect("/")?;
+                        let param_count = line.next()?;
+                        let param_count: usize = param_count.text.parse().map_err(|_| {
+                            Diagnostic::error(
+                                ErrorKind::InvalidConstant,
+                                format!("Invalid parameter count {}", param_count.text),
+                            )
+                            .with_span(param_count.span)
+                        })?;
+                        (
+                            int_function(param_count),
+                            Attrs::Fun {
+                                defined: false,
+                                global: true,
+                            },
+                        )
+                    }
+                    _ => return Err(unexpected("static or function", kind)),
+                };
+                line.finish()?;
+                declare(&mut symbols, name, entry)?;
+            }
+            _ => {
+                let global = first.text == "global";
+                let keyword = if global { line.next()? } else { first };
+                match keyword.text {
+                    "static" => {
+                        let name = line.name()?;
+                        line.expect("=")?;
+                        let init = line.constant()?;
+                        line.finish()?;
+                        let entry = (
+                            init.ty(),
+                            Attrs::Static {
+                                init: InitValue::Initial(init),
+                                global,
+                            },
+                        );
+                        declare(&mut symbols, name, entry)?;
+                        program.push(TopLevel::StaticVar {
+                            name: name.text.to_string(),
+                            global,
+                            init,
+                        });
+                    }
+                    "function" => {
+                        let name = line.name()?;
+                        let params = line.list(Line::name)?;
+                        line.expect("{")?;
+                        line.finish()?;
+                        let entry = (
+                            int_function(params.len()),
+                            Attrs::Fun {
+                                defined: true,
+                                global,
+                            },
+                        );
+                        declare(&mut symbols, name, entry)?;
+                        let instructions =
+                            function_body(&mut lines, name, &mut symbols, &mut calls)?;
+                        program.push(TopLevel::TackyFunction {
+                            name: name.text.to_string(),
+                            params: params.iter().map(|p| p.text.to_string()).collect(),
+                            instructions,
+                            global,
+                        });
+                    }
+                    _ => return Err(unexpected("function, static or extern", keyword)),
+                }
+            }
+        }
+    }
+
+    for call in calls {
+        if !matches!(symbols.get(call.text), Some((Type::Fun { .. }, _))) {
+            return Err(Diagnostic::error(
+                ErrorKind::UndeclaredIdentifier,
+                format!("Call to undeclared function {}", call.text),
+            )
+            .with_span(call.span)
+            .with_note("declare it with an `extern function` line"));
+        }
+    }
+    Ok((program, symbols))
+}
+
+// Functions only carry their parameter count; parameters and return values
+// declared with another type in the body keep their own types.
+fn int_function(param_count: usize) -> Type {
+    Type::Fun {
+        params: vec![Type::Int; param_count],
+        ret: Box::new(Type::Int),
+    }
+}
+
+fn declare(symbols: &mut SymbolTable, name: Word, entry: (Type, Attrs)) -> Result<()> {
+    if symbols.insert(name.text.to_string(), entry).is_some() {
+        return Err(Diagnostic::error(
+            ErrorKind::DuplicateDefinition,
+            format!("Duplicate definition of {}", name.text),
+        )
+        .with_span(name.span));
+    }
+    Ok(())
+}
+
+fn function_body<'a>(
+    lines: &mut impl Iterator<Item = Line<'a>>,
+    name: Word,
+    symbols: &mut SymbolTable,
+    calls: &mut Vec<Word<'a>>,
+) -> Result<Vec<Instr>> {
+    let mut instructions = vec![];
+    let mut labels = HashSet::new();
+    let mut targets = vec![];
+
+    loop {
+        let Some(mut line) = lines.next() else {
+            return Err(Diagnostic::error(
+                ErrorKind::UnexpectedEof,
+                format!("Missing closing brace for function {}", name.text),
+            )
+            .with_span(name.span));
+        };
+        let first = line.next()?;
+        let instr = match first.text {
+            "}" => {
+                line.finish()?;
+                break;
+            }
+            "int" | "long" | "unsigned" => {
+                let ty = declared_type(first, &mut line)?;
+                loop {
+                    let local = line.name()?;
+                    declare(symbols, local, (ty.clone(), Attrs::Local))?;
+                    if line.peek().is_none() {
+                        break;
+                    }
+                    line.expect(",")?;
+                }
+                continue;
+            }
+            "return" => Instr::Return(line.val()?),
+            "store" => {
+                let src = line.val()?;
+                line.expect(",")?;
+                Instr::Store {
+                    src,
+                    dst_ptr: line.val()?,
+                }
+            }
+            "jump" => {
+                let target = line.name()?;
+                targets.push(target);
+                Instr::Jump {
+                    target: target.text.to_string(),
+                }
+            }
+            "jump_if_zero" | "jump_if_not_zero" => {
+                let condition = line.val()?;
+                line.expect(",")?;
+                let target = line.name()?;
+                targets.push(target);
+                let target = target.text.to_string();
+                if first.text == "jump_if_zero" {
+                    Instr::JumpIfZero { condition, target }
+                } else {
+                    Instr::JumpIfNotZero { condition, target }
+                }
+            }
+            _ if line.peek().map(|w| w.text) == Some(":") => {
+                line.next()?;
+                let label = identifier(first)?;
+                if !labels.insert(label.text) {
+                    return Err(Diagnostic::error(
+                        ErrorKind::DuplicateLabel,
+                        format!("Duplicate label {}", label.text),
+                    )
+                    .with_span(label.span));
+                }
+                Instr::Label(label.text.to_string())
+            }
+            _ => {
+                let dst = Val::Var(identifier(first)?.text.to_string());
+                line.expect("=")?;
+                assignment(&mut line, dst, calls)?
+            }
+        };
+        line.finish()?;
+        instructions.push(instr);
+    }
+
+    for target in targets {
+        if !labels.contains(target.text) {
+            return Err(Diagnostic::error(
+                ErrorKind::UnknownLabel,
+                format!("Jump to unknown label {}", target.text),
+            )
+            .with_span(target.span));
+        }
+    }
+    Ok(instructions)
+}
+
+fn assignment<'a>(line: &mut Line<'a>, dst: Val, calls: &mut Vec<Word<'a>>) -> Result<Instr> {
+    let first = line.next()?;
+    if first.text == "call" {
+        let name = line.name()?;
+        calls.push(name);
+        let params = line.list(Line::val)?;
+        return Ok(Instr::Call {
+            name: name.text.to_string(),
+            params,
+            dst,
+        });
+    }
+    if first.text == "get_address" {
+        let src = line.name()?;
+        return Ok(Instr::GetAddress {
+            src: Val::Var(src.text.to_string()),
+            dst,
+        });
+    }
+    if let "sign_extend" | "truncate" | "zero_extend" | "load" = first.text {
+        let src = line.val()?;
+        return Ok(match first.text {
+            "sign_extend" => Instr::SignExtend { src, dst },
+            "truncate" => Instr::Truncate { src, dst },
+            "zero_extend" => Instr::ZeroExtend { src, dst },
+            _ => Instr::Load { src_ptr: src, dst },
+        });
+    }
+    if let Some(unop) = unary_op(first.text) {
+        return Ok(Instr::Unary {
+            unop,
+            src: line.val()?,
+            dst,
+        });
+    }
+    let src1 = val(first)?;
+    let Some(op) = line.peek() else {
+        return Ok(Instr::Copy { src: src1, dst });
+    };
+    line.next()?;
+    let binop = binary_op(op.text).ok_or_else(|| unexpected("binary operator", op))?;
+    Ok(Instr::Binary {
+        binop,
+        src1,
+        src2: line.val()?,
+        dst,
+    })
+}
+
+fn unary_op(text: &str) -> Option<UnaryOp> {
+    match text {
+        "~" => Some(UnaryOp::Complement),
+        "-" => Some(UnaryOp::Negate),
+        "!" => Some(UnaryOp::Not),
+        _ => None,
+    }
+}
+
+fn binary_op(text: &str) -> Option<BinaryOp> {
+    let binop = match text {
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Subtract,
+        "*" => BinaryOp::Multiply,
+        "/" => BinaryOp::Divide,
+        "%" => BinaryOp::Remainder,
+        "&" => BinaryOp::BitAnd,
+        "|" => BinaryOp::BitOr,
+        "^" => BinaryOp::BitXOr,
+        "<<" => BinaryOp::ShiftLeft,
+        ">>" => BinaryOp::ShiftRight,
+        "<" => BinaryOp::LessThan,
+        "<=" => BinaryOp::LessThanEquals,
+        ">" => BinaryOp::GreaterThan,
+        ">=" => BinaryOp::GreaterThanEquals,
+        "==" => BinaryOp::Equals,
+        "!=" => BinaryOp::NotEquals,
+        _ => return None,
+    };
+    Some(binop)
+}
+
+// Types spelled the way they print: `int`, `long`, `unsigned int` or
+// `unsigned long`, starting with the already consumed `first`, and followed
+// by a `*` for each level of pointer.
+fn declared_type(first: Word, line: &mut Line) -> Result<Type> {
+    let mut ty = match first.text {
+        "int" => Type::Int,
+        "long" => Type::Long,
+        _ => {
+            let word = line.next()?;
+            match word.text {
+                "int" => Type::UInt,
+                "long" => Type::ULong,
+                _ => return Err(unexpected("`int` or `long`", word)),
+            }
+        }
+    };
+    while let Some(word) = line.peek()
+        && word.text.chars().all(|c| c == '*')
+    {
+        line.next()?;
+        for _ in word.text.chars() {
+            ty = Type::Pointer(Box::new(ty));
+        }
+    }
+    Ok(ty)
+}
+
+fn identifier(word: Word) -> Result<Word> {
+    let mut chars = word.text.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if valid {
+        Ok(word)
+    } else {
+        Err(unexpected("identifier", word))
+    }
+}
+
+fn val(word: Word) -> Result<Val> {
+    if word
+        .text
+        .starts_with(|c: char| c.is_ascii_digit() || c == '-')
+    {
+        constant(word).map(Val::Constant)
+    } else {
+        Ok(Val::Var(identifier(word)?.text.to_string()))
+    }
+}
+
+fn constant(word: Word) -> Result<Const> {
+    let parsed = if let Some(digits) = word.text.strip_suffix("UL") {
+        digits.parse().map(Const::ULong)
+    } else if let Some(digits) = word.text.strip_suffix('U') {
+        digits.parse().map(Const::UInt)
+    } else if let Some(digits) = word.text.strip_suffix('L') {
+        digits.parse().map(Const::Long)
+    } else {
+        word.text.parse().map(Const::Int)
+    };
+    parsed.map_err(|_| {
+        Diagnostic::error(
+            ErrorKind::InvalidConstant,
+            format!("Invalid constant {}", word.text),
+        )
+        .with_span(word.span)
+    })
+}
+
+fn unexpected(expected: &str, word: Word) -> Diagnostic {
+    Diagnostic::error(
+        ErrorKind::UnexpectedToken,
+        format!("Expected {}, got {}", expected, word.text),
+    )
+    .with_span(word.span)
+}
+
+fn split_lines(text: &str) -> Vec<Line<'_>> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for raw in text.split_inclusive('\n') {
+        let start = offset;
+        offset += raw.len();
+        let content = raw.split('#').next().unwrap_or("");
+        let mut words = vec![];
+        let mut word_start = None;
+        for (i, c) in content.char_indices() {
+            let punct = matches!(c, '(' | ')' | ',' | '{' | '}' | ':' | '/');
+            if c.is_whitespace() || punct {
+                if let Some(s) = word_start.take() {
+                    words.push(word(text, start + s, start + i));
+                }
+                if punct {
+                    words.push(word(text, start + i, start + i + 1));
+                }
+            } else if word_start.is_none() {
+                word_start = Some(i);
+            }
+        }
+        if let Some(s) = word_start {
+            words.push(word(text, start + s, start + content.len()));
+        }
+        if !words.is_empty() {
+            let end = start + content.trim_end().len();
+            lines.push(Line {
+                words,
+                position: 0,
+                end: Span { start: end, end },
+            });
+        }
+    }
+    lines
+}
+
+fn word(text: &str, start: usize, end: usize) -> Word<'_> {
+    Word {
+        text: &text[start..end],
+        span: Span { start, end },
+    }
+}
+
+impl<'a> Line<'a> {
+    fn peek(&self) -> Option<Word<'a>> {
+        self.words.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<Word<'a>> {
+        let word = self.peek().ok_or_else(|| {
+            Diagnostic::error(ErrorKind::UnexpectedToken, "Unexpected end of line")
+                .with_span(self.end)
+        })?;
+        self.position += 1;
+        Ok(word)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let word = self.next()?;
+        if word.text == text {
+            Ok(())
+        } else {
+            Err(unexpected(&format!("`{}`", text), word))
+        }
+    }
+
+    fn name(&mut self) -> Result<Word<'a>> {
+        identifier(self.next()?)
+    }
+
+    fn val(&mut self) -> Result<Val> {
+        val(self.next()?)
+    }
+
+    fn constant(&mut self) -> Result<Const> {
+        constant(self.next()?)
+    }
+
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        self.expect("(")?;
+        let mut items = vec![];
+        if self.peek().map(|w| w.text) == Some(")") {
+            self.next()?;
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            let word = self.next()?;
+            match word.text {
+                "," => (),
+                ")" => return Ok(items),
+                _ => return Err(unexpected("`,` or `)`", word)),
+            }
+        }
+    }
+
+    fn finish(&self) -> Result<()> {
+        match self.peek() {
+            Some(word) => Err(unexpected("end of line", word)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn externs(program: &[TopLevel], symbols: &SymbolTable) -> Vec<String> {
+    let defined = program
+        .iter()
+        .map(|top_level| match top_level {
+            TopLevel::TackyFunction { name, .. } | TopLevel::StaticVar { name, .. } => {
+                name.as_str()
+            }
+        })
+        .collect::<HashSet<_>>();
+    let mut externs = symbols
+        .iter()
+        .filter(|(name, _)| !defined.contains(name.as_str()))
+        .filter_map(|(name, entry)| match entry {
+            (Type::Int, Attrs::Static { .. }) => Some(format!("extern static {}", name)),
+            (ty, Attrs::Static { .. }) => Some(format!("extern static {} {}", ty, name)),
+            (Type::Fun { params, .. }, Attrs::Fun { .. }) => {
+                Some(format!("extern function {}/{}", name, params.len()))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    externs.sort();
+    externs
+}
+
+// Most tests of passes over one function start from TACKY text; this returns
+// the parameters and instructions of its first function.
+#[cfg(test)]
+pub(crate) fn parse_function(text: &str) -> (Vec<String>, Vec<Instr>, SymbolTable) {
+    let (program, symbols) = parse(text).unwrap();
+    let function = program.into_iter().find_map(|top_level| match top_level {
+        TopLevel::TackyFunction {
+            params,
+            instructions,
+            ..
+        } => Some((params, instructions)),
+        TopLevel::StaticVar { .. } => None,
+    });
+    let (params, instructions) = function.expect("expected a function");
+    (params, instructions, symbols)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pretty;
+    use crate::session::Compiler;
+    use crate::target::Target;
+    use std::fs;
+    use std::path::Path;
+
+    fn error_kind(text: &str) -> ErrorKind {
+        parse(text).unwrap_err().kind
+    }
+
+    #[test]
+    fn instructions() {
+        let text = "extern static counter
+extern function putchar/1
+
+global function main(a, b) {
+    tmp.0 = - -5
+    tmp.1 = a << tmp.0   # shift
+    tmp.2 = ! tmp.1
+    jump_if_zero tmp.2, end
+    counter = call putchar(65)
+    jump end
+  end:
+    return counter
+}
+
+static s = -3
+";
+        let (program, symbols) = parse(text).unwrap();
+        let (params, instructions, _) = parse_function(text);
+        assert!(matches!(
+            program[0],
+            TopLevel::TackyFunction { global: true, .. }
+        ));
+        assert_eq!(params, ["a", "b"]);
+        assert_eq!(
+            instructions[0],
+            Instr::Unary {
+                unop: UnaryOp::Negate,
+                src: Val::Constant(Const::Int(-5)),
+                dst: Val::Var("tmp.0".to_string()),
+            }
+        );
+        assert_eq!(
+            instructions[4],
+            Instr::Call {
+                name: "putchar".to_string(),
+                params: vec![Val::Constant(Const::Int(65))],
+                dst: Val::Var("counter".to_string()),
+            }
+        );
+        assert_eq!(
+            program[1],
+            TopLevel::StaticVar {
+                name: "s".to_string(),
+                global: false,
+                init: Const::Int(-3),
+            }
+        );
+        assert!(matches!(symbols["counter"].1, Attrs::Static { .. }));
+        assert_eq!(
+            externs(&program, &symbols),
+            ["extern function putchar/1", "extern static counter"]
+        );
+    }
+
+    #[test]
+    fn long_values() {
+        let text = "extern static long total
+
+global function widen(a, b) {
+    long b, wide
+    wide = sign_extend a
+    b = wide + 4294967296L
+    total = b
+    narrow = truncate b
+    return narrow
+}
+
+static big = -1L
+";
+        let (program, symbols) = parse(text).unwrap();
+        let (_, instructions, _) = parse_function(text);
+        assert_eq!(
+            instructions[1],
+            Instr::Binary {
+                binop: BinaryOp::Add,
+                src1: Val::Var("wide".to_string()),
+                src2: Val::Constant(Const::Long(4294967296)),
+                dst: Val::Var("b".to_string()),
+            }
+        );
+        assert_eq!(symbols["b"].0, Type::Long);
+        assert!(!symbols.contains_key("a"));
+        assert_eq!(symbols["total"].0, Type::Long);
+        assert_eq!(symbols["big"].0, Type::Long);
+        assert_eq!(pretty::tacky(&program, &symbols), text);
+    }
+
+    #[test]
+    fn unsigned_values() {
+        let text = "extern static unsigned long total
+
+global function widen(a, b, c) {
+    unsigned int a, c
+    long b
+    unsigned long wide
+    wide = zero_extend a
+    b = wide + 4294967296UL
+    c = c / 3U
+    total = b
+    return c
+}
+
+static big = 4294967295U
+";
+        let (program, symbols) = parse(text).unwrap();
+        assert_eq!(symbols["a"].0, Type::UInt);
+        assert_eq!(symbols["wide"].0, Type::ULong);
+        assert_eq!(symbols["total"].0, Type::ULong);
+        assert_eq!(symbols["big"].0, Type::UInt);
+        assert_eq!(pretty::tacky(&program, &symbols), text);
+        assert_eq!(
+            error_kind("function f() {\n  unsigned x\n  return x\n}\n"),
+            ErrorKind::UnexpectedToken
+        );
+    }
+
+    #[test]
+    fn pointer_values() {
+        let text = "extern static int * last
+
+global function f(p) {
+    long ** p
+    long * q
+    long x
+    q = load p
+    x = load q
+    store x, q
+    last = get_address x
+    return x
+}
+";
+        let (program, symbols) = parse(text).unwrap();
+        let (_, instructions, _) = parse_function(text);
+        assert_eq!(
+            instructions[2],
+            Instr::Store {
+                src: Val::Var("x".to_string()),
+                dst_ptr: Val::Var("q".to_string()),
+            }
+        );
+        let pointer = |ty| Type::Pointer(Box::new(ty));
+        assert_eq!(symbols["p"].0, pointer(pointer(Type::Long)));
+        assert_eq!(symbols["last"].0, pointer(Type::Int));
+        assert_eq!(pretty::tacky(&program, &symbols), text);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error_kind("function f() {\n  x = y +\n}\n"),
+            ErrorKind::UnexpectedToken
+        );
+        assert_eq!(
+            error_kind("function f() {\n  return 99999999999\n}\n"),
+            ErrorKind::InvalidConstant
+        );
+        assert_eq!(
+            error_kind("function f() {\n  long x, x\n  return x\n}\n"),
+            ErrorKind::DuplicateDefinition
+        );
+        assert_eq!(
+            error_kind("function f() {\n  jump nowhere\n}\n"),
+            ErrorKind::UnknownLabel
+        );
+        assert_eq!(
+            error_kind("function f() {\n  x = call g()\n}\n"),
+            ErrorKind::UndeclaredIdentifier
+        );
+        assert_eq!(
+            error_kind("function f() {\n  return 0\n"),
+            ErrorKind::UnexpectedEof
+        );
+        let error = parse("function f() {\n  p = get_address 5\n}\n").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnexpectedToken);
+        assert_eq!(error.span, Some(Span { start: 33, end: 34 }));
+        let error = parse("static s = 1\nstatic s = 2\n").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DuplicateDefinition);
+        assert_eq!(error.span, Some(Span { start: 20, end: 21 }));
+    }
+
+    #[test]
+    fn round_trip() {
+        let compiler = Compiler::new(Target::LinuxGnu);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("c-src");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "c") {
+                continue;
+            }
+            let session = compiler.session_for_file(&path);
+            let (ast, mut symbols) = session.analyze(session.parse().unwrap()).unwrap();
+            let program = session.tacky(ast, &mut symbols).unwrap();
+            let text = pretty::tacky(&program, &symbols);
+
+            let (parsed, parsed_symbols) = parse(&text).unwrap();
+            assert_eq!(parsed, program, "{}", path.display());
+            assert_eq!(pretty::tacky(&parsed, &parsed_symbols), text);
+        }
+    }
+
+    #[test]
+    fn golden() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let compiler = Compiler::new(Target::LinuxGnu);
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "tacky") {
+                continue;
+            }
+            let session = compiler.session_for_tacky(&path);
+            let mut out = vec![];
+            session.compile_tacky(&mut out).unwrap();
+            let actual = String::from_utf8(out).unwrap();
+            let expected_path = path.with_extension("s");
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                fs::write(&expected_path, &actual).unwrap();
+            }
+            let expected = fs::read_to_string(&expected_path).unwrap();
+            assert_eq!(actual, expected, "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    LinuxGnu,
+    AppleDarwin,
+}
+
+impl Target {
+    pub fn host() -> Self {
+        if cfg!(target_os = "macos") {
+            Target::AppleDarwin
+        } else {
+            Target::LinuxGnu
+        }
+    }
+
+    pub fn symbol(&self, name: &str) -> String {
+        match self {
+            Target::LinuxGnu => name.to_string(),
+            Target::AppleDarwin => format!("_{}", name),
+        }
+    }
+
+    pub fn local_label(&self, label: &str) -> String {
+        match self {
+            Target::LinuxGnu => format!(".L{}", label),
+            Target::AppleDarwin => format!("L{}", label),
+        }
+    }
+
+    pub fn call_target(&self, name: &str, external: bool) -> String {
+        match self {
+            Target::LinuxGnu if external => format!("{}@PLT", name),
+            _ => self.symbol(name),
+        }
+    }
+
+    pub fn is_elf(&self) -> bool {
+        matches!(self, Target::LinuxGnu)
+    }
+
+    // Searched for headers after any -I directories, in the order gcc uses.
+    pub fn system_include_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from("/usr/local/include")];
+        if self.is_elf() {
+            paths.push(PathBuf::from("/usr/include/x86_64-linux-gnu"));
+        }
+        paths.push(PathBuf::from("/usr/include"));
+        paths
+    }
+
+    pub fn compiler(&self) -> Command {
+        match self {
+            Target::LinuxGnu => Command::new("gcc"),
+            Target::AppleDarwin => {
+                let mut command = Command::new("arch");
+                command.args(["-x86_64", "gcc"]);
+                command
+            }
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64-linux-gnu" | "x86_64-unknown-linux-gnu" => Ok(Target::LinuxGnu),
+            "x86_64-apple-darwin" => Ok(Target::AppleDarwin),
+            _ => Err(format!(
+                "Unknown target {} (expected x86_64-linux-gnu or x86_64-apple-darwin)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::LinuxGnu => write!(f, "x86_64-linux-gnu"),
+            Target::AppleDarwin => write!(f, "x86_64-apple-darwin"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("x86_64-linux-gnu".parse(), Ok(Target::LinuxGnu));
+        assert_eq!("x86_64-apple-darwin".parse(), Ok(Target::AppleDarwin));
+        assert!("riscv64-linux-gnu".parse::<Target>().is_err());
+        assert_eq!(Target::LinuxGnu.to_string().parse(), Ok(Target::LinuxGnu));
+    }
+
+    #[test]
+    fn symbols() {
+        assert_eq!(Target::LinuxGnu.symbol("main"), "main");
+        assert_eq!(Target::AppleDarwin.symbol("main"), "_main");
+        assert_eq!(Target::LinuxGnu.call_target("putchar", true), "putchar@PLT");
+        assert_eq!(Target::LinuxGnu.call_target("foo", false), "foo");
+        assert_eq!(Target::AppleDarwin.call_target("putchar", true), "_putchar");
+        assert_eq!(Target::LinuxGnu.local_label("end.1"), ".Lend.1");
+    }
+
+    #[test]
+    fn include_paths() {
+        assert_eq!(
+            Target::LinuxGnu.system_include_paths(),
+            [
+                "/usr/local/include",
+                "/usr/include/x86_64-linux-gnu",
+                "/usr/include"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+}
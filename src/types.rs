@@ -0,0 +1,87 @@
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Type {
+    Int,
+    Long,
+    UInt,
+    ULong,
+    Pointer(Box<Type>),
+    Fun { params: Vec<Type>, ret: Box<Type> },
+}
+
+impl Type {
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Int | Type::UInt => 4,
+            Type::Long | Type::ULong | Type::Pointer(_) => 8,
+            Type::Fun { .. } => unreachable!("function types have no size"),
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Int | Type::Long)
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Pointer(_))
+    }
+
+    // The integer promotions: operands narrower than int are widened to int
+    // before any arithmetic happens on them.
+    pub fn promoted(&self) -> Type {
+        if self.size() < Type::Int.size() {
+            Type::Int
+        } else {
+            self.clone()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Const {
+    Int(i32),
+    Long(i64),
+    UInt(u32),
+    ULong(u64),
+}
+
+impl Const {
+    pub fn zero(ty: &Type) -> Const {
+        Const::Int(0).convert(ty)
+    }
+
+    pub fn ty(self) -> Type {
+        match self {
+            Const::Int(_) => Type::Int,
+            Const::Long(_) => Type::Long,
+            Const::UInt(_) => Type::UInt,
+            Const::ULong(_) => Type::ULong,
+        }
+    }
+
+    // The value extended to 64 bits according to the constant's signedness;
+    // an unsigned long keeps its bit pattern.
+    pub fn value(self) -> i64 {
+        match self {
+            Const::Int(n) => n as i64,
+            Const::Long(n) => n,
+            Const::UInt(n) => n as i64,
+            Const::ULong(n) => n as i64,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.value() == 0
+    }
+
+    // Conversion to a narrower type keeps the low bits, like truncating
+    // movl does; conversion to a wider one sign- or zero-extends depending
+    // on the source type. Pointers are represented as unsigned longs.
+    pub fn convert(self, ty: &Type) -> Const {
+        match ty {
+            Type::Long => Const::Long(self.value()),
+            Type::ULong | Type::Pointer(_) => Const::ULong(self.value() as u64),
+            Type::UInt => Const::UInt(self.value() as u32),
+            _ => Const::Int(self.value() as i32),
+        }
+    }
+}
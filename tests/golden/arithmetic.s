@@ -0,0 +1,27 @@
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	$17, %ecx
+	movl	$5, %edi
+	movl	%ecx, %eax
+	cdq
+	idivl	%edi
+	movl	%eax, %esi
+	movl	%ecx, %eax
+	cdq
+	idivl	%edi
+	movl	%edx, %ecx
+	shll	%cl, %esi
+	negl	%esi
+	notl	%esi
+	movl	$1, %ecx
+	sarl	%cl, %esi
+	movl	%esi, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.section .note.GNU-stack,"",@progbits
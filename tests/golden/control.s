@@ -0,0 +1,46 @@
+	.globl sum
+	.text
+	.type sum, @function
+sum:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	$0, %eax
+	movl	$0, %edx
+.Lloop:
+	cmpl	%edi, %edx
+	movl	$0, %ecx
+	setge	%cl
+	testl	%ecx, %ecx
+	jne	.Lend
+	addl	%edx, %eax
+	addl	$1, %edx
+	jmp	.Lloop
+.Lend:
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size sum, .-sum
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	$10, %edi
+	call sum
+	cmpl	$45, %eax
+	movl	$0, %eax
+	sete	%al
+	testl	%eax, %eax
+	je	.Lfail
+	xorl	%eax, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+.Lfail:
+	movl	$1, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.section .note.GNU-stack,"",@progbits
@@ -0,0 +1,51 @@
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	$-17, %ecx
+	movl	%ecx, %eax
+	movl	%eax, %edx
+	sarl	$31, %edx
+	andl	$3, %edx
+	addl	%eax, %edx
+	sarl	$2, %edx
+	movl	%edx, %r8d
+	movl	%ecx, %eax
+	movl	%eax, %edx
+	sarl	$31, %edx
+	andl	$3, %edx
+	addl	%eax, %edx
+	sarl	$2, %edx
+	imull	$4, %edx
+	movl	%ecx, %eax
+	subl	%edx, %eax
+	movl	%eax, %edi
+	movl	$-1840700269, %eax
+	imull	%ecx
+	addl	%ecx, %edx
+	sarl	$2, %edx
+	movl	%edx, %eax
+	sarl	$31, %eax
+	subl	%eax, %edx
+	movl	%edx, %esi
+	movl	$1840700269, %eax
+	imull	%ecx
+	subl	%ecx, %edx
+	sarl	$2, %edx
+	movl	%edx, %eax
+	sarl	$31, %eax
+	subl	%eax, %edx
+	imull	$-7, %edx
+	movl	%ecx, %eax
+	subl	%edx, %eax
+	addl	%edi, %r8d
+	addl	%eax, %esi
+	imull	%esi, %r8d
+	movl	%r8d, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.section .note.GNU-stack,"",@progbits
@@ -0,0 +1,71 @@
+	.text
+	.type spread, @function
+spread:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movq	16(%rbp), %rax
+	movq	24(%rbp), %rcx
+	addq	%rax, %rdi
+	subq	%rcx, %rdi
+	movl	%edi, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size spread, .-spread
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	subq	$8, %rsp
+	pushq %rbx
+	pushq %r12
+	pushq %r13
+	movl	$-3, %r10d
+	movslq	%r10d, %r13
+	movq	$8589934592, %rbx
+	imulq	%r13, %rbx
+	movq	%rbx, %rax
+	cqo
+	movq	$1000, %r10
+	idivq	%r10
+	movq	%rax, %r12
+	movq	%rbx, %rax
+	cqo
+	movq	$7, %r10
+	idivq	%r10
+	addq	%rdx, %r12
+	movl	%r12d, %edi
+	call putchar@PLT
+	movq	%rbx, %rdi
+	movl	$1, %esi
+	movl	$2, %edx
+	movl	$3, %ecx
+	movl	$4, %r8d
+	movl	$5, %r9d
+	movq	$12884901888, %r10
+	pushq %r10
+	movq	%r12, %rax
+	pushq %rax
+	call spread
+	addq	$16, %rsp
+	testl	%eax, %eax
+	je	.Ldone
+	addq	%r13, counter(%rip)
+.Ldone:
+	movl	counter(%rip), %eax
+	popq	%r13
+	popq	%r12
+	popq	%rbx
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.data
+	.balign 8
+	.type counter, @object
+	.size counter, 8
+counter:
+	.quad 4294967339
+	.section .note.GNU-stack,"",@progbits
@@ -0,0 +1,46 @@
+	.text
+	.type eight, @function
+eight:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	16(%rbp), %eax
+	movl	24(%rbp), %eax
+	addl	%eax, %edi
+	movl	%edi, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size eight, .-eight
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	addl	$1, counter(%rip)
+	movl	counter(%rip), %r10d
+	movl	%r10d, shared(%rip)
+	movl	$1, %edi
+	movl	$2, %esi
+	movl	$3, %edx
+	movl	$4, %ecx
+	movl	$5, %r8d
+	movl	$6, %r9d
+	movl	counter(%rip), %eax
+	pushq %rax
+	pushq $7
+	call eight
+	addq	$16, %rsp
+	movl	%eax, %edi
+	call putchar@PLT
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.data
+	.balign 4
+	.type counter, @object
+	.size counter, 4
+counter:
+	.long 64
+	.section .note.GNU-stack,"",@progbits
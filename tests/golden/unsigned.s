@@ -0,0 +1,69 @@
+	.text
+	.type mix, @function
+mix:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	movl	%edi, %eax
+	xorl	%edx, %edx
+	divl	%esi
+	movl	%eax, %r8d
+	movl	%edi, %eax
+	xorl	%edx, %edx
+	movl	$10, %r10d
+	divl	%r10d
+	movl	$3, %ecx
+	shrl	%cl, %r8d
+	cmpl	%esi, %edi
+	movl	$0, %eax
+	setb	%al
+	cmpl	%edx, %r8d
+	movl	$0, %ecx
+	setae	%cl
+	addl	%edx, %r8d
+	addl	%eax, %r8d
+	addl	%ecx, %r8d
+	movl	%r8d, %eax
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size mix, .-mix
+	.globl main
+	.text
+	.type main, @function
+main:
+	pushq	%rbp
+	movq	%rsp, %rbp
+	subq	$8, %rsp
+	pushq %rbx
+	movl	$-1, %edi
+	movl	%edi, %ecx
+	movq	%rcx, %rax
+	xorl	%edx, %edx
+	movq	$2, %r10
+	divq	%r10
+	cmpq	%rcx, %rax
+	movl	$0, %ecx
+	setbe	%cl
+	testl	%ecx, %ecx
+	je	.Ldone
+	addq	%rax, total(%rip)
+.Ldone:
+	movl	$7, %esi
+	call mix
+	movl	total(%rip), %ebx
+	addl	%eax, %ebx
+	movl	$65, %edi
+	call putchar@PLT
+	movl	%ebx, %eax
+	popq	%rbx
+	movq 	%rbp, %rsp
+	popq	%rbp
+	ret
+	.size main, .-main
+	.data
+	.balign 8
+	.type total, @object
+	.size total, 8
+total:
+	.quad -1
+	.section .note.GNU-stack,"",@progbits